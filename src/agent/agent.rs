@@ -1,12 +1,95 @@
-use crate::llm::{ContentBlock, LLMProvider, LLMRequest, Message, MessageContent, MessageRole};
+use super::system_message::{
+    render_system_message, DEFAULT_SYSTEM_MESSAGE, PLAN_MODE_INSTRUCTIONS, REVIEW_SYSTEM_MESSAGE,
+};
+use crate::checkpoint::CheckpointStore;
+use crate::export::tool_name_and_params;
+use crate::forge::Forge;
+use crate::hooks::{Hook, HookAction, HookEvent, HookOutcome};
+use crate::llm::{
+    estimate_tokens, ContentBlock, LLMProvider, LLMRequest, LLMResponse, Message, MessageContent,
+    MessageRole, StopReason, Usage,
+};
+use crate::memory::ProjectMemory;
+use crate::metrics;
+use crate::permissions::{
+    AuditDecision, Grant, PermissionAuditLog, PermissionDecision, PermissionGrants,
+    PermissionMediator, PermissionMode, PermissionPolicy, PermissionRequest,
+    PermissionRequestReason, RuleAction,
+};
 use crate::persistence::StatePersistence;
+use crate::prompts::{parse_values, split_arguments, PromptLibrary};
+use crate::settings::{BudgetSettings, ProjectRulesSettings};
+use crate::stats::estimate_cost;
+use crate::tracker::Tracker;
 use crate::types::*;
-use crate::ui::{UIMessage, UserInterface};
-use crate::utils::{format_with_line_numbers, CommandExecutor};
+use crate::ui::{UIEvent, UIMessage, UserInterface};
+use crate::utils::{
+    apply_content_updates, format_with_line_numbers, unified_diff, CommandExecutor,
+};
 use anyhow::Result;
-use std::path::PathBuf;
+use chrono::Utc;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 use tracing::{debug, trace, warn};
 
+/// Files with instructions for coding agents, as used by various tools
+const PROJECT_RULES_FILES: &[&str] = &["AGENTS.md", "CLAUDE.md", ".cursorrules"];
+
+/// Question recorded when the user interrupted the agent, the answer is the new instruction
+const INTERRUPTION_QUESTION: &str = "The user interrupted you. What should you do instead?";
+
+/// How often a response cut off by the token limit is continued
+const MAX_CONTINUATIONS: usize = 3;
+
+/// Share of the context window in percent above which the user is warned
+const CONTEXT_WARNING_PERCENT: usize = 80;
+
+/// After this many failures of the same tool call in a row, the user is asked how to proceed
+const REPEATED_FAILURE_LIMIT: usize = 3;
+
+/// Consumption since the start of the run, or since the user agreed to exceed the budget
+struct BudgetUsage {
+    started: Instant,
+    iterations: usize,
+    usage: Usage,
+}
+
+impl BudgetUsage {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            iterations: 0,
+            usage: Usage::default(),
+        }
+    }
+}
+
+/// Whether the user agreed in the response to a question
+fn is_approval(response: &str) -> bool {
+    matches!(response.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// Another project the task spans, next to the one the agent works in, like the
+/// backend of a frontend
+pub struct AdditionalProject {
+    pub root_dir: PathBuf,
+    /// Snapshots of the project files, `None` if it is not a git repository
+    checkpoints: Option<Box<dyn CheckpointStore>>,
+    /// First checkpoint of this run, the changes since then are reviewed
+    first_checkpoint: Option<usize>,
+}
+
+impl AdditionalProject {
+    pub fn new(root_dir: PathBuf, checkpoints: Option<Box<dyn CheckpointStore>>) -> Self {
+        Self {
+            root_dir,
+            checkpoints,
+            first_checkpoint: None,
+        }
+    }
+}
+
 pub struct Agent {
     working_memory: WorkingMemory,
     llm_provider: Box<dyn LLMProvider>,
@@ -14,6 +97,60 @@ pub struct Agent {
     command_executor: Box<dyn CommandExecutor>,
     ui: Box<dyn UserInterface>,
     state_persistence: Box<dyn StatePersistence>,
+    attachments: Vec<PathBuf>,
+    checkpoints: Option<Box<dyn CheckpointStore>>,
+    /// Latest checkpoint taken in this run
+    last_checkpoint: Option<usize>,
+    /// Whether a checkpoint was taken since the last input of the user, further
+    /// modifications of the turn don't need another snapshot
+    turn_checkpoint_taken: bool,
+    /// First checkpoint of this run, the changes since then are reviewed
+    first_checkpoint: Option<usize>,
+    /// Review the changes before completing the task
+    self_review: bool,
+    reviewed: bool,
+    hooks: Vec<Hook>,
+    /// Output of the hooks run before the current request
+    hook_output: Vec<String>,
+    project_rules: ProjectRulesSettings,
+    /// Template of the system message
+    system_message: String,
+    /// Only read-only tools are available until the user approved a plan
+    plan_mode: bool,
+    /// Only report what tools modifying the project would do
+    dry_run: bool,
+    /// Names of the tools the agent may use, all tools if `None`
+    tool_scope: Option<Vec<String>>,
+    /// Names of the tools the agent may not use, left out of the system message
+    disabled_tools: Vec<String>,
+    /// Additional instructions for the system message
+    instructions: Option<String>,
+    project_memory: Option<ProjectMemory>,
+    budget: BudgetSettings,
+    budget_usage: BudgetUsage,
+    /// Why the agent stopped, if it was because of the budget
+    budget_exceeded: Option<String>,
+    /// Whether the agent completed the task in this run
+    completed: bool,
+    /// Model used to estimate the cost for the budget
+    model: Option<String>,
+    /// Asks the user before tools are executed, tools run without asking if `None`
+    permissions: Option<Box<dyn PermissionMediator>>,
+    /// Tools the user allowed for the rest of the session
+    granted_tools: HashSet<String>,
+    /// Directories outside of the project the user allowed writing to for the session
+    granted_paths: Vec<PathBuf>,
+    permission_policy: PermissionPolicy,
+    permission_grants: Option<PermissionGrants>,
+    permission_audit: Option<PermissionAuditLog>,
+    /// Hosting service of the project's repository for issues and pull requests
+    forge: Option<Box<dyn Forge>>,
+    /// Issue tracker the tickets of the project are in
+    tracker: Option<Box<dyn Tracker>>,
+    /// Further projects the task spans, their files are referred to by absolute paths
+    projects: Vec<AdditionalProject>,
+    /// Task templates the user can give as instruction when steering the agent
+    prompt_library: Option<PromptLibrary>,
 }
 
 impl Agent {
@@ -31,15 +168,788 @@ impl Agent {
             ui,
             command_executor,
             state_persistence,
+            attachments: Vec::new(),
+            checkpoints: None,
+            last_checkpoint: None,
+            turn_checkpoint_taken: false,
+            first_checkpoint: None,
+            self_review: false,
+            reviewed: false,
+            hooks: Vec::new(),
+            hook_output: Vec::new(),
+            project_rules: ProjectRulesSettings::default(),
+            system_message: DEFAULT_SYSTEM_MESSAGE.to_string(),
+            plan_mode: false,
+            dry_run: false,
+            tool_scope: None,
+            disabled_tools: Vec::new(),
+            instructions: None,
+            project_memory: None,
+            budget: BudgetSettings::default(),
+            budget_usage: BudgetUsage::new(),
+            budget_exceeded: None,
+            completed: false,
+            model: None,
+            permissions: None,
+            granted_tools: HashSet::new(),
+            granted_paths: Vec::new(),
+            permission_policy: PermissionPolicy::default(),
+            permission_grants: None,
+            permission_audit: None,
+            forge: None,
+            tracker: None,
+            projects: Vec::new(),
+            prompt_library: None,
+        }
+    }
+
+    /// Enables `/prompt` when steering the agent
+    pub fn set_prompt_library(&mut self, prompt_library: PromptLibrary) {
+        self.prompt_library = Some(prompt_library);
+    }
+
+    /// Renders a prompt of the library from arguments like `release version=1.2`.
+    /// Without arguments, the prompts are listed and `None` is returned.
+    async fn prompt_instruction(&self, arguments: &str) -> Result<Option<String>> {
+        let library = self
+            .prompt_library
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No prompt library available"))?;
+        let arguments = split_arguments(arguments);
+        let Some((name, assignments)) = arguments.split_first() else {
+            let prompts = library.list()?;
+            if prompts.is_empty() {
+                anyhow::bail!("There are no prompts");
+            }
+            for prompt in prompts {
+                let variables = prompt.variables();
+                self.ui
+                    .display(UIMessage::Action(if variables.is_empty() {
+                        format!("{}: {}", prompt.name, prompt.summary())
+                    } else {
+                        format!(
+                            "{}: {} ({})",
+                            prompt.name,
+                            prompt.summary(),
+                            variables.join(", ")
+                        )
+                    }))
+                    .await?;
+            }
+            return Ok(None);
+        };
+        let prompt = library.get(name)?;
+        Ok(Some(prompt.render(&parse_values(assignments)?)?))
+    }
+
+    /// Lets the agent work in further projects besides the one of its explorer
+    pub fn set_additional_projects(&mut self, projects: Vec<AdditionalProject>) {
+        self.projects = projects;
+    }
+
+    /// Lists the further projects for the system prompt
+    fn render_additional_projects(&self) -> String {
+        if self.projects.is_empty() {
+            return String::new();
+        }
+        let projects: Vec<_> = self
+            .projects
+            .iter()
+            .map(|project| format!("- {}", project.root_dir.display()))
+            .collect();
+        format!(
+            "\n\nBesides {}, the task spans the following projects. Refer to their files by \
+             absolute paths, a Search without a path covers all of them:\n{}",
+            self.explorer.root_dir().display(),
+            projects.join("\n")
+        )
+    }
+
+    /// Creates the file trees of the further projects
+    fn create_project_trees(&mut self) {
+        self.working_memory.project_trees.clear();
+        for project in &self.projects {
+            match self.explorer.list_files(&project.root_dir, Some(2)) {
+                Ok(tree) => {
+                    self.working_memory
+                        .project_trees
+                        .insert(project.root_dir.clone(), tree);
+                }
+                Err(e) => warn!(
+                    "Failed to list the files of {}: {}",
+                    project.root_dir.display(),
+                    e
+                ),
+            }
+        }
+    }
+
+    /// Enables notes about the project which are kept across sessions
+    pub fn set_project_memory(&mut self, project_memory: ProjectMemory) {
+        self.project_memory = Some(project_memory);
+    }
+
+    /// Renders the project memory for the system prompt
+    fn render_project_memory(&self) -> String {
+        let Some(project_memory) = &self.project_memory else {
+            return String::new();
+        };
+        match project_memory.load() {
+            Ok(content) if !content.trim().is_empty() => format!(
+                "\n\nYour notes about the project from previous sessions:\n{}",
+                content.trim_end()
+            ),
+            Ok(_) => String::new(),
+            Err(e) => {
+                warn!("{}", e);
+                String::new()
+            }
+        }
+    }
+
+    pub fn set_permission_mediator(&mut self, permissions: Box<dyn PermissionMediator>) {
+        self.permissions = Some(permissions);
+    }
+
+    pub fn set_permission_policy(&mut self, policy: PermissionPolicy) {
+        self.permission_policy = policy;
+    }
+
+    /// Switches the permission mode, takes effect with the next action
+    pub fn set_permission_mode(&mut self, mode: PermissionMode) {
+        self.permission_policy.mode = mode;
+        self.plan_mode = mode == PermissionMode::Plan;
+    }
+
+    /// Records every permission decision for auditing
+    pub fn set_permission_audit(&mut self, audit: PermissionAuditLog) {
+        self.permission_audit = Some(audit);
+    }
+
+    /// Stores the permissions the user grants permanently for the project
+    pub fn set_permission_grants(&mut self, grants: PermissionGrants) {
+        self.permission_grants = Some(grants);
+    }
+
+    /// Asks the user for the permission to execute the tool if needed.
+    /// Returns the error for the agent if the permission was denied.
+    async fn check_permission(&mut self, tool: &Tool) -> Result<Option<String>> {
+        let writable_roots: Vec<_> = self
+            .permission_policy
+            .writable_roots
+            .iter()
+            .chain(&self.granted_paths)
+            .chain(self.projects.iter().map(|project| &project.root_dir))
+            .cloned()
+            .collect();
+        let Some(request) =
+            PermissionRequest::for_tool(tool, &self.explorer.root_dir(), &writable_roots)
+        else {
+            return Ok(None);
+        };
+
+        let (decision, error) = self.decide_permission(&request).await?;
+        if let Some(audit) = &self.permission_audit {
+            if let Err(e) = audit.record(&request, decision) {
+                warn!("Failed to record the permission decision: {:#}", e);
+            }
+        }
+        Ok(error)
+    }
+
+    async fn decide_permission(
+        &mut self,
+        request: &PermissionRequest,
+    ) -> Result<(AuditDecision, Option<String>)> {
+        // Paths outside of the project are granted by directory instead of by tool
+        let outside_paths = match &request.reason {
+            PermissionRequestReason::WritePath { paths } => Some(paths.clone()),
+            _ => None,
+        };
+        match self.permission_policy.evaluate(request) {
+            Some(RuleAction::Allow) => return Ok((AuditDecision::AllowedByRule, None)),
+            Some(RuleAction::Deny) => {
+                self.ui
+                    .display(UIMessage::Action(format!(
+                        "{} denied by a rule in the settings",
+                        request.tool
+                    )))
+                    .await?;
+                return Ok((
+                    AuditDecision::DeniedByRule,
+                    Some(
+                        "The settings of the user deny this command. Don't retry it, \
+                         ask the user if you don't know how to proceed without it."
+                            .to_string(),
+                    ),
+                ));
+            }
+            // Rules asking for permission apply even if the tool was allowed for the session
+            Some(RuleAction::Ask) => {}
+            None => {
+                if self.permission_policy.auto_approves(request) {
+                    return Ok((AuditDecision::AllowedByClass, None));
+                }
+                if !self.permission_policy.asks_for(request) {
+                    return Ok((AuditDecision::AllowedByMode, None));
+                }
+                if outside_paths.is_none() && self.granted_tools.contains(&request.tool) {
+                    return Ok((AuditDecision::AllowedForSession, None));
+                }
+            }
+        }
+        let Some(permissions) = &self.permissions else {
+            return Ok((AuditDecision::AllowedUnattended, None));
+        };
+
+        let decision = permissions.request_permission(request).await?;
+        let pattern = request.grant_pattern();
+        match decision {
+            PermissionDecision::Granted => {}
+            PermissionDecision::GrantedSession | PermissionDecision::GrantedProject
+                if pattern.is_none() =>
+            {
+                match outside_paths {
+                    Some(paths) => self.granted_paths.extend(
+                        paths
+                            .iter()
+                            .filter_map(|path| path.parent().map(Path::to_path_buf)),
+                    ),
+                    None => {
+                        self.granted_tools.insert(request.tool.clone());
+                    }
+                }
+            }
+            PermissionDecision::GrantedSession => {
+                self.granted_tools.insert(request.tool.clone());
+            }
+            PermissionDecision::GrantedProject => {
+                // The user confirms or narrows down what is allowed from now on
+                let confirmed = match &pattern {
+                    Some(pattern) => permissions.confirm_grant(request, pattern).await?,
+                    None => None,
+                };
+                let Some(pattern) = confirmed else {
+                    return Ok((AuditDecision::User(PermissionDecision::Granted), None));
+                };
+                let grant = Grant {
+                    tool: request.tool.clone(),
+                    pattern,
+                    granted_at: Utc::now(),
+                };
+                self.permission_policy.rules.push(grant.rule());
+                match &self.permission_grants {
+                    Some(grants) => grants.add(grant)?,
+                    None => warn!("No place to store permissions, the grant is kept for this run"),
+                }
+            }
+            PermissionDecision::Denied => {
+                self.ui
+                    .display(UIMessage::Action(format!(
+                        "Permission to use {} denied",
+                        request.tool
+                    )))
+                    .await?;
+                return Ok((
+                    AuditDecision::User(decision),
+                    Some(
+                        "The user denied the permission to use this tool. Don't retry it, \
+                         ask the user if you don't know how to proceed without it."
+                            .to_string(),
+                    ),
+                ));
+            }
+        }
+        Ok((AuditDecision::User(decision), None))
+    }
+
+    pub fn set_forge(&mut self, forge: Box<dyn Forge>) {
+        self.forge = Some(forge);
+    }
+
+    /// The forge for the tools working with issues and pull requests
+    fn forge(&self) -> Result<&dyn Forge> {
+        self.forge
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("The project is not hosted on a supported forge"))
+    }
+
+    pub fn set_tracker(&mut self, tracker: Box<dyn Tracker>) {
+        self.tracker = Some(tracker);
+    }
+
+    fn tracker(&self) -> Result<&dyn Tracker> {
+        self.tracker
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("No issue tracker is configured for the project"))
+    }
+
+    /// Name of the branch checked out in the project
+    async fn current_branch(&self) -> Result<String> {
+        let output = self
+            .command_executor
+            .execute("git rev-parse --abbrev-ref HEAD", None)
+            .await?;
+        let branch = output.stdout.trim();
+        if !output.success || branch.is_empty() || branch == "HEAD" {
+            anyhow::bail!("No branch is checked out");
+        }
+        Ok(branch.to_string())
+    }
+
+    pub fn set_tool_scope(&mut self, tools: Option<Vec<String>>) {
+        self.tool_scope = tools;
+    }
+
+    pub fn set_disabled_tools(&mut self, tools: Vec<String>) {
+        self.disabled_tools = tools;
+    }
+
+    pub fn set_instructions(&mut self, instructions: String) {
+        self.instructions = Some(instructions);
+    }
+
+    /// Renders the additional instructions and the tool scope for the system prompt
+    fn render_instructions(&self) -> String {
+        let mut rendered = String::new();
+        if let Some(instructions) = &self.instructions {
+            rendered.push_str(&format!("\n\n{}", instructions));
+        }
+        if let Some(tools) = &self.tool_scope {
+            let tools: Vec<_> = tools
+                .iter()
+                .filter(|tool| !self.disabled_tools.contains(tool))
+                .map(String::as_str)
+                .collect();
+            rendered.push_str(&format!(
+                "\n\nOnly these tools are available in this session: {}, CompleteTask",
+                tools.join(", ")
+            ));
+        }
+        rendered
+    }
+
+    /// Whether the tool scope allows the tool and it is not disabled, completing the
+    /// task is always possible
+    fn in_tool_scope(&self, tool: &Tool) -> bool {
+        if matches!(tool, Tool::CompleteTask { .. }) {
+            return true;
+        }
+        let (name, _) = tool_name_and_params(tool);
+        !self.disabled_tools.contains(&name)
+            && self
+                .tool_scope
+                .as_ref()
+                .is_none_or(|tools| tools.contains(&name))
+    }
+
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    pub fn set_budget(&mut self, budget: BudgetSettings, model: Option<String>) {
+        self.budget = budget;
+        self.model = model;
+    }
+
+    pub fn task(&self) -> &str {
+        &self.working_memory.current_task
+    }
+
+    /// Actions of the session so far, including those of earlier runs
+    pub fn actions(&self) -> &[ActionResult] {
+        &self.working_memory.action_history
+    }
+
+    /// Whether the agent completed the task, false if it stopped or failed before
+    pub fn completed(&self) -> bool {
+        self.completed
+    }
+
+    /// Message the agent completed the task with
+    pub fn completion_message(&self) -> Option<&str> {
+        match self.working_memory.action_history.last() {
+            Some(ActionResult {
+                tool: Tool::CompleteTask { message },
+                success: true,
+                ..
+            }) if self.completed => Some(message),
+            _ => None,
+        }
+    }
+
+    /// Limit of the budget the agent stopped at, if the user didn't agree to continue
+    pub fn budget_exceeded(&self) -> Option<&str> {
+        self.budget_exceeded.as_deref()
+    }
+
+    pub fn set_plan_mode(&mut self, plan_mode: bool) {
+        self.plan_mode = plan_mode;
+    }
+
+    fn approve_plan(&mut self, steps: Vec<String>) {
+        self.working_memory.plan = steps;
+        self.plan_mode = false;
+    }
+
+    /// Replaces the default template of the system message, see `system_message.rs`
+    /// for the supported placeholders
+    pub fn set_system_message(&mut self, template: String) {
+        self.system_message = template;
+    }
+
+    pub fn set_project_rules(&mut self, project_rules: ProjectRulesSettings) {
+        self.project_rules = project_rules;
+    }
+
+    pub fn set_hooks(&mut self, hooks: Vec<Hook>) {
+        self.hooks = hooks;
+    }
+
+    /// Runs the hooks configured for the event, in the project directory
+    async fn run_hooks(&self, event: HookEvent, tool: Option<&Tool>) -> Result<Vec<HookOutcome>> {
+        let mut outcomes = Vec::new();
+        for hook in self.hooks.iter().filter(|h| h.applies_to(event, tool)) {
+            let outcome = match &hook.action {
+                HookAction::Block(reason) => HookOutcome {
+                    hook: hook.description(),
+                    success: false,
+                    output: reason.clone(),
+                },
+                HookAction::Command(command) => {
+                    self.ui
+                        .display(UIMessage::Action(format!("Running hook: {}", command)))
+                        .await?;
+                    match self
+                        .command_executor
+                        .execute(command, Some(&self.explorer.root_dir()))
+                        .await
+                    {
+                        Ok(output) => HookOutcome {
+                            hook: hook.description(),
+                            success: output.success,
+                            output: format!("{}{}", output.stdout, output.stderr),
+                        },
+                        Err(e) => HookOutcome {
+                            hook: hook.description(),
+                            success: false,
+                            output: e.to_string(),
+                        },
+                    }
+                }
+            };
+            outcomes.push(outcome);
+        }
+        Ok(outcomes)
+    }
+
+    /// Executes an action unless a pre-tool hook blocks it, and adds the output
+    /// of post-tool hooks to the result
+    async fn execute_action_with_hooks(&mut self, action: &AgentAction) -> Result<ActionResult> {
+        let outcomes = self
+            .run_hooks(HookEvent::PreTool, Some(&action.tool))
+            .await?;
+        if let Some(outcome) = outcomes.iter().find(|o| !o.success) {
+            self.ui
+                .display(UIMessage::Action(format!(
+                    "Tool call blocked by hook {}",
+                    outcome.hook
+                )))
+                .await?;
+            return Ok(ActionResult {
+                tool: action.tool.clone(),
+                success: false,
+                result: String::new(),
+                error: Some(format!(
+                    "Blocked by hook {}: {}",
+                    outcome.hook, outcome.output
+                )),
+                reasoning: action.reasoning.clone(),
+            });
+        }
+
+        let started = Instant::now();
+        let mut result = self.execute_action(action).await?;
+        metrics::global().record_tool(
+            &tool_name_and_params(&action.tool).0,
+            result.success,
+            started.elapsed(),
+        );
+
+        let event = match action.tool {
+            Tool::CompleteTask { .. } => HookEvent::PostTurn,
+            _ => HookEvent::PostTool,
+        };
+        for outcome in self.run_hooks(event, Some(&action.tool)).await? {
+            result.result.push_str(&format!("\n{}", outcome.format()));
+            // The task is not complete as long as a post-turn hook fails
+            if !outcome.success && event == HookEvent::PostTurn {
+                result.success = false;
+                result.error = Some(format!(
+                    "Hook {} failed, the task is not complete yet",
+                    outcome.hook
+                ));
+            }
+        }
+
+        if event == HookEvent::PostTurn && result.success && self.self_review && !self.reviewed {
+            if let Some(findings) = self.review_changes().await? {
+                result.success = false;
+                result.result.push_str(&format!("\nReview:\n{}", findings));
+                result.error = Some(
+                    "The review found issues in your changes, address them before completing the task"
+                        .to_string(),
+                );
+            }
+        }
+        Ok(result)
+    }
+
+    pub fn set_self_review(&mut self, self_review: bool) {
+        self.self_review = self_review;
+    }
+
+    /// Lets a separate reviewer go over the changes of this run once. Returns the findings,
+    /// or `None` if there are no changes or nothing needs to be addressed.
+    async fn review_changes(&mut self) -> Result<Option<String>> {
+        let mut stores = vec![(
+            self.explorer.root_dir(),
+            self.checkpoints.as_deref(),
+            self.first_checkpoint,
+        )];
+        stores.extend(self.projects.iter().map(|project| {
+            (
+                project.root_dir.clone(),
+                project.checkpoints.as_deref(),
+                project.first_checkpoint,
+            )
+        }));
+        let mut diffs = Vec::new();
+        for (root_dir, checkpoints, number) in stores {
+            let (Some(checkpoints), Some(number)) = (checkpoints, number) else {
+                continue;
+            };
+            match checkpoints.diff(number) {
+                Ok(diff) => diffs.push((root_dir, diff)),
+                Err(e) => warn!(
+                    "Failed to get the changes in {} for the review: {}",
+                    root_dir.display(),
+                    e
+                ),
+            }
+        }
+        if diffs.is_empty() {
+            return Ok(None);
+        }
+        self.reviewed = true;
+        diffs.retain(|(_, diff)| !diff.is_empty());
+        if diffs.is_empty() {
+            return Ok(None);
+        }
+        // Changes spanning several projects are shown per project
+        let changes = if self.projects.is_empty() {
+            format!("Changes:\n```diff\n{}\n```", diffs[0].1)
+        } else {
+            diffs
+                .iter()
+                .map(|(root_dir, diff)| {
+                    format!("Changes in {}:\n```diff\n{}\n```", root_dir.display(), diff)
+                })
+                .collect::<Vec<_>>()
+                .join("\n\n")
+        };
+
+        self.ui
+            .display(UIMessage::Action("Reviewing the changes...".to_string()))
+            .await?;
+        let request = LLMRequest {
+            messages: vec![Message {
+                role: MessageRole::User,
+                content: MessageContent::Text(format!(
+                    "Task: {}\n\n{}",
+                    self.working_memory.current_task, changes
+                )),
+            }],
+            max_tokens: 4096,
+            temperature: 0.2,
+            system_prompt: Some(REVIEW_SYSTEM_MESSAGE.to_string()),
+        };
+        let response = self.send_with_continuations(request).await?;
+        let review = response.text().trim().to_string();
+        self.ui.display(UIMessage::Review(review.clone())).await?;
+
+        if review.is_empty() || review.starts_with("LGTM") {
+            return Ok(None);
+        }
+        Ok(Some(review))
+    }
+
+    /// Enables snapshots of the project files before the agent modifies them
+    pub fn set_checkpoints(&mut self, checkpoints: Box<dyn CheckpointStore>) {
+        self.checkpoints = Some(checkpoints);
+    }
+
+    /// Takes a checkpoint before the first modification of the files in a turn, so that
+    /// the project files can be restored to their state before any input of the user.
+    /// Failing to take it doesn't stop the agent.
+    async fn ensure_checkpoint(&mut self) {
+        if self.turn_checkpoint_taken {
+            return;
+        }
+        self.turn_checkpoint_taken = true;
+        if let Err(e) = self.create_checkpoint().await {
+            warn!("Failed to create checkpoint: {}", e);
+        }
+    }
+
+    async fn create_checkpoint(&mut self) -> Result<()> {
+        let action_count = self.working_memory.action_history.len();
+        let description = format!("Before action {}", action_count + 1);
+        // Further projects are only snapshotted for reviewing their changes
+        for project in &mut self.projects {
+            if let Some(checkpoints) = &project.checkpoints {
+                let checkpoint = checkpoints.create(&description)?;
+                project.first_checkpoint.get_or_insert(checkpoint.number);
+            }
+        }
+        let Some(checkpoints) = &self.checkpoints else {
+            return Ok(());
+        };
+
+        let checkpoint = checkpoints.create(&description)?;
+        self.state_persistence
+            .record_checkpoint(action_count, checkpoint.number)?;
+        self.first_checkpoint.get_or_insert(checkpoint.number);
+
+        // Unchanged files don't result in a new checkpoint
+        if self.last_checkpoint != Some(checkpoint.number) {
+            self.last_checkpoint = Some(checkpoint.number);
+            self.ui
+                .display(UIMessage::Action(format!(
+                    "Created checkpoint {}",
+                    checkpoint.number
+                )))
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn rollback_to_checkpoint(&mut self, number: Option<usize>) -> Result<String> {
+        let checkpoints = self
+            .checkpoints
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Checkpoints are only available in git repositories"))?;
+        let number = match number {
+            Some(number) => number,
+            None => checkpoints
+                .list()?
+                .last()
+                .map(|c| c.number)
+                .ok_or_else(|| anyhow::anyhow!("No checkpoints were created yet"))?,
+        };
+        let checkpoint = checkpoints.restore(number)?;
+
+        // Files in working memory might have changed
+        let root_dir = self.explorer.root_dir();
+        let mut removed = Vec::new();
+        for (path, content) in self.working_memory.loaded_files.iter_mut() {
+            match self.explorer.read_file(&root_dir.join(path)) {
+                Ok(restored) => *content = restored,
+                Err(_) => removed.push(path.clone()),
+            }
+        }
+        for path in removed {
+            self.working_memory.loaded_files.remove(&path);
+        }
+
+        Ok(format!(
+            "Restored checkpoint {} ({})",
+            checkpoint.number, checkpoint.description
+        ))
+    }
+
+    /// Files which are loaded into working memory when the agent starts
+    pub fn set_attachments(&mut self, attachments: Vec<PathBuf>) {
+        self.attachments = attachments;
+    }
+
+    async fn load_attachments(&mut self) -> Result<()> {
+        for path in self.attachments.clone() {
+            let full_path = if path.is_absolute() {
+                path.clone()
+            } else {
+                self.explorer.root_dir().join(&path)
+            };
+            match self.explorer.read_file(&full_path) {
+                Ok(content) => {
+                    self.working_memory.loaded_files.insert(path, content);
+                }
+                Err(e) => {
+                    self.ui
+                        .display(UIMessage::Action(format!(
+                            "Failed to load attachment `{}`: {}",
+                            path.display(),
+                            e
+                        )))
+                        .await?;
+                }
+            }
         }
+        Ok(())
     }
 
-    async fn run_agent_loop(&mut self) -> Result<()> {
+    async fn run_agent_loop(&mut self, mut pending_action: Option<AgentAction>) -> Result<()> {
         // Main agent loop
         loop {
-            let action = self.get_next_action().await?;
+            let action = match pending_action.take() {
+                Some(action) => action,
+                None => {
+                    if !self.check_budget().await? {
+                        self.ui
+                            .display(UIMessage::Action(
+                                "Stopped, the task can be resumed with --continue-task".to_string(),
+                            ))
+                            .await?;
+                        return Ok(());
+                    }
+                    self.budget_usage.iterations += 1;
+
+                    let interrupted = self.ui.interrupted();
+                    let next_action = tokio::select! {
+                        biased;
+                        _ = interrupted => None,
+                        action = self.get_next_action() => Some(action?),
+                    };
+                    let Some(action) = next_action else {
+                        if !self.steer().await? {
+                            return Ok(());
+                        }
+                        continue;
+                    };
+                    // Remember the action until it has been executed
+                    self.state_persistence.save_pending_action(&action)?;
+                    action
+                }
+            };
 
-            let result = self.execute_action(&action).await?;
+            let (tool, params) = tool_name_and_params(&action.tool);
+            self.ui
+                .event(UIEvent::ToolCall {
+                    tool: tool.clone(),
+                    params,
+                })
+                .await?;
+            let result = self.execute_action_with_hooks(&action).await?;
+            self.ui
+                .event(UIEvent::ToolResult {
+                    tool,
+                    success: result.success,
+                    output: result.result.clone(),
+                    error: result.error.clone(),
+                })
+                .await?;
+            let completed = matches!(action.tool, Tool::CompleteTask { .. }) && result.success;
+            let failed = !result.success;
             self.working_memory.action_history.push(result);
 
             // Save state after each action
@@ -48,18 +958,197 @@ impl Agent {
                 self.working_memory.action_history.clone(),
             )?;
 
-            // Check if this was a CompleteTask action
-            if let Tool::CompleteTask { .. } = action.tool {
-                // Clean up state file on successful completion
-                self.state_persistence.cleanup()?;
+            // Check if this was a successful CompleteTask action
+            if completed {
+                self.state_persistence.mark_completed()?;
+                self.completed = true;
                 break;
             }
+
+            if failed
+                && repeated_failures(&self.working_memory.action_history) >= REPEATED_FAILURE_LIMIT
+                && !self.break_failure_loop().await?
+            {
+                return Ok(());
+            }
         }
 
         debug!("Task completed");
         Ok(())
     }
 
+    /// Asks the user for a new instruction after an interruption and records it like an
+    /// answer to a question. Returns false if the user wants to stop the agent.
+    async fn steer(&mut self) -> Result<bool> {
+        self.ui
+            .display(UIMessage::Question(
+                "Interrupted. What should the agent do instead? (leave empty to stop)".to_string(),
+            ))
+            .await?;
+        loop {
+            let instruction = self.ui.get_input("> ").await?;
+            if instruction.trim().is_empty() {
+                return Ok(false);
+            }
+
+            // `/mode <name>` switches the permission mode and lets the agent continue
+            if let Some(name) = instruction.trim().strip_prefix("/mode") {
+                match PermissionMode::parse(name) {
+                    Ok(mode) => {
+                        self.set_permission_mode(mode);
+                        self.ui
+                            .display(UIMessage::Action(format!(
+                                "Switched to permission mode {}",
+                                mode.name()
+                            )))
+                            .await?;
+                        return Ok(true);
+                    }
+                    Err(e) => {
+                        self.ui
+                            .display(UIMessage::Question(format!("{}, try again:", e)))
+                            .await?;
+                        continue;
+                    }
+                }
+            }
+
+            // `/prompt <name> [name=value...]` gives a prompt of the library as instruction
+            if let Some(arguments) = instruction.trim().strip_prefix("/prompt") {
+                match self.prompt_instruction(arguments).await {
+                    Ok(Some(instruction)) => {
+                        self.record_instruction(INTERRUPTION_QUESTION.to_string(), instruction)?;
+                        return Ok(true);
+                    }
+                    Ok(None) => continue,
+                    Err(e) => {
+                        self.ui
+                            .display(UIMessage::Question(format!("{}, try again:", e)))
+                            .await?;
+                        continue;
+                    }
+                }
+            }
+
+            self.record_instruction(INTERRUPTION_QUESTION.to_string(), instruction)?;
+            return Ok(true);
+        }
+    }
+
+    /// Pauses for the user when the agent keeps repeating a failing tool call, so it
+    /// doesn't burn tokens retrying. Returns false if the user wants to stop the agent.
+    async fn break_failure_loop(&mut self) -> Result<bool> {
+        let Some(last) = self.working_memory.action_history.last() else {
+            return Ok(true);
+        };
+        let (name, _) = tool_name_and_params(&last.tool);
+        let error = last.error.clone().unwrap_or_default();
+
+        self.ui
+            .display(UIMessage::Question(format!(
+                "The agent called {} {} times in a row with the same input and it failed: {}\n\
+                 How should it proceed? (leave empty to stop)",
+                name, REPEATED_FAILURE_LIMIT, error
+            )))
+            .await?;
+        let instruction = self.ui.get_input("> ").await?;
+        if instruction.trim().is_empty() {
+            self.ui
+                .display(UIMessage::Action(
+                    "Stopped, the task can be resumed with --continue-task".to_string(),
+                ))
+                .await?;
+            return Ok(false);
+        }
+
+        self.record_instruction(
+            format!(
+                "Your call of {} failed {} times in a row with the same input. \
+                 Don't retry it unchanged. How should you proceed?",
+                name, REPEATED_FAILURE_LIMIT
+            ),
+            instruction,
+        )?;
+        Ok(true)
+    }
+
+    /// Records an instruction of the user like an answer to a question
+    fn record_instruction(&mut self, question: String, instruction: String) -> Result<()> {
+        self.turn_checkpoint_taken = false;
+        self.working_memory.action_history.push(ActionResult {
+            tool: Tool::AskUser { question },
+            success: true,
+            result: instruction,
+            error: None,
+            reasoning: String::new(),
+        });
+        self.state_persistence.save_state(
+            self.working_memory.current_task.clone(),
+            self.working_memory.action_history.clone(),
+        )
+    }
+
+    /// Returns which limit of the budget was exceeded, if any
+    fn exceeded_budget(&self) -> Option<String> {
+        let usage = &self.budget_usage;
+        if let Some(max_iterations) = self.budget.max_iterations {
+            if usage.iterations >= max_iterations {
+                return Some(format!(
+                    "The limit of {} actions was reached",
+                    max_iterations
+                ));
+            }
+        }
+        if let Some(max_cost) = self.budget.max_cost {
+            let cost = self
+                .model
+                .as_deref()
+                .and_then(|model| estimate_cost(model, &usage.usage));
+            if let Some(cost) = cost.filter(|cost| *cost >= max_cost) {
+                return Some(format!(
+                    "The estimated cost of ${:.2} reached the limit of ${:.2}",
+                    cost, max_cost
+                ));
+            }
+        }
+        if let Some(max_tokens) = self.budget.max_tokens {
+            let tokens = usage.usage.input_tokens + usage.usage.output_tokens;
+            if tokens >= max_tokens {
+                return Some(format!(
+                    "The {} tokens used reached the limit of {} tokens",
+                    tokens, max_tokens
+                ));
+            }
+        }
+        if let Some(max_minutes) = self.budget.max_minutes {
+            if usage.started.elapsed().as_secs() >= max_minutes * 60 {
+                return Some(format!("The limit of {} minutes was reached", max_minutes));
+            }
+        }
+        None
+    }
+
+    /// Asks the user whether to continue once the budget is exceeded, in which case the
+    /// budget starts over. Returns false if the agent should stop.
+    async fn check_budget(&mut self) -> Result<bool> {
+        let Some(reason) = self.exceeded_budget() else {
+            return Ok(true);
+        };
+        self.budget_exceeded = Some(reason.clone());
+        self.ui
+            .display(UIMessage::Question(format!(
+                "{}. Continue working on the task? (yes/no)",
+                reason
+            )))
+            .await?;
+        if !is_approval(&self.ui.get_input("> ").await?) {
+            return Ok(false);
+        }
+        self.budget_usage = BudgetUsage::new();
+        self.budget_exceeded = None;
+        Ok(true)
+    }
+
     /// Start a new agent task
     pub async fn start_with_task(&mut self, task: String) -> Result<()> {
         debug!("Starting agent with task: {}", task);
@@ -72,12 +1161,14 @@ impl Agent {
             .await?;
 
         self.working_memory.file_tree = Some(self.explorer.create_initial_tree(2)?);
+        self.create_project_trees();
+        self.load_attachments().await?;
 
         // Save initial state
         self.state_persistence
             .save_state(task, self.working_memory.action_history.clone())?;
 
-        self.run_agent_loop().await
+        self.run_agent_loop(None).await
     }
 
     /// Continue from a saved state
@@ -88,6 +1179,8 @@ impl Agent {
 
             // Create fresh working memory
             self.working_memory.file_tree = Some(self.explorer.create_initial_tree(2)?);
+            self.create_project_trees();
+            self.load_attachments().await?;
 
             self.ui
                 .display(UIMessage::Action(format!(
@@ -100,6 +1193,27 @@ impl Agent {
             // Replay each action
             for original_action in state.actions {
                 debug!("Replaying action: {:?}", original_action.tool);
+
+                // Don't ask the user again, undo what happened since, open another pull
+                // request or overwrite newer notes, but use the recorded results
+                match &original_action.tool {
+                    Tool::SubmitPlan { steps } => {
+                        if original_action.success {
+                            self.approve_plan(steps.clone());
+                        }
+                        self.working_memory.action_history.push(original_action);
+                        continue;
+                    }
+                    Tool::AskUser { .. }
+                    | Tool::RollbackToCheckpoint { .. }
+                    | Tool::CreatePullRequest { .. }
+                    | Tool::UpdateProjectMemory { .. } => {
+                        self.working_memory.action_history.push(original_action);
+                        continue;
+                    }
+                    _ => {}
+                }
+
                 let action = AgentAction {
                     tool: original_action.tool.clone(),
                     reasoning: original_action.reasoning.clone(),
@@ -129,150 +1243,52 @@ impl Agent {
                 }
             }
 
-            self.run_agent_loop().await
+            // Resume the action which was interrupted when the agent stopped
+            if let Some(action) = &state.pending_action {
+                self.ui
+                    .display(UIMessage::Action(format!(
+                        "Resuming interrupted action: {:?}",
+                        action.tool
+                    )))
+                    .await?;
+            }
+
+            self.run_agent_loop(state.pending_action).await
         } else {
             anyhow::bail!("No saved state found")
         }
     }
 
     /// Get next action from LLM
-    async fn get_next_action(&self) -> Result<AgentAction> {
+    async fn get_next_action(&mut self) -> Result<AgentAction> {
+        self.hook_output = self
+            .run_hooks(HookEvent::PreRequest, None)
+            .await?
+            .iter()
+            .map(|outcome| outcome.format())
+            .collect();
         let messages = self.prepare_messages();
 
-        let tools_description = r#"
-        Available tools:
-        1. ListFiles
-           - Expands the contents of directories marked with " [...]" in the repository structure
-           - Parameters: {"paths": ["path/to/dir1", "path/to/dir2", ...]}
-           - Returns: Confirmation of which directories were expanded
-
-        2. ReadFiles
-           - Reads the content of one or multiple files
-           - Parameters: {"paths": ["path/to/file1", "path/to/file2", ...]}
-           - Returns: Confirmation of which files were loaded into working memory
-
-        3. WriteFile
-           - Creates or overwrites a file. Use for new files only or when files are short. Prefer to use "UpdateFile".
-           - Parameters: {
-               "path": "path/to/file",
-               "content": "content to write"
-             }
-           - Returns: Confirmation message
-
-        4. UpdateFile
-           - Applies updates to a file. Make sure the updates apply cleanly.
-             To insert new content without replacing anything, specify the same line number for start_line and end_line.
-             Make sure to generate the new_content first and then specify the line numbers after you know exactly what needs replacing.
-             You need to split large updates across multiple calls of the tool, otherwise your message might be truncated, as there is a token limit.
-             Note that you will see your changes in the working memory after this tool is executed.
-           - Parameters: {
-               "path": "path/to/file",
-               "updates": [
-                 {
-                   "new_content": "the new content without leading line numbers, can have more or fewer lines",
-                   "start_line": <first line number to replace>,
-                   "end_line": <line number after the last line to replace (exclusive)>
-                 },
-                 {
-                   "new_content": "the new content",
-                   "start_line": <first line number of another replaced section>,
-                   "end_line": <line number after the section (exclusive)>
-                 },
-                 ...
-               ]
-           }
-           - Returns: Confirmation message
-
-        5. Summarize
-           - Replaces file contents with summaries in working memory
-           - Parameters: {
-               "files": [
-                   {"path": "path/to/file1", "summary": "your summary of the file1"},
-                   {"path": "path/to/file2", "summary": "your summary of the file2"}
-               ]
-             }
-           - Returns: Confirmation message
-           - Use this to maintain a high-level understanding while managing memory usage
-
-        6. AskUser
-           - Asks the user a question and provides their response
-           - Parameters: {"question": "your question here?"}
-           - Returns: The user's response as a string
-           - Use this when you need clarification or a decision from the user
-
-        7. MessageUser
-           - Provide a message to the user. Use the "AskUser" tool instead if you need a response.
-           - Parameters: {"message": "your message here"}
-           - Returns: Confirmation message
-           - Use this when you need to inform the user
-
-        8. ExecuteCommand
-           - Execute a command line program
-           - Parameters: {
-               "command_line": "the complete command to execute",
-               "working_dir": "optional: working directory for the command"
-           }
-           - Returns: The command's output and error streams
-           - Use this to run CLI commands like 'cargo', 'git', etc.
-
-        9. DeleteFiles
-           - Delete one or more files from the filesystem
-           - Parameters: {"paths": ["path/to/file1", "path/to/file2", ...]}
-           - Returns: Confirmation of which files were deleted
-
-        10. Search
-           - Search for text in files
-           - Parameters: {
-               "query": "text to search for",
-               "path": "optional: directory path to search in",
-               "case_sensitive": false,
-               "whole_words": false,
-               "regex_mode": false,
-               "max_results": null
-           }
-           - Returns: List of matches with file paths, line numbers, and matching lines
-           - Use this to find code, text, or patterns in files
-
-        11. CompleteTask
-           - Complete the current task with a final message to the user
-           - Parameters: {"message": "your completion message here"}
-           - Returns: Confirmation message
-           - Use this when you have successfully completed the task and want to inform the user about it"#;
-
         let request = LLMRequest {
             messages,
             max_tokens: 8192,
             temperature: 0.7,
             system_prompt: Some(format!(
-                "You are an agent assisting the user in programming tasks. Your task is to analyze codebases and complete specific tasks.\n\n\
-                Your goal is to either gather relevant information in the working memory, \
-                or complete the task(s) if you have all necessary information.\n\n\
-                Working Memory Management:\n\
-                - All path parameters are expected relative to the root directory\n\
-                - Use ListFiles to expand collapsed directories (marked with ' [...]') in the repository structure\n\
-                - Use ReadFiles to load important files into working memory\n\
-                - Use Summarize to remove files that turned out to be less relevant\n\
-                - Keep only information that's necessary for the current task\n\
-                - Use UpdateFile to make changes to existing files\n\
-                - Use WriteFile to create new files or replace existing (small) files. Always provide the complete content when using WriteFile!\n\n\
-                {}\n\n\
-                Before making changes to files, unless you already know the used libraries/dependencies,\n\
-                always confirm that methods exist on the respective types by inspecting dependencies within the code-base!\n\n\
-                After making changes to code, always validate them using the ExecuteCommand tool with appropriate commands for the project type:\n\
-                - For Rust projects: Use 'cargo check' and 'cargo test'\n\
-                - For Node.js projects: Check package.json for test/lint scripts and use them\n\
-                - For Python projects: Use pytest, mypy, or similar tools if available\n\
-                - For other projects: Look for common build/test scripts and configuration files\n\n\
-                ALWAYS respond with a single, valid JSON object matching the following schema:\n\n\
-                {{\
-                    \"reasoning\": <explain your thought process>,\
-                    \"tool\": {{\
-                        \"name\": <ToolName>,\
-                        \"params\": <tool-specific parameters>\
-                    }}\
-                }}\n\n\
-                Always explain your reasoning before choosing a tool. Think step by step. Execute only one tool per response.",
-                tools_description
+                "{}{}{}{}{}{}",
+                render_system_message(
+                    &self.system_message,
+                    &self.explorer.root_dir(),
+                    &self.disabled_tools,
+                ),
+                self.render_additional_projects(),
+                self.render_instructions(),
+                if self.plan_mode {
+                    PLAN_MODE_INSTRUCTIONS
+                } else {
+                    ""
+                },
+                self.render_project_rules(),
+                self.render_project_memory()
             )),
         };
 
@@ -282,7 +1298,9 @@ impl Agent {
             }
         }
 
-        let response = self.llm_provider.send_message(request).await?;
+        self.check_context_window(&request).await?;
+
+        let response = self.send_with_continuations(request).await?;
 
         debug!("Raw LLM response:");
         for block in &response.content {
@@ -294,6 +1312,157 @@ impl Agent {
         parse_llm_response(&response)
     }
 
+    /// Sends the request, responses cut off by the token limit are continued by sending
+    /// the partial response back as prefill of the assistant turn
+    async fn send_with_continuations(&mut self, request: LLMRequest) -> Result<LLMResponse> {
+        let mut response = self.llm_provider.send_message(request.clone()).await?;
+        self.state_persistence.record_usage(&response.usage);
+        self.budget_usage.usage.add(&response.usage);
+
+        let mut continuations = 0;
+        while response.stop_reason == Some(StopReason::MaxTokens)
+            && continuations < MAX_CONTINUATIONS
+        {
+            continuations += 1;
+            debug!(
+                "Response was truncated, requesting continuation {}",
+                continuations
+            );
+
+            // Providers reject assistant turns ending in whitespace
+            let partial = response.text().trim_end().to_string();
+            let mut continuation_request = request.clone();
+            continuation_request.messages.push(Message {
+                role: MessageRole::Assistant,
+                content: MessageContent::Text(partial.clone()),
+            });
+            let continuation = self.llm_provider.send_message(continuation_request).await?;
+            self.state_persistence.record_usage(&continuation.usage);
+            self.budget_usage.usage.add(&continuation.usage);
+
+            let mut usage = response.usage;
+            usage.add(&continuation.usage);
+            response = LLMResponse {
+                content: vec![ContentBlock::Text {
+                    text: partial + &continuation.text(),
+                }],
+                usage,
+                stop_reason: continuation.stop_reason,
+            };
+        }
+        self.ui
+            .event(UIEvent::Usage {
+                input_tokens: self.budget_usage.usage.input_tokens,
+                output_tokens: self.budget_usage.usage.output_tokens,
+                cost: self.estimated_cost(),
+            })
+            .await?;
+        Ok(response)
+    }
+
+    /// Tokens used by this run
+    pub fn usage(&self) -> &Usage {
+        &self.budget_usage.usage
+    }
+
+    /// Estimated cost of this run in USD, if the prices of the model are known
+    pub fn estimated_cost(&self) -> Option<f64> {
+        self.model
+            .as_deref()
+            .and_then(|model| estimate_cost(model, &self.budget_usage.usage))
+    }
+
+    /// Verifies that the request fits into the context window of the model, so that
+    /// an oversized request fails with a clear error instead of a rejection by the provider.
+    /// Shows how much of the context window is used and what the run has cost so far.
+    async fn check_context_window(&self, request: &LLMRequest) -> Result<()> {
+        let Some(context_window) = self.llm_provider.context_window() else {
+            return Ok(());
+        };
+        let tokens = match self.llm_provider.count_tokens(request).await {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                warn!("Failed to count tokens, using an estimate instead: {}", e);
+                estimate_tokens(request)
+            }
+        };
+        debug!("Request has {} input tokens", tokens);
+
+        if tokens > context_window {
+            anyhow::bail!(
+                "The request needs {} input tokens, but the context window of the model is limited to {} tokens. \
+                Continue the session with a model that has a larger context window (see `session model`), \
+                or fork it at an earlier action with `--fork-at`.",
+                tokens,
+                context_window
+            );
+        }
+
+        let percent = tokens * 100 / context_window;
+        let mut status = format!(
+            "Context: {} of {} tokens ({}%)",
+            tokens, context_window, percent
+        );
+        if let Some(cost) = self.estimated_cost() {
+            status.push_str(&format!(", cost so far: ${:.2}", cost));
+        }
+        if percent >= CONTEXT_WARNING_PERCENT {
+            status.push_str(
+                " - close to the limit, consider forking the session at an earlier action",
+            );
+        }
+        self.ui.display(UIMessage::Action(status)).await?;
+        Ok(())
+    }
+
+    /// Collects the rules files of the project root and of the directories containing
+    /// files in working memory, to be added to the system prompt
+    fn render_project_rules(&self) -> String {
+        if !self.project_rules.enabled {
+            return String::new();
+        }
+
+        let mut dirs = vec![PathBuf::new()];
+        for path in self.working_memory.loaded_files.keys() {
+            for dir in path.ancestors().skip(1) {
+                if !dirs.contains(&dir.to_path_buf()) {
+                    dirs.push(dir.to_path_buf());
+                }
+            }
+        }
+
+        let root_dir = self.explorer.root_dir();
+        let mut rules = String::new();
+        for dir in dirs {
+            for name in PROJECT_RULES_FILES {
+                let path = dir.join(name);
+                if let Ok(content) = self.explorer.read_file(&root_dir.join(&path)) {
+                    rules.push_str(&format!("\n\n-----{}:\n{}", path.display(), content.trim()));
+                }
+            }
+        }
+        if rules.is_empty() {
+            return rules;
+        }
+
+        if rules.len() > self.project_rules.max_size {
+            warn!(
+                "Project rules exceed {} bytes and were truncated",
+                self.project_rules.max_size
+            );
+            let mut end = self.project_rules.max_size;
+            while !rules.is_char_boundary(end) {
+                end -= 1;
+            }
+            rules.truncate(end);
+            rules.push_str("\n[truncated]");
+        }
+        format!(
+            "\n\nThe project defines the following rules, follow them:{}",
+            rules
+        )
+    }
+
     pub fn render_working_memory(&self) -> String {
         let mut memory = format!("Task: {}\n\n", self.working_memory.current_task);
 
@@ -305,6 +1474,11 @@ impl Agent {
             memory.push_str("No file tree available");
         }
         memory.push_str("\n\n");
+        for (root_dir, tree) in &self.working_memory.project_trees {
+            memory.push_str(&format!("Structure of {}:\n", root_dir.display()));
+            memory.push_str(&tree.to_string());
+            memory.push_str("\n\n");
+        }
 
         // Add loaded files with their contents
         memory.push_str("Current Working Memory:\n");
@@ -323,12 +1497,33 @@ impl Agent {
             memory.push_str(&format!("  {}: {}\n", path.display(), summary));
         }
 
+        if !self.working_memory.plan.is_empty() {
+            memory.push_str("\n- Plan approved by the user:\n");
+            for (i, step) in self.working_memory.plan.iter().enumerate() {
+                memory.push_str(&format!("  {}. {}\n", i + 1, step));
+            }
+        }
+
+        if !self.hook_output.is_empty() {
+            memory.push_str("\n- Output of hooks:\n");
+            for output in &self.hook_output {
+                memory.push_str(&format!("{}\n", output));
+            }
+        }
+
         // Add action history
         memory.push_str("\nPrevious actions:\n");
+        let superseded = superseded_results(&self.working_memory.action_history);
         for (i, action) in self.working_memory.action_history.iter().enumerate() {
             memory.push_str(&format!("\n{}. Tool: {:?}\n", i + 1, action.tool));
             memory.push_str(&format!("   Reasoning: {}\n", action.reasoning));
-            memory.push_str(&format!("   Result: {}\n", action.result));
+            match superseded.get(&i) {
+                Some(later) => memory.push_str(&format!(
+                    "   Result: [Outdated, see the result of action {}]\n",
+                    later + 1
+                )),
+                None => memory.push_str(&format!("   Result: {}\n", action.result)),
+            }
             if let Some(error) = &action.error {
                 memory.push_str(&format!("   Error: {}\n", error));
             }
@@ -346,6 +1541,89 @@ impl Agent {
         }]
     }
 
+    /// Shows the changes made to a file
+    async fn display_diff(&self, path: &Path, old_content: &str, new_content: &str) -> Result<()> {
+        let diff = unified_diff(&path.display().to_string(), old_content, new_content);
+        if !diff.is_empty() {
+            self.ui.display(UIMessage::Diff(diff)).await?;
+        }
+        Ok(())
+    }
+
+    /// Describes what a tool modifying the project would do, `None` for other tools
+    fn simulate(&self, tool: &Tool) -> Option<Result<String>> {
+        let root_dir = self.explorer.root_dir();
+        let full_path = |path: &PathBuf| {
+            if path.is_absolute() {
+                path.clone()
+            } else {
+                root_dir.join(path)
+            }
+        };
+
+        Some(match tool {
+            Tool::WriteFile { path, content } => {
+                let old_content = self
+                    .explorer
+                    .read_file(&full_path(path))
+                    .unwrap_or_default();
+                let name = path.display().to_string();
+                Ok(format!(
+                    "Would write `{}`:\n{}",
+                    name,
+                    unified_diff(&name, &old_content, content)
+                ))
+            }
+            Tool::UpdateFile { path, updates } => self
+                .explorer
+                .read_file(&full_path(path))
+                .and_then(|old_content| {
+                    let new_content = apply_content_updates(&old_content, updates)?;
+                    let name = path.display().to_string();
+                    Ok(format!(
+                        "Would update `{}`:\n{}",
+                        name,
+                        unified_diff(&name, &old_content, &new_content)
+                    ))
+                }),
+            Tool::DeleteFiles { paths } => Ok(format!(
+                "Would delete {}",
+                paths
+                    .iter()
+                    .map(|path| format!("`{}`", path.display()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+            Tool::ExecuteCommand {
+                command_line,
+                working_dir,
+            } => Ok(match working_dir {
+                Some(dir) => format!("Would execute `{}` in `{}`", command_line, dir.display()),
+                None => format!("Would execute `{}`", command_line),
+            }),
+            Tool::RollbackToCheckpoint { checkpoint } => Ok(match checkpoint {
+                Some(number) => format!("Would roll back to checkpoint {}", number),
+                None => "Would roll back to the latest checkpoint".to_string(),
+            }),
+            Tool::CreatePullRequest { title, .. } => {
+                Ok(format!("Would open the pull request \"{}\"", title))
+            }
+            Tool::UpdateProjectMemory { content } => {
+                let old_content = match &self.project_memory {
+                    Some(project_memory) => project_memory.load(),
+                    None => Ok(String::new()),
+                };
+                old_content.map(|old_content| {
+                    format!(
+                        "Would update the project memory:\n{}",
+                        unified_diff("memory.md", &old_content, content)
+                    )
+                })
+            }
+            _ => return None,
+        })
+    }
+
     /// Executes an action and returns the result
     async fn execute_action(&mut self, action: &AgentAction) -> Result<ActionResult> {
         debug!("Executing action: {:?}", action.tool);
@@ -355,11 +1633,107 @@ impl Agent {
             .display(UIMessage::Reasoning(action.reasoning.clone()))
             .await?;
 
+        if self.plan_mode && !action.tool.is_read_only() {
+            return Ok(ActionResult {
+                tool: action.tool.clone(),
+                success: false,
+                result: String::new(),
+                error: Some(
+                    "Tool not available in plan mode, submit a plan with SubmitPlan first"
+                        .to_string(),
+                ),
+                reasoning: action.reasoning.clone(),
+            });
+        }
+
+        if !self.in_tool_scope(&action.tool) {
+            let (name, _) = tool_name_and_params(&action.tool);
+            return Ok(ActionResult {
+                tool: action.tool.clone(),
+                success: false,
+                result: String::new(),
+                error: Some(format!("Tool {} is not available in this session", name)),
+                reasoning: action.reasoning.clone(),
+            });
+        }
+
+        if self.dry_run {
+            if let Some(simulation) = self.simulate(&action.tool) {
+                let (success, result, error) = match simulation {
+                    Ok(simulation) => {
+                        self.ui
+                            .display(UIMessage::Action(format!("Dry run: {}", simulation)))
+                            .await?;
+                        (
+                            true,
+                            format!(
+                                "Dry run, nothing was changed. Continue as if the tool succeeded.\n{}",
+                                simulation
+                            ),
+                            None,
+                        )
+                    }
+                    Err(e) => (false, String::new(), Some(e.to_string())),
+                };
+                return Ok(ActionResult {
+                    tool: action.tool.clone(),
+                    success,
+                    result,
+                    error,
+                    reasoning: action.reasoning.clone(),
+                });
+            }
+        }
+
+        if let Some(error) = self.check_permission(&action.tool).await? {
+            return Ok(ActionResult {
+                tool: action.tool.clone(),
+                success: false,
+                result: String::new(),
+                error: Some(error),
+                reasoning: action.reasoning.clone(),
+            });
+        }
+
+        if matches!(
+            action.tool,
+            Tool::WriteFile { .. } | Tool::UpdateFile { .. } | Tool::DeleteFiles { .. }
+        ) {
+            self.ensure_checkpoint().await;
+        }
+
         let result = match &action.tool {
+            Tool::RollbackToCheckpoint { checkpoint } => {
+                self.ui
+                    .display(UIMessage::Action(match checkpoint {
+                        Some(number) => format!("Rolling back to checkpoint {}", number),
+                        None => "Rolling back to the latest checkpoint".to_string(),
+                    }))
+                    .await?;
+
+                match self.rollback_to_checkpoint(*checkpoint).await {
+                    Ok(result) => ActionResult {
+                        tool: action.tool.clone(),
+                        success: true,
+                        result,
+                        error: None,
+                        reasoning: action.reasoning.clone(),
+                    },
+                    Err(e) => ActionResult {
+                        tool: action.tool.clone(),
+                        success: false,
+                        result: String::new(),
+                        error: Some(format!("Failed to roll back: {}", e)),
+                        reasoning: action.reasoning.clone(),
+                    },
+                }
+            }
+
             Tool::ListFiles { paths, max_depth } => {
                 let mut expanded_paths = Vec::new();
                 let mut failed_paths = Vec::new();
 
+                let mut full_paths = Vec::new();
                 for path in paths {
                     self.ui
                         .display(UIMessage::Action(format!(
@@ -368,16 +1742,33 @@ impl Agent {
                         )))
                         .await?;
 
-                    let full_path = if path.is_absolute() {
+                    full_paths.push(if path.is_absolute() {
                         path.clone()
                     } else {
                         self.explorer.root_dir().join(path)
-                    };
+                    });
+                }
 
-                    match self.explorer.list_files(&full_path, *max_depth) {
+                // The paths are listed concurrently
+                let results = self.explorer.list_paths(&full_paths, *max_depth);
+                for ((path, full_path), result) in paths.iter().zip(full_paths).zip(results) {
+                    match result {
                         Ok(tree_entry) => {
-                            // Update the file tree with the new expanded entry
-                            if let Some(ref mut file_tree) = self.working_memory.file_tree {
+                            // Update the file tree with the new expanded entry, paths in
+                            // further projects go to the tree of their project
+                            let project = self
+                                .projects
+                                .iter()
+                                .find(|project| full_path.starts_with(&project.root_dir));
+                            if let Some(project) = project {
+                                let relative = full_path.strip_prefix(&project.root_dir)?;
+                                let trees = &mut self.working_memory.project_trees;
+                                if relative.as_os_str().is_empty() {
+                                    trees.insert(project.root_dir.clone(), tree_entry);
+                                } else if let Some(tree) = trees.get_mut(&project.root_dir) {
+                                    update_tree_entry(tree, &relative.to_path_buf(), tree_entry)?;
+                                }
+                            } else if let Some(ref mut file_tree) = self.working_memory.file_tree {
                                 update_tree_entry(file_tree, path, tree_entry)?;
                             }
                             expanded_paths.push(path.display().to_string());
@@ -422,6 +1813,7 @@ impl Agent {
                 let mut loaded_files = Vec::new();
                 let mut failed_files = Vec::new();
 
+                let mut full_paths = Vec::new();
                 for path in paths {
                     self.ui
                         .display(UIMessage::Action(format!(
@@ -430,13 +1822,17 @@ impl Agent {
                         )))
                         .await?;
 
-                    let full_path = if path.is_absolute() {
+                    full_paths.push(if path.is_absolute() {
                         path.clone()
                     } else {
                         self.explorer.root_dir().join(path)
-                    };
+                    });
+                }
 
-                    match self.explorer.read_file(&full_path) {
+                // The files are read concurrently
+                let results = self.explorer.read_files(&full_paths);
+                for (path, result) in paths.iter().zip(results) {
+                    match result {
                         Ok(content) => {
                             self.working_memory
                                 .loaded_files
@@ -495,14 +1891,18 @@ impl Agent {
                     std::fs::create_dir_all(parent)?;
                 }
 
+                let old_content = self.explorer.read_file(&full_path).unwrap_or_default();
                 match std::fs::write(&full_path, content) {
-                    Ok(_) => ActionResult {
-                        tool: action.tool.clone(),
-                        success: true,
-                        result: format!("Successfully wrote to {}", full_path.display()),
-                        error: None,
-                        reasoning: action.reasoning.clone(),
-                    },
+                    Ok(_) => {
+                        self.display_diff(path, &old_content, content).await?;
+                        ActionResult {
+                            tool: action.tool.clone(),
+                            success: true,
+                            result: format!("Successfully wrote to {}", full_path.display()),
+                            error: None,
+                            reasoning: action.reasoning.clone(),
+                        }
+                    }
                     Err(e) => ActionResult {
                         tool: action.tool.clone(),
                         success: false,
@@ -530,8 +1930,10 @@ impl Agent {
 
                 match self.explorer.apply_updates(&full_path, updates) {
                     Ok(new_content) => {
+                        let old_content = self.explorer.read_file(&full_path).unwrap_or_default();
                         // Write the updated file
                         std::fs::write(&full_path, new_content.clone())?;
+                        self.display_diff(path, &old_content, &new_content).await?;
 
                         // Also update the working memory in case it is currently loaded there
                         if let Some(old_content) = self.working_memory.loaded_files.get_mut(path) {
@@ -587,21 +1989,215 @@ impl Agent {
                 }
             }
 
-            Tool::AskUser { question } => {
-                // Display the question
+            Tool::UpdateProjectMemory { content } => {
                 self.ui
-                    .display(UIMessage::Question(question.clone()))
+                    .display(UIMessage::Action("Updating the project memory".to_string()))
+                    .await?;
+
+                let result = match &self.project_memory {
+                    Some(project_memory) => project_memory.save(content),
+                    None => Err(anyhow::anyhow!("The project memory is not available")),
+                };
+                match result {
+                    Ok(()) => ActionResult {
+                        tool: action.tool.clone(),
+                        success: true,
+                        result: "Updated the project memory".to_string(),
+                        error: None,
+                        reasoning: action.reasoning.clone(),
+                    },
+                    Err(e) => ActionResult {
+                        tool: action.tool.clone(),
+                        success: false,
+                        result: String::new(),
+                        error: Some(e.to_string()),
+                        reasoning: action.reasoning.clone(),
+                    },
+                }
+            }
+
+            Tool::FetchIssue { number } => {
+                self.ui
+                    .display(UIMessage::Action(format!("Fetching issue #{}", number)))
+                    .await?;
+
+                let result = match self.forge() {
+                    Ok(forge) => forge.issue(*number).await.map(|issue| issue.format()),
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok(result) => ActionResult {
+                        tool: action.tool.clone(),
+                        success: true,
+                        result,
+                        error: None,
+                        reasoning: action.reasoning.clone(),
+                    },
+                    Err(e) => ActionResult {
+                        tool: action.tool.clone(),
+                        success: false,
+                        result: String::new(),
+                        error: Some(format!("Failed to fetch issue #{}: {:#}", number, e)),
+                        reasoning: action.reasoning.clone(),
+                    },
+                }
+            }
+
+            Tool::FetchPullRequest { number } => {
+                self.ui
+                    .display(UIMessage::Action(format!(
+                        "Fetching pull request #{}",
+                        number
+                    )))
+                    .await?;
+
+                let result = match self.forge() {
+                    Ok(forge) => forge
+                        .pull_request(*number)
+                        .await
+                        .map(|pull_request| pull_request.format()),
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok(result) => ActionResult {
+                        tool: action.tool.clone(),
+                        success: true,
+                        result,
+                        error: None,
+                        reasoning: action.reasoning.clone(),
+                    },
+                    Err(e) => ActionResult {
+                        tool: action.tool.clone(),
+                        success: false,
+                        result: String::new(),
+                        error: Some(format!("Failed to fetch pull request #{}: {:#}", number, e)),
+                        reasoning: action.reasoning.clone(),
+                    },
+                }
+            }
+
+            Tool::CreatePullRequest { title, body, base } => {
+                self.ui
+                    .display(UIMessage::Action(format!(
+                        "Opening pull request \"{}\"",
+                        title
+                    )))
+                    .await?;
+
+                let result = match (self.forge(), self.current_branch().await) {
+                    (Ok(forge), Ok(head)) => {
+                        forge
+                            .create_pull_request(&head, base.as_deref(), title, body)
+                            .await
+                    }
+                    (Err(e), _) | (_, Err(e)) => Err(e),
+                };
+                match result {
+                    Ok(url) => ActionResult {
+                        tool: action.tool.clone(),
+                        success: true,
+                        result: format!("Opened pull request {}", url),
+                        error: None,
+                        reasoning: action.reasoning.clone(),
+                    },
+                    Err(e) => ActionResult {
+                        tool: action.tool.clone(),
+                        success: false,
+                        result: String::new(),
+                        error: Some(format!("Failed to open the pull request: {:#}", e)),
+                        reasoning: action.reasoning.clone(),
+                    },
+                }
+            }
+
+            Tool::FetchTicket { key } => {
+                self.ui
+                    .display(UIMessage::Action(format!("Fetching ticket {}", key)))
+                    .await?;
+
+                let result = match self.tracker() {
+                    Ok(tracker) => tracker.ticket(key).await.map(|ticket| ticket.format()),
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok(result) => ActionResult {
+                        tool: action.tool.clone(),
+                        success: true,
+                        result,
+                        error: None,
+                        reasoning: action.reasoning.clone(),
+                    },
+                    Err(e) => ActionResult {
+                        tool: action.tool.clone(),
+                        success: false,
+                        result: String::new(),
+                        error: Some(format!("Failed to fetch ticket {}: {:#}", key, e)),
+                        reasoning: action.reasoning.clone(),
+                    },
+                }
+            }
+
+            Tool::SubmitPlan { steps } => {
+                let plan = steps
+                    .iter()
+                    .enumerate()
+                    .map(|(i, step)| format!("{}. {}", i + 1, step))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                self.ui
+                    .display(UIMessage::Question(format!(
+                        "Proposed plan:\n{}\n\nApprove the plan with 'yes', or describe what to change:",
+                        plan
+                    )))
                     .await?;
 
-                // Get the response
                 match self.ui.get_input("> ").await {
+                    Ok(response) if is_approval(&response) => {
+                        self.approve_plan(steps.clone());
+                        ActionResult {
+                            tool: action.tool.clone(),
+                            success: true,
+                            result: "The user approved the plan, all tools are available now"
+                                .to_string(),
+                            error: None,
+                            reasoning: action.reasoning.clone(),
+                        }
+                    }
                     Ok(response) => ActionResult {
                         tool: action.tool.clone(),
-                        success: true,
+                        success: false,
                         result: response,
-                        error: None,
+                        error: Some("The user did not approve the plan".to_string()),
+                        reasoning: action.reasoning.clone(),
+                    },
+                    Err(e) => ActionResult {
+                        tool: action.tool.clone(),
+                        success: false,
+                        result: String::new(),
+                        error: Some(e.to_string()),
                         reasoning: action.reasoning.clone(),
                     },
+                }
+            }
+
+            Tool::AskUser { question } => {
+                // Display the question
+                self.ui
+                    .display(UIMessage::Question(question.clone()))
+                    .await?;
+
+                // Get the response, the user's answer starts a new turn
+                match self.ui.get_input("> ").await {
+                    Ok(response) => {
+                        self.turn_checkpoint_taken = false;
+                        ActionResult {
+                            tool: action.tool.clone(),
+                            success: true,
+                            result: response,
+                            error: None,
+                            reasoning: action.reasoning.clone(),
+                        }
+                    }
                     Err(e) => ActionResult {
                         tool: action.tool.clone(),
                         success: false,
@@ -738,21 +2334,28 @@ impl Agent {
                 regex_mode,
                 max_results,
             } => {
-                let search_path = if let Some(p) = path {
-                    if p.is_absolute() {
+                // Without a path, all projects of the task are searched
+                let search_paths = if let Some(p) = path {
+                    vec![if p.is_absolute() {
                         p.clone()
                     } else {
                         self.explorer.root_dir().join(p)
-                    }
+                    }]
                 } else {
-                    self.explorer.root_dir()
+                    std::iter::once(self.explorer.root_dir())
+                        .chain(self.projects.iter().map(|project| project.root_dir.clone()))
+                        .collect()
                 };
 
                 self.ui
                     .display(UIMessage::Action(format!(
                         "Searching for '{}' in {}",
                         query,
-                        search_path.display()
+                        search_paths
+                            .iter()
+                            .map(|path| path.display().to_string())
+                            .collect::<Vec<_>>()
+                            .join(", ")
                     )))
                     .await?;
 
@@ -768,7 +2371,26 @@ impl Agent {
                     max_results: *max_results,
                 };
 
-                match self.explorer.search(&search_path, options) {
+                let mut search_result: Result<Vec<SearchResult>> = Ok(Vec::new());
+                for search_path in &search_paths {
+                    let Ok(results) = &mut search_result else {
+                        break;
+                    };
+                    let remaining = options.max_results.map(|max| max - results.len());
+                    if remaining == Some(0) {
+                        break;
+                    }
+                    let options = SearchOptions {
+                        max_results: remaining,
+                        ..options.clone()
+                    };
+                    match self.explorer.search(search_path, options) {
+                        Ok(found) => results.extend(found),
+                        Err(e) => search_result = Err(e),
+                    }
+                }
+
+                match search_result {
                     Ok(results) => {
                         let mut output = String::new();
                         for result in &results {
@@ -831,6 +2453,50 @@ impl Agent {
     }
 }
 
+/// Finds results of commands and searches which were repeated later on. Only the
+/// latest output is relevant, so older ones are left out of the working memory.
+/// Maps the index of each outdated action to the index of the action replacing it.
+fn superseded_results(actions: &[ActionResult]) -> HashMap<usize, usize> {
+    let mut latest: HashMap<String, usize> = HashMap::new();
+    let mut superseded = HashMap::new();
+    for (i, action) in actions.iter().enumerate().rev() {
+        if !matches!(
+            action.tool,
+            Tool::ExecuteCommand { .. } | Tool::Search { .. }
+        ) {
+            continue;
+        }
+        let key = serde_json::to_string(&action.tool).unwrap_or_default();
+        match latest.get(&key) {
+            Some(&later) => {
+                superseded.insert(i, later);
+            }
+            None => {
+                latest.insert(key, i);
+            }
+        }
+    }
+    superseded
+}
+
+/// Counts the failed actions at the end of the history which repeat the last one.
+/// Inputs only differing in whitespace are considered the same.
+fn repeated_failures(actions: &[ActionResult]) -> usize {
+    let key = |action: &ActionResult| {
+        let input = serde_json::to_string(&action.tool).unwrap_or_default();
+        input.split_whitespace().collect::<String>()
+    };
+    let Some(last) = actions.last() else {
+        return 0;
+    };
+    let last_key = key(last);
+    actions
+        .iter()
+        .rev()
+        .take_while(|action| !action.success && key(action) == last_key)
+        .count()
+}
+
 // Helper function to parse LLM response into a Tool
 fn parse_llm_response(response: &crate::llm::LLMResponse) -> Result<AgentAction> {
     // Extract the text content from the response
@@ -1017,6 +2683,52 @@ fn parse_llm_response(response: &crate::llm::LLMResponse) -> Result<AgentAction>
                 .as_u64()
                 .map(|n| n as usize),
         },
+        "UpdateProjectMemory" => Tool::UpdateProjectMemory {
+            content: tool_params["content"]
+                .as_str()
+                .ok_or_else(|| anyhow::anyhow!("Missing content parameter"))?
+                .to_string(),
+        },
+        "SubmitPlan" => Tool::SubmitPlan {
+            steps: tool_params["steps"]
+                .as_array()
+                .ok_or_else(|| anyhow::anyhow!("Missing or invalid steps array"))?
+                .iter()
+                .map(|step| {
+                    Ok(step
+                        .as_str()
+                        .ok_or_else(|| anyhow::anyhow!("Invalid step in array"))?
+                        .to_string())
+                })
+                .collect::<Result<Vec<_>>>()?,
+        },
+        "FetchIssue" => Tool::FetchIssue {
+            number: tool_params["number"]
+                .as_u64()
+                .ok_or_else(|| anyhow::anyhow!("Missing number parameter"))?,
+        },
+        "FetchPullRequest" => Tool::FetchPullRequest {
+            number: tool_params["number"]
+                .as_u64()
+                .ok_or_else(|| anyhow::anyhow!("Missing number parameter"))?,
+        },
+        "CreatePullRequest" => Tool::CreatePullRequest {
+            title: tool_params["title"]
+                .as_str()
+                .ok_or_else(|| anyhow::anyhow!("Missing title parameter"))?
+                .to_string(),
+            body: tool_params["body"].as_str().unwrap_or_default().to_string(),
+            base: tool_params["base"].as_str().map(str::to_string),
+        },
+        "FetchTicket" => Tool::FetchTicket {
+            key: tool_params["key"]
+                .as_str()
+                .ok_or_else(|| anyhow::anyhow!("Missing key parameter"))?
+                .to_string(),
+        },
+        "RollbackToCheckpoint" => Tool::RollbackToCheckpoint {
+            checkpoint: tool_params["checkpoint"].as_u64().map(|n| n as usize),
+        },
         _ => anyhow::bail!("Unknown tool: {}", tool_name),
     };
 
@@ -2,4 +2,6 @@
 mod tests;
 
 mod agent;
-pub use agent::Agent;
+mod system_message;
+pub use agent::{AdditionalProject, Agent};
+pub use system_message::tool_names;
@@ -1,11 +1,23 @@
 use super::*;
 use crate::llm::{types::*, LLMProvider, LLMRequest};
-use crate::persistence::MockStatePersistence;
+use crate::checkpoint::{Checkpoint, CheckpointStore};
+use crate::forge::{Forge, Issue, PullRequest};
+use crate::memory::ProjectMemory;
+use crate::permissions::{
+    AuditDecision, CommandPattern, CommandRule, PermissionAuditLog, PermissionDecision,
+    PermissionGrants, PermissionMediator, PermissionMode, PermissionPolicy, PermissionRequest,
+    PermissionRequestReason,
+};
+use crate::persistence::{AgentState, MockStatePersistence};
+use crate::prompts::PromptLibrary;
+use crate::settings::BudgetSettings;
+use crate::tracker::{Ticket, Tracker};
 use crate::types::*;
-use crate::ui::{UIError, UIMessage, UserInterface};
+use crate::ui::{UIError, UIEvent, UIMessage, UserInterface};
 use crate::utils::{CommandExecutor, CommandOutput};
 use anyhow::Result;
 use async_trait::async_trait;
+use futures::future::BoxFuture;
 use regex::RegexBuilder;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
@@ -118,20 +130,29 @@ impl CommandExecutor for MockCommandExecutor {
 #[derive(Default, Clone)]
 struct MockUI {
     messages: Arc<Mutex<Vec<UIMessage>>>,
+    events: Arc<Mutex<Vec<UIEvent>>>,
     responses: Arc<Mutex<Vec<Result<String, UIError>>>>,
+    // Number of times the user interrupts the agent
+    interruptions: Arc<AtomicUsize>,
 }
 
 impl MockUI {
     fn new(responses: Vec<Result<String, UIError>>) -> Self {
         Self {
             messages: Arc::new(Mutex::new(Vec::new())),
+            events: Arc::new(Mutex::new(Vec::new())),
             responses: Arc::new(Mutex::new(responses)),
+            interruptions: Arc::new(AtomicUsize::new(0)),
         }
     }
 
     fn get_messages(&self) -> Vec<UIMessage> {
         self.messages.lock().unwrap().clone()
     }
+
+    fn get_events(&self) -> Vec<UIEvent> {
+        self.events.lock().unwrap().clone()
+    }
 }
 
 #[async_trait]
@@ -151,10 +172,27 @@ impl UserInterface for MockUI {
                 "No more mock responses",
             ))))
     }
+
+    async fn event(&self, event: UIEvent) -> Result<(), UIError> {
+        self.events.lock().unwrap().push(event);
+        Ok(())
+    }
+
+    fn interrupted(&self) -> BoxFuture<'static, ()> {
+        let interrupt = self
+            .interruptions
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+            .is_ok();
+        Box::pin(async move {
+            if !interrupt {
+                std::future::pending::<()>().await
+            }
+        })
+    }
 }
 
 // Mock Explorer
-#[derive(Default)]
+#[derive(Default, Clone)]
 struct MockExplorer {
     files: Arc<Mutex<HashMap<PathBuf, String>>>,
     file_tree: Arc<Mutex<Option<FileTreeEntry>>>,
@@ -291,6 +329,13 @@ fn create_test_response(tool: Tool, reasoning: &str) -> LLMResponse {
                 Tool::ExecuteCommand { .. } => "ExecuteCommand",
                 Tool::CompleteTask { .. } => "CompleteTask",
                 Tool::Search { .. } => "Search",
+                Tool::RollbackToCheckpoint { .. } => "RollbackToCheckpoint",
+                Tool::SubmitPlan { .. } => "SubmitPlan",
+                Tool::UpdateProjectMemory { .. } => "UpdateProjectMemory",
+                Tool::FetchIssue { .. } => "FetchIssue",
+                Tool::FetchPullRequest { .. } => "FetchPullRequest",
+                Tool::CreatePullRequest { .. } => "CreatePullRequest",
+                Tool::FetchTicket { .. } => "FetchTicket",
             },
             "params": match &tool {
                 Tool::ListFiles { paths, max_depth } => {
@@ -351,6 +396,26 @@ fn create_test_response(tool: Tool, reasoning: &str) -> LLMResponse {
                     "regex_mode": regex_mode,
                     "max_results": max_results
                 }),
+                Tool::RollbackToCheckpoint { checkpoint } => serde_json::json!({
+                    "checkpoint": checkpoint
+                }),
+                Tool::SubmitPlan { steps } => serde_json::json!({
+                    "steps": steps
+                }),
+                Tool::UpdateProjectMemory { content } => serde_json::json!({
+                    "content": content
+                }),
+                Tool::FetchIssue { number } | Tool::FetchPullRequest { number } => serde_json::json!({
+                    "number": number
+                }),
+                Tool::CreatePullRequest { title, body, base } => serde_json::json!({
+                    "title": title,
+                    "body": body,
+                    "base": base
+                }),
+                Tool::FetchTicket { key } => serde_json::json!({
+                    "key": key
+                }),
             }
         }
     });
@@ -359,6 +424,8 @@ fn create_test_response(tool: Tool, reasoning: &str) -> LLMResponse {
         content: vec![ContentBlock::Text {
             text: response.to_string(),
         }],
+        usage: Default::default(),
+        stop_reason: None,
     }
 }
 
@@ -675,3 +742,1838 @@ async fn test_execute_command() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_agent_loads_attachments() -> Result<()> {
+    let mock_llm = MockLLMProvider::new(vec![Ok(create_test_response(
+        Tool::MessageUser {
+            message: "Looked at the attachment".to_string(),
+        },
+        "Dummy reason",
+    ))]);
+    let mock_llm_ref = mock_llm.clone();
+    let mock_ui = MockUI::default();
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(create_explorer_mock()),
+        Box::new(create_command_executor_mock()),
+        Box::new(mock_ui.clone()),
+        Box::new(MockStatePersistence::new()),
+    );
+    agent.set_attachments(vec![
+        PathBuf::from("test.txt"),
+        PathBuf::from("missing.txt"),
+    ]);
+
+    agent.start_with_task("Test task".to_string()).await?;
+
+    // The attachment is part of the working memory of the very first request
+    let locked_requests = mock_llm_ref.requests.lock().unwrap();
+    if let MessageContent::Text(content) = &locked_requests[0].messages[0].content {
+        assert!(content.contains("-----test.txt:\n   1 | line 1\n"));
+    } else {
+        panic!("Expected text content in message");
+    }
+
+    // Missing attachments are reported instead of failing the task
+    assert!(mock_ui.get_messages().iter().any(|message| matches!(
+        message,
+        UIMessage::Action(msg) if msg.starts_with("Failed to load attachment `missing.txt`")
+    )));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_repeated_command_output_is_pruned() -> Result<()> {
+    let mock_command_executor = MockCommandExecutor::new(vec![
+        Ok(CommandOutput {
+            success: true,
+            stdout: "second run".to_string(),
+            stderr: "".to_string(),
+        }),
+        Ok(CommandOutput {
+            success: false,
+            stdout: "first run".to_string(),
+            stderr: "".to_string(),
+        }),
+    ]);
+
+    let command = Tool::ExecuteCommand {
+        command_line: "cargo test".to_string(),
+        working_dir: None,
+    };
+    let mock_llm = MockLLMProvider::new(vec![
+        Ok(create_test_response(
+            command.clone(),
+            "Running the tests again",
+        )),
+        Ok(create_test_response(command, "Running the tests")),
+    ]);
+    let mock_llm_ref = mock_llm.clone();
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(create_explorer_mock()),
+        Box::new(mock_command_executor),
+        Box::new(MockUI::default()),
+        Box::new(MockStatePersistence::new()),
+    );
+
+    agent.start_with_task("Test task".to_string()).await?;
+
+    // The last request only contains the output of the second run
+    let locked_requests = mock_llm_ref.requests.lock().unwrap();
+    if let MessageContent::Text(content) = &locked_requests[2].messages[0].content {
+        assert!(!content.contains("first run"));
+        assert!(content.contains("Output:\nsecond run"));
+        assert!(content.contains("Result: [Outdated, see the result of action 2]"));
+    } else {
+        panic!("Expected text content in message");
+    }
+
+    Ok(())
+}
+
+// LLM provider with a tiny context window, which must never receive a request
+struct TinyContextLLMProvider;
+
+#[async_trait]
+impl LLMProvider for TinyContextLLMProvider {
+    async fn send_message(&self, _request: LLMRequest) -> Result<LLMResponse, anyhow::Error> {
+        panic!("Request exceeding the context window was sent");
+    }
+
+    fn context_window(&self) -> Option<usize> {
+        Some(10)
+    }
+}
+
+#[tokio::test]
+async fn test_request_exceeding_context_window_fails_early() -> Result<()> {
+    let mut agent = Agent::new(
+        Box::new(TinyContextLLMProvider),
+        Box::new(create_explorer_mock()),
+        Box::new(create_command_executor_mock()),
+        Box::new(MockUI::default()),
+        Box::new(MockStatePersistence::new()),
+    );
+
+    let error = agent
+        .start_with_task("Test task".to_string())
+        .await
+        .unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("context window of the model is limited to 10 tokens"));
+
+    Ok(())
+}
+
+// Mock LLM provider with a known context window
+struct LargeContextLLMProvider(MockLLMProvider);
+
+#[async_trait]
+impl LLMProvider for LargeContextLLMProvider {
+    async fn send_message(&self, request: LLMRequest) -> Result<LLMResponse, anyhow::Error> {
+        self.0.send_message(request).await
+    }
+
+    fn context_window(&self) -> Option<usize> {
+        Some(1_000_000)
+    }
+}
+
+#[tokio::test]
+async fn test_context_status() -> Result<()> {
+    let mock_ui = MockUI::default();
+    let mut agent = Agent::new(
+        Box::new(LargeContextLLMProvider(MockLLMProvider::new(Vec::new()))),
+        Box::new(create_explorer_mock()),
+        Box::new(create_command_executor_mock()),
+        Box::new(mock_ui.clone()),
+        Box::new(MockStatePersistence::new()),
+    );
+    agent.set_budget(Default::default(), Some("gpt-4o".to_string()));
+
+    // The mock has no responses, only the request matters
+    let _ = agent.start_with_task("Test task".to_string()).await;
+
+    assert!(mock_ui.get_messages().iter().any(|msg| matches!(
+        msg,
+        UIMessage::Action(status) if status.starts_with("Context: ")
+            && status.ends_with("of 1000000 tokens (0%), cost so far: $0.00")
+    )));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_hooks() -> Result<()> {
+    let hooks: Vec<crate::hooks::Hook> = serde_json::from_str(
+        r#"[
+            {"event": "pre_tool", "tools": ["ExecuteCommand"], "block": "No commands please"},
+            {"event": "post_turn", "command": "cargo test"}
+        ]"#,
+    )?;
+
+    // The first attempt to complete the task fails because of the failing tests
+    let mock_command_executor = MockCommandExecutor::new(vec![
+        Ok(CommandOutput {
+            success: true,
+            stdout: "all tests passed".to_string(),
+            stderr: "".to_string(),
+        }),
+        Ok(CommandOutput {
+            success: false,
+            stdout: "1 test failed".to_string(),
+            stderr: "".to_string(),
+        }),
+    ]);
+    let mock_command_executor_ref = mock_command_executor.clone();
+
+    let mock_llm = MockLLMProvider::new(vec![
+        Ok(create_test_response(
+            Tool::CompleteTask {
+                message: "Done".to_string(),
+            },
+            "Completing too early",
+        )),
+        Ok(create_test_response(
+            Tool::ExecuteCommand {
+                command_line: "rm -rf target".to_string(),
+                working_dir: None,
+            },
+            "Cleaning up",
+        )),
+    ]);
+    let mock_llm_ref = mock_llm.clone();
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(create_explorer_mock()),
+        Box::new(mock_command_executor),
+        Box::new(MockUI::default()),
+        Box::new(MockStatePersistence::new()),
+    );
+    agent.set_hooks(hooks);
+
+    agent.start_with_task("Test task".to_string()).await?;
+
+    // Only the post-turn hook ran a command, once for each attempt to complete
+    let captured_commands = mock_command_executor_ref.get_captured_commands();
+    assert_eq!(captured_commands.len(), 2);
+    assert_eq!(captured_commands[0].0, "cargo test");
+    assert_eq!(captured_commands[0].1, Some(PathBuf::from("./root")));
+
+    let locked_requests = mock_llm_ref.requests.lock().unwrap();
+    assert_eq!(locked_requests.len(), 3);
+    if let MessageContent::Text(content) = &locked_requests[2].messages[0].content {
+        assert!(content.contains("Error: Blocked by hook (block): No commands please"));
+        assert!(content.contains("Hook `cargo test` failed:\n1 test failed"));
+        assert!(content.contains("Error: Hook `cargo test` failed, the task is not complete yet"));
+    } else {
+        panic!("Expected text content in message");
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_project_rules_in_system_prompt() -> Result<()> {
+    let mut files = HashMap::new();
+    files.insert(
+        PathBuf::from("./root/AGENTS.md"),
+        "Use tabs for indentation".to_string(),
+    );
+    files.insert(
+        PathBuf::from("./root/frontend/CLAUDE.md"),
+        "Run npm test".to_string(),
+    );
+    files.insert(
+        PathBuf::from("./root/frontend/app.js"),
+        "main();\n".to_string(),
+    );
+    let file_tree = Some(FileTreeEntry {
+        name: "./root".to_string(),
+        entry_type: FileSystemEntryType::Directory,
+        children: HashMap::new(),
+        is_expanded: true,
+    });
+    let explorer = MockExplorer::new(files, file_tree);
+
+    let mock_llm = MockLLMProvider::new(vec![Ok(create_test_response(
+        Tool::ReadFiles {
+            paths: vec![PathBuf::from("frontend/app.js")],
+        },
+        "Reading the app",
+    ))]);
+    let mock_llm_ref = mock_llm.clone();
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(explorer),
+        Box::new(create_command_executor_mock()),
+        Box::new(MockUI::default()),
+        Box::new(MockStatePersistence::new()),
+    );
+
+    agent.start_with_task("Test task".to_string()).await?;
+
+    // Rules of subdirectories are only added once files in them were loaded
+    let locked_requests = mock_llm_ref.requests.lock().unwrap();
+    let first_prompt = locked_requests[0].system_prompt.clone().unwrap();
+    assert!(first_prompt.contains("-----AGENTS.md:\nUse tabs for indentation"));
+    assert!(!first_prompt.contains("Run npm test"));
+    let second_prompt = locked_requests[1].system_prompt.clone().unwrap();
+    assert!(second_prompt.contains("-----frontend/CLAUDE.md:\nRun npm test"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_plan_mode() -> Result<()> {
+    let command = Tool::ExecuteCommand {
+        command_line: "cargo fmt".to_string(),
+        working_dir: None,
+    };
+    let mock_llm = MockLLMProvider::new(vec![
+        Ok(create_test_response(command.clone(), "Executing the plan")),
+        Ok(create_test_response(
+            Tool::SubmitPlan {
+                steps: vec!["Format the code".to_string()],
+            },
+            "Submitting the plan",
+        )),
+        Ok(create_test_response(command, "Formatting right away")),
+    ]);
+    let mock_llm_ref = mock_llm.clone();
+    let mock_ui = MockUI::new(vec![Ok("yes".to_string())]);
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(create_explorer_mock()),
+        Box::new(MockCommandExecutor::new(vec![Ok(CommandOutput {
+            success: true,
+            stdout: "Formatted".to_string(),
+            stderr: String::new(),
+        })])),
+        Box::new(mock_ui.clone()),
+        Box::new(MockStatePersistence::new()),
+    );
+    agent.set_plan_mode(true);
+
+    agent.start_with_task("Test task".to_string()).await?;
+
+    assert!(mock_ui.get_messages().iter().any(|msg| matches!(
+        msg,
+        UIMessage::Question(q) if q.starts_with("Proposed plan:\n1. Format the code")
+    )));
+
+    let locked_requests = mock_llm_ref.requests.lock().unwrap();
+    let first_prompt = locked_requests[0].system_prompt.clone().unwrap();
+    assert!(first_prompt.contains("You are in plan mode."));
+    let last_prompt = locked_requests[3].system_prompt.clone().unwrap();
+    assert!(!last_prompt.contains("You are in plan mode."));
+
+    if let MessageContent::Text(content) = &locked_requests[3].messages[0].content {
+        assert!(content.contains(
+            "Error: Tool not available in plan mode, submit a plan with SubmitPlan first"
+        ));
+        assert!(content.contains("- Plan approved by the user:\n  1. Format the code\n"));
+        assert!(content.contains("Result: Output:\nFormatted"));
+    } else {
+        panic!("Expected text content in message");
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_truncated_response_is_continued() -> Result<()> {
+    let text = create_test_response(
+        Tool::ReadFiles {
+            paths: vec![PathBuf::from("test.txt")],
+        },
+        "Reading the file",
+    )
+    .text();
+    let (first_part, second_part) = text.split_at(text.len() / 2);
+    let mock_llm = MockLLMProvider::new(vec![
+        Ok(LLMResponse {
+            content: vec![ContentBlock::Text {
+                text: second_part.to_string(),
+            }],
+            usage: Default::default(),
+            stop_reason: Some(StopReason::EndTurn),
+        }),
+        Ok(LLMResponse {
+            content: vec![ContentBlock::Text {
+                text: first_part.to_string(),
+            }],
+            usage: Default::default(),
+            stop_reason: Some(StopReason::MaxTokens),
+        }),
+    ]);
+    let mock_llm_ref = mock_llm.clone();
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(create_explorer_mock()),
+        Box::new(create_command_executor_mock()),
+        Box::new(MockUI::default()),
+        Box::new(MockStatePersistence::new()),
+    );
+
+    agent.start_with_task("Test task".to_string()).await?;
+
+    let locked_requests = mock_llm_ref.requests.lock().unwrap();
+    // The partial response is sent back for the model to continue it
+    let continuation = locked_requests[1].messages.last().unwrap();
+    assert!(matches!(continuation.role, MessageRole::Assistant));
+    assert!(matches!(&continuation.content, MessageContent::Text(t) if t == first_part.trim_end()));
+
+    // The stitched response was parsed into the action
+    if let MessageContent::Text(content) = &locked_requests[2].messages[0].content {
+        assert!(content.contains("-----test.txt:\n   1 | line 1"));
+    } else {
+        panic!("Expected text content in message");
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_budget_exceeded() -> Result<()> {
+    let read = Tool::ReadFiles {
+        paths: vec![PathBuf::from("test.txt")],
+    };
+    let mock_llm = MockLLMProvider::new(vec![
+        Ok(create_test_response(read.clone(), "Reading again")),
+        Ok(create_test_response(read.clone(), "Reading again")),
+        Ok(create_test_response(read.clone(), "Reading again")),
+        Ok(create_test_response(read, "Reading the file")),
+    ]);
+    let mock_llm_ref = mock_llm.clone();
+    // Continue once, then stop
+    let mock_ui = MockUI::new(vec![Ok("no".to_string()), Ok("yes".to_string())]);
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(create_explorer_mock()),
+        Box::new(create_command_executor_mock()),
+        Box::new(mock_ui.clone()),
+        Box::new(MockStatePersistence::new()),
+    );
+    agent.set_budget(
+        BudgetSettings {
+            max_iterations: Some(2),
+            ..Default::default()
+        },
+        None,
+    );
+
+    agent.start_with_task("Test task".to_string()).await?;
+
+    assert_eq!(mock_llm_ref.requests.lock().unwrap().len(), 4);
+    let questions = mock_ui
+        .get_messages()
+        .into_iter()
+        .filter(|msg| matches!(msg, UIMessage::Question(q) if q.starts_with("The limit of 2 actions was reached")))
+        .count();
+    assert_eq!(questions, 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_token_budget() -> Result<()> {
+    let read = || {
+        let mut response = create_test_response(
+            Tool::ReadFiles {
+                paths: vec![PathBuf::from("test.txt")],
+            },
+            "Reading the file",
+        );
+        response.usage = Usage {
+            input_tokens: 800,
+            output_tokens: 300,
+        };
+        Ok(response)
+    };
+    let mock_llm = MockLLMProvider::new(vec![read(), read()]);
+    let mock_llm_ref = mock_llm.clone();
+    // Don't continue
+    let mock_ui = MockUI::new(vec![Ok("no".to_string())]);
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(create_explorer_mock()),
+        Box::new(create_command_executor_mock()),
+        Box::new(mock_ui),
+        Box::new(MockStatePersistence::new()),
+    );
+    agent.set_budget(
+        BudgetSettings {
+            max_tokens: Some(1000),
+            ..Default::default()
+        },
+        None,
+    );
+
+    agent.start_with_task("Test task".to_string()).await?;
+
+    assert_eq!(mock_llm_ref.requests.lock().unwrap().len(), 1);
+    assert!(!agent.completed());
+    assert_eq!(
+        agent.budget_exceeded(),
+        Some("The 1100 tokens used reached the limit of 1000 tokens")
+    );
+    assert_eq!(agent.actions().len(), 1);
+
+    Ok(())
+}
+
+/// Checkpoints with a fixed diff
+struct MockCheckpoints {
+    diff: String,
+}
+
+impl CheckpointStore for MockCheckpoints {
+    fn create(&self, description: &str) -> Result<Checkpoint> {
+        Ok(Checkpoint {
+            number: 1,
+            commit: "0000000".to_string(),
+            description: description.to_string(),
+        })
+    }
+
+    fn list(&self) -> Result<Vec<Checkpoint>> {
+        Ok(Vec::new())
+    }
+
+    fn restore(&self, number: usize) -> Result<Checkpoint> {
+        anyhow::bail!("Checkpoint {} not found", number)
+    }
+
+    fn diff(&self, _number: usize) -> Result<String> {
+        Ok(self.diff.clone())
+    }
+}
+
+#[tokio::test]
+async fn test_self_review_before_completion() -> Result<()> {
+    let review = "src/main.rs: the debug output should be removed";
+    let mock_llm = MockLLMProvider::new(vec![
+        Ok(LLMResponse {
+            content: vec![ContentBlock::Text {
+                text: review.to_string(),
+            }],
+            usage: Default::default(),
+            stop_reason: Some(StopReason::EndTurn),
+        }),
+        Ok(create_test_response(
+            Tool::CompleteTask {
+                message: "Done".to_string(),
+            },
+            "Task is done",
+        )),
+        // Modifications trigger a checkpoint, even if they fail
+        Ok(create_test_response(
+            Tool::UpdateFile {
+                path: PathBuf::from("missing.txt"),
+                updates: vec![FileUpdate {
+                    start_line: 1,
+                    end_line: 1,
+                    new_content: "new content".to_string(),
+                }],
+            },
+            "Updating the file",
+        )),
+    ]);
+    let mock_llm_ref = mock_llm.clone();
+    let mock_ui = MockUI::default();
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(create_explorer_mock()),
+        Box::new(create_command_executor_mock()),
+        Box::new(mock_ui.clone()),
+        Box::new(MockStatePersistence::new()),
+    );
+    agent.set_checkpoints(Box::new(MockCheckpoints {
+        diff: "+println!(\"debug\");".to_string(),
+    }));
+    agent.set_self_review(true);
+
+    agent.start_with_task("Test task".to_string()).await?;
+
+    assert!(mock_ui
+        .get_messages()
+        .iter()
+        .any(|msg| matches!(msg, UIMessage::Review(r) if r == review)));
+
+    // Update, first completion, review and the completion after the review
+    let locked_requests = mock_llm_ref.requests.lock().unwrap();
+    assert_eq!(locked_requests.len(), 4);
+    let review_request = &locked_requests[2];
+    assert!(review_request
+        .system_prompt
+        .as_ref()
+        .unwrap()
+        .contains("reviewing changes"));
+    if let MessageContent::Text(content) = &locked_requests[3].messages[0].content {
+        assert!(content.contains(&format!("Review:\n{}", review)));
+        assert!(content.contains("Error: The review found issues in your changes"));
+    } else {
+        panic!("Expected text content in message");
+    }
+
+    Ok(())
+}
+
+/// Counts the snapshots and restores, and fails them
+#[derive(Clone, Default)]
+struct FailingCheckpoints {
+    created: Arc<AtomicUsize>,
+    restored: Arc<AtomicUsize>,
+}
+
+impl CheckpointStore for FailingCheckpoints {
+    fn create(&self, _description: &str) -> Result<Checkpoint> {
+        self.created.fetch_add(1, Ordering::SeqCst);
+        anyhow::bail!("git add -A failed")
+    }
+
+    fn list(&self) -> Result<Vec<Checkpoint>> {
+        Ok(Vec::new())
+    }
+
+    fn restore(&self, number: usize) -> Result<Checkpoint> {
+        self.restored.fetch_add(1, Ordering::SeqCst);
+        anyhow::bail!("Checkpoint {} not found", number)
+    }
+
+    fn diff(&self, _number: usize) -> Result<String> {
+        Ok(String::new())
+    }
+}
+
+#[tokio::test]
+async fn test_checkpoint_once_per_turn() -> Result<()> {
+    // Updating missing files fails without touching the working tree, but a failed
+    // modification needs a checkpoint as well
+    let write = |path: &str| {
+        Ok(create_test_response(
+            Tool::UpdateFile {
+                path: PathBuf::from(path),
+                updates: vec![FileUpdate {
+                    start_line: 1,
+                    end_line: 1,
+                    new_content: "content".to_string(),
+                }],
+            },
+            "Updating a file",
+        ))
+    };
+    let mock_llm = MockLLMProvider::new(vec![
+        Ok(create_test_response(
+            Tool::CompleteTask {
+                message: "Done".to_string(),
+            },
+            "Task is done",
+        )),
+        write("c.txt"),
+        Ok(create_test_response(
+            Tool::AskUser {
+                question: "Continue?".to_string(),
+            },
+            "Asking the user",
+        )),
+        write("b.txt"),
+        write("a.txt"),
+    ]);
+    let checkpoints = FailingCheckpoints::default();
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(create_explorer_mock()),
+        Box::new(create_command_executor_mock()),
+        Box::new(MockUI::new(vec![Ok("yes".to_string())])),
+        Box::new(MockStatePersistence::new()),
+    );
+    agent.set_checkpoints(Box::new(checkpoints.clone()));
+
+    // Failing snapshots don't stop the agent
+    agent.start_with_task("Test task".to_string()).await?;
+    assert!(agent.completed());
+    // Before the first update and the first update after the answer
+    assert_eq!(checkpoints.created.load(Ordering::SeqCst), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_continue_without_repeating_actions() -> Result<()> {
+    let recorded = |tool: Tool, result: &str| ActionResult {
+        tool,
+        success: true,
+        result: result.to_string(),
+        error: None,
+        reasoning: String::new(),
+    };
+    let state = AgentState {
+        task: "Test task".to_string(),
+        actions: vec![
+            recorded(
+                Tool::RollbackToCheckpoint {
+                    checkpoint: Some(1),
+                },
+                "Restored checkpoint 1 (Before action 1)",
+            ),
+            recorded(
+                Tool::CreatePullRequest {
+                    title: "Fix the parser".to_string(),
+                    body: "Fixes #3".to_string(),
+                    base: None,
+                },
+                "Opened pull request https://github.com/owner/repo/pull/8",
+            ),
+            recorded(
+                Tool::UpdateProjectMemory {
+                    content: "- Build with `make`\n".to_string(),
+                },
+                "Updated the project memory",
+            ),
+        ],
+        pending_action: None,
+        attachments: Vec::new(),
+        checkpoints: Default::default(),
+    };
+    let mock_llm = MockLLMProvider::new(vec![Ok(create_test_response(
+        Tool::CompleteTask {
+            message: "Done".to_string(),
+        },
+        "Task is done",
+    ))]);
+    let mock_llm_ref = mock_llm.clone();
+    let checkpoints = FailingCheckpoints::default();
+    let forge = MockForge::default();
+    let command_executor = MockCommandExecutor::new(vec![Ok(CommandOutput {
+        success: true,
+        stdout: "fix-parser\n".to_string(),
+        stderr: "".to_string(),
+    })]);
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(create_explorer_mock()),
+        Box::new(command_executor),
+        Box::new(MockUI::default()),
+        Box::new(MockStatePersistence::with_state(state)),
+    );
+    agent.set_checkpoints(Box::new(checkpoints.clone()));
+    agent.set_forge(Box::new(forge.clone()));
+    let temp_dir = tempfile::TempDir::new()?;
+    let memory = ProjectMemory::new(temp_dir.path(), Path::new("/project"));
+    memory.save("- Build with `cargo`\n")?;
+    agent.set_project_memory(memory.clone());
+    agent.start_from_state().await?;
+    assert!(agent.completed());
+
+    // The recorded results are used instead of rolling back, opening the pull
+    // request and updating the memory again
+    assert_eq!(checkpoints.restored.load(Ordering::SeqCst), 0);
+    assert!(forge.created.lock().unwrap().is_empty());
+    assert_eq!(memory.load()?, "- Build with `cargo`\n");
+    let locked_requests = mock_llm_ref.requests.lock().unwrap();
+    if let MessageContent::Text(content) = &locked_requests[0].messages[0].content {
+        assert!(content.contains("Restored checkpoint 1 (Before action 1)"));
+        assert!(content.contains("Opened pull request https://github.com/owner/repo/pull/8"));
+    } else {
+        panic!("Expected text content in message");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_additional_projects() -> Result<()> {
+    let mock_llm = MockLLMProvider::new(vec![
+        Ok(LLMResponse {
+            content: vec![ContentBlock::Text {
+                text: "LGTM".to_string(),
+            }],
+            usage: Default::default(),
+            stop_reason: Some(StopReason::EndTurn),
+        }),
+        Ok(create_test_response(
+            Tool::CompleteTask {
+                message: "Done".to_string(),
+            },
+            "Task is done",
+        )),
+        Ok(create_test_response(
+            Tool::UpdateFile {
+                path: PathBuf::from("/backend/missing.rs"),
+                updates: vec![FileUpdate {
+                    start_line: 1,
+                    end_line: 1,
+                    new_content: "new content".to_string(),
+                }],
+            },
+            "Updating the backend",
+        )),
+        Ok(create_test_response(
+            Tool::Search {
+                query: "login".to_string(),
+                path: None,
+                case_sensitive: false,
+                whole_words: false,
+                regex_mode: false,
+                max_results: None,
+            },
+            "Searching both projects",
+        )),
+    ]);
+    let mock_llm_ref = mock_llm.clone();
+
+    let explorer = create_explorer_mock();
+    explorer.files.lock().unwrap().extend([
+        (PathBuf::from("./root/app.js"), "login(user)\n".to_string()),
+        (
+            PathBuf::from("/backend/api.rs"),
+            "fn login() {}\n".to_string(),
+        ),
+    ]);
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(explorer),
+        Box::new(create_command_executor_mock()),
+        Box::new(MockUI::default()),
+        Box::new(MockStatePersistence::new()),
+    );
+    agent.set_checkpoints(Box::new(MockCheckpoints {
+        diff: "+frontend".to_string(),
+    }));
+    agent.set_additional_projects(vec![AdditionalProject::new(
+        PathBuf::from("/backend"),
+        Some(Box::new(MockCheckpoints {
+            diff: "+backend".to_string(),
+        })),
+    )]);
+    agent.set_self_review(true);
+
+    agent.start_with_task("Test task".to_string()).await?;
+    assert!(agent.completed());
+
+    // Search, update, completion and review
+    let locked_requests = mock_llm_ref.requests.lock().unwrap();
+    assert_eq!(locked_requests.len(), 4);
+    assert!(locked_requests[0]
+        .system_prompt
+        .as_ref()
+        .unwrap()
+        .contains("a Search without a path covers all of them:\n- /backend"));
+    if let MessageContent::Text(content) = &locked_requests[1].messages[0].content {
+        assert!(content.contains("Found 2 matches"));
+        assert!(content.contains("./root/app.js:1:login(user)"));
+        assert!(content.contains("/backend/api.rs:1:fn login() {}"));
+    } else {
+        panic!("Expected text content in message");
+    }
+    if let MessageContent::Text(content) = &locked_requests[3].messages[0].content {
+        assert!(content.contains("Changes in ./root:\n```diff\n+frontend\n```"));
+        assert!(content.contains("Changes in /backend:\n```diff\n+backend\n```"));
+    } else {
+        panic!("Expected text content in message");
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_dry_run() -> Result<()> {
+    let temp_dir = tempfile::TempDir::new()?;
+    let memory = ProjectMemory::new(temp_dir.path(), Path::new("/project"));
+    memory.save("- Build with `make`\n")?;
+    let mock_llm = MockLLMProvider::new(vec![
+        Ok(create_test_response(
+            Tool::UpdateProjectMemory {
+                content: "- Build with `cargo`\n".to_string(),
+            },
+            "Remembering the build command",
+        )),
+        Ok(create_test_response(
+            Tool::ExecuteCommand {
+                command_line: "rm -rf target".to_string(),
+                working_dir: None,
+            },
+            "Cleaning up",
+        )),
+        Ok(create_test_response(
+            Tool::UpdateFile {
+                path: PathBuf::from("test.txt"),
+                updates: vec![FileUpdate {
+                    start_line: 2,
+                    end_line: 3,
+                    new_content: "line two".to_string(),
+                }],
+            },
+            "Updating the file",
+        )),
+    ]);
+    let mock_llm_ref = mock_llm.clone();
+    let explorer = create_explorer_mock();
+    let command_executor = create_command_executor_mock();
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(explorer.clone()),
+        Box::new(command_executor.clone()),
+        Box::new(MockUI::default()),
+        Box::new(MockStatePersistence::new()),
+    );
+    agent.set_dry_run(true);
+    agent.set_project_memory(memory.clone());
+
+    agent.start_with_task("Test task".to_string()).await?;
+
+    // Nothing was changed or executed
+    assert_eq!(
+        explorer.read_file(&PathBuf::from("./root/test.txt"))?,
+        "line 1\nline 2\nline 3\n"
+    );
+    assert!(command_executor.get_captured_commands().is_empty());
+    assert_eq!(memory.load()?, "- Build with `make`\n");
+
+    let locked_requests = mock_llm_ref.requests.lock().unwrap();
+    if let MessageContent::Text(content) = &locked_requests[2].messages[0].content {
+        assert!(content.contains(
+            "Would update `test.txt`:\n--- a/test.txt\n+++ b/test.txt\n@@ -1,3 +1,3 @@\n line 1\n-line 2\n+line two\n line 3\n"
+        ));
+        assert!(content.contains("Would execute `rm -rf target`"));
+    } else {
+        panic!("Expected text content in message");
+    }
+    if let MessageContent::Text(content) = &locked_requests[3].messages[0].content {
+        assert!(content.contains("Would update the project memory:\n"));
+        assert!(content.contains("-- Build with `make`\n+- Build with `cargo`\n"));
+    } else {
+        panic!("Expected text content in message");
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_project_memory() -> Result<()> {
+    let temp_dir = tempfile::TempDir::new()?;
+    let memory = ProjectMemory::new(temp_dir.path(), Path::new("/project"));
+    memory.save("- Build with `make`\n")?;
+
+    let mock_llm = MockLLMProvider::new(vec![Ok(create_test_response(
+        Tool::UpdateProjectMemory {
+            content: "- Build with `make`\n- Tests need a running database\n".to_string(),
+        },
+        "Remembering the database",
+    ))]);
+    let mock_llm_ref = mock_llm.clone();
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(create_explorer_mock()),
+        Box::new(create_command_executor_mock()),
+        Box::new(MockUI::default()),
+        Box::new(MockStatePersistence::new()),
+    );
+    agent.set_project_memory(memory.clone());
+
+    agent.start_with_task("Test task".to_string()).await?;
+
+    let locked_requests = mock_llm_ref.requests.lock().unwrap();
+    let first_prompt = locked_requests[0].system_prompt.clone().unwrap();
+    assert!(first_prompt
+        .ends_with("Your notes about the project from previous sessions:\n- Build with `make`"));
+    let second_prompt = locked_requests[1].system_prompt.clone().unwrap();
+    assert!(second_prompt.ends_with("- Tests need a running database"));
+    assert_eq!(
+        memory.load()?,
+        "- Build with `make`\n- Tests need a running database\n"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_steer_after_interruption() -> Result<()> {
+    let mock_llm = MockLLMProvider::new(vec![Ok(create_test_response(
+        Tool::CompleteTask {
+            message: "Looked at the tests".to_string(),
+        },
+        "Following the instruction",
+    ))]);
+    let mock_llm_ref = mock_llm.clone();
+    let mock_ui = MockUI::new(vec![Ok("Only look at the tests".to_string())]);
+    mock_ui.interruptions.store(1, Ordering::Relaxed);
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(create_explorer_mock()),
+        Box::new(create_command_executor_mock()),
+        Box::new(mock_ui.clone()),
+        Box::new(MockStatePersistence::new()),
+    );
+
+    agent.start_with_task("Test task".to_string()).await?;
+
+    // The interrupted request was never answered, the instruction is part of the next one
+    let locked_requests = mock_llm_ref.requests.lock().unwrap();
+    assert_eq!(locked_requests.len(), 1);
+    if let MessageContent::Text(content) = &locked_requests[0].messages[0].content {
+        assert!(content.contains("The user interrupted you. What should you do instead?"));
+        assert!(content.contains("Result: Only look at the tests"));
+    } else {
+        panic!("Expected text content in message");
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_switch_permission_mode_after_interruption() -> Result<()> {
+    let mock_llm = MockLLMProvider::new(vec![Ok(create_test_response(
+        Tool::ExecuteCommand {
+            command_line: "cargo build".to_string(),
+            working_dir: None,
+        },
+        "Building the project",
+    ))]);
+    let command_executor = create_command_executor_mock();
+    // Responses are taken from the end, an unknown mode is asked for again
+    let mock_ui = MockUI::new(vec![
+        Ok("/mode full-auto".to_string()),
+        Ok("/mode yolo".to_string()),
+    ]);
+    mock_ui.interruptions.store(1, Ordering::Relaxed);
+    let permissions = MockPermissionMediator::default();
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(create_explorer_mock()),
+        Box::new(command_executor.clone()),
+        Box::new(mock_ui.clone()),
+        Box::new(MockStatePersistence::new()),
+    );
+    agent.set_permission_mediator(Box::new(permissions.clone()));
+
+    agent.start_with_task("Test task".to_string()).await?;
+
+    // The command ran without asking in the new mode
+    assert!(permissions.requests.lock().unwrap().is_empty());
+    assert_eq!(command_executor.get_captured_commands().len(), 1);
+    assert!(mock_ui.get_messages().iter().any(|message| matches!(
+        message,
+        UIMessage::Action(text) if text == "Switched to permission mode full-auto"
+    )));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_prompt_after_interruption() -> Result<()> {
+    let prompts_dir = tempfile::TempDir::new()?;
+    std::fs::write(
+        prompts_dir.path().join("bump.md"),
+        "Bump the version to {{version}}",
+    )?;
+    let mock_llm = MockLLMProvider::new(vec![]);
+    let mock_llm_ref = mock_llm.clone();
+    // Responses are taken from the end: listing, a missing value and the prompt
+    let mock_ui = MockUI::new(vec![
+        Ok(r#"/prompt bump version="2.0 beta""#.to_string()),
+        Ok("/prompt bump".to_string()),
+        Ok("/prompt".to_string()),
+    ]);
+    mock_ui.interruptions.store(1, Ordering::Relaxed);
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(create_explorer_mock()),
+        Box::new(create_command_executor_mock()),
+        Box::new(mock_ui.clone()),
+        Box::new(MockStatePersistence::new()),
+    );
+    agent.set_prompt_library(PromptLibrary::new(vec![prompts_dir.path().to_path_buf()]));
+
+    // Fails once the mock LLM has no more responses, after the instruction was recorded
+    let _ = agent.start_with_task("Test task".to_string()).await;
+
+    let messages = mock_ui.get_messages();
+    assert!(messages.iter().any(|message| matches!(
+        message,
+        UIMessage::Action(text) if text == "bump: Bump the version to {{version}} (version)"
+    )));
+    assert!(messages.iter().any(|message| matches!(
+        message,
+        UIMessage::Question(text) if text.starts_with("Prompt 'bump' needs a value for version")
+    )));
+    let locked_requests = mock_llm_ref.requests.lock().unwrap();
+    if let MessageContent::Text(content) = &locked_requests[0].messages[0].content {
+        assert!(content.contains("Bump the version to 2.0 beta"));
+    } else {
+        panic!("Expected text content in message");
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_tool_scope_and_instructions() -> Result<()> {
+    let mock_llm = MockLLMProvider::new(vec![Ok(create_test_response(
+        Tool::ExecuteCommand {
+            command_line: "cargo build".to_string(),
+            working_dir: None,
+        },
+        "Building the project",
+    ))]);
+    let mock_llm_ref = mock_llm.clone();
+    let command_executor = create_command_executor_mock();
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(create_explorer_mock()),
+        Box::new(command_executor.clone()),
+        Box::new(MockUI::default()),
+        Box::new(MockStatePersistence::new()),
+    );
+    agent.set_tool_scope(Some(vec!["ReadFiles".to_string(), "Search".to_string()]));
+    agent.set_instructions("Only write documentation.".to_string());
+
+    agent.start_with_task("Test task".to_string()).await?;
+
+    assert!(command_executor.get_captured_commands().is_empty());
+    let locked_requests = mock_llm_ref.requests.lock().unwrap();
+    let prompt = locked_requests[0].system_prompt.clone().unwrap();
+    assert!(prompt.contains("\n\nOnly write documentation."));
+    assert!(prompt.contains(
+        "Only these tools are available in this session: ReadFiles, Search, CompleteTask"
+    ));
+    if let MessageContent::Text(content) = &locked_requests[1].messages[0].content {
+        assert!(content.contains("Error: Tool ExecuteCommand is not available in this session"));
+    } else {
+        panic!("Expected text content in message");
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_events_for_tool_calls() -> Result<()> {
+    let mock_llm = MockLLMProvider::new(vec![Ok(create_test_response(
+        Tool::ExecuteCommand {
+            command_line: "cargo test".to_string(),
+            working_dir: None,
+        },
+        "Running the tests",
+    ))]);
+    let command_executor = MockCommandExecutor::new(vec![Ok(CommandOutput {
+        success: true,
+        stdout: "ok".to_string(),
+        stderr: "".to_string(),
+    })]);
+    let mock_ui = MockUI::default();
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(create_explorer_mock()),
+        Box::new(command_executor),
+        Box::new(mock_ui.clone()),
+        Box::new(MockStatePersistence::new()),
+    );
+
+    agent.start_with_task("Test task".to_string()).await?;
+
+    let events = mock_ui.get_events();
+    let usage_events = events
+        .iter()
+        .filter(|event| matches!(event, UIEvent::Usage { .. }))
+        .count();
+    assert_eq!(usage_events, 2);
+    let tool_events: Vec<_> = events
+        .into_iter()
+        .filter(|event| !matches!(event, UIEvent::Usage { .. }))
+        .collect();
+    assert_eq!(tool_events.len(), 4);
+    assert_eq!(
+        tool_events[0],
+        UIEvent::ToolCall {
+            tool: "ExecuteCommand".to_string(),
+            params: serde_json::json!({"command_line": "cargo test", "working_dir": null}),
+        }
+    );
+    assert!(matches!(
+        &tool_events[1],
+        UIEvent::ToolResult { tool, success: true, .. } if tool == "ExecuteCommand"
+    ));
+    assert!(matches!(
+        &tool_events[3],
+        UIEvent::ToolResult { tool, success: true, .. } if tool == "CompleteTask"
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_disabled_tools() -> Result<()> {
+    let mock_llm = MockLLMProvider::new(vec![Ok(create_test_response(
+        Tool::WriteFile {
+            path: PathBuf::from("test.txt"),
+            content: "content".to_string(),
+        },
+        "Writing a file",
+    ))]);
+    let mock_llm_ref = mock_llm.clone();
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(create_explorer_mock()),
+        Box::new(create_command_executor_mock()),
+        Box::new(MockUI::default()),
+        Box::new(MockStatePersistence::new()),
+    );
+    agent.set_disabled_tools(vec!["WriteFile".to_string()]);
+
+    agent.start_with_task("Test task".to_string()).await?;
+
+    let locked_requests = mock_llm_ref.requests.lock().unwrap();
+    let prompt = locked_requests[0].system_prompt.clone().unwrap();
+    assert!(prompt.contains("UpdateFile\n"));
+    assert!(!prompt.contains("WriteFile\n"));
+    if let MessageContent::Text(content) = &locked_requests[1].messages[0].content {
+        assert!(content.contains("Error: Tool WriteFile is not available in this session"));
+    } else {
+        panic!("Expected text content in message");
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_repeated_failures_pause_for_user() -> Result<()> {
+    let update = || {
+        Ok(create_test_response(
+            Tool::UpdateFile {
+                path: PathBuf::from("missing.txt"),
+                updates: vec![FileUpdate {
+                    start_line: 1,
+                    end_line: 1,
+                    new_content: "new content".to_string(),
+                }],
+            },
+            "Updating the file",
+        ))
+    };
+    let mock_llm = MockLLMProvider::new(vec![update(), update(), update()]);
+    let mock_llm_ref = mock_llm.clone();
+    let mock_ui = MockUI::new(vec![Ok("Create the file with WriteFile".to_string())]);
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(create_explorer_mock()),
+        Box::new(create_command_executor_mock()),
+        Box::new(mock_ui.clone()),
+        Box::new(MockStatePersistence::new()),
+    );
+
+    agent.start_with_task("Test task".to_string()).await?;
+
+    assert!(mock_ui.get_messages().iter().any(|msg| matches!(
+        msg,
+        UIMessage::Question(q) if q.contains("called UpdateFile 3 times in a row")
+    )));
+
+    // The instruction of the user is part of the request after the third failure
+    let locked_requests = mock_llm_ref.requests.lock().unwrap();
+    assert_eq!(locked_requests.len(), 4);
+    if let MessageContent::Text(content) = &locked_requests[3].messages[0].content {
+        assert!(content.contains("Don't retry it unchanged"));
+        assert!(content.contains("Result: Create the file with WriteFile"));
+    } else {
+        panic!("Expected text content in message");
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_file_changes_are_shown_as_diff() -> Result<()> {
+    let temp_dir = tempfile::TempDir::new()?;
+    let path = temp_dir.path().join("main.rs");
+    let mock_llm = MockLLMProvider::new(vec![Ok(create_test_response(
+        Tool::WriteFile {
+            path: path.clone(),
+            content: "fn main() {}\n".to_string(),
+        },
+        "Creating the main file",
+    ))]);
+    let mock_ui = MockUI::default();
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(create_explorer_mock()),
+        Box::new(create_command_executor_mock()),
+        Box::new(mock_ui.clone()),
+        Box::new(MockStatePersistence::new()),
+    );
+
+    agent.start_with_task("Test task".to_string()).await?;
+
+    assert_eq!(std::fs::read_to_string(&path)?, "fn main() {}\n");
+    assert!(mock_ui.get_messages().iter().any(|msg| matches!(
+        msg,
+        UIMessage::Diff(diff) if diff.ends_with("@@ -0,0 +1,1 @@\n+fn main() {}\n")
+    )));
+
+    Ok(())
+}
+
+// Permission mediator answering with prepared decisions
+#[derive(Default, Clone)]
+struct MockPermissionMediator {
+    decisions: Arc<Mutex<Vec<PermissionDecision>>>,
+    requests: Arc<Mutex<Vec<PermissionRequest>>>,
+    /// Answers to confirming a permanent grant, the suggestion is kept when empty
+    confirmed_grants: Arc<Mutex<Vec<Option<CommandPattern>>>>,
+}
+
+#[async_trait]
+impl PermissionMediator for MockPermissionMediator {
+    async fn request_permission(&self, request: &PermissionRequest) -> Result<PermissionDecision> {
+        self.requests.lock().unwrap().push(request.clone());
+        Ok(self
+            .decisions
+            .lock()
+            .unwrap()
+            .pop()
+            .unwrap_or(PermissionDecision::Denied))
+    }
+
+    async fn confirm_grant(
+        &self,
+        _request: &PermissionRequest,
+        pattern: &CommandPattern,
+    ) -> Result<Option<CommandPattern>> {
+        Ok(self
+            .confirmed_grants
+            .lock()
+            .unwrap()
+            .pop()
+            .unwrap_or(Some(pattern.clone())))
+    }
+}
+
+#[tokio::test]
+async fn test_permission_requests() -> Result<()> {
+    let command = |command_line: &str| {
+        Ok(create_test_response(
+            Tool::ExecuteCommand {
+                command_line: command_line.to_string(),
+                working_dir: None,
+            },
+            "Running a command",
+        ))
+    };
+    let mock_llm = MockLLMProvider::new(vec![
+        command("cargo test"),
+        command("cargo test"),
+        command("rm -rf target"),
+    ]);
+    let mock_llm_ref = mock_llm.clone();
+    let output = || {
+        Ok(CommandOutput {
+            success: true,
+            stdout: "ok".to_string(),
+            stderr: "".to_string(),
+        })
+    };
+    let command_executor = MockCommandExecutor::new(vec![output(), output()]);
+    let permissions = MockPermissionMediator::default();
+    // Decisions are taken from the end
+    *permissions.decisions.lock().unwrap() = vec![
+        PermissionDecision::GrantedSession,
+        PermissionDecision::Denied,
+    ];
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(create_explorer_mock()),
+        Box::new(command_executor.clone()),
+        Box::new(MockUI::default()),
+        Box::new(MockStatePersistence::new()),
+    );
+    agent.set_permission_mediator(Box::new(permissions.clone()));
+
+    agent.start_with_task("Test task".to_string()).await?;
+
+    // The denied command was not run, the second `cargo test` didn't ask again
+    let commands: Vec<_> = command_executor
+        .get_captured_commands()
+        .into_iter()
+        .map(|(command_line, _)| command_line)
+        .collect();
+    assert_eq!(commands, vec!["cargo test", "cargo test"]);
+    assert_eq!(permissions.requests.lock().unwrap().len(), 2);
+
+    let locked_requests = mock_llm_ref.requests.lock().unwrap();
+    if let MessageContent::Text(content) = &locked_requests[1].messages[0].content {
+        assert!(content.contains("Error: The user denied the permission to use this tool"));
+    } else {
+        panic!("Expected text content in message");
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_permanent_permission_grants() -> Result<()> {
+    let command = |command_line: &str| {
+        Ok(create_test_response(
+            Tool::ExecuteCommand {
+                command_line: command_line.to_string(),
+                working_dir: None,
+            },
+            "Running a command",
+        ))
+    };
+    let mock_llm = MockLLMProvider::new(vec![
+        command("cargo test"),
+        command("cargo test --lib"),
+    ]);
+    let output = || {
+        Ok(CommandOutput {
+            success: true,
+            stdout: "ok".to_string(),
+            stderr: "".to_string(),
+        })
+    };
+    let command_executor = MockCommandExecutor::new(vec![output(), output()]);
+    let permissions = MockPermissionMediator::default();
+    *permissions.decisions.lock().unwrap() = vec![PermissionDecision::GrantedProject];
+
+    let temp_dir = tempfile::TempDir::new()?;
+    let grants = PermissionGrants::new(temp_dir.path(), Path::new("./root"));
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(create_explorer_mock()),
+        Box::new(command_executor.clone()),
+        Box::new(MockUI::default()),
+        Box::new(MockStatePersistence::new()),
+    );
+    agent.set_permission_mediator(Box::new(permissions.clone()));
+    agent.set_permission_grants(grants.clone());
+
+    agent.start_with_task("Test task".to_string()).await?;
+
+    // The grant applied right away and was stored for the next sessions
+    assert_eq!(command_executor.get_captured_commands().len(), 2);
+    assert_eq!(permissions.requests.lock().unwrap().len(), 1);
+    let stored = grants.load()?;
+    assert_eq!(stored.len(), 1);
+    assert_eq!(
+        stored[0].pattern,
+        CommandPattern::Prefix("cargo test".to_string())
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_narrowed_permission_grant() -> Result<()> {
+    let command = |command_line: &str| {
+        Ok(create_test_response(
+            Tool::ExecuteCommand {
+                command_line: command_line.to_string(),
+                working_dir: None,
+            },
+            "Running a command",
+        ))
+    };
+    let mock_llm = MockLLMProvider::new(vec![
+        command("cargo test"),
+        command("cargo test --lib"),
+        command("cargo test --lib parser"),
+    ]);
+    let output = || {
+        Ok(CommandOutput {
+            success: true,
+            stdout: "ok".to_string(),
+            stderr: "".to_string(),
+        })
+    };
+    let command_executor = MockCommandExecutor::new(vec![output(), output()]);
+    let permissions = MockPermissionMediator::default();
+    *permissions.decisions.lock().unwrap() = vec![PermissionDecision::GrantedProject];
+    *permissions.confirmed_grants.lock().unwrap() =
+        vec![Some(CommandPattern::Prefix("cargo test --lib".to_string()))];
+
+    let temp_dir = tempfile::TempDir::new()?;
+    let grants = PermissionGrants::new(temp_dir.path(), Path::new("./root"));
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(create_explorer_mock()),
+        Box::new(command_executor.clone()),
+        Box::new(MockUI::default()),
+        Box::new(MockStatePersistence::new()),
+    );
+    agent.set_permission_mediator(Box::new(permissions.clone()));
+    agent.set_permission_grants(grants.clone());
+
+    agent.start_with_task("Test task".to_string()).await?;
+
+    // Only the narrowed prefix was granted, `cargo test` needed permission again
+    let commands: Vec<String> = command_executor
+        .get_captured_commands()
+        .into_iter()
+        .map(|(command_line, _)| command_line)
+        .collect();
+    assert_eq!(commands, vec!["cargo test --lib parser", "cargo test --lib"]);
+    assert_eq!(permissions.requests.lock().unwrap().len(), 2);
+    let stored = grants.load()?;
+    assert_eq!(stored.len(), 1);
+    assert_eq!(
+        stored[0].pattern,
+        CommandPattern::Prefix("cargo test --lib".to_string())
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_writing_outside_of_project_needs_permission() -> Result<()> {
+    let temp_dir = tempfile::TempDir::new()?;
+    let write = |name: &str| {
+        Ok(create_test_response(
+            Tool::WriteFile {
+                path: temp_dir.path().join(name),
+                content: "content\n".to_string(),
+            },
+            "Writing a file outside of the project",
+        ))
+    };
+    let mock_llm = MockLLMProvider::new(vec![
+        write("second.txt"),
+        write("first.txt"),
+        write("denied.txt"),
+    ]);
+    let permissions = MockPermissionMediator::default();
+    *permissions.decisions.lock().unwrap() = vec![
+        PermissionDecision::GrantedSession,
+        PermissionDecision::Denied,
+    ];
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(create_explorer_mock()),
+        Box::new(create_command_executor_mock()),
+        Box::new(MockUI::default()),
+        Box::new(MockStatePersistence::new()),
+    );
+    agent.set_permission_mediator(Box::new(permissions.clone()));
+    agent.set_permission_mode(PermissionMode::FullAuto);
+
+    agent.start_with_task("Test task".to_string()).await?;
+
+    // Even in full-auto mode the user was asked, the grant covers the directory
+    assert!(!temp_dir.path().join("denied.txt").exists());
+    assert!(temp_dir.path().join("first.txt").exists());
+    assert!(temp_dir.path().join("second.txt").exists());
+    let requests = permissions.requests.lock().unwrap();
+    assert_eq!(requests.len(), 2);
+    assert!(matches!(
+        &requests[1].reason,
+        PermissionRequestReason::WritePath { paths } if paths == &[temp_dir.path().join("first.txt")]
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_command_rules() -> Result<()> {
+    let command = |command_line: &str| {
+        Ok(create_test_response(
+            Tool::ExecuteCommand {
+                command_line: command_line.to_string(),
+                working_dir: None,
+            },
+            "Running a command",
+        ))
+    };
+    let mock_llm = MockLLMProvider::new(vec![
+        command("git status"),
+        command("rm -rf target"),
+        command("cargo test"),
+    ]);
+    let mock_llm_ref = mock_llm.clone();
+    let command_executor = MockCommandExecutor::new(vec![Ok(CommandOutput {
+        success: true,
+        stdout: "ok".to_string(),
+        stderr: "".to_string(),
+    })]);
+    let permissions = MockPermissionMediator::default();
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(create_explorer_mock()),
+        Box::new(command_executor.clone()),
+        Box::new(MockUI::default()),
+        Box::new(MockStatePersistence::new()),
+    );
+    agent.set_permission_mediator(Box::new(permissions.clone()));
+    agent.set_permission_policy(PermissionPolicy {
+        mode: PermissionMode::AutoEdit,
+        rules: serde_json::from_str::<Vec<CommandRule>>(
+            r#"[{"action": "allow", "prefix": "cargo test"}, {"action": "deny", "prefix": "rm"}]"#,
+        )?,
+        ..Default::default()
+    });
+    let audit_dir = tempfile::TempDir::new()?;
+    let audit_path = audit_dir.path().join("audit.jsonl");
+    agent.set_permission_audit(PermissionAuditLog::new(
+        audit_path.clone(),
+        "session",
+        Path::new("./root"),
+    ));
+
+    agent.start_with_task("Test task".to_string()).await?;
+
+    // Only the command without a rule asked for permission, which the user denied
+    let commands: Vec<_> = command_executor
+        .get_captured_commands()
+        .into_iter()
+        .map(|(command_line, _)| command_line)
+        .collect();
+    assert_eq!(commands, vec!["cargo test"]);
+    let requests = permissions.requests.lock().unwrap();
+    assert_eq!(requests.len(), 1);
+    assert!(matches!(
+        &requests[0].reason,
+        PermissionRequestReason::ExecuteCommand { command_line, .. } if command_line == "git status"
+    ));
+
+    let locked_requests = mock_llm_ref.requests.lock().unwrap();
+    if let MessageContent::Text(content) = &locked_requests[2].messages[0].content {
+        assert!(content.contains("Error: The settings of the user deny this command"));
+    } else {
+        panic!("Expected text content in message");
+    }
+
+    // Every decision was recorded, in order
+    let decisions: Vec<_> = PermissionAuditLog::load(&audit_path)?
+        .into_iter()
+        .map(|entry| entry.decision)
+        .collect();
+    assert_eq!(
+        decisions,
+        vec![
+            AuditDecision::AllowedByRule,
+            AuditDecision::DeniedByRule,
+            AuditDecision::User(PermissionDecision::Denied),
+        ]
+    );
+
+    Ok(())
+}
+
+// Mock forge with a single pull request, records the pull requests opened
+#[derive(Default, Clone)]
+struct MockForge {
+    created: Arc<Mutex<Vec<String>>>,
+}
+
+#[async_trait]
+impl Forge for MockForge {
+    fn repository(&self) -> String {
+        "github.com/owner/repo".to_string()
+    }
+
+    async fn issue(&self, number: u64) -> Result<Issue> {
+        anyhow::bail!("Issue #{} not found", number)
+    }
+
+    async fn pull_request(&self, number: u64) -> Result<PullRequest> {
+        Ok(PullRequest {
+            issue: Issue {
+                number,
+                title: "Fix the parser".to_string(),
+                body: "Fixes #3".to_string(),
+                state: "open".to_string(),
+                author: "octocat".to_string(),
+                comments: Vec::new(),
+            },
+            head: "fix-parser".to_string(),
+            base: "main".to_string(),
+            diff: "+fixed\n".to_string(),
+        })
+    }
+
+    async fn create_pull_request(
+        &self,
+        head: &str,
+        base: Option<&str>,
+        title: &str,
+        _body: &str,
+    ) -> Result<String> {
+        self.created.lock().unwrap().push(format!(
+            "{} from {} into {}",
+            title,
+            head,
+            base.unwrap_or("the default branch")
+        ));
+        Ok("https://github.com/owner/repo/pull/8".to_string())
+    }
+}
+
+#[tokio::test]
+async fn test_forge_tools() -> Result<()> {
+    let mock_llm = MockLLMProvider::new(vec![
+        Ok(create_test_response(
+            Tool::CreatePullRequest {
+                title: "Address the review".to_string(),
+                body: "Adds a test".to_string(),
+                base: None,
+            },
+            "Opening a pull request",
+        )),
+        Ok(create_test_response(
+            Tool::FetchIssue { number: 3 },
+            "Reading the issue",
+        )),
+        Ok(create_test_response(
+            Tool::FetchPullRequest { number: 7 },
+            "Reading the pull request",
+        )),
+    ]);
+    let mock_llm_ref = mock_llm.clone();
+    let command_executor = MockCommandExecutor::new(vec![Ok(CommandOutput {
+        success: true,
+        stdout: "fix-parser\n".to_string(),
+        stderr: "".to_string(),
+    })]);
+    let permissions = MockPermissionMediator::default();
+    permissions
+        .decisions
+        .lock()
+        .unwrap()
+        .push(PermissionDecision::Granted);
+    let forge = MockForge::default();
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(create_explorer_mock()),
+        Box::new(command_executor),
+        Box::new(MockUI::default()),
+        Box::new(MockStatePersistence::new()),
+    );
+    agent.set_permission_mediator(Box::new(permissions.clone()));
+    agent.set_forge(Box::new(forge.clone()));
+
+    agent.start_with_task("Test task".to_string()).await?;
+
+    let locked_requests = mock_llm_ref.requests.lock().unwrap();
+    if let MessageContent::Text(content) = &locked_requests[1].messages[0].content {
+        assert!(content.contains("#7 Fix the parser (open, opened by octocat)"));
+        assert!(content.contains("Merges fix-parser into main"));
+    } else {
+        panic!("Expected text content in message");
+    }
+    if let MessageContent::Text(content) = &locked_requests[2].messages[0].content {
+        assert!(content.contains("Error: Failed to fetch issue #3: Issue #3 not found"));
+    } else {
+        panic!("Expected text content in message");
+    }
+
+    // Only opening the pull request needs permission, from the current branch
+    let requests = permissions.requests.lock().unwrap();
+    assert_eq!(requests.len(), 1);
+    assert!(matches!(
+        &requests[0].reason,
+        PermissionRequestReason::Publish { description } if description == "Pull request \"Address the review\""
+    ));
+    assert_eq!(
+        *forge.created.lock().unwrap(),
+        vec!["Address the review from fix-parser into the default branch"]
+    );
+
+    Ok(())
+}
+
+// Mock tracker knowing a single ticket
+struct MockTracker;
+
+#[async_trait]
+impl Tracker for MockTracker {
+    fn description(&self) -> String {
+        "Jira at https://example.atlassian.net".to_string()
+    }
+
+    async fn ticket(&self, key: &str) -> Result<Ticket> {
+        if key != "PROJ-1234" {
+            anyhow::bail!("Issue does not exist");
+        }
+        Ok(Ticket {
+            key: key.to_string(),
+            title: "Export as CSV".to_string(),
+            status: "To Do".to_string(),
+            description: "Users want to export reports".to_string(),
+            acceptance_criteria: Some("Has a header row".to_string()),
+            comments: Vec::new(),
+        })
+    }
+}
+
+#[tokio::test]
+async fn test_fetch_ticket() -> Result<()> {
+    let mock_llm = MockLLMProvider::new(vec![
+        Ok(create_test_response(
+            Tool::FetchTicket {
+                key: "PROJ-99".to_string(),
+            },
+            "Reading the related ticket",
+        )),
+        Ok(create_test_response(
+            Tool::FetchTicket {
+                key: "PROJ-1234".to_string(),
+            },
+            "Reading the ticket",
+        )),
+    ]);
+    let mock_llm_ref = mock_llm.clone();
+
+    let mut agent = Agent::new(
+        Box::new(mock_llm),
+        Box::new(create_explorer_mock()),
+        Box::new(create_command_executor_mock()),
+        Box::new(MockUI::default()),
+        Box::new(MockStatePersistence::new()),
+    );
+    agent.set_tracker(Box::new(MockTracker));
+
+    agent.start_with_task("Implement PROJ-1234".to_string()).await?;
+
+    let locked_requests = mock_llm_ref.requests.lock().unwrap();
+    if let MessageContent::Text(content) = &locked_requests[1].messages[0].content {
+        assert!(content.contains("PROJ-1234 Export as CSV (To Do)"));
+        assert!(content.contains("Acceptance criteria:\nHas a header row"));
+    } else {
+        panic!("Expected text content in message");
+    }
+    if let MessageContent::Text(content) = &locked_requests[2].messages[0].content {
+        assert!(content.contains("Error: Failed to fetch ticket PROJ-99: Issue does not exist"));
+    } else {
+        panic!("Expected text content in message");
+    }
+
+    Ok(())
+}
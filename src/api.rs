@@ -0,0 +1,408 @@
+use crate::auth::local::LocalAuth;
+use crate::ci::CiUI;
+use crate::llm::Usage;
+use crate::persistence::{Session, SessionStore};
+use crate::settings::Settings;
+use crate::ui::pipe::PipeUI;
+use crate::{create_agent, permission_mode, RunOptions};
+use anyhow::{Context, Result};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::Deserialize;
+use serde_json::json;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// Model name standing for the default model of the project
+const DEFAULT_MODEL: &str = "code-assistant";
+
+#[derive(Debug, Deserialize)]
+struct ChatRequest {
+    #[serde(default)]
+    model: Option<String>,
+    messages: Vec<ChatMessage>,
+    #[serde(default)]
+    stream: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatMessage {
+    role: String,
+    /// Text, or a list of parts of which only the text parts are used
+    #[serde(default)]
+    content: serde_json::Value,
+}
+
+impl ChatMessage {
+    fn text(&self) -> String {
+        match &self.content {
+            serde_json::Value::String(text) => text.clone(),
+            serde_json::Value::Array(parts) => parts
+                .iter()
+                .filter_map(|part| part["text"].as_str())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            _ => String::new(),
+        }
+    }
+}
+
+struct State {
+    root_path: PathBuf,
+    auth: LocalAuth,
+    /// Runs work in the same project directory, so they take turns
+    running: Mutex<()>,
+}
+
+/// Serves an OpenAI-compatible `/v1/chat/completions` endpoint on localhost, each request
+/// runs the agent on the project without saving a session. The answer is the completion
+/// message of the agent. Clients authenticate like for the daemon.
+pub async fn run(root_path: PathBuf, port: u16, auth: LocalAuth) -> Result<()> {
+    let state = Arc::new(State {
+        root_path,
+        auth,
+        running: Mutex::new(()),
+    });
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let state = state.clone();
+                async move { Ok::<_, Infallible>(handle(state, request).await) }
+            }))
+        }
+    });
+    let address = SocketAddr::from(([127, 0, 0, 1], port));
+    let server = Server::try_bind(&address)
+        .with_context(|| format!("Failed to listen on port {}", port))?
+        .serve(make_service);
+    info!("Serving the OpenAI-compatible API on http://{}/v1", address);
+    server.await?;
+    Ok(())
+}
+
+async fn handle(state: Arc<State>, request: Request<Body>) -> Response<Body> {
+    let header = |name: &str| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    // Only bearer tokens, tokens in the URL would end up in logs and histories
+    if let Err(reason) = state
+        .auth
+        .check(header("Origin"), header("Authorization"), None)
+    {
+        warn!("Rejected API request: {}", reason);
+        return if reason == "Origin not allowed" {
+            error_response(StatusCode::FORBIDDEN, "permission_error", reason)
+        } else {
+            error_response(StatusCode::UNAUTHORIZED, "invalid_api_key", reason)
+        };
+    }
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/v1/models") => models(&state),
+        (&Method::POST, "/v1/chat/completions") => {
+            // Web pages can send other content types without a preflight request
+            let is_json = header("Content-Type")
+                .and_then(|value| value.split(';').next())
+                .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"));
+            if !is_json {
+                return error_response(
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    "invalid_request_error",
+                    "Content-Type must be application/json",
+                );
+            }
+            let body = match hyper::body::to_bytes(request.into_body()).await {
+                Ok(body) => body,
+                Err(e) => {
+                    return error_response(
+                        StatusCode::BAD_REQUEST,
+                        "invalid_request_error",
+                        &e.to_string(),
+                    )
+                }
+            };
+            let request: ChatRequest = match serde_json::from_slice(&body) {
+                Ok(request) => request,
+                Err(e) => {
+                    return error_response(
+                        StatusCode::BAD_REQUEST,
+                        "invalid_request_error",
+                        &format!("Invalid request: {}", e),
+                    )
+                }
+            };
+            chat_completion(&state, request).await
+        }
+        _ => error_response(StatusCode::NOT_FOUND, "not_found", "Unknown endpoint"),
+    }
+}
+
+/// The default model and the model aliases of the settings
+fn models(state: &State) -> Response<Body> {
+    let aliases = Settings::load_for_project(&state.root_path)
+        .map(|settings| settings.models.aliases.into_keys().collect::<Vec<_>>())
+        .unwrap_or_default();
+    let data: Vec<_> = std::iter::once(DEFAULT_MODEL.to_string())
+        .chain(aliases)
+        .map(|id| json!({ "id": id, "object": "model", "owned_by": "code-assistant" }))
+        .collect();
+    json_response(StatusCode::OK, &json!({ "object": "list", "data": data }))
+}
+
+async fn chat_completion(state: &State, request: ChatRequest) -> Response<Body> {
+    let task = match task_from_messages(&request.messages) {
+        Ok(task) => task,
+        Err(e) => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "invalid_request_error",
+                &e.to_string(),
+            )
+        }
+    };
+    let model = request
+        .model
+        .filter(|model| model != DEFAULT_MODEL && !model.is_empty());
+    let _running = state.running.lock().await;
+    match run_agent(&state.root_path, task, model.clone()).await {
+        Ok((answer, usage)) => {
+            let model = model.as_deref().unwrap_or(DEFAULT_MODEL);
+            let id = format!("chatcmpl-{}", chrono::Utc::now().timestamp_millis());
+            if request.stream {
+                let body = completion_chunks(&id, model, &answer)
+                    .iter()
+                    .map(|chunk| format!("data: {}\n\n", chunk))
+                    .chain(std::iter::once("data: [DONE]\n\n".to_string()))
+                    .collect::<String>();
+                Response::builder()
+                    .header("Content-Type", "text/event-stream")
+                    .body(Body::from(body))
+                    .unwrap_or_default()
+            } else {
+                json_response(StatusCode::OK, &completion(&id, model, &answer, &usage))
+            }
+        }
+        Err(e) => error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "agent_error",
+            &format!("{:#}", e),
+        ),
+    }
+}
+
+/// Runs the agent on the task, returns its answer and the tokens it used
+async fn run_agent(
+    root_path: &std::path::Path,
+    task: String,
+    model: Option<String>,
+) -> Result<(String, Usage)> {
+    let settings = Settings::load_for_project(root_path)?;
+    let model = model.or_else(|| settings.models.default_for(root_path).cloned());
+    let (provider, model) = settings.models.resolve(None, model);
+    let mut session = Session::new(root_path.to_path_buf());
+    session
+        .metadata
+        .llm_config
+        .apply_overrides(provider, model, None);
+    // Nobody answers questions or permission requests of the agent
+    let ui = CiUI::new(Box::new(PipeUI));
+    let mode = permission_mode(None, false, &settings);
+    let mut agent = create_agent(
+        settings,
+        root_path,
+        SessionStore::default_location()?,
+        session,
+        Box::new(ui.clone()),
+        Box::new(ui),
+        RunOptions {
+            mode,
+            dry_run: false,
+            self_review: false,
+            ephemeral: true,
+        },
+    )?;
+    agent.start_with_task(task).await?;
+    let answer = agent
+        .completion_message()
+        .unwrap_or("The agent stopped without completing the task")
+        .to_string();
+    Ok((answer, *agent.usage()))
+}
+
+/// The last message of the user is the task, earlier messages are its context
+fn task_from_messages(messages: &[ChatMessage]) -> Result<String> {
+    let Some(last) = messages.iter().rposition(|message| message.role == "user") else {
+        anyhow::bail!("The messages contain no user message");
+    };
+    let task = messages[last].text();
+    if task.trim().is_empty() {
+        anyhow::bail!("The last user message is empty");
+    }
+    let context: Vec<String> = messages[..last]
+        .iter()
+        .map(|message| format!("{}: {}", message.role, message.text()))
+        .collect();
+    if context.is_empty() {
+        return Ok(task);
+    }
+    Ok(format!(
+        "{}\n\nThe conversation so far:\n{}",
+        task,
+        context.join("\n\n")
+    ))
+}
+
+fn completion(id: &str, model: &str, answer: &str, usage: &Usage) -> serde_json::Value {
+    json!({
+        "id": id,
+        "object": "chat.completion",
+        "created": chrono::Utc::now().timestamp(),
+        "model": model,
+        "choices": [{
+            "index": 0,
+            "message": { "role": "assistant", "content": answer },
+            "finish_reason": "stop",
+        }],
+        "usage": {
+            "prompt_tokens": usage.input_tokens,
+            "completion_tokens": usage.output_tokens,
+            "total_tokens": usage.input_tokens + usage.output_tokens,
+        },
+    })
+}
+
+/// The answer as stream for clients which ask for one, it comes in a single chunk
+/// since the agent only answers at the end of its run
+fn completion_chunks(id: &str, model: &str, answer: &str) -> Vec<serde_json::Value> {
+    let chunk = |delta: serde_json::Value, finish_reason: Option<&str>| {
+        json!({
+            "id": id,
+            "object": "chat.completion.chunk",
+            "created": chrono::Utc::now().timestamp(),
+            "model": model,
+            "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+        })
+    };
+    vec![
+        chunk(json!({ "role": "assistant", "content": answer }), None),
+        chunk(json!({}), Some("stop")),
+    ]
+}
+
+fn json_response(status: StatusCode, value: &serde_json::Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(value.to_string()))
+        .unwrap_or_default()
+}
+
+fn error_response(status: StatusCode, error_type: &str, message: &str) -> Response<Body> {
+    json_response(
+        status,
+        &json!({ "error": { "message": message, "type": error_type } }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(role: &str, content: serde_json::Value) -> ChatMessage {
+        ChatMessage {
+            role: role.to_string(),
+            content,
+        }
+    }
+
+    #[test]
+    fn test_task_from_messages() {
+        assert_eq!(
+            task_from_messages(&[message("user", json!("Explain main.rs"))]).unwrap(),
+            "Explain main.rs"
+        );
+        let task = task_from_messages(&[
+            message("system", json!("Answer briefly")),
+            message("user", json!("Explain main.rs")),
+            message("assistant", json!("It parses the arguments")),
+            message("user", json!([{ "type": "text", "text": "And then?" }])),
+        ])
+        .unwrap();
+        assert_eq!(
+            task,
+            "And then?\n\nThe conversation so far:\nsystem: Answer briefly\n\n\
+             user: Explain main.rs\n\nassistant: It parses the arguments"
+        );
+        assert!(task_from_messages(&[message("system", json!("Answer briefly"))]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rejected_requests() {
+        let state = Arc::new(State {
+            root_path: PathBuf::from("/nonexistent"),
+            auth: LocalAuth::new(
+                "secret".to_string(),
+                vec!["http://localhost:3000".to_string()],
+            ),
+            running: Mutex::new(()),
+        });
+        let request = |headers: &[(&str, &str)]| {
+            let mut builder = Request::post("/v1/chat/completions");
+            for (name, value) in headers {
+                builder = builder.header(*name, *value);
+            }
+            builder.body(Body::from("{}")).unwrap()
+        };
+        let status = |headers: &[(&str, &str)]| {
+            let state = state.clone();
+            let request = request(headers);
+            async move { handle(state, request).await.status() }
+        };
+        let auth = ("Authorization", "Bearer secret");
+        let json = ("Content-Type", "application/json");
+        assert_eq!(status(&[json]).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            status(&[("Authorization", "Bearer secre"), json]).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(&[auth, json, ("Origin", "https://evil.example")]).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(&[auth, ("Content-Type", "text/plain")]).await,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        assert_eq!(status(&[auth]).await, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        // Passes the checks, but the body misses the messages
+        assert_eq!(
+            status(&[auth, ("Content-Type", "application/json; charset=utf-8")]).await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn test_completion() {
+        let usage = Usage {
+            input_tokens: 100,
+            output_tokens: 20,
+        };
+        let completion = completion("chatcmpl-1", "fast", "It parses the arguments", &usage);
+        assert_eq!(
+            completion["choices"][0]["message"]["content"],
+            "It parses the arguments"
+        );
+        assert_eq!(completion["usage"]["total_tokens"], 120);
+
+        let chunks = completion_chunks("chatcmpl-1", "fast", "Done");
+        assert_eq!(chunks[0]["choices"][0]["delta"]["content"], "Done");
+        assert_eq!(chunks[1]["choices"][0]["finish_reason"], "stop");
+    }
+}
@@ -0,0 +1,40 @@
+use crate::settings::config_profile;
+use anyhow::{Context, Result};
+use keyring::Entry;
+
+/// Service name of the entries in the credential store
+const SERVICE: &str = "code-assistant";
+
+/// Entry of a provider or forge, by its name
+fn entry(name: &str) -> Result<Entry> {
+    // Configuration profiles don't share their keys
+    let service = match config_profile() {
+        Some(profile) => format!("{}:{}", SERVICE, profile),
+        None => SERVICE.to_string(),
+    };
+    Entry::new(&service, name).context("Failed to access the OS credential store")
+}
+
+/// Reads the API key or token stored for a name, `None` if none is stored
+pub fn get(name: &str) -> Result<Option<String>> {
+    match entry(name)?.get_password() {
+        Ok(key) => Ok(Some(key)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(e).context("Failed to read from the OS credential store"),
+    }
+}
+
+pub fn set(name: &str, key: &str) -> Result<()> {
+    entry(name)?
+        .set_password(key)
+        .context("Failed to write to the OS credential store")
+}
+
+/// Removes the API key or token stored for a name, returns false if none was stored
+pub fn delete(name: &str) -> Result<bool> {
+    match entry(name)?.delete_credential() {
+        Ok(()) => Ok(true),
+        Err(keyring::Error::NoEntry) => Ok(false),
+        Err(e) => Err(e).context("Failed to delete from the OS credential store"),
+    }
+}
@@ -0,0 +1,145 @@
+use crate::settings::config_dir;
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Environment variable with the token clients of the local APIs send, instead of the
+/// token file
+pub const API_KEY_VAR: &str = "CODE_ASSISTANT_API_KEY";
+
+/// Access to the daemon and the OpenAI-compatible API on localhost. Any web page the user
+/// opens can send requests to localhost, so clients need a token and browsers are only
+/// let in from the origins allowed in the settings.
+#[derive(Debug, Clone)]
+pub struct LocalAuth {
+    token: String,
+    allowed_origins: Vec<String>,
+}
+
+impl LocalAuth {
+    pub fn new(token: String, allowed_origins: Vec<String>) -> Self {
+        Self {
+            token,
+            allowed_origins,
+        }
+    }
+
+    /// Uses the token of `CODE_ASSISTANT_API_KEY`, or of the token file in the config
+    /// directory which is created on first use
+    pub fn load(allowed_origins: Vec<String>) -> Result<Self> {
+        let token = match std::env::var(API_KEY_VAR) {
+            Ok(token) if !token.is_empty() => token,
+            _ => read_or_create_token(&token_path()?)?,
+        };
+        Ok(Self::new(token, allowed_origins))
+    }
+
+    /// Checks the `Origin` and the token of a request, returns the reason for rejecting it.
+    /// The token is sent as bearer token, or as `token` query parameter by browsers which
+    /// can't set headers for WebSockets.
+    pub fn check(
+        &self,
+        origin: Option<&str>,
+        authorization: Option<&str>,
+        query: Option<&str>,
+    ) -> Result<(), &'static str> {
+        if let Some(origin) = origin {
+            if !self.allowed_origins.iter().any(|allowed| allowed == origin) {
+                return Err("Origin not allowed");
+            }
+        }
+        let bearer = authorization.and_then(|value| value.strip_prefix("Bearer "));
+        let from_query = query.and_then(|query| {
+            query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("token="))
+        });
+        match bearer.or(from_query) {
+            Some(token) if constant_time_eq(token.as_bytes(), self.token.as_bytes()) => Ok(()),
+            _ => Err("Invalid API key"),
+        }
+    }
+}
+
+/// File with the token of the local APIs
+pub fn token_path() -> Result<PathBuf> {
+    Ok(config_dir()?.join("api-token"))
+}
+
+/// Reads the token, or creates a random one readable only by the user
+fn read_or_create_token(path: &Path) -> Result<String> {
+    if let Ok(token) = std::fs::read_to_string(path) {
+        if !token.trim().is_empty() {
+            return Ok(token.trim().to_string());
+        }
+    }
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes).context("Failed to generate an API token")?;
+    let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(token.as_bytes()))
+        .with_context(|| format!("Failed to write API token {}", path.display()))?;
+    Ok(token)
+}
+
+/// Compares without returning early, so the time taken doesn't tell how much matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_token_file() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("api-token");
+        let token = read_or_create_token(&path)?;
+        assert_eq!(token.len(), 64);
+        assert_eq!(read_or_create_token(&path)?, token);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path)?.permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_check() {
+        let auth = LocalAuth::new(
+            "secret".to_string(),
+            vec!["http://localhost:3000".to_string()],
+        );
+        assert_eq!(auth.check(None, Some("Bearer secret"), None), Ok(()));
+        assert_eq!(auth.check(None, None, Some("a=1&token=secret")), Ok(()));
+        assert_eq!(
+            auth.check(Some("http://localhost:3000"), None, Some("token=secret")),
+            Ok(())
+        );
+        assert_eq!(auth.check(None, None, None), Err("Invalid API key"));
+        assert_eq!(
+            auth.check(None, Some("Bearer secre"), None),
+            Err("Invalid API key")
+        );
+        // Web pages can't use the token even if they know it
+        assert_eq!(
+            auth.check(Some("https://evil.example"), Some("Bearer secret"), None),
+            Err("Origin not allowed")
+        );
+    }
+}
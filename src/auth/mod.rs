@@ -0,0 +1,104 @@
+pub mod keyring;
+pub mod local;
+
+use crate::forge::ForgeType;
+use crate::llm::LLMProviderType;
+use crate::tracker::TrackerType;
+use anyhow::Result;
+use clap::ValueEnum;
+use tracing::{debug, warn};
+
+/// Environment variable holding the API key of a provider, `None` if it needs no key
+pub fn env_var(provider: LLMProviderType) -> Option<&'static str> {
+    match provider {
+        LLMProviderType::Anthropic => Some("ANTHROPIC_API_KEY"),
+        LLMProviderType::OpenAI => Some("OPENAI_API_KEY"),
+        LLMProviderType::Ollama => None,
+    }
+}
+
+/// Where the API key of a provider was found
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeySource {
+    Environment,
+    Keyring,
+}
+
+/// Looks up the API key of a provider, the environment variable takes precedence
+/// over the OS credential store. An unavailable credential store counts as empty.
+pub fn api_key(provider: LLMProviderType) -> Option<(String, KeySource)> {
+    let var = env_var(provider)?;
+    if let Ok(key) = std::env::var(var) {
+        return Some((key, KeySource::Environment));
+    }
+    debug!("{} not set, looking up the OS credential store", var);
+    match keyring::get(provider.name()) {
+        Ok(key) => key.map(|key| (key, KeySource::Keyring)),
+        Err(e) => {
+            warn!("{:#}", e);
+            None
+        }
+    }
+}
+
+/// Like `api_key`, but fails with instructions if no key is found
+pub fn require_api_key(provider: LLMProviderType) -> Result<String> {
+    let var = env_var(provider).unwrap_or_default();
+    match api_key(provider) {
+        Some((key, _)) => Ok(key),
+        None => anyhow::bail!(
+            "No API key for {}: set {} or store a key with `code-assistant auth set {}`",
+            provider.name(),
+            var,
+            provider
+                .to_possible_value()
+                .map_or(String::new(), |value| value.get_name().to_string())
+        ),
+    }
+}
+
+/// Service besides the LLM providers an access token is stored for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenService {
+    Forge(ForgeType),
+    Tracker(TrackerType),
+}
+
+impl TokenService {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TokenService::Forge(forge) => forge.name(),
+            TokenService::Tracker(tracker) => tracker.name(),
+        }
+    }
+
+    pub fn env_var(&self) -> &'static str {
+        match self {
+            TokenService::Forge(forge) => forge.env_var(),
+            TokenService::Tracker(tracker) => tracker.env_var(),
+        }
+    }
+
+    pub fn token(&self) -> Option<String> {
+        match self {
+            TokenService::Forge(forge) => forge.token(),
+            TokenService::Tracker(tracker) => tracker.token(),
+        }
+    }
+}
+
+impl ValueEnum for TokenService {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[
+            TokenService::Forge(ForgeType::GitHub),
+            TokenService::Forge(ForgeType::GitLab),
+            TokenService::Forge(ForgeType::Gitea),
+            TokenService::Tracker(TrackerType::Jira),
+            TokenService::Tracker(TrackerType::Linear),
+        ]
+    }
+
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        Some(clap::builder::PossibleValue::new(self.name()))
+    }
+}
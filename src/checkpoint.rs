@@ -0,0 +1,317 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::debug;
+
+/// Snapshot of the project files, taken before the agent modified them
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    /// Number of the checkpoint within its session, starting at 1
+    pub number: usize,
+    /// Shadow commit holding the snapshot
+    pub commit: String,
+    pub description: String,
+}
+
+pub trait CheckpointStore: Send + Sync {
+    /// Takes a snapshot of the current project files
+    fn create(&self, description: &str) -> Result<Checkpoint>;
+    /// Lists the checkpoints, oldest first
+    fn list(&self) -> Result<Vec<Checkpoint>>;
+    /// Restores the project files of a checkpoint, files created after it are removed
+    fn restore(&self, number: usize) -> Result<Checkpoint>;
+    /// Diff of the current project files against a checkpoint
+    fn diff(&self, number: usize) -> Result<String>;
+}
+
+/// Stores checkpoints of a session as shadow commits in the git repository of the project.
+/// The commits are referenced by `refs/code-assistant/<session>/<number>` and neither touch
+/// the branches nor the index of the repository. If the project is a subdirectory of the
+/// repository, only the files inside of it are part of the snapshots.
+pub struct GitCheckpoints {
+    root_dir: PathBuf,
+    session_id: String,
+    ref_prefix: String,
+}
+
+impl GitCheckpoints {
+    /// Returns `None` if the project is not a git repository
+    pub fn new(root_dir: &Path, session_id: &str) -> Option<Self> {
+        let checkpoints = Self {
+            root_dir: root_dir.to_path_buf(),
+            session_id: session_id.to_string(),
+            ref_prefix: format!("refs/code-assistant/{}/", session_id),
+        };
+        match checkpoints.git(&["rev-parse", "--is-inside-work-tree"]) {
+            Ok(output) if output == "true" => Some(checkpoints),
+            _ => None,
+        }
+    }
+
+    fn git(&self, args: &[&str]) -> Result<String> {
+        self.git_with_index(args, None)
+    }
+
+    /// Runs git, optionally with a separate index file to leave the user's index alone
+    fn git_with_index(&self, args: &[&str], index_file: Option<&Path>) -> Result<String> {
+        let mut command = Command::new("git");
+        command.args(args).current_dir(&self.root_dir);
+        if let Some(index_file) = index_file {
+            command.env("GIT_INDEX_FILE", index_file);
+        }
+        // Shadow commits must not depend on the user's git identity
+        for var in ["GIT_AUTHOR", "GIT_COMMITTER"] {
+            command
+                .env(format!("{}_NAME", var), "code-assistant")
+                .env(format!("{}_EMAIL", var), "code-assistant@localhost");
+        }
+
+        let output = command.output().context("Failed to run git")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    /// Index of the session used for snapshots, inside the git directory. It is kept
+    /// between snapshots, so that `git add` only hashes the files changed since the last one.
+    fn index_file(&self) -> Result<PathBuf> {
+        let name = format!("code-assistant-index-{}", self.session_id);
+        let path = self.git(&["rev-parse", "--git-path", &name])?;
+        Ok(self.root_dir.join(path))
+    }
+
+    /// Writes the current state of all non-ignored project files into a tree object
+    fn write_tree(&self) -> Result<String> {
+        let index_file = self.index_file()?;
+        self.git_with_index(&["add", "-A", "."], Some(&index_file))?;
+        self.git_with_index(&["write-tree"], Some(&index_file))
+    }
+
+    /// Removes the checkpoints of the session and its index
+    pub fn remove(&self) -> Result<()> {
+        for checkpoint in self.list()? {
+            self.git(&[
+                "update-ref",
+                "-d",
+                &format!("{}{}", self.ref_prefix, checkpoint.number),
+            ])?;
+        }
+        let index_file = self.index_file()?;
+        if index_file.exists() {
+            std::fs::remove_file(&index_file)?;
+        }
+        Ok(())
+    }
+
+    fn tree_of(&self, commit: &str) -> Result<String> {
+        self.git(&["rev-parse", &format!("{}^{{tree}}", commit)])
+    }
+
+    fn find(&self, number: usize) -> Result<Checkpoint> {
+        self.list()?
+            .into_iter()
+            .find(|c| c.number == number)
+            .with_context(|| format!("Checkpoint {} not found", number))
+    }
+}
+
+impl CheckpointStore for GitCheckpoints {
+    fn create(&self, description: &str) -> Result<Checkpoint> {
+        let tree = self.write_tree()?;
+        let checkpoints = self.list()?;
+
+        // Nothing changed since the last checkpoint
+        if let Some(latest) = checkpoints.last() {
+            if self.tree_of(&latest.commit)? == tree {
+                debug!("Project unchanged since checkpoint {}", latest.number);
+                return Ok(latest.clone());
+            }
+        }
+
+        let mut args = vec!["commit-tree", tree.as_str(), "-m", description];
+        let head = self.git(&["rev-parse", "--verify", "--quiet", "HEAD"]).ok();
+        if let Some(head) = &head {
+            args.extend(["-p", head.as_str()]);
+        }
+        let commit = self.git(&args)?;
+
+        let number = checkpoints.last().map_or(1, |c| c.number + 1);
+        self.git(&[
+            "update-ref",
+            &format!("{}{}", self.ref_prefix, number),
+            &commit,
+        ])?;
+        debug!("Created checkpoint {} ({})", number, commit);
+
+        Ok(Checkpoint {
+            number,
+            commit,
+            description: description.to_string(),
+        })
+    }
+
+    fn list(&self) -> Result<Vec<Checkpoint>> {
+        let output = self.git(&[
+            "for-each-ref",
+            "--format=%(refname) %(objectname) %(contents:subject)",
+            &self.ref_prefix,
+        ])?;
+
+        let mut checkpoints: Vec<_> = output
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.splitn(3, ' ');
+                let number = parts.next()?.strip_prefix(&self.ref_prefix)?.parse().ok()?;
+                Some(Checkpoint {
+                    number,
+                    commit: parts.next()?.to_string(),
+                    description: parts.next().unwrap_or("").to_string(),
+                })
+            })
+            .collect();
+        checkpoints.sort_by_key(|c| c.number);
+        Ok(checkpoints)
+    }
+
+    fn restore(&self, number: usize) -> Result<Checkpoint> {
+        let checkpoint = self.find(number)?;
+
+        // Remove files which did not exist when the checkpoint was taken,
+        // with paths relative to the project instead of the repository
+        let current_tree = self.write_tree()?;
+        let added = self.git(&[
+            "diff-tree",
+            "-r",
+            "--relative",
+            "--name-only",
+            "--diff-filter=A",
+            &checkpoint.commit,
+            &current_tree,
+        ])?;
+        for path in added.lines() {
+            std::fs::remove_file(self.root_dir.join(path))
+                .with_context(|| format!("Failed to remove {}", path))?;
+        }
+
+        // Check out the files of the checkpoint through the session's index,
+        // `checkout-index -a` only writes the files of the project directory
+        let index_file = self.index_file()?;
+        self.git_with_index(&["read-tree", &checkpoint.commit], Some(&index_file))?;
+        self.git_with_index(&["checkout-index", "-a", "-f", "-u"], Some(&index_file))?;
+
+        Ok(checkpoint)
+    }
+
+    fn diff(&self, number: usize) -> Result<String> {
+        let checkpoint = self.find(number)?;
+        let current_tree = self.write_tree()?;
+        self.git(&["diff", "--relative", &checkpoint.commit, &current_tree])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn create_repository() -> Result<TempDir> {
+        let temp_dir = TempDir::new()?;
+        Command::new("git")
+            .args(["init", "-q"])
+            .current_dir(temp_dir.path())
+            .status()?;
+        std::fs::write(temp_dir.path().join("main.rs"), "fn main() {}\n")?;
+        Ok(temp_dir)
+    }
+
+    #[test]
+    fn test_restore_checkpoint() -> Result<()> {
+        let temp_dir = create_repository()?;
+        let root = temp_dir.path();
+        let checkpoints = GitCheckpoints::new(root, "session").unwrap();
+
+        let checkpoint = checkpoints.create("Before action 1")?;
+        assert_eq!(checkpoint.number, 1);
+        // Unchanged files don't result in another checkpoint
+        assert_eq!(checkpoints.create("Before action 2")?, checkpoint);
+
+        std::fs::write(root.join("main.rs"), "fn main() { broken }\n")?;
+        std::fs::write(root.join("new.rs"), "// new file\n")?;
+        assert_eq!(checkpoints.create("Before action 3")?.number, 2);
+        // The index is kept for the next snapshot
+        assert!(root
+            .join(".git")
+            .join("code-assistant-index-session")
+            .exists());
+
+        let diff = checkpoints.diff(1)?;
+        assert!(diff.contains("+fn main() { broken }"));
+        assert!(diff.contains("+++ b/new.rs"));
+
+        let restored = checkpoints.restore(1)?;
+        assert_eq!(restored.description, "Before action 1");
+        assert_eq!(
+            std::fs::read_to_string(root.join("main.rs"))?,
+            "fn main() {}\n"
+        );
+        assert!(!root.join("new.rs").exists());
+        assert_eq!(checkpoints.list()?.len(), 2);
+
+        // The user's index was not touched
+        let status = Command::new("git")
+            .args(["status", "--porcelain"])
+            .current_dir(root)
+            .output()?;
+        assert_eq!(String::from_utf8_lossy(&status.stdout), "?? main.rs\n");
+
+        checkpoints.remove()?;
+        assert!(checkpoints.list()?.is_empty());
+        assert!(!root
+            .join(".git")
+            .join("code-assistant-index-session")
+            .exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkpoints_of_subdirectory() -> Result<()> {
+        let temp_dir = create_repository()?;
+        let root = temp_dir.path().join("crates").join("app");
+        std::fs::create_dir_all(root.join("src"))?;
+        std::fs::write(root.join("src").join("lib.rs"), "pub fn app() {}\n")?;
+        let checkpoints = GitCheckpoints::new(&root, "session").unwrap();
+        checkpoints.create("Before action 1")?;
+
+        std::fs::write(root.join("src").join("lib.rs"), "broken\n")?;
+        std::fs::write(root.join("src").join("new.rs"), "// new file\n")?;
+        std::fs::write(temp_dir.path().join("main.rs"), "// outside\n")?;
+        let diff = checkpoints.diff(1)?;
+        assert!(diff.contains("+++ b/src/new.rs"));
+        assert!(!diff.contains("main.rs"));
+
+        checkpoints.restore(1)?;
+        assert_eq!(
+            std::fs::read_to_string(root.join("src").join("lib.rs"))?,
+            "pub fn app() {}\n"
+        );
+        assert!(!root.join("src").join("new.rs").exists());
+        // Files outside of the project are left alone
+        assert_eq!(
+            std::fs::read_to_string(temp_dir.path().join("main.rs"))?,
+            "// outside\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_checkpoints_outside_of_repository() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        assert!(GitCheckpoints::new(temp_dir.path(), "session").is_none());
+        Ok(())
+    }
+}
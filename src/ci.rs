@@ -0,0 +1,207 @@
+use crate::llm::Usage;
+use crate::permissions::{PermissionDecision, PermissionMediator, PermissionRequest};
+use crate::types::{ActionResult, Tool};
+use crate::ui::{UIError, UIEvent, UIMessage, UserInterface};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How a run in CI mode ended, each with its own exit code
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CiOutcome {
+    /// The agent completed the task
+    Completed,
+    /// The run failed with an error, like an unreachable LLM provider
+    Error,
+    /// The agent stopped without completing the task, e.g. after repeated failures
+    NotCompleted,
+    /// A limit of the budget was reached
+    BudgetExceeded,
+}
+
+impl CiOutcome {
+    pub fn new(result: &Result<()>, completed: bool, budget_exceeded: bool) -> Self {
+        if completed {
+            CiOutcome::Completed
+        } else if budget_exceeded {
+            CiOutcome::BudgetExceeded
+        } else if result.is_err() {
+            CiOutcome::Error
+        } else {
+            CiOutcome::NotCompleted
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CiOutcome::Completed => 0,
+            CiOutcome::Error => 1,
+            CiOutcome::NotCompleted => 2,
+            CiOutcome::BudgetExceeded => 3,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CiOutcome::Completed => "completed",
+            CiOutcome::Error => "error",
+            CiOutcome::NotCompleted => "not_completed",
+            CiOutcome::BudgetExceeded => "budget_exceeded",
+        }
+    }
+}
+
+/// Everything the report of a CI run is made of
+pub struct CiRun<'a> {
+    pub session_id: &'a str,
+    pub task: &'a str,
+    pub result: &'a Result<()>,
+    pub actions: &'a [ActionResult],
+    pub budget_exceeded: Option<&'a str>,
+    pub usage: &'a Usage,
+    pub cost: Option<f64>,
+    pub duration: Duration,
+}
+
+/// Machine-readable report of a CI run
+pub fn report(outcome: CiOutcome, run: &CiRun) -> serde_json::Value {
+    let failed_actions = run.actions.iter().filter(|action| !action.success).count();
+    let message = run.actions.last().and_then(|action| match &action.tool {
+        Tool::CompleteTask { message } if action.success => Some(message),
+        _ => None,
+    });
+    json!({
+        "outcome": outcome.name(),
+        "exit_code": outcome.exit_code(),
+        "session_id": run.session_id,
+        "task": run.task,
+        "message": message,
+        "error": run.result.as_ref().err().map(|e| format!("{:#}", e)),
+        "budget_exceeded": run.budget_exceeded,
+        "actions": run.actions.len(),
+        "failed_actions": failed_actions,
+        "input_tokens": run.usage.input_tokens,
+        "output_tokens": run.usage.output_tokens,
+        "cost": run.cost,
+        "duration_secs": run.duration.as_secs_f64(),
+    })
+}
+
+/// Interface for runs without anybody watching: messages and events go to the wrapped
+/// interface, questions fail and permission requests are denied, so only what the
+/// permission policy of the settings allows is executed
+#[derive(Clone)]
+pub struct CiUI {
+    inner: Arc<dyn UserInterface>,
+}
+
+impl CiUI {
+    pub fn new(inner: Box<dyn UserInterface>) -> Self {
+        Self {
+            inner: inner.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl UserInterface for CiUI {
+    async fn display(&self, message: UIMessage) -> Result<(), UIError> {
+        self.inner.display(message).await
+    }
+
+    async fn get_input(&self, _prompt: &str) -> Result<String, UIError> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "Nobody can answer in CI mode, decide on your own",
+        )
+        .into())
+    }
+
+    async fn event(&self, event: UIEvent) -> Result<(), UIError> {
+        self.inner.event(event).await
+    }
+}
+
+#[async_trait]
+impl PermissionMediator for CiUI {
+    async fn request_permission(&self, request: &PermissionRequest) -> Result<PermissionDecision> {
+        self.inner
+            .display(UIMessage::Action(format!(
+                "Permission to use {} denied, the settings don't allow it in CI mode",
+                request.tool
+            )))
+            .await?;
+        Ok(PermissionDecision::Denied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outcome() {
+        let failed = || -> Result<()> { anyhow::bail!("No API key") };
+        assert_eq!(CiOutcome::new(&Ok(()), true, false), CiOutcome::Completed);
+        assert_eq!(CiOutcome::new(&Ok(()), false, true).exit_code(), 3);
+        // Stopping at the budget fails the question whether to continue
+        assert_eq!(
+            CiOutcome::new(&failed(), false, true),
+            CiOutcome::BudgetExceeded
+        );
+        assert_eq!(CiOutcome::new(&failed(), false, false).exit_code(), 1);
+        assert_eq!(CiOutcome::new(&Ok(()), false, false).exit_code(), 2);
+    }
+
+    #[test]
+    fn test_report() {
+        let actions = vec![
+            ActionResult {
+                tool: Tool::ExecuteCommand {
+                    command_line: "cargo test".to_string(),
+                    working_dir: None,
+                },
+                success: false,
+                result: String::new(),
+                error: Some("1 test failed".to_string()),
+                reasoning: String::new(),
+            },
+            ActionResult {
+                tool: Tool::CompleteTask {
+                    message: "Fixed the test".to_string(),
+                },
+                success: true,
+                result: "Task completed".to_string(),
+                error: None,
+                reasoning: String::new(),
+            },
+        ];
+        let usage = Usage {
+            input_tokens: 1000,
+            output_tokens: 200,
+        };
+        let report = report(
+            CiOutcome::Completed,
+            &CiRun {
+                session_id: "session",
+                task: "Fix the test",
+                result: &Ok(()),
+                actions: &actions,
+                budget_exceeded: None,
+                usage: &usage,
+                cost: Some(0.01),
+                duration: Duration::from_secs(90),
+            },
+        );
+        assert_eq!(report["outcome"], "completed");
+        assert_eq!(report["exit_code"], 0);
+        assert_eq!(report["message"], "Fixed the test");
+        assert_eq!(report["actions"], 2);
+        assert_eq!(report["failed_actions"], 1);
+        assert_eq!(report["input_tokens"], 1000);
+        assert_eq!(report["duration_secs"], 90.0);
+        assert!(report["error"].is_null());
+    }
+}
@@ -0,0 +1,475 @@
+use crate::auth::local::LocalAuth;
+use crate::llm::LLMProviderType;
+use crate::metrics;
+use crate::notify::{Notification, Notifier, NotifyingMediator};
+use crate::permissions::{PermissionDecision, PermissionMediator, PermissionRequest};
+use crate::persistence::{Session, SessionStore};
+use crate::schedule::{self, ScheduledTask};
+use crate::settings::Settings;
+use crate::ui::json::{message_json, parse_decision, permission_json, summary_json};
+use crate::ui::{UIError, UIEvent, UIMessage, UserInterface};
+use crate::worktree::Worktree;
+use crate::{create_agent, permission_mode, RunOptions};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::handshake::server::{
+    Callback, ErrorResponse, Request as HandshakeRequest, Response as HandshakeResponse,
+};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, info, warn};
+
+/// Requests of a client, sent as JSON text messages
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+    /// Lists the saved sessions, of one project if a path is given
+    ListSessions { path: Option<PathBuf> },
+    /// Loads a page of the actions of a saved session
+    LoadActions {
+        id: String,
+        #[serde(default)]
+        offset: usize,
+        #[serde(default = "default_page_size")]
+        limit: usize,
+    },
+    /// Starts a new session working on a task
+    StartTask {
+        path: PathBuf,
+        task: String,
+        provider: Option<LLMProviderType>,
+        model: Option<String>,
+        /// Work in a git worktree of its own, see `agent --worktree`
+        #[serde(default)]
+        worktree: bool,
+    },
+    /// Continues an unfinished session
+    ContinueSession { id: String },
+    /// Answers the pending question or permission request of the running session
+    Answer { text: String },
+}
+
+fn default_page_size() -> usize {
+    50
+}
+
+/// Session running for a connection
+struct Run {
+    task: JoinHandle<()>,
+    answers: mpsc::UnboundedSender<String>,
+}
+
+/// Interface of an agent run to the client of a connection: messages and events are
+/// sent as JSON objects like with `--output json`, questions and permission requests
+/// wait for an answer of the client
+#[derive(Clone)]
+struct ChannelUI {
+    events: mpsc::UnboundedSender<serde_json::Value>,
+    answers: Arc<Mutex<mpsc::UnboundedReceiver<String>>>,
+}
+
+impl ChannelUI {
+    fn send(&self, value: serde_json::Value) -> Result<(), UIError> {
+        self.events
+            .send(value)
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe).into())
+    }
+
+    async fn answer(&self) -> Result<String, UIError> {
+        self.answers.lock().await.recv().await.ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Client disconnected").into()
+        })
+    }
+}
+
+#[async_trait]
+impl UserInterface for ChannelUI {
+    async fn display(&self, message: UIMessage) -> Result<(), UIError> {
+        self.send(message_json(&message))
+    }
+
+    async fn get_input(&self, prompt: &str) -> Result<String, UIError> {
+        self.send(json!({ "type": "input_requested", "prompt": prompt }))?;
+        self.answer().await
+    }
+
+    async fn event(&self, event: UIEvent) -> Result<(), UIError> {
+        self.send(serde_json::to_value(event).map_err(std::io::Error::from)?)
+    }
+}
+
+#[async_trait]
+impl PermissionMediator for ChannelUI {
+    async fn request_permission(&self, request: &PermissionRequest) -> Result<PermissionDecision> {
+        self.send(permission_json(request))?;
+        Ok(parse_decision(&self.answer().await?))
+    }
+}
+
+/// Serves the WebSocket API on localhost until the process is stopped. Each connection
+/// can run one session at a time, several connections work in parallel. Plain HTTP
+/// requests for `/metrics` on the same port get the metrics for Prometheus.
+/// The scheduled tasks are started meanwhile.
+pub async fn run(port: u16, schedules: Vec<ScheduledTask>, auth: LocalAuth) -> Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .await
+        .with_context(|| format!("Failed to listen on port {}", port))?;
+    info!("Listening on ws://127.0.0.1:{}", port);
+    schedule::start(schedules);
+
+    let auth = Arc::new(auth);
+    loop {
+        let (stream, address) = listener.accept().await?;
+        debug!("Connection from {}", address);
+        let auth = auth.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &auth).await {
+                warn!("Connection from {} failed: {:#}", address, e);
+            }
+        });
+    }
+}
+
+/// Lets the WebSocket handshake through if the client sent the token and is not a web
+/// page of a foreign origin, which could otherwise start tasks and grant permissions
+struct Authorize<'a>(&'a LocalAuth);
+
+impl Callback for Authorize<'_> {
+    fn on_request(
+        self,
+        request: &HandshakeRequest,
+        response: HandshakeResponse,
+    ) -> Result<HandshakeResponse, ErrorResponse> {
+        let header = |name: &str| {
+            request
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+        };
+        match self.0.check(
+            header("Origin"),
+            header("Authorization"),
+            request.uri().query(),
+        ) {
+            Ok(()) => Ok(response),
+            Err(reason) => {
+                warn!("Rejected WebSocket connection: {}", reason);
+                let mut error = ErrorResponse::new(Some(reason.to_string()));
+                *error.status_mut() = if reason == "Origin not allowed" {
+                    StatusCode::FORBIDDEN
+                } else {
+                    StatusCode::UNAUTHORIZED
+                };
+                Err(error)
+            }
+        }
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, auth: &LocalAuth) -> Result<()> {
+    if is_metrics_request(&stream).await? {
+        let body = metrics::global().render();
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await?;
+        return Ok(());
+    }
+    let websocket = tokio_tungstenite::accept_hdr_async(stream, Authorize(auth)).await?;
+    let (mut sink, mut source) = websocket.split();
+
+    let (events, mut outgoing) = mpsc::unbounded_channel::<serde_json::Value>();
+    let writer = tokio::spawn(async move {
+        while let Some(event) = outgoing.recv().await {
+            if sink.send(Message::Text(event.to_string())).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut run: Option<Run> = None;
+    while let Some(message) = source.next().await {
+        let text = match message? {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        let result = serde_json::from_str(&text)
+            .context("Invalid request")
+            .and_then(|request| handle_request(request, &events, &mut run));
+        if let Err(e) = result {
+            let _ = events.send(json!({ "type": "error", "message": format!("{:#}", e) }));
+        }
+    }
+
+    // The session can be continued later
+    if let Some(run) = run {
+        run.task.abort();
+    }
+    writer.abort();
+    Ok(())
+}
+
+/// Whether the connection asks for the metrics instead of opening a WebSocket, looking
+/// at the request line without consuming it
+async fn is_metrics_request(stream: &TcpStream) -> Result<bool> {
+    const REQUEST_LINE: &[u8] = b"GET /metrics ";
+    let mut buffer = [0; REQUEST_LINE.len()];
+    // Both kinds of requests are GET requests, a WebSocket upgrade goes to another path
+    loop {
+        let read = stream.peek(&mut buffer).await?;
+        if read == 0 || !REQUEST_LINE.starts_with(&buffer[..read]) {
+            return Ok(false);
+        }
+        if read == buffer.len() {
+            return Ok(true);
+        }
+        // Only part of the request line arrived so far
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+fn handle_request(
+    request: Request,
+    events: &mpsc::UnboundedSender<serde_json::Value>,
+    run: &mut Option<Run>,
+) -> Result<()> {
+    let busy = run.as_ref().is_some_and(|run| !run.task.is_finished());
+    match request {
+        Request::ListSessions { path } => {
+            let sessions: Vec<_> = SessionStore::default_location()?
+                .list()?
+                .into_iter()
+                .filter(|session| {
+                    path.as_ref()
+                        .is_none_or(|path| &session.metadata.root_dir == path)
+                })
+                .map(|session| {
+                    json!({
+                        "id": session.metadata.id,
+                        "title": session.title(),
+                        "root_dir": session.metadata.root_dir,
+                        "updated_at": session.metadata.updated_at,
+                        "completed": session.metadata.completed,
+                        "actions": session.action_count,
+                    })
+                })
+                .collect();
+            events.send(json!({ "type": "sessions", "sessions": sessions }))?;
+        }
+        Request::LoadActions { id, offset, limit } => {
+            let store = SessionStore::default_location()?;
+            let actions = store.load_actions(&id, offset, limit)?;
+            events.send(json!({
+                "type": "actions",
+                "id": id,
+                "offset": offset,
+                "actions": actions,
+            }))?;
+        }
+        Request::StartTask {
+            path,
+            task,
+            provider,
+            model,
+            worktree,
+        } => {
+            if busy {
+                anyhow::bail!("A session is already running on this connection");
+            }
+            let root_path = path
+                .canonicalize()
+                .context("Failed to resolve project path")?;
+            let settings = Settings::load_for_project(&root_path)?;
+            // New sessions without a provider or model of their own use the default model
+            let model = match (&provider, model) {
+                (None, None) => settings.models.default_for(&root_path).cloned(),
+                (_, model) => model,
+            };
+            let (provider, model) = settings.models.resolve(provider, model);
+            let mut session = Session::new(root_path.clone());
+            session
+                .metadata
+                .llm_config
+                .apply_overrides(provider, model, None);
+            if worktree {
+                session.metadata.worktree = Some(Worktree::create_in_default_location(
+                    &root_path,
+                    &session.metadata.id,
+                )?);
+            }
+            *run = Some(start(settings, &root_path, session, Some(task), events)?);
+        }
+        Request::ContinueSession { id } => {
+            if busy {
+                anyhow::bail!("A session is already running on this connection");
+            }
+            let session = SessionStore::default_location()?.load(&id)?;
+            let root_path = session.metadata.root_dir.clone();
+            let settings = Settings::load_for_project(&root_path)?;
+            *run = Some(start(settings, &root_path, session, None, events)?);
+        }
+        Request::Answer { text } => match run {
+            Some(run) if busy => run.answers.send(text)?,
+            _ => anyhow::bail!("No session is running on this connection"),
+        },
+    }
+    Ok(())
+}
+
+/// Runs the agent on a session, starting with the task or continuing the saved state
+fn start(
+    settings: Settings,
+    root_path: &Path,
+    session: Session,
+    task: Option<String>,
+    events: &mpsc::UnboundedSender<serde_json::Value>,
+) -> Result<Run> {
+    let (answers, answers_receiver) = mpsc::unbounded_channel();
+    let ui = ChannelUI {
+        events: events.clone(),
+        answers: Arc::new(Mutex::new(answers_receiver)),
+    };
+    let id = session.metadata.id.clone();
+    let mode = permission_mode(None, false, &settings);
+    let notifier = Arc::new(Notifier::new(&settings.notifications));
+    let permissions: Box<dyn PermissionMediator> = if notifier.is_empty() {
+        Box::new(ui.clone())
+    } else {
+        Box::new(NotifyingMediator::new(
+            Box::new(ui.clone()),
+            notifier.clone(),
+            &id,
+            root_path,
+        ))
+    };
+    let mut agent = create_agent(
+        settings,
+        root_path,
+        SessionStore::default_location()?,
+        session,
+        Box::new(ui),
+        permissions,
+        RunOptions {
+            mode,
+            dry_run: false,
+            self_review: false,
+            ephemeral: false,
+        },
+    )?;
+    events.send(json!({ "type": "session_started", "id": id }))?;
+
+    let events = events.clone();
+    let root_path = root_path.to_path_buf();
+    let task = tokio::spawn(async move {
+        let started = Instant::now();
+        let active = metrics::global().session_started();
+        let result = match task {
+            Some(task) => agent.start_with_task(task).await,
+            None => agent.start_from_state().await,
+        };
+        active.ended(match &result {
+            Ok(()) if agent.completed() => "completed",
+            Ok(()) => "not_completed",
+            Err(_) => "error",
+        });
+        notifier
+            .send(&Notification::run_ended(
+                &id,
+                &root_path,
+                agent.task(),
+                &result,
+                agent.completed(),
+                agent.completion_message(),
+                started.elapsed(),
+            ))
+            .await;
+        let mut summary = summary_json(&result, agent.usage(), agent.estimated_cost());
+        summary["session_id"] = json!(id);
+        let _ = events.send(summary);
+    });
+    Ok(Run { task, answers })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_channel_ui() -> Result<()> {
+        let (events, mut outgoing) = mpsc::unbounded_channel();
+        let (answers, answers_receiver) = mpsc::unbounded_channel();
+        let ui = ChannelUI {
+            events,
+            answers: Arc::new(Mutex::new(answers_receiver)),
+        };
+
+        answers.send("granted".to_string())?;
+        let request = PermissionRequest {
+            tool: "ExecuteCommand".to_string(),
+            reason: crate::permissions::PermissionRequestReason::ExecuteCommand {
+                command_line: "cargo test".to_string(),
+                working_dir: PathBuf::from("/project"),
+            },
+        };
+        assert_eq!(
+            ui.request_permission(&request).await?,
+            PermissionDecision::Granted
+        );
+        assert_eq!(
+            outgoing.recv().await.unwrap()["type"],
+            "permission_requested"
+        );
+
+        // Questions fail once the client is gone
+        drop(answers);
+        assert!(ui.get_input("Continue?").await.is_err());
+        assert_eq!(outgoing.recv().await.unwrap()["prompt"], "Continue?");
+        Ok(())
+    }
+
+    #[test]
+    fn test_authorize_handshake() {
+        let auth = LocalAuth::new("secret".to_string(), Vec::new());
+        let handshake = |origin: Option<&str>, uri: &str| {
+            let mut request = HandshakeRequest::builder().uri(uri);
+            if let Some(origin) = origin {
+                request = request.header("Origin", origin);
+            }
+            Authorize(&auth)
+                .on_request(&request.body(()).unwrap(), HandshakeResponse::default())
+                .map(|_| ())
+                .map_err(|error| error.status())
+        };
+        assert_eq!(handshake(None, "/?token=secret"), Ok(()));
+        assert_eq!(handshake(None, "/"), Err(StatusCode::UNAUTHORIZED));
+        assert_eq!(
+            handshake(Some("https://example.com"), "/?token=secret"),
+            Err(StatusCode::FORBIDDEN)
+        );
+    }
+
+    #[test]
+    fn test_answer_without_running_session() {
+        let (events, _outgoing) = mpsc::unbounded_channel();
+        let request = serde_json::from_str(r#"{"type": "answer", "text": "yes"}"#).unwrap();
+        let error = handle_request(request, &events, &mut None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No session is running on this connection"
+        );
+    }
+}
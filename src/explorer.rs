@@ -1,15 +1,22 @@
 use crate::types::{CodeExplorer, FileSystemEntryType, FileTreeEntry, FileUpdate, SearchMode, SearchOptions, SearchResult};
 use anyhow::Result;
 use ignore::WalkBuilder;
-use regex::RegexBuilder;
-use std::collections::HashMap;
+use crate::search_index::{required_trigrams, SearchIndex};
+use regex::{Regex, RegexBuilder};
+use std::collections::{HashMap, HashSet};
 use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
-use tracing::debug;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use tracing::{debug, warn};
+
+/// Number of files read or listed at the same time
+const MAX_PARALLEL_READS: usize = 8;
 
 /// Handles file system operations for code exploration
 pub struct Explorer {
     root_dir: PathBuf,
+    search_index: Option<Mutex<SearchIndex>>,
 }
 
 impl FileTreeEntry {
@@ -86,7 +93,16 @@ impl Explorer {
     /// # Arguments
     /// * `root_dir` - The root directory to explore
     pub fn new(root_dir: PathBuf) -> Self {
-        Self { root_dir }
+        Self {
+            root_dir,
+            search_index: None,
+        }
+    }
+
+    /// Lets searches skip the files the index rules out
+    pub fn with_search_index(mut self, index: SearchIndex) -> Self {
+        self.search_index = Some(Mutex::new(index));
+        self
     }
 
     fn expand_directory(
@@ -225,6 +241,18 @@ impl CodeExplorer for Explorer {
         Ok(entry)
     }
 
+    fn read_files(&self, paths: &[PathBuf]) -> Vec<Result<String>> {
+        parallel_map(paths, |path| self.read_file(path))
+    }
+
+    fn list_paths(
+        &self,
+        paths: &[PathBuf],
+        max_depth: Option<usize>,
+    ) -> Vec<Result<FileTreeEntry>> {
+        parallel_map(paths, |path| self.list_files(path, max_depth))
+    }
+
     fn apply_updates(&self, path: &Path, updates: &[FileUpdate]) -> Result<String> {
         let content = std::fs::read_to_string(path)?;
         let updated_content = crate::utils::apply_content_updates(&content, updates)?;
@@ -240,30 +268,38 @@ impl CodeExplorer for Explorer {
         let max_results = options.max_results.unwrap_or(usize::MAX);
 
         // Prepare regex for different search modes
-        let regex = match options.mode {
+        let pattern = match options.mode {
             SearchMode::Exact => {
                 // For exact search, escape regex special characters and optionally add word boundaries
-                let pattern = if options.whole_words {
+                if options.whole_words {
                     format!(r"\b{}\b", regex::escape(&options.query))
                 } else {
                     regex::escape(&options.query)
-                };
-                RegexBuilder::new(&pattern)
-                    .case_insensitive(!options.case_sensitive)
-                    .build()?
+                }
             }
             SearchMode::Regex => {
                 // For regex search, optionally add word boundaries to user's pattern
-                let pattern = if options.whole_words {
+                if options.whole_words {
                     format!(r"\b{}\b", options.query)
                 } else {
                     options.query.clone()
-                };
-                RegexBuilder::new(&pattern)
-                    .case_insensitive(!options.case_sensitive)
-                    .build()?
+                }
             }
         };
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(!options.case_sensitive)
+            .build()?;
+
+        // The index only covers the files of the project, while it is cold all files are read
+        let mut index = self
+            .search_index
+            .as_ref()
+            .map(|index| index.lock().unwrap())
+            .filter(|index| path.starts_with(index.root_dir()));
+        let required = index
+            .as_ref()
+            .and_then(|_| required_trigrams(&pattern, !options.case_sensitive));
+        let mut seen = HashSet::new();
 
         let walker = WalkBuilder::new(path)
             .hidden(false)
@@ -279,33 +315,105 @@ impl CodeExplorer for Explorer {
                 continue;
             }
 
-            let file = std::fs::File::open(path)?;
-            let reader = BufReader::new(file);
-
-            for (line_idx, line) in reader.lines().enumerate() {
-                let line = line?;
-                
-                // Find all matches in the line
-                let matches: Vec<_> = regex.find_iter(&line).collect();
-                if !matches.is_empty() {
-                    results.push(SearchResult {
-                        file: path.to_path_buf(),
-                        line_number: line_idx + 1,
-                        line_content: line.to_string(),
-                        match_ranges: matches.iter().map(|m| (m.start(), m.end())).collect(),
-                    });
-
+            let Some(index) = index.as_mut() else {
+                let file = std::fs::File::open(path)?;
+                let reader = BufReader::new(file);
+                for (line_idx, line) in reader.lines().enumerate() {
+                    search_line(&regex, path, line_idx, &line?, &mut results);
                     if results.len() >= max_results {
                         return Ok(results);
                     }
                 }
+                continue;
+            };
+
+            seen.insert(path.to_path_buf());
+            let metadata = std::fs::metadata(path)?;
+            if let Some(required) = &required {
+                if index.excludes(path, &metadata, required) {
+                    continue;
+                }
+            }
+            let content = std::fs::read_to_string(path)?;
+            index.update(path, &metadata, &content);
+            for (line_idx, line) in content.lines().enumerate() {
+                search_line(&regex, path, line_idx, line, &mut results);
+                if results.len() >= max_results {
+                    save_index(index);
+                    return Ok(results);
+                }
             }
         }
 
+        if let Some(index) = index.as_mut() {
+            if path == index.root_dir() {
+                index.retain(&seen);
+            }
+            save_index(index);
+        }
         Ok(results)
     }
 }
 
+/// Adds the matches in a line to the results
+fn search_line(
+    regex: &Regex,
+    path: &Path,
+    line_idx: usize,
+    line: &str,
+    results: &mut Vec<SearchResult>,
+) {
+    let matches: Vec<_> = regex.find_iter(line).collect();
+    if !matches.is_empty() {
+        results.push(SearchResult {
+            file: path.to_path_buf(),
+            line_number: line_idx + 1,
+            line_content: line.to_string(),
+            match_ranges: matches.iter().map(|m| (m.start(), m.end())).collect(),
+        });
+    }
+}
+
+/// A failure to store the index only costs time in later searches
+fn save_index(index: &mut SearchIndex) {
+    if let Err(e) = index.save() {
+        warn!("{:#}", e);
+    }
+}
+
+/// Calls `f` for the items on up to `MAX_PARALLEL_READS` threads, which hides the latency
+/// of network file systems. The results are in the order of the items.
+fn parallel_map<T, R, F>(items: &[T], f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    if items.len() < 2 {
+        return items.iter().map(f).collect();
+    }
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0..items.len()).map(|_| None).collect::<Vec<_>>());
+    std::thread::scope(|scope| {
+        for _ in 0..items.len().min(MAX_PARALLEL_READS) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(index) else {
+                    break;
+                };
+                let result = f(item);
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("every item was processed"))
+        .collect()
+}
+
 /// Helper function to determine if a file is likely to be a text file
 fn is_text_file(path: &Path) -> bool {
     let text_extensions = [
@@ -353,6 +461,36 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_read_files() -> Result<()> {
+        let (temp_dir, explorer) = setup_test_directory()?;
+        let mut paths = Vec::new();
+        for i in 0..20 {
+            paths.push(create_test_file(
+                temp_dir.path(),
+                &format!("file{}.txt", i),
+                &format!("Content {}", i),
+            )?);
+        }
+        paths.insert(3, temp_dir.path().join("missing.txt"));
+
+        let results = explorer.read_files(&paths);
+        assert_eq!(results.len(), 21);
+        assert!(results[3].is_err());
+        assert_eq!(results[0].as_ref().unwrap(), "Content 0");
+        assert_eq!(results[4].as_ref().unwrap(), "Content 3");
+        assert_eq!(results[20].as_ref().unwrap(), "Content 19");
+
+        fs::create_dir(temp_dir.path().join("subdir"))?;
+        create_test_file(&temp_dir.path().join("subdir"), "nested.txt", "")?;
+        let subdir = temp_dir.path().join("subdir");
+        let results = explorer.list_paths(&[subdir, paths[0].clone()], None);
+        let listing = results[0].as_ref().unwrap();
+        assert!(listing.children.contains_key("nested.txt"));
+        assert_eq!(results[1].as_ref().unwrap().name, "file0.txt");
+        Ok(())
+    }
+
     #[test]
     fn test_format_with_line_numbers() {
         let input = "First line\nSecond line\nThird line";
@@ -466,6 +604,49 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_search_with_index() -> Result<()> {
+        let index_dir = TempDir::new()?;
+        let temp_dir = TempDir::new()?;
+        let index = SearchIndex::new(index_dir.path(), temp_dir.path());
+        let explorer = Explorer::new(temp_dir.path().to_path_buf()).with_search_index(index);
+        create_test_file(temp_dir.path(), "lib.rs", "pub fn parse_query() {}")?;
+        create_test_file(
+            temp_dir.path(),
+            "main.rs",
+            "fn main() {\n    parse_query();\n}",
+        )?;
+        // Recently modified files are left out of the index
+        for name in ["lib.rs", "main.rs"] {
+            let old = std::time::SystemTime::now() - std::time::Duration::from_secs(60);
+            fs::File::options()
+                .write(true)
+                .open(temp_dir.path().join(name))?
+                .set_modified(old)?;
+        }
+
+        let search = |query: &str, mode: SearchMode| {
+            explorer.search(
+                temp_dir.path(),
+                SearchOptions {
+                    query: query.to_string(),
+                    mode,
+                    ..Default::default()
+                },
+            )
+        };
+        // Searches while the index is cold and once it was used give the same results
+        for _ in 0..2 {
+            let results = search("PARSE_QUERY", SearchMode::Exact)?;
+            assert_eq!(results.len(), 2);
+            let results = search(r"fn \w+\(\)", SearchMode::Regex)?;
+            assert_eq!(results.len(), 2);
+            assert_eq!(search("nonexistent", SearchMode::Exact)?.len(), 0);
+        }
+        assert_eq!(fs::read_dir(index_dir.path())?.count(), 1);
+        Ok(())
+    }
+
     #[test]
     fn test_create_initial_tree() -> Result<()> {
         let (temp_dir, explorer) = setup_test_directory()?;
@@ -0,0 +1,255 @@
+use crate::persistence::Session;
+use crate::types::{ActionResult, Tool};
+use clap::ValueEnum;
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum ExportFormat {
+    #[value(name = "md")]
+    Markdown,
+    Html,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Html => "html",
+        }
+    }
+}
+
+/// Renders a session into a standalone transcript
+pub fn export_session(session: &Session, format: ExportFormat) -> String {
+    match format {
+        ExportFormat::Markdown => render_markdown(session),
+        ExportFormat::Html => render_html(session),
+    }
+}
+
+/// Splits a tool into its name and parameters as they are used in the LLM responses
+pub fn tool_name_and_params(tool: &Tool) -> (String, serde_json::Value) {
+    let value = serde_json::to_value(tool).unwrap_or_default();
+    let name = value["tool"].as_str().unwrap_or("Unknown").to_string();
+    (name, value["params"].clone())
+}
+
+/// Renders file modifications as diff, other tools as their JSON parameters
+fn render_tool_input(tool: &Tool) -> (&'static str, String) {
+    match tool {
+        Tool::WriteFile { path, content } => (
+            "diff",
+            format!(
+                "+++ {}\n{}",
+                path.display(),
+                content
+                    .lines()
+                    .map(|line| format!("+{}", line))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            ),
+        ),
+        Tool::UpdateFile { path, updates } => {
+            let mut diff = format!("+++ {}", path.display());
+            for update in updates {
+                diff.push_str(&format!(
+                    "\n@@ replacing lines {}-{} @@",
+                    update.start_line, update.end_line
+                ));
+                for line in update.new_content.lines() {
+                    diff.push_str(&format!("\n+{}", line));
+                }
+            }
+            ("diff", diff)
+        }
+        _ => {
+            let (_, params) = tool_name_and_params(tool);
+            (
+                "json",
+                serde_json::to_string_pretty(&params).unwrap_or_default(),
+            )
+        }
+    }
+}
+
+fn action_output(action: &ActionResult) -> String {
+    match &action.error {
+        Some(error) if action.result.is_empty() => format!("Error: {}", error),
+        Some(error) => format!("{}\nError: {}", action.result, error),
+        None => action.result.clone(),
+    }
+}
+
+fn render_markdown(session: &Session) -> String {
+    let metadata = &session.metadata;
+    let mut output = format!("# {}\n\n", session.title());
+    output.push_str(&format!("- Session: `{}`\n", metadata.id));
+    output.push_str(&format!("- Project: `{}`\n", metadata.root_dir.display()));
+    output.push_str(&format!(
+        "- Created: {}\n",
+        metadata.created_at.format("%Y-%m-%d %H:%M")
+    ));
+    output.push_str(&format!(
+        "- Updated: {}\n",
+        metadata.updated_at.format("%Y-%m-%d %H:%M")
+    ));
+    if !metadata.tags.is_empty() {
+        output.push_str(&format!("- Tags: {}\n", metadata.tags.join(", ")));
+    }
+
+    output.push_str(&format!("\n## Task\n\n{}\n", session.state.task));
+
+    for (i, action) in session.state.actions.iter().enumerate() {
+        let (name, _) = tool_name_and_params(&action.tool);
+        let (language, input) = render_tool_input(&action.tool);
+        output.push_str(&format!("\n## {}. {}\n\n", i + 1, name));
+        output.push_str(&format!("{}\n\n", action.reasoning));
+        output.push_str(&format!("```{}\n{}\n```\n\n", language, input));
+        output.push_str(&format!(
+            "<details>\n<summary>{}</summary>\n\n```\n{}\n```\n\n</details>\n",
+            if action.success { "Result" } else { "Failed" },
+            action_output(action)
+        ));
+    }
+
+    output
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn render_diff_html(diff: &str) -> String {
+    diff.lines()
+        .map(|line| {
+            let class = if line.starts_with("+++") || line.starts_with("@@") {
+                "hunk"
+            } else if line.starts_with('+') {
+                "added"
+            } else {
+                "context"
+            };
+            format!("<span class=\"{}\">{}</span>", class, escape_html(line))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+const HTML_STYLE: &str = "body { font-family: sans-serif; max-width: 60em; margin: 2em auto; }
+pre { background: #f5f5f5; padding: 0.8em; overflow-x: auto; }
+.meta { color: #666; }
+.reasoning { font-style: italic; }
+.failed summary { color: #b00020; }
+.added { color: #22863a; }
+.hunk { color: #6f42c1; }";
+
+fn render_html(session: &Session) -> String {
+    let metadata = &session.metadata;
+    let title = escape_html(&session.title());
+    let mut body = format!("<h1>{}</h1>\n", title);
+    body.push_str(&format!(
+        "<p class=\"meta\">Session <code>{}</code> in <code>{}</code>, updated {}</p>\n",
+        escape_html(&metadata.id),
+        escape_html(&metadata.root_dir.display().to_string()),
+        metadata.updated_at.format("%Y-%m-%d %H:%M")
+    ));
+    body.push_str(&format!(
+        "<h2>Task</h2>\n<pre>{}</pre>\n",
+        escape_html(&session.state.task)
+    ));
+
+    for (i, action) in session.state.actions.iter().enumerate() {
+        let (name, _) = tool_name_and_params(&action.tool);
+        let (language, input) = render_tool_input(&action.tool);
+        let input = if language == "diff" {
+            render_diff_html(&input)
+        } else {
+            escape_html(&input)
+        };
+        body.push_str(&format!(
+            "<h2>{}. {}</h2>\n<p class=\"reasoning\">{}</p>\n<pre>{}</pre>\n",
+            i + 1,
+            escape_html(&name),
+            escape_html(&action.reasoning),
+            input
+        ));
+        body.push_str(&format!(
+            "<details{}><summary>{}</summary>\n<pre>{}</pre>\n</details>\n",
+            if action.success {
+                ""
+            } else {
+                " class=\"failed\""
+            },
+            if action.success { "Result" } else { "Failed" },
+            escape_html(&action_output(action))
+        ));
+    }
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}\n</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        title, HTML_STYLE, body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::FileUpdate;
+    use std::path::PathBuf;
+
+    fn create_session() -> Session {
+        let mut session = Session::new(PathBuf::from("/project"));
+        session.state.task = "Fix the <bug>".to_string();
+        session.state.actions = vec![
+            ActionResult {
+                tool: Tool::ReadFiles {
+                    paths: vec![PathBuf::from("src/main.rs")],
+                },
+                success: true,
+                result: "Successfully loaded files: src/main.rs".to_string(),
+                error: None,
+                reasoning: "Need to look at the code".to_string(),
+            },
+            ActionResult {
+                tool: Tool::UpdateFile {
+                    path: PathBuf::from("src/main.rs"),
+                    updates: vec![FileUpdate {
+                        start_line: 2,
+                        end_line: 3,
+                        new_content: "fixed();".to_string(),
+                    }],
+                },
+                success: false,
+                result: String::new(),
+                error: Some("Overlapping updates".to_string()),
+                reasoning: "Apply the fix".to_string(),
+            },
+        ];
+        session
+    }
+
+    #[test]
+    fn test_export_markdown() {
+        let output = export_session(&create_session(), ExportFormat::Markdown);
+        assert!(output.starts_with("# Fix the <bug>\n"));
+        assert!(output.contains("## 1. ReadFiles\n\nNeed to look at the code\n"));
+        assert!(output.contains("\"src/main.rs\""));
+        assert!(
+            output.contains("```diff\n+++ src/main.rs\n@@ replacing lines 2-3 @@\n+fixed();\n```")
+        );
+        assert!(
+            output.contains("<summary>Failed</summary>\n\n```\nError: Overlapping updates\n```")
+        );
+    }
+
+    #[test]
+    fn test_export_html() {
+        let output = export_session(&create_session(), ExportFormat::Html);
+        assert!(output.contains("<title>Fix the &lt;bug&gt;</title>"));
+        assert!(output.contains("<h2>2. UpdateFile</h2>"));
+        assert!(output.contains("<span class=\"added\">+fixed();</span>"));
+        assert!(output.contains("<details class=\"failed\"><summary>Failed</summary>"));
+    }
+}
@@ -0,0 +1,171 @@
+use super::{check_response, missing_token, Comment, Forge, ForgeType, Issue, PullRequest, Remote};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder};
+use serde::Deserialize;
+use serde_json::json;
+
+#[derive(Debug, Deserialize)]
+struct GiteaUser {
+    login: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaIssue {
+    number: u64,
+    title: String,
+    #[serde(default)]
+    body: Option<String>,
+    state: String,
+    user: GiteaUser,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaComment {
+    #[serde(default)]
+    body: Option<String>,
+    user: GiteaUser,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaBranch {
+    #[serde(rename = "ref")]
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaPullRequest {
+    head: GiteaBranch,
+    base: GiteaBranch,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaRepository {
+    default_branch: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaCreated {
+    html_url: String,
+}
+
+/// Client of the Gitea REST API for one repository, also works with Forgejo instances
+/// like Codeberg
+pub struct Gitea {
+    client: Client,
+    base_url: String,
+    host: String,
+    repository: String,
+    token: Option<String>,
+}
+
+impl Gitea {
+    pub fn new(remote: &Remote, token: Option<String>) -> Self {
+        Self {
+            client: Client::new(),
+            base_url: format!("https://{}/api/v1", remote.host),
+            host: remote.host.clone(),
+            repository: remote.path.clone(),
+            token,
+        }
+    }
+
+    /// Request to an endpoint of the repository, the repository itself for an empty path
+    fn request(&self, method: reqwest::Method, path: &str) -> RequestBuilder {
+        let mut url = format!("{}/repos/{}", self.base_url, self.repository);
+        if !path.is_empty() {
+            url.push('/');
+            url.push_str(path);
+        }
+        let request = self
+            .client
+            .request(method, url)
+            .header("User-Agent", "code-assistant");
+        match &self.token {
+            Some(token) => request.header("Authorization", format!("token {}", token)),
+            None => request,
+        }
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let response = self.request(reqwest::Method::GET, path).send().await?;
+        check_response(response, "Gitea")
+            .await?
+            .json()
+            .await
+            .context("Failed to parse Gitea API response")
+    }
+
+    /// Issue or pull request with its comments
+    async fn issue_with_comments(&self, number: u64) -> Result<Issue> {
+        let issue: GiteaIssue = self.get(&format!("issues/{}", number)).await?;
+        let comments: Vec<GiteaComment> = self.get(&format!("issues/{}/comments", number)).await?;
+        Ok(Issue {
+            number: issue.number,
+            title: issue.title,
+            body: issue.body.unwrap_or_default(),
+            state: issue.state,
+            author: issue.user.login,
+            comments: comments
+                .into_iter()
+                .map(|comment| Comment {
+                    author: comment.user.login,
+                    body: comment.body.unwrap_or_default(),
+                })
+                .collect(),
+        })
+    }
+}
+
+#[async_trait]
+impl Forge for Gitea {
+    fn repository(&self) -> String {
+        format!("{}/{}", self.host, self.repository)
+    }
+
+    async fn issue(&self, number: u64) -> Result<Issue> {
+        self.issue_with_comments(number).await
+    }
+
+    async fn pull_request(&self, number: u64) -> Result<PullRequest> {
+        let issue = self.issue_with_comments(number).await?;
+        let pull_request: GiteaPullRequest = self.get(&format!("pulls/{}", number)).await?;
+        let response = self
+            .request(reqwest::Method::GET, &format!("pulls/{}.diff", number))
+            .send()
+            .await?;
+        let diff = check_response(response, "Gitea").await?.text().await?;
+        Ok(PullRequest {
+            issue,
+            head: pull_request.head.name,
+            base: pull_request.base.name,
+            diff,
+        })
+    }
+
+    async fn create_pull_request(
+        &self,
+        head: &str,
+        base: Option<&str>,
+        title: &str,
+        body: &str,
+    ) -> Result<String> {
+        if self.token.is_none() {
+            return Err(missing_token(ForgeType::Gitea));
+        }
+        let base = match base {
+            Some(base) => base.to_string(),
+            None => {
+                let repository: GiteaRepository = self.get("").await?;
+                repository.default_branch
+            }
+        };
+        let response = self
+            .request(reqwest::Method::POST, "pulls")
+            .json(&json!({ "title": title, "body": body, "head": head, "base": base }))
+            .send()
+            .await?;
+        let created: GiteaCreated = check_response(response, "Gitea").await?.json().await?;
+        Ok(created.html_url)
+    }
+}
@@ -0,0 +1,183 @@
+use super::{check_response, missing_token, Comment, Forge, ForgeType, Issue, PullRequest, Remote};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder};
+use serde::Deserialize;
+use serde_json::json;
+
+const API_URL: &str = "https://api.github.com";
+const JSON_MEDIA_TYPE: &str = "application/vnd.github+json";
+const DIFF_MEDIA_TYPE: &str = "application/vnd.github.diff";
+
+#[derive(Debug, Deserialize)]
+struct GitHubUser {
+    login: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubIssue {
+    number: u64,
+    title: String,
+    #[serde(default)]
+    body: Option<String>,
+    state: String,
+    user: GitHubUser,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubComment {
+    #[serde(default)]
+    body: Option<String>,
+    user: GitHubUser,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubBranch {
+    #[serde(rename = "ref")]
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubPullRequest {
+    head: GitHubBranch,
+    base: GitHubBranch,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubRepository {
+    default_branch: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubCreated {
+    html_url: String,
+}
+
+/// Client of the GitHub REST API for one repository
+pub struct GitHub {
+    client: Client,
+    base_url: String,
+    repository: String,
+    token: Option<String>,
+}
+
+impl GitHub {
+    pub fn new(remote: &Remote, token: Option<String>) -> Self {
+        Self {
+            client: Client::new(),
+            base_url: API_URL.to_string(),
+            repository: remote.path.clone(),
+            token,
+        }
+    }
+
+    /// Request to an endpoint of the repository, the repository itself for an empty path
+    fn request(&self, method: reqwest::Method, path: &str, accept: &str) -> RequestBuilder {
+        let mut url = format!("{}/repos/{}", self.base_url, self.repository);
+        if !path.is_empty() {
+            url.push('/');
+            url.push_str(path);
+        }
+        let request = self
+            .client
+            .request(method, url)
+            .header("User-Agent", "code-assistant")
+            .header("Accept", accept)
+            .header("X-GitHub-Api-Version", "2022-11-28");
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let response = self
+            .request(reqwest::Method::GET, path, JSON_MEDIA_TYPE)
+            .send()
+            .await?;
+        check_response(response, "GitHub")
+            .await?
+            .json()
+            .await
+            .context("Failed to parse GitHub API response")
+    }
+
+    async fn issue_with_comments(&self, number: u64) -> Result<Issue> {
+        let issue: GitHubIssue = self.get(&format!("issues/{}", number)).await?;
+        let comments: Vec<GitHubComment> = self
+            .get(&format!("issues/{}/comments?per_page=100", number))
+            .await?;
+        Ok(Issue {
+            number: issue.number,
+            title: issue.title,
+            body: issue.body.unwrap_or_default(),
+            state: issue.state,
+            author: issue.user.login,
+            comments: comments
+                .into_iter()
+                .map(|comment| Comment {
+                    author: comment.user.login,
+                    body: comment.body.unwrap_or_default(),
+                })
+                .collect(),
+        })
+    }
+}
+
+#[async_trait]
+impl Forge for GitHub {
+    fn repository(&self) -> String {
+        format!("github.com/{}", self.repository)
+    }
+
+    async fn issue(&self, number: u64) -> Result<Issue> {
+        self.issue_with_comments(number).await
+    }
+
+    async fn pull_request(&self, number: u64) -> Result<PullRequest> {
+        // Pull requests are issues as well, which gives us the conversation
+        let issue = self.issue_with_comments(number).await?;
+        let pull_request: GitHubPullRequest = self.get(&format!("pulls/{}", number)).await?;
+        let response = self
+            .request(
+                reqwest::Method::GET,
+                &format!("pulls/{}", number),
+                DIFF_MEDIA_TYPE,
+            )
+            .send()
+            .await?;
+        let diff = check_response(response, "GitHub").await?.text().await?;
+        Ok(PullRequest {
+            issue,
+            head: pull_request.head.name,
+            base: pull_request.base.name,
+            diff,
+        })
+    }
+
+    async fn create_pull_request(
+        &self,
+        head: &str,
+        base: Option<&str>,
+        title: &str,
+        body: &str,
+    ) -> Result<String> {
+        if self.token.is_none() {
+            return Err(missing_token(ForgeType::GitHub));
+        }
+        let base = match base {
+            Some(base) => base.to_string(),
+            None => {
+                let repository: GitHubRepository = self.get("").await?;
+                repository.default_branch
+            }
+        };
+        let response = self
+            .request(reqwest::Method::POST, "pulls", JSON_MEDIA_TYPE)
+            .json(&json!({ "title": title, "body": body, "head": head, "base": base }))
+            .send()
+            .await?;
+        let created: GitHubCreated = check_response(response, "GitHub").await?.json().await?;
+        Ok(created.html_url)
+    }
+}
@@ -0,0 +1,244 @@
+use super::{check_response, missing_token, Comment, Forge, ForgeType, Issue, PullRequest, Remote};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder};
+use serde::Deserialize;
+use serde_json::json;
+
+#[derive(Debug, Deserialize)]
+struct GitLabUser {
+    username: String,
+}
+
+/// Issues and merge requests share these fields
+#[derive(Debug, Deserialize)]
+struct GitLabIssue {
+    iid: u64,
+    title: String,
+    #[serde(default)]
+    description: Option<String>,
+    state: String,
+    author: GitLabUser,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitLabMergeRequest {
+    #[serde(flatten)]
+    issue: GitLabIssue,
+    source_branch: String,
+    target_branch: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitLabNote {
+    body: String,
+    author: GitLabUser,
+    /// Notes GitLab adds itself, like "changed the description"
+    #[serde(default)]
+    system: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitLabDiff {
+    old_path: String,
+    new_path: String,
+    diff: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitLabProject {
+    default_branch: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitLabCreated {
+    web_url: String,
+}
+
+/// Client of the GitLab REST API for one project, on gitlab.com or a self-hosted instance.
+/// Pull requests are called merge requests on GitLab.
+pub struct GitLab {
+    client: Client,
+    base_url: String,
+    host: String,
+    project: String,
+    token: Option<String>,
+}
+
+impl GitLab {
+    pub fn new(remote: &Remote, token: Option<String>) -> Self {
+        Self {
+            client: Client::new(),
+            base_url: format!("https://{}/api/v4", remote.host),
+            host: remote.host.clone(),
+            project: remote.path.clone(),
+            token,
+        }
+    }
+
+    /// Request to an endpoint of the project, the project itself for an empty path
+    fn request(&self, method: reqwest::Method, path: &str) -> RequestBuilder {
+        // Projects are addressed by their URL-encoded path, subgroups included
+        let mut url = format!(
+            "{}/projects/{}",
+            self.base_url,
+            self.project.replace('/', "%2F")
+        );
+        if !path.is_empty() {
+            url.push('/');
+            url.push_str(path);
+        }
+        let request = self
+            .client
+            .request(method, url)
+            .header("User-Agent", "code-assistant");
+        match &self.token {
+            Some(token) => request.header("PRIVATE-TOKEN", token),
+            None => request,
+        }
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let response = self.request(reqwest::Method::GET, path).send().await?;
+        check_response(response, "GitLab")
+            .await?
+            .json()
+            .await
+            .context("Failed to parse GitLab API response")
+    }
+
+    /// Comments of an issue or merge request, without the notes of GitLab itself
+    async fn comments(&self, path: &str) -> Result<Vec<Comment>> {
+        let notes: Vec<GitLabNote> = self
+            .get(&format!("{}/notes?sort=asc&per_page=100", path))
+            .await?;
+        Ok(notes
+            .into_iter()
+            .filter(|note| !note.system)
+            .map(|note| Comment {
+                author: note.author.username,
+                body: note.body,
+            })
+            .collect())
+    }
+
+    fn issue_from(issue: GitLabIssue, comments: Vec<Comment>) -> Issue {
+        Issue {
+            number: issue.iid,
+            title: issue.title,
+            body: issue.description.unwrap_or_default(),
+            state: issue.state,
+            author: issue.author.username,
+            comments,
+        }
+    }
+}
+
+/// Renders the changed files of a merge request as unified diff
+fn format_diffs(diffs: Vec<GitLabDiff>) -> String {
+    diffs
+        .into_iter()
+        .map(|diff| {
+            format!(
+                "diff --git a/{old} b/{new}\n--- a/{old}\n+++ b/{new}\n{}",
+                diff.diff,
+                old = diff.old_path,
+                new = diff.new_path
+            )
+        })
+        .collect()
+}
+
+#[async_trait]
+impl Forge for GitLab {
+    fn repository(&self) -> String {
+        format!("{}/{}", self.host, self.project)
+    }
+
+    async fn issue(&self, number: u64) -> Result<Issue> {
+        let path = format!("issues/{}", number);
+        let issue: GitLabIssue = self.get(&path).await?;
+        Ok(Self::issue_from(issue, self.comments(&path).await?))
+    }
+
+    async fn pull_request(&self, number: u64) -> Result<PullRequest> {
+        let path = format!("merge_requests/{}", number);
+        let merge_request: GitLabMergeRequest = self.get(&path).await?;
+        let diffs: Vec<GitLabDiff> = self.get(&format!("{}/diffs?per_page=100", path)).await?;
+        Ok(PullRequest {
+            issue: Self::issue_from(merge_request.issue, self.comments(&path).await?),
+            head: merge_request.source_branch,
+            base: merge_request.target_branch,
+            diff: format_diffs(diffs),
+        })
+    }
+
+    async fn create_pull_request(
+        &self,
+        head: &str,
+        base: Option<&str>,
+        title: &str,
+        body: &str,
+    ) -> Result<String> {
+        if self.token.is_none() {
+            return Err(missing_token(ForgeType::GitLab));
+        }
+        let base = match base {
+            Some(base) => base.to_string(),
+            None => {
+                let project: GitLabProject = self.get("").await?;
+                project.default_branch
+            }
+        };
+        let response = self
+            .request(reqwest::Method::POST, "merge_requests")
+            .json(&json!({
+                "source_branch": head,
+                "target_branch": base,
+                "title": title,
+                "description": body,
+            }))
+            .send()
+            .await?;
+        let created: GitLabCreated = check_response(response, "GitLab").await?.json().await?;
+        Ok(created.web_url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_request_response() {
+        let merge_request: GitLabMergeRequest = serde_json::from_str(
+            r#"{
+                "iid": 12,
+                "title": "Add a cache",
+                "description": null,
+                "state": "opened",
+                "author": {"username": "jane"},
+                "source_branch": "cache",
+                "target_branch": "main"
+            }"#,
+        )
+        .unwrap();
+        let issue = GitLab::issue_from(merge_request.issue, Vec::new());
+        assert_eq!(
+            issue.format(),
+            "#12 Add a cache (opened, opened by jane)\n\n\n"
+        );
+        assert_eq!(merge_request.source_branch, "cache");
+
+        let diff = format_diffs(vec![GitLabDiff {
+            old_path: "src/cache.rs".to_string(),
+            new_path: "src/cache.rs".to_string(),
+            diff: "@@ -1 +1 @@\n-old\n+new\n".to_string(),
+        }]);
+        assert_eq!(
+            diff,
+            "diff --git a/src/cache.rs b/src/cache.rs\n--- a/src/cache.rs\n+++ b/src/cache.rs\n\
+             @@ -1 +1 @@\n-old\n+new\n"
+        );
+    }
+}
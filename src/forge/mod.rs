@@ -0,0 +1,336 @@
+pub mod gitea;
+pub mod github;
+pub mod gitlab;
+
+use crate::auth::keyring;
+use anyhow::Result;
+use async_trait::async_trait;
+use clap::ValueEnum;
+use reqwest::Response;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::Command;
+use tracing::{debug, warn};
+
+pub use gitea::Gitea;
+pub use github::GitHub;
+pub use gitlab::GitLab;
+
+/// Names of the tools working with the forge, left out if the project has none
+pub const FORGE_TOOLS: &[&str] = &["FetchIssue", "FetchPullRequest", "CreatePullRequest"];
+
+/// Diffs of pull requests are cut off after this many characters
+const MAX_DIFF_LENGTH: usize = 50_000;
+
+/// Hosting service of a repository
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ForgeType {
+    #[value(name = "github")]
+    GitHub,
+    #[value(name = "gitlab")]
+    GitLab,
+    /// Gitea and its fork Forgejo
+    Gitea,
+}
+
+impl ForgeType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ForgeType::GitHub => "github",
+            ForgeType::GitLab => "gitlab",
+            ForgeType::Gitea => "gitea",
+        }
+    }
+
+    /// Environment variable holding the access token
+    pub fn env_var(&self) -> &'static str {
+        match self {
+            ForgeType::GitHub => "GITHUB_TOKEN",
+            ForgeType::GitLab => "GITLAB_TOKEN",
+            ForgeType::Gitea => "GITEA_TOKEN",
+        }
+    }
+
+    /// Forge running on a host: the configured one for self-hosted instances, which are
+    /// listed in `hosts`, otherwise the forge of a well-known public instance
+    fn for_host(host: &str, hosts: &BTreeMap<String, ForgeType>) -> Option<Self> {
+        if let Some((_, forge_type)) = hosts.iter().find(|(h, _)| h.eq_ignore_ascii_case(host)) {
+            return Some(*forge_type);
+        }
+        match host {
+            "github.com" => Some(ForgeType::GitHub),
+            "gitlab.com" => Some(ForgeType::GitLab),
+            "gitea.com" | "codeberg.org" => Some(ForgeType::Gitea),
+            _ => None,
+        }
+    }
+
+    /// Looks up the access token, the environment variable takes precedence over
+    /// the OS credential store
+    pub fn token(&self) -> Option<String> {
+        if let Ok(token) = std::env::var(self.env_var()) {
+            return Some(token);
+        }
+        match keyring::get(self.name()) {
+            Ok(token) => token,
+            Err(e) => {
+                warn!("{:#}", e);
+                None
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub author: String,
+    pub body: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Issue {
+    pub number: u64,
+    pub title: String,
+    pub body: String,
+    pub state: String,
+    pub author: String,
+    pub comments: Vec<Comment>,
+}
+
+impl Issue {
+    /// Renders the issue and its comments for the agent
+    pub fn format(&self) -> String {
+        let mut text = format!(
+            "#{} {} ({}, opened by {})\n\n{}\n",
+            self.number,
+            self.title,
+            self.state,
+            self.author,
+            self.body.trim()
+        );
+        for comment in &self.comments {
+            text.push_str(&format!(
+                "\nComment by {}:\n{}\n",
+                comment.author,
+                comment.body.trim()
+            ));
+        }
+        text
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PullRequest {
+    pub issue: Issue,
+    /// Branch with the changes
+    pub head: String,
+    /// Branch the changes are merged into
+    pub base: String,
+    pub diff: String,
+}
+
+impl PullRequest {
+    pub fn format(&self) -> String {
+        let mut diff = self.diff.as_str();
+        let mut truncated = "";
+        if diff.len() > MAX_DIFF_LENGTH {
+            let mut end = MAX_DIFF_LENGTH;
+            while !diff.is_char_boundary(end) {
+                end -= 1;
+            }
+            diff = &diff[..end];
+            truncated = "\n[Diff truncated, read the changed files instead]\n";
+        }
+        format!(
+            "{}\nMerges {} into {}\n\nDiff:\n{}{}",
+            self.issue.format(),
+            self.head,
+            self.base,
+            diff,
+            truncated
+        )
+    }
+}
+
+/// Issues and pull requests of the repository a project is hosted in
+#[async_trait]
+pub trait Forge: Send + Sync {
+    /// Description of the repository, like `github.com/owner/repo`
+    fn repository(&self) -> String;
+
+    async fn issue(&self, number: u64) -> Result<Issue>;
+
+    async fn pull_request(&self, number: u64) -> Result<PullRequest>;
+
+    /// Opens a pull request for the branch `head`, into the default branch if no
+    /// `base` is given. Returns the URL of the pull request.
+    async fn create_pull_request(
+        &self,
+        head: &str,
+        base: Option<&str>,
+        title: &str,
+        body: &str,
+    ) -> Result<String>;
+}
+
+/// Location of a repository, parsed from a remote URL
+#[derive(Debug, Clone, PartialEq)]
+pub struct Remote {
+    pub host: String,
+    /// Path of the repository on the host, like `owner/repo`
+    pub path: String,
+}
+
+impl Remote {
+    /// Parses HTTPS URLs like `https://github.com/owner/repo.git` as well as SSH URLs
+    /// like `git@github.com:owner/repo.git` or `ssh://git@host:22/owner/repo`
+    pub fn parse(url: &str) -> Option<Self> {
+        let url = url.trim();
+        let (host, path) = if let Some((_, rest)) = url.split_once("://") {
+            let (authority, path) = rest.split_once('/')?;
+            let host = authority.rsplit('@').next()?;
+            (host.split(':').next()?, path)
+        } else {
+            // scp-like syntax of SSH
+            let (authority, path) = url.split_once(':')?;
+            (authority.rsplit('@').next()?, path)
+        };
+        let path = path.trim_matches('/');
+        let path = path.strip_suffix(".git").unwrap_or(path);
+        if host.is_empty() || !path.contains('/') {
+            return None;
+        }
+        Some(Self {
+            host: host.to_lowercase(),
+            path: path.to_string(),
+        })
+    }
+}
+
+/// Finds the forge hosting the project in `root_dir` through the URL of its `origin`
+/// remote, `hosts` maps the hosts of self-hosted instances to their forge. Returns
+/// `None` if the project has no such remote or it is hosted elsewhere.
+pub fn detect(root_dir: &Path, hosts: &BTreeMap<String, ForgeType>) -> Option<Box<dyn Forge>> {
+    let output = Command::new("git")
+        .args(["remote", "get-url", "origin"])
+        .current_dir(root_dir)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let url = String::from_utf8_lossy(&output.stdout);
+    let Some(remote) = Remote::parse(&url) else {
+        debug!("Unsupported remote URL {}", url.trim());
+        return None;
+    };
+    let Some(forge_type) = ForgeType::for_host(&remote.host, hosts) else {
+        debug!("No forge configured for {}", remote.host);
+        return None;
+    };
+    let token = forge_type.token();
+    Some(match forge_type {
+        ForgeType::GitHub => Box::new(GitHub::new(&remote, token)),
+        ForgeType::GitLab => Box::new(GitLab::new(&remote, token)),
+        ForgeType::Gitea => Box::new(Gitea::new(&remote, token)),
+    })
+}
+
+/// Fails with the error message of the API if the request was not successful
+async fn check_response(response: Response, service: &str) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let text = response.text().await.unwrap_or_default();
+    let message = serde_json::from_str::<serde_json::Value>(&text)
+        .ok()
+        .and_then(|value| value["message"].as_str().map(str::to_string))
+        .unwrap_or(text);
+    anyhow::bail!("{} API request failed ({}): {}", service, status, message)
+}
+
+/// Error for creating pull requests without an access token
+fn missing_token(forge_type: ForgeType) -> anyhow::Error {
+    anyhow::anyhow!(
+        "Creating pull requests needs an access token, set {} or run \
+         `code-assistant auth set-token {}`",
+        forge_type.env_var(),
+        forge_type.name()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_remote() {
+        let github = Some(Remote {
+            host: "github.com".to_string(),
+            path: "owner/repo".to_string(),
+        });
+        assert_eq!(Remote::parse("https://github.com/owner/repo.git\n"), github);
+        assert_eq!(Remote::parse("https://github.com/owner/repo"), github);
+        assert_eq!(Remote::parse("git@github.com:owner/repo.git"), github);
+        assert_eq!(Remote::parse("ssh://git@GitHub.com:22/owner/repo"), github);
+        assert_eq!(
+            Remote::parse("https://gitlab.example.com/group/sub/project.git"),
+            Some(Remote {
+                host: "gitlab.example.com".to_string(),
+                path: "group/sub/project".to_string(),
+            })
+        );
+        assert_eq!(Remote::parse("/srv/git/repo.git"), None);
+    }
+
+    #[test]
+    fn test_forge_for_host() {
+        let hosts = BTreeMap::from([("Git.Example.com".to_string(), ForgeType::Gitea)]);
+        assert_eq!(
+            ForgeType::for_host("github.com", &hosts),
+            Some(ForgeType::GitHub)
+        );
+        assert_eq!(
+            ForgeType::for_host("gitlab.com", &hosts),
+            Some(ForgeType::GitLab)
+        );
+        assert_eq!(
+            ForgeType::for_host("codeberg.org", &hosts),
+            Some(ForgeType::Gitea)
+        );
+        assert_eq!(
+            ForgeType::for_host("git.example.com", &hosts),
+            Some(ForgeType::Gitea)
+        );
+        assert_eq!(ForgeType::for_host("gitlab.example.com", &hosts), None);
+    }
+
+    #[test]
+    fn test_format_pull_request() {
+        let pull_request = PullRequest {
+            issue: Issue {
+                number: 7,
+                title: "Fix the parser".to_string(),
+                body: "Fixes #3\n".to_string(),
+                state: "open".to_string(),
+                author: "octocat".to_string(),
+                comments: vec![Comment {
+                    author: "reviewer".to_string(),
+                    body: "Please add a test".to_string(),
+                }],
+            },
+            head: "fix-parser".to_string(),
+            base: "main".to_string(),
+            diff: "x".repeat(MAX_DIFF_LENGTH + 1),
+        };
+        let text = pull_request.format();
+        assert!(text.starts_with(
+            "#7 Fix the parser (open, opened by octocat)\n\nFixes #3\n\n\
+             Comment by reviewer:\nPlease add a test\n\nMerges fix-parser into main\n"
+        ));
+        assert!(text.ends_with("[Diff truncated, read the changed files instead]\n"));
+    }
+}
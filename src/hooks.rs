@@ -0,0 +1,163 @@
+use crate::export::tool_name_and_params;
+use crate::types::Tool;
+use serde::Deserialize;
+use std::path::PathBuf;
+use tracing::warn;
+
+/// Points in the agent loop at which hooks are run
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookEvent {
+    /// Before each request to the LLM, the output is added to the working memory
+    PreRequest,
+    /// Before a tool is executed, a failing hook blocks the tool call
+    PreTool,
+    /// After a tool was executed, the output is added to the result of the tool
+    PostTool,
+    /// When the agent wants to complete the task, a failing hook sends its output
+    /// back to the agent instead
+    PostTurn,
+}
+
+/// What a hook does when it is triggered
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookAction {
+    /// Runs a shell command in the project directory
+    Command(String),
+    /// Fails right away with the given reason
+    Block(String),
+}
+
+/// User-defined hook, configured in the settings
+#[derive(Debug, Clone, Deserialize)]
+pub struct Hook {
+    pub event: HookEvent,
+    /// Names of the tools the hook applies to, all tools if empty
+    #[serde(default)]
+    pub tools: Vec<String>,
+    /// Glob patterns, if given the hook only applies to tools touching matching paths
+    #[serde(default)]
+    pub paths: Vec<String>,
+    #[serde(flatten)]
+    pub action: HookAction,
+}
+
+impl Hook {
+    /// Whether the hook is triggered by the event for the given tool
+    pub fn applies_to(&self, event: HookEvent, tool: Option<&Tool>) -> bool {
+        if self.event != event {
+            return false;
+        }
+        let Some(tool) = tool else {
+            return true;
+        };
+
+        if !self.tools.is_empty() {
+            let (name, _) = tool_name_and_params(tool);
+            if !self.tools.contains(&name) {
+                return false;
+            }
+        }
+        if !self.paths.is_empty() {
+            let paths = tool_paths(tool);
+            let matches = self
+                .paths
+                .iter()
+                .any(|pattern| match glob::Pattern::new(pattern) {
+                    Ok(pattern) => paths.iter().any(|path| pattern.matches_path(path)),
+                    Err(e) => {
+                        warn!("Invalid path pattern '{}' in hook: {}", pattern, e);
+                        false
+                    }
+                });
+            if !matches {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Short description for messages to the user and the agent
+    pub fn description(&self) -> String {
+        match &self.action {
+            HookAction::Command(command) => format!("`{}`", command),
+            HookAction::Block(_) => "(block)".to_string(),
+        }
+    }
+}
+
+/// Result of running a hook
+#[derive(Debug)]
+pub struct HookOutcome {
+    pub hook: String,
+    pub success: bool,
+    pub output: String,
+}
+
+impl HookOutcome {
+    pub fn format(&self) -> String {
+        format!(
+            "Hook {} {}:\n{}",
+            self.hook,
+            if self.success { "succeeded" } else { "failed" },
+            self.output
+        )
+    }
+}
+
+/// Paths a tool reads or modifies
+fn tool_paths(tool: &Tool) -> Vec<PathBuf> {
+    match tool {
+        Tool::ListFiles { paths, .. } | Tool::ReadFiles { paths } | Tool::DeleteFiles { paths } => {
+            paths.clone()
+        }
+        Tool::WriteFile { path, .. } | Tool::UpdateFile { path, .. } => vec![path.clone()],
+        Tool::Summarize { files } => files.iter().map(|(path, _)| path.clone()).collect(),
+        Tool::Search { path, .. } => path.iter().cloned().collect(),
+        Tool::ExecuteCommand { working_dir, .. } => working_dir.iter().cloned().collect(),
+        Tool::AskUser { .. }
+        | Tool::MessageUser { .. }
+        | Tool::CompleteTask { .. }
+        | Tool::SubmitPlan { .. }
+        | Tool::UpdateProjectMemory { .. }
+        | Tool::RollbackToCheckpoint { .. }
+        | Tool::FetchIssue { .. }
+        | Tool::FetchPullRequest { .. }
+        | Tool::CreatePullRequest { .. }
+        | Tool::FetchTicket { .. } => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hook_settings() {
+        let hooks: Vec<Hook> = serde_json::from_str(
+            r#"[
+                {"event": "post_tool", "tools": ["WriteFile", "UpdateFile"], "command": "cargo fmt"},
+                {"event": "pre_tool", "paths": ["deploy/**"], "block": "Deployment files are off limits"}
+            ]"#,
+        )
+        .unwrap();
+
+        let write = Tool::WriteFile {
+            path: PathBuf::from("deploy/prod.yaml"),
+            content: String::new(),
+        };
+        let read = Tool::ReadFiles {
+            paths: vec![PathBuf::from("src/main.rs")],
+        };
+
+        assert!(matches!(&hooks[0].action, HookAction::Command(c) if c == "cargo fmt"));
+        assert!(hooks[0].applies_to(HookEvent::PostTool, Some(&write)));
+        assert!(!hooks[0].applies_to(HookEvent::PostTool, Some(&read)));
+        assert!(!hooks[0].applies_to(HookEvent::PreTool, Some(&write)));
+
+        assert!(matches!(&hooks[1].action, HookAction::Block(_)));
+        assert!(hooks[1].applies_to(HookEvent::PreTool, Some(&write)));
+        assert!(!hooks[1].applies_to(HookEvent::PreTool, Some(&read)));
+    }
+}
@@ -135,6 +135,20 @@ struct AnthropicRequest {
     system: Option<String>,
 }
 
+/// Request structure of the token counting endpoint
+#[derive(Debug, Serialize)]
+struct AnthropicCountTokensRequest<'a> {
+    model: &'a str,
+    messages: &'a [Message],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<&'a String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct AnthropicCountTokensResponse {
+    input_tokens: usize,
+}
+
 pub struct AnthropicClient {
     client: Client,
     api_key: String,
@@ -147,11 +161,17 @@ impl AnthropicClient {
         Self {
             client: Client::new(),
             api_key,
-            base_url: "https://api.anthropic.com/v1/messages".to_string(),
+            base_url: "https://api.anthropic.com/v1".to_string(),
             model,
         }
     }
 
+    /// Uses another API endpoint, like a proxy or gateway, instead of the default `https://api.anthropic.com/v1`
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
     async fn send_with_retry(
         &self,
         request: &AnthropicRequest,
@@ -245,7 +265,7 @@ impl AnthropicClient {
     ) -> Result<(LLMResponse, AnthropicRateLimitInfo)> {
         let response = self
             .client
-            .post(&self.base_url)
+            .post(format!("{}/messages", self.base_url))
             .header("x-api-key", &self.api_key)
             .header("anthropic-version", "2023-06-01")
             .json(request)
@@ -319,6 +339,42 @@ impl AnthropicClient {
 
 #[async_trait]
 impl LLMProvider for AnthropicClient {
+    fn context_window(&self) -> Option<usize> {
+        // All Claude 3 models come with a context window of 200k tokens
+        Some(200_000)
+    }
+
+    async fn count_tokens(&self, request: &LLMRequest) -> Result<usize> {
+        let count_request = AnthropicCountTokensRequest {
+            model: &self.model,
+            messages: &request.messages,
+            system: request.system_prompt.as_ref(),
+        };
+
+        let response = self
+            .client
+            .post(format!("{}/messages/count_tokens", self.base_url))
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", "2023-06-01")
+            .json(&count_request)
+            .send()
+            .await
+            .map_err(|e| ApiError::NetworkError(e.to_string()))?;
+
+        let status = response.status();
+        let response_text = response
+            .text()
+            .await
+            .map_err(|e| ApiError::NetworkError(e.to_string()))?;
+        if !status.is_success() {
+            return Err(ApiError::Unknown(format!("Status {}: {}", status, response_text)).into());
+        }
+
+        let count: AnthropicCountTokensResponse = serde_json::from_str(&response_text)
+            .map_err(|e| ApiError::Unknown(format!("Failed to parse response: {}", e)))?;
+        Ok(count.input_tokens)
+    }
+
     async fn send_message(&self, request: LLMRequest) -> Result<LLMResponse> {
         let anthropic_request = AnthropicRequest {
             model: self.model.clone(),
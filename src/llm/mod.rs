@@ -10,10 +10,71 @@ pub use types::*;
 
 use anyhow::Result;
 use async_trait::async_trait;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LLMProviderType {
+    #[default]
+    Anthropic,
+    OpenAI,
+    Ollama,
+}
+
+impl LLMProviderType {
+    /// Name of the provider as used in sessions and settings
+    pub fn name(&self) -> &'static str {
+        match self {
+            LLMProviderType::Anthropic => "anthropic",
+            LLMProviderType::OpenAI => "openai",
+            LLMProviderType::Ollama => "ollama",
+        }
+    }
+
+    /// Model used when none is configured, Ollama has no default model
+    pub fn default_model(&self) -> Option<&'static str> {
+        match self {
+            LLMProviderType::Anthropic => Some("claude-3-5-sonnet-20241022"),
+            LLMProviderType::OpenAI => Some("gpt-4o"),
+            LLMProviderType::Ollama => None,
+        }
+    }
+}
 
 /// Trait for different LLM provider implementations
 #[async_trait]
-pub trait LLMProvider {
+pub trait LLMProvider: Send + Sync {
     /// Sends a request to the LLM service
     async fn send_message(&self, request: LLMRequest) -> Result<LLMResponse>;
+
+    /// Size of the context window of the model in tokens, if known
+    fn context_window(&self) -> Option<usize> {
+        None
+    }
+
+    /// Counts the input tokens of a request. Providers without a token counting API
+    /// fall back to an estimate.
+    async fn count_tokens(&self, request: &LLMRequest) -> Result<usize> {
+        Ok(estimate_tokens(request))
+    }
+}
+
+/// Rough estimate of the input tokens of a request, assuming four characters per token
+pub fn estimate_tokens(request: &LLMRequest) -> usize {
+    let mut chars = request.system_prompt.as_ref().map_or(0, |p| p.len());
+    for message in &request.messages {
+        chars += match &message.content {
+            MessageContent::Text(text) => text.len(),
+            MessageContent::Structured(blocks) => blocks
+                .iter()
+                .map(|block| match block {
+                    ContentBlock::Text { text } => text.len(),
+                    ContentBlock::ToolUse { input, .. } => input.to_string().len(),
+                    ContentBlock::ToolResult { content, .. } => content.len(),
+                })
+                .sum(),
+        };
+    }
+    chars.div_ceil(4)
 }
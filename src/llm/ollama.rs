@@ -28,6 +28,12 @@ struct OllamaMessage {
 #[derive(Debug, Deserialize)]
 struct OllamaResponse {
     message: OllamaResponseMessage,
+    #[serde(default)]
+    prompt_eval_count: u64,
+    #[serde(default)]
+    eval_count: u64,
+    #[serde(default)]
+    done_reason: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -46,12 +52,18 @@ impl OllamaClient {
     pub fn new(model: String, num_ctx: usize) -> Self {
         Self {
             client: Client::new(),
-            base_url: "http://localhost:11434/api/chat".to_string(),
+            base_url: "http://localhost:11434".to_string(),
             model,
             num_ctx,
         }
     }
 
+    /// Uses another API endpoint, like a proxy or gateway, instead of the default `http://localhost:11434`
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
     fn convert_message(message: &Message) -> OllamaMessage {
         OllamaMessage {
             role: match message.role {
@@ -68,7 +80,7 @@ impl OllamaClient {
     async fn try_send_request(&self, request: &OllamaRequest) -> Result<OllamaResponse> {
         let response = self
             .client
-            .post(&self.base_url)
+            .post(format!("{}/api/chat", self.base_url))
             .json(request)
             .send()
             .await
@@ -100,6 +112,10 @@ impl OllamaClient {
 
 #[async_trait]
 impl LLMProvider for OllamaClient {
+    fn context_window(&self) -> Option<usize> {
+        Some(self.num_ctx)
+    }
+
     async fn send_message(&self, request: LLMRequest) -> Result<LLMResponse> {
         let mut messages: Vec<OllamaMessage> = Vec::new();
 
@@ -132,6 +148,15 @@ impl LLMProvider for OllamaClient {
             content: vec![ContentBlock::Text {
                 text: response.message.content,
             }],
+            usage: Usage {
+                input_tokens: response.prompt_eval_count,
+                output_tokens: response.eval_count,
+            },
+            stop_reason: response.done_reason.as_deref().map(|reason| match reason {
+                "stop" => StopReason::EndTurn,
+                "length" => StopReason::MaxTokens,
+                _ => StopReason::Other,
+            }),
         })
     }
 }
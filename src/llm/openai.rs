@@ -26,11 +26,21 @@ struct OpenAIChatMessage {
 #[derive(Debug, Deserialize)]
 struct OpenAIResponse {
     choices: Vec<OpenAIChoice>,
+    #[serde(default)]
+    usage: Option<OpenAIUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIUsage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
 }
 
 #[derive(Debug, Deserialize)]
 struct OpenAIChoice {
     message: OpenAIChatMessage,
+    #[serde(default)]
+    finish_reason: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -155,11 +165,17 @@ impl OpenAIClient {
         Self {
             client: Client::new(),
             api_key,
-            base_url: "https://api.openai.com/v1/chat/completions".to_string(),
+            base_url: "https://api.openai.com/v1".to_string(),
             model,
         }
     }
 
+    /// Uses another API endpoint, like a proxy or gateway, instead of the default `https://api.openai.com/v1`
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
     fn convert_message(message: &Message) -> OpenAIChatMessage {
         OpenAIChatMessage {
             role: match message.role {
@@ -241,7 +257,7 @@ impl OpenAIClient {
     ) -> Result<(LLMResponse, OpenAIRateLimitInfo)> {
         let response = self
             .client
-            .post(&self.base_url)
+            .post(format!("{}/chat/completions", self.base_url))
             .header("Authorization", format!("Bearer {}", self.api_key))
             .header("Content-Type", "application/json")
             .json(request)
@@ -296,6 +312,22 @@ impl OpenAIClient {
             content: vec![ContentBlock::Text {
                 text: openai_response.choices[0].message.content.clone(),
             }],
+            usage: openai_response
+                .usage
+                .map(|usage| Usage {
+                    input_tokens: usage.prompt_tokens,
+                    output_tokens: usage.completion_tokens,
+                })
+                .unwrap_or_default(),
+            stop_reason: openai_response.choices[0]
+                .finish_reason
+                .as_deref()
+                .map(|reason| match reason {
+                    "stop" => StopReason::EndTurn,
+                    "length" => StopReason::MaxTokens,
+                    "tool_calls" => StopReason::ToolUse,
+                    _ => StopReason::Other,
+                }),
         };
 
         Ok((response, rate_limits))
@@ -304,6 +336,14 @@ impl OpenAIClient {
 
 #[async_trait]
 impl LLMProvider for OpenAIClient {
+    fn context_window(&self) -> Option<usize> {
+        if self.model.starts_with("gpt-4o") {
+            Some(128_000)
+        } else {
+            None
+        }
+    }
+
     async fn send_message(&self, request: LLMRequest) -> Result<LLMResponse> {
         let mut messages: Vec<OpenAIChatMessage> = Vec::new();
 
@@ -3,7 +3,7 @@ use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
 /// Generic request structure that can be mapped to different providers
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct LLMRequest {
     pub messages: Vec<Message>,
     pub max_tokens: usize,
@@ -11,27 +11,27 @@ pub struct LLMRequest {
     pub system_prompt: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub role: MessageRole,
     pub content: MessageContent,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum MessageRole {
     User,
     Assistant,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum MessageContent {
     Text(String),
     Structured(Vec<ContentBlock>),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum ContentBlock {
     #[serde(rename = "text")]
@@ -49,10 +49,54 @@ pub enum ContentBlock {
     },
 }
 
+/// Token usage reported by the provider for a request
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub struct Usage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
+impl Usage {
+    pub fn add(&mut self, other: &Usage) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+    }
+}
+
+/// Why the model stopped generating the response
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    EndTurn,
+    /// The response was cut off by the token limit
+    MaxTokens,
+    StopSequence,
+    ToolUse,
+    #[serde(other)]
+    Other,
+}
+
 /// Generic response structure
 #[derive(Debug, Deserialize)]
 pub struct LLMResponse {
     pub content: Vec<ContentBlock>,
+    #[serde(default)]
+    pub usage: Usage,
+    #[serde(default)]
+    pub stop_reason: Option<StopReason>,
+}
+
+impl LLMResponse {
+    /// Text of all text blocks of the response
+    pub fn text(&self) -> String {
+        self.content
+            .iter()
+            .filter_map(|block| match block {
+                ContentBlock::Text { text } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
 }
 
 /// Common error types for all LLM providers
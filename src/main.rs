@@ -1,70 +1,280 @@
 mod agent;
+mod api;
+mod auth;
+mod checkpoint;
+mod ci;
+mod daemon;
 mod explorer;
+mod export;
+mod forge;
+mod hooks;
 mod llm;
 mod mcp;
+mod memory;
+mod metrics;
+mod notify;
+mod permissions;
 mod persistence;
+mod prompts;
+mod schedule;
+mod search_index;
+mod settings;
+mod stats;
+mod tracker;
 mod types;
 mod ui;
 mod utils;
+mod worktree;
 
-use crate::agent::Agent;
+use crate::agent::{AdditionalProject, Agent};
+use crate::auth::local::LocalAuth;
+use crate::auth::TokenService;
+use crate::checkpoint::{CheckpointStore, GitCheckpoints};
+use crate::ci::{CiOutcome, CiRun, CiUI};
 use crate::explorer::Explorer;
-use crate::llm::{AnthropicClient, LLMProvider, OllamaClient, OpenAIClient};
+use crate::export::{export_session, ExportFormat};
+use crate::forge::FORGE_TOOLS;
+use crate::llm::{AnthropicClient, LLMProvider, LLMProviderType, OllamaClient, OpenAIClient};
 use crate::mcp::MCPServer;
+use crate::memory::ProjectMemory;
+use crate::metrics::MeteredProvider;
+use crate::notify::{Notification, Notifier, NotifyingMediator};
+use crate::permissions::{
+    Grant, PermissionAuditLog, PermissionGrants, PermissionMediator, PermissionMode,
+    PermissionPolicy,
+};
+use crate::prompts::PromptLibrary;
+use crate::search_index::SearchIndex;
+use crate::tracker::TRACKER_TOOLS;
+use crate::ui::json::{summary_json, JsonUI};
+use crate::ui::pipe::PipeUI;
 use crate::ui::terminal::TerminalUI;
+use crate::ui::UserInterface;
 use crate::utils::DefaultCommandExecutor;
+use crate::worktree::Worktree;
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand, ValueEnum};
-use persistence::FileStatePersistence;
+use persistence::{
+    EphemeralStatePersistence, FileStatePersistence, LlmSessionConfig, Session, SessionStore,
+    StatePersistence,
+};
+use settings::{Profile, ProvidersSettings, Settings};
+use stats::SessionStats;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{info, warn};
 use tracing_subscriber::fmt::SubscriberBuilder;
 
-#[derive(ValueEnum, Debug, Clone)]
-enum LLMProviderType {
-    Anthropic,
-    OpenAI,
-    Ollama,
-}
-
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
+    /// Configuration profile with its own settings, sessions and API keys
+    /// (defaults to $CODE_ASSISTANT_CONFIG_PROFILE)
+    #[arg(long, global = true)]
+    config_profile: Option<String>,
+
     #[command(subcommand)]
     mode: Mode,
 }
 
 #[derive(Subcommand, Debug)]
+#[allow(clippy::large_enum_variant)] // Parsed once at startup
 enum Mode {
     /// Run as autonomous agent with LLM support
+    #[command(alias = "run")]
     Agent {
         /// Path to the code directory to analyze
         #[arg(long, default_value = ".")]
         path: PathBuf,
 
         /// Task to perform on the codebase (required unless --continue is used)
-        #[arg(short, long, required_unless_present = "continue_task")]
+        #[arg(short, long, required_unless_present_any = ["continue_task", "list_sessions", "editor", "pipe", "prompt"])]
         task: Option<String>,
 
+        /// Prompt from the prompt library to use as task, see `prompts list`
+        #[arg(long, conflicts_with_all = ["task", "continue_task"])]
+        prompt: Option<String>,
+
+        /// Value of a variable of the prompt as NAME=VALUE (can be repeated)
+        #[arg(long = "var", requires = "prompt")]
+        vars: Vec<String>,
+
+        /// Compose the task in $EDITOR, starting from --task if given
+        #[arg(long, conflicts_with = "continue_task")]
+        editor: bool,
+
         /// Continue from previous state
         #[arg(long)]
         continue_task: bool,
 
+        /// Session to continue (defaults to the latest unfinished session of the project)
+        #[arg(long, requires = "continue_task")]
+        session: Option<String>,
+
+        /// Choose the session to continue from a list of the project's sessions
+        #[arg(long, requires = "continue_task", conflicts_with = "session")]
+        pick_session: bool,
+
+        /// Fork the continued session after the given number of actions into a new session
+        #[arg(long, requires = "continue_task")]
+        fork_at: Option<usize>,
+
+        /// Edit a message of the user in the continued session and regenerate everything after it
+        /// (0 is the task, any other number refers to an AskUser action)
+        #[arg(long, requires_all = ["continue_task", "message"])]
+        edit_message: Option<usize>,
+
+        /// New text for the message selected with --edit-message
+        #[arg(long, requires = "edit_message")]
+        message: Option<String>,
+
+        /// List the saved sessions and exit
+        #[arg(long)]
+        list_sessions: bool,
+
+        /// List archived instead of active sessions (with --list-sessions)
+        #[arg(long, requires = "list_sessions")]
+        archived: bool,
+
+        /// List the sessions of all projects instead of only the one in --path (with --list-sessions)
+        #[arg(long, requires = "list_sessions")]
+        all_projects: bool,
+
+        /// File to load into working memory with the task, kept with the session (can be repeated)
+        #[arg(long = "attach")]
+        attachments: Vec<PathBuf>,
+
+        /// Further project the task spans, like the backend of a frontend in --path
+        /// (can be repeated, sticks with the session)
+        #[arg(long = "add-project")]
+        projects: Vec<PathBuf>,
+
+        /// File with a system message template to use for the session instead of the default
+        #[arg(long)]
+        system_message: Option<PathBuf>,
+
+        /// Profile from the settings bundling model, tools and instructions
+        /// (sticks with the session)
+        #[arg(long)]
+        profile: Option<String>,
+
+        /// Start in plan mode: the agent analyzes the task with read-only tools and can
+        /// only modify files once the user approved its plan
+        #[arg(long)]
+        plan: bool,
+
+        /// Only report the changes and commands the agent would make instead of running them
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Work in a git worktree of its own on a new branch `code-assistant/<SESSION>`,
+        /// leaving the project directory alone (sticks with the session)
+        #[arg(long, conflicts_with = "continue_task")]
+        worktree: bool,
+
+        /// Review the changes before completing the task and address the findings
+        #[arg(long)]
+        self_review: bool,
+
+        /// Ask for permission before the agent runs a command (same as --mode auto-edit)
+        #[arg(long)]
+        ask_permission: bool,
+
+        /// Permission mode, can be switched during the session with `/mode <MODE>`
+        /// after pressing Ctrl-C
+        #[arg(long)]
+        mode: Option<PermissionMode>,
+
+        /// Ask whether to continue after this many actions (overrides the settings)
+        #[arg(long)]
+        max_iterations: Option<usize>,
+
+        /// Ask whether to continue once the estimated cost in USD reaches this limit
+        /// (overrides the settings)
+        #[arg(long)]
+        max_cost: Option<f64>,
+
+        /// Ask whether to continue after running for this many minutes (overrides the settings)
+        #[arg(long)]
+        max_minutes: Option<u64>,
+
+        /// Ask whether to continue once this many input and output tokens were used
+        /// (overrides the settings)
+        #[arg(long)]
+        max_tokens: Option<u64>,
+
+        /// Run without asking anything: only what the permission settings allow is
+        /// executed, the budget is a hard limit and the exit code tells how the run
+        /// ended (0 completed, 1 error, 2 not completed, 3 budget exceeded)
+        #[arg(long, conflicts_with_all = ["editor", "pick_session", "plan"])]
+        ci: bool,
+
+        /// File to write a JSON report of the run to (with --ci)
+        #[arg(long, requires = "ci", default_value = "code-assistant-report.json")]
+        report: PathBuf,
+
+        /// One-shot run for shell pipelines: read the task from stdin (appended to --task
+        /// if given) and print only the final answer, without saving a session. Asks
+        /// nothing, like --ci.
+        #[arg(
+            long,
+            conflicts_with_all = ["continue_task", "editor", "list_sessions", "plan", "worktree", "ci"]
+        )]
+        pipe: bool,
+
+        /// Tag for the new session, or filter for --list-sessions (can be repeated)
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+
+        /// Format of the output: text for humans, or newline-delimited JSON events
+        /// for scripts, with logs on stderr
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+
         /// Enable verbose logging
         #[arg(short, long)]
         verbose: bool,
 
-        /// LLM provider to use
-        #[arg(short = 'p', long, default_value = "anthropic")]
-        provider: LLMProviderType,
+        /// LLM provider to use (defaults to anthropic, or the provider of the continued session)
+        #[arg(short = 'p', long)]
+        provider: Option<LLMProviderType>,
 
-        /// Model name to use (provider-specific)
+        /// Model name to use (provider-specific) or a model alias from the settings
         #[arg(short = 'm', long)]
         model: Option<String>,
 
-        /// Context window size (in tokens, only relevant for Ollama)
-        #[arg(long, default_value = "8192")]
-        num_ctx: usize,
+        /// Context window size (in tokens, only relevant for Ollama, defaults to 8192)
+        #[arg(long)]
+        num_ctx: Option<usize>,
+    },
+    /// Keep running and serve a WebSocket API on localhost for starting, continuing
+    /// and following sessions, e.g. for editor plugins. Starts the scheduled tasks of
+    /// the settings.
+    Daemon {
+        /// Port to listen on
+        #[arg(long, default_value_t = 7878)]
+        port: u16,
+
+        /// Enable verbose logging
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Serve an OpenAI-compatible chat completions API on localhost, each request
+    /// runs the agent on the project
+    Api {
+        /// Path to the project the agent works on
+        #[arg(long, default_value = ".")]
+        path: PathBuf,
+
+        /// Port to listen on
+        #[arg(long, default_value_t = 8787)]
+        port: u16,
+
+        /// Enable verbose logging
+        #[arg(short, long)]
+        verbose: bool,
     },
     /// Run as MCP server
     Server {
@@ -76,43 +286,1333 @@ enum Mode {
         #[arg(short, long)]
         verbose: bool,
     },
+    /// Manage saved sessions
+    Session {
+        #[command(subcommand)]
+        command: SessionCommand,
+    },
+    /// List the reusable task templates of the user and the project
+    Prompts {
+        #[command(subcommand)]
+        command: PromptsCommand,
+    },
+    /// Inspect or edit the notes the agent keeps about a project across sessions
+    Memory {
+        #[command(subcommand)]
+        command: MemoryCommand,
+    },
+    /// Manage the API keys stored in the OS credential store
+    Auth {
+        #[command(subcommand)]
+        command: AuthCommand,
+    },
+    /// Review and revoke the permissions granted permanently for a project
+    Permissions {
+        #[command(subcommand)]
+        command: PermissionsCommand,
+    },
+    /// Show the models of the providers and the model aliases from the settings
+    Models {
+        #[command(subcommand)]
+        command: ModelsCommand,
+    },
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Manage the default models of project directories in the settings
+    Projects {
+        #[command(subcommand)]
+        command: ProjectsCommand,
+    },
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Subcommand, Debug)]
+enum PermissionsCommand {
+    /// List the permissions granted for the project
+    List {
+        /// Path to the project
+        #[arg(long, default_value = ".")]
+        path: PathBuf,
+    },
+    /// Revoke a permission by its number in the list
+    Revoke {
+        /// Number of the permission
+        number: usize,
+        /// Path to the project
+        #[arg(long, default_value = ".")]
+        path: PathBuf,
+    },
+    /// Revoke all permissions granted for the project
+    Clear {
+        /// Path to the project
+        #[arg(long, default_value = ".")]
+        path: PathBuf,
+    },
+    /// Show the log of permission requests and how they were decided
+    Log {
+        /// Path to the project
+        #[arg(long, default_value = ".")]
+        path: PathBuf,
+        /// Only show requests of this session
+        #[arg(long)]
+        session: Option<String>,
+        /// Show requests of all projects
+        #[arg(long)]
+        all_projects: bool,
+        /// Only show denied requests
+        #[arg(long)]
+        denied: bool,
+        /// Maximum number of entries to show, the latest ones
+        #[arg(short = 'n', long, default_value_t = 50)]
+        limit: usize,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ModelsCommand {
+    /// List the default models of the providers and the model aliases
+    List {
+        /// Only show models of this provider
+        #[arg(short = 'p', long)]
+        provider: Option<LLMProviderType>,
+        /// Path to the project, whose configuration may add aliases
+        #[arg(long, default_value = ".")]
+        path: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Check the settings files and API keys
+    Doctor {
+        /// Path to the project whose configuration is checked as well
+        #[arg(long, default_value = ".")]
+        path: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ProjectsCommand {
+    /// Use a model for new sessions in a project directory
+    Add {
+        /// Model name to use (provider-specific) or a model alias from the settings
+        #[arg(short = 'm', long)]
+        model: String,
+        /// Path to the project
+        #[arg(long, default_value = ".")]
+        path: PathBuf,
+    },
+    /// Remove the default model of a project directory
+    Remove {
+        /// Path to the project
+        #[arg(long, default_value = ".")]
+        path: PathBuf,
+    },
+    /// List the project directories with a default model
+    List,
+}
+
+#[derive(Subcommand, Debug)]
+enum AuthCommand {
+    /// Store the API key of a provider, read from stdin
+    Set {
+        /// LLM provider the key belongs to
+        provider: LLMProviderType,
+    },
+    /// Remove the stored API key of a provider
+    Delete {
+        /// LLM provider the key belongs to
+        provider: LLMProviderType,
+    },
+    /// Store the access token of a forge like GitHub or a tracker like Jira, read from stdin
+    SetToken {
+        /// Forge or tracker the token belongs to
+        service: TokenService,
+    },
+    /// Remove the stored access token of a forge or tracker
+    DeleteToken {
+        /// Forge or tracker the token belongs to
+        service: TokenService,
+    },
+    /// Show where the API key of each provider comes from
+    Status,
+}
+
+#[derive(Subcommand, Debug)]
+enum PromptsCommand {
+    /// List the prompts with their variables
+    List {
+        /// Path to the project, whose prompts are listed as well
+        #[arg(long, default_value = ".")]
+        path: PathBuf,
+    },
+    /// Print a prompt
+    Show {
+        /// Name of the prompt
+        name: String,
+        /// Path to the project, whose prompts take precedence
+        #[arg(long, default_value = ".")]
+        path: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum MemoryCommand {
+    /// Print the project memory
+    Show {
+        /// Path to the project
+        #[arg(long, default_value = ".")]
+        path: PathBuf,
+    },
+    /// Open the project memory in $EDITOR
+    Edit {
+        /// Path to the project
+        #[arg(long, default_value = ".")]
+        path: PathBuf,
+    },
+    /// Delete the project memory
+    Clear {
+        /// Path to the project
+        #[arg(long, default_value = ".")]
+        path: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum SessionCommand {
+    /// Add tags to a session
+    Tag {
+        /// Id of the session
+        id: String,
+        /// Tags to add
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+    /// Remove tags from a session
+    Untag {
+        /// Id of the session
+        id: String,
+        /// Tags to remove
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+    /// Give a session a name, which is shown instead of its task
+    Rename {
+        /// Id of the session
+        id: String,
+        /// New name, an empty name removes it
+        name: String,
+    },
+    /// Copy a session into a new session, which can be continued independently
+    Duplicate {
+        /// Id of the session
+        id: String,
+    },
+    /// Remove the worktree of a session, keeping its branch. The session continues
+    /// in the project directory
+    RemoveWorktree {
+        /// Id of the session
+        id: String,
+    },
+    /// Delete an active or archived session, including its worktree
+    Delete {
+        /// Id of the session
+        id: String,
+        /// Don't ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Move a session into the archive
+    Archive {
+        /// Id of the session
+        id: String,
+    },
+    /// Restore a session from the archive
+    Unarchive {
+        /// Id of the session
+        id: String,
+    },
+    /// Archive and delete old sessions according to the retention settings
+    Vacuum,
+    /// Change the LLM provider or model used when the session is continued
+    Model {
+        /// Id of the session
+        id: String,
+        /// LLM provider to use
+        #[arg(short = 'p', long)]
+        provider: Option<LLMProviderType>,
+        /// Model name to use (provider-specific) or a model alias from the settings
+        #[arg(short = 'm', long)]
+        model: Option<String>,
+        /// Context window size (in tokens, only relevant for Ollama)
+        #[arg(long)]
+        num_ctx: Option<usize>,
+    },
+    /// Show token usage, estimated cost and tool statistics of a session
+    Stats {
+        /// Id of the session
+        id: String,
+    },
+    /// Show the changes of a session since its first checkpoint, per project
+    Diff {
+        /// Id of the session
+        id: String,
+    },
+    /// List the checkpoints taken before the agent modified files
+    Checkpoints {
+        /// Id of the session
+        id: String,
+    },
+    /// Restore the project files of a checkpoint
+    Rollback {
+        /// Id of the session
+        id: String,
+        /// Number of the checkpoint
+        checkpoint: usize,
+    },
+    /// Export a session as transcript
+    Export {
+        /// Id of the session
+        id: String,
+        /// Format of the transcript
+        #[arg(long, default_value = "md")]
+        format: ExportFormat,
+        /// Output file (defaults to <ID>.<FORMAT> in the current directory)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
 }
 
 fn create_llm_client(
-    provider: LLMProviderType,
-    model: Option<String>,
-    num_ctx: usize,
+    config: &LlmSessionConfig,
+    providers: &ProvidersSettings,
 ) -> Result<Box<dyn LLMProvider>> {
-    match provider {
+    // Only Ollama comes without a default model
+    let model = config
+        .model_name()
+        .context("Model name is required for Ollama provider")?;
+    let base_url = providers.get(config.provider).base_url.as_deref();
+    match config.provider {
         LLMProviderType::Anthropic => {
-            let api_key = std::env::var("ANTHROPIC_API_KEY")
-                .context("ANTHROPIC_API_KEY environment variable not set")?;
-
-            Ok(Box::new(AnthropicClient::new(
-                api_key,
-                model
-                    .clone()
-                    .unwrap_or_else(|| "claude-3-5-sonnet-20241022".to_string()),
-            )))
+            let api_key = auth::require_api_key(config.provider)?;
+            let client = AnthropicClient::new(api_key, model);
+            Ok(Box::new(match base_url {
+                Some(url) => client.with_base_url(url),
+                None => client,
+            }))
         }
 
         LLMProviderType::OpenAI => {
-            let api_key = std::env::var("OPENAI_API_KEY")
-                .context("OPENAI_API_KEY environment variable not set")?;
+            let api_key = auth::require_api_key(config.provider)?;
+            let client = OpenAIClient::new(api_key, model);
+            Ok(Box::new(match base_url {
+                Some(url) => client.with_base_url(url),
+                None => client,
+            }))
+        }
 
-            Ok(Box::new(OpenAIClient::new(
-                api_key,
-                model.clone().unwrap_or_else(|| "gpt-4o".to_string()),
-            )))
+        LLMProviderType::Ollama => {
+            let client = OllamaClient::new(model, config.num_ctx);
+            Ok(Box::new(match base_url {
+                Some(url) => client.with_base_url(url),
+                None => client,
+            }))
         }
+    }
+}
 
-        LLMProviderType::Ollama => Ok(Box::new(OllamaClient::new(
-            model
-                .clone()
-                .context("Model name is required for Ollama provider")?,
+/// Lists the saved sessions, limited to the project in `root_dir` if given
+fn list_sessions(
+    store: &SessionStore,
+    root_dir: Option<&Path>,
+    tags: &[String],
+    archived: bool,
+) -> Result<()> {
+    let sessions = if archived {
+        store.list_archived()?
+    } else {
+        store.list()?
+    };
+    let sessions: Vec<_> = sessions
+        .into_iter()
+        .filter(|s| root_dir.is_none_or(|dir| s.metadata.root_dir == dir))
+        .filter(|s| s.metadata.has_tags(tags))
+        .collect();
+    if sessions.is_empty() {
+        match root_dir {
+            Some(dir) => println!(
+                "No saved sessions for {} (use --all-projects to see all sessions)",
+                dir.display()
+            ),
+            None => println!("No saved sessions"),
+        }
+        return Ok(());
+    }
+
+    for session in sessions {
+        let metadata = &session.metadata;
+        let mut line = format!(
+            "{}  {}  {} actions{}  {}",
+            metadata.id,
+            metadata.updated_at.format("%Y-%m-%d %H:%M"),
+            session.action_count,
+            if metadata.completed {
+                " (completed)"
+            } else {
+                ""
+            },
+            metadata.root_dir.display(),
+        );
+        if let Some(name) = &metadata.name {
+            line.push_str(&format!("  \"{}\"", name));
+        }
+        if let Some(origin) = &metadata.forked_from {
+            line.push_str(&format!(
+                "  [forked from {} at action {}]",
+                origin.session_id, origin.action_count
+            ));
+        }
+        if let Some(worktree) = &metadata.worktree {
+            line.push_str(&format!("  [branch {}]", worktree.branch));
+        }
+        for project in &metadata.projects {
+            line.push_str(&format!("  [+ {}]", project.display()));
+        }
+        if !metadata.tags.is_empty() {
+            line.push_str(&format!("  #{}", metadata.tags.join(" #")));
+        }
+        println!("{}", line);
+        println!("    {}", session.task.lines().next().unwrap_or(""));
+    }
+    Ok(())
+}
+
+/// Lets the user choose one of the sessions of a project, typed text narrows the list down
+fn pick_session(store: &SessionStore, root_dir: &Path) -> Result<Session> {
+    let sessions: Vec<_> = store
+        .list()?
+        .into_iter()
+        .filter(|s| s.metadata.root_dir == root_dir)
+        .collect();
+    if sessions.is_empty() {
+        anyhow::bail!("No saved session found for this project");
+    }
+
+    let mut query = String::new();
+    loop {
+        let matching: Vec<_> = sessions.iter().filter(|s| s.matches(&query)).collect();
+        if matching.is_empty() {
+            println!("No sessions match '{}'", query);
+        }
+        for (i, session) in matching.iter().enumerate() {
+            let usage = session.metadata.usage;
+            println!(
+                "{:>3}  {:<8}  {:>8} tokens  {}{}",
+                i + 1,
+                format_age(session.metadata.updated_at),
+                usage.input_tokens + usage.output_tokens,
+                session.title(),
+                if session.metadata.completed {
+                    " (completed)"
+                } else {
+                    ""
+                }
+            );
+        }
+
+        print!("Number of the session to continue, text to search for, or nothing to cancel: ");
+        io::Write::flush(&mut io::stdout())?;
+        let mut input = String::new();
+        io::stdin().read_line(&mut input)?;
+        let input = input.trim();
+        if input.is_empty() {
+            anyhow::bail!("No session selected");
+        }
+        match input.parse::<usize>() {
+            Ok(number) if (1..=matching.len()).contains(&number) => {
+                return store.load(&matching[number - 1].metadata.id);
+            }
+            _ => query = input.to_string(),
+        }
+    }
+}
+
+/// Time since a session was updated, e.g. `5m ago`
+fn format_age(time: chrono::DateTime<chrono::Utc>) -> String {
+    let age = chrono::Utc::now() - time;
+    if age.num_days() > 0 {
+        format!("{}d ago", age.num_days())
+    } else if age.num_hours() > 0 {
+        format!("{}h ago", age.num_hours())
+    } else {
+        format!("{}m ago", age.num_minutes())
+    }
+}
+
+/// Resolves attached files, files inside the project are kept relative to its root
+fn resolve_attachments(root_path: &Path, paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    paths
+        .iter()
+        .map(|path| {
+            let full_path = path
+                .canonicalize()
+                .with_context(|| format!("Attachment '{}' not found", path.display()))?;
+            Ok(full_path
+                .strip_prefix(root_path)
+                .map(|p| p.to_path_buf())
+                .unwrap_or(full_path))
+        })
+        .collect()
+}
+
+/// Resolves the further projects of a session, which must be directories
+fn resolve_projects(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    paths
+        .iter()
+        .map(|path| {
+            let project = path
+                .canonicalize()
+                .with_context(|| format!("Project '{}' not found", path.display()))?;
+            if !project.is_dir() {
+                anyhow::bail!("Project '{}' is not a directory", path.display());
+            }
+            Ok(project)
+        })
+        .collect()
+}
+
+fn vacuum_sessions(store: &SessionStore, settings: &Settings) -> Result<()> {
+    let report = store.vacuum(&settings.sessions)?;
+    if !report.archived.is_empty() {
+        info!("Archived {} old sessions", report.archived.len());
+    }
+    if !report.deleted.is_empty() {
+        info!(
+            "Deleted {} sessions to stay within the storage limit",
+            report.deleted.len()
+        );
+    }
+    Ok(())
+}
+
+fn session_checkpoints(store: &SessionStore, id: &str) -> Result<GitCheckpoints> {
+    let session = store.load(id)?;
+    let work_dir = session.metadata.work_dir();
+    GitCheckpoints::new(work_dir, id)
+        .with_context(|| format!("Project {} is not a git repository", work_dir.display()))
+}
+
+/// Asks a yes/no question on the terminal, anything but yes is a no
+fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    io::Write::flush(&mut io::stdout())?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Asks whether the project files should be restored to a checkpoint of a rewound session
+fn offer_restore(store: &SessionStore, id: &str, checkpoint: usize) -> Result<()> {
+    if !confirm(&format!(
+        "Files were modified after this point. Restore the project files to checkpoint {}?",
+        checkpoint
+    ))? {
+        return Ok(());
+    }
+    let checkpoint = session_checkpoints(store, id)?.restore(checkpoint)?;
+    println!(
+        "Restored checkpoint {} ({})",
+        checkpoint.number, checkpoint.description
+    );
+    Ok(())
+}
+
+fn run_session_command(store: &SessionStore, command: SessionCommand) -> Result<()> {
+    match command {
+        SessionCommand::Tag { id, tags } => {
+            let mut session = store.load(&id)?;
+            session.metadata.add_tags(&tags);
+            store.save(&session)?;
+            println!(
+                "Tags of session {}: {}",
+                id,
+                session.metadata.tags.join(", ")
+            );
+        }
+        SessionCommand::Untag { id, tags } => {
+            let mut session = store.load(&id)?;
+            session.metadata.remove_tags(&tags);
+            store.save(&session)?;
+            println!(
+                "Tags of session {}: {}",
+                id,
+                session.metadata.tags.join(", ")
+            );
+        }
+        SessionCommand::Rename { id, name } => {
+            let mut session = store.load(&id)?;
+            let name = name.trim();
+            session.metadata.name = (!name.is_empty()).then(|| name.to_string());
+            store.save(&session)?;
+            match &session.metadata.name {
+                Some(name) => println!("Renamed session {} to \"{}\"", id, name),
+                None => println!("Removed the name of session {}", id),
+            }
+        }
+        SessionCommand::Duplicate { id } => {
+            let session = store.load(&id)?;
+            let copy = store.fork(&session, session.state.actions.len())?;
+            println!("Duplicated session {} into {}", id, copy.metadata.id);
+            if let Some(worktree) = &copy.metadata.worktree {
+                println!(
+                    "The copy works in worktree {} on branch {}",
+                    worktree.path.display(),
+                    worktree.branch
+                );
+            }
+        }
+        SessionCommand::Delete { id, yes } => {
+            if !yes && !confirm(&format!("Delete session {}?", id))? {
+                return Ok(());
+            }
+            // Worktrees of archived sessions are left alone
+            if let Ok(session) = store.load(&id) {
+                if let Some(worktree) = &session.metadata.worktree {
+                    worktree.remove(&session.metadata.root_dir)?;
+                    println!(
+                        "Removed worktree {}, the changes are kept on branch {}",
+                        worktree.path.display(),
+                        worktree.branch
+                    );
+                }
+            }
+            store.delete(&id)?;
+            println!("Deleted session {}", id);
+        }
+        SessionCommand::RemoveWorktree { id } => {
+            let mut session = store.load(&id)?;
+            let worktree = session
+                .metadata
+                .worktree
+                .take()
+                .with_context(|| format!("Session {} has no worktree", id))?;
+            worktree.remove(&session.metadata.root_dir)?;
+            store.save(&session)?;
+            println!(
+                "Removed worktree {}, the changes are kept on branch {}",
+                worktree.path.display(),
+                worktree.branch
+            );
+        }
+        SessionCommand::Archive { id } => {
+            store.archive(&id)?;
+            println!("Archived session {}", id);
+        }
+        SessionCommand::Unarchive { id } => {
+            store.unarchive(&id)?;
+            println!("Restored session {}", id);
+        }
+        SessionCommand::Vacuum => {
+            let report = store.vacuum(&Settings::load()?.sessions)?;
+            println!(
+                "Archived {} sessions, deleted {} sessions",
+                report.archived.len(),
+                report.deleted.len()
+            );
+        }
+        SessionCommand::Model {
+            id,
+            provider,
+            model,
             num_ctx,
-        ))),
+        } => {
+            let mut session = store.load(&id)?;
+            let (provider, model) = Settings::load_for_project(&session.metadata.root_dir)?
+                .models
+                .resolve(provider, model);
+            session
+                .metadata
+                .llm_config
+                .apply_overrides(provider, model, num_ctx);
+            store.save(&session)?;
+            println!(
+                "Session {} will continue with {}",
+                id, session.metadata.llm_config
+            );
+        }
+        SessionCommand::Stats { id } => {
+            let session = store.load(&id)?;
+            println!("Session {}", id);
+            print!("{}", SessionStats::from_session(&session));
+        }
+        SessionCommand::Diff { id } => {
+            let session = store.load(&id)?;
+            let work_dir = session.metadata.work_dir();
+            for project in std::iter::once(work_dir)
+                .chain(session.metadata.projects.iter().map(PathBuf::as_path))
+            {
+                let Some(checkpoints) = GitCheckpoints::new(project, &id) else {
+                    println!("{}: not a git repository", project.display());
+                    continue;
+                };
+                let diff = match checkpoints.list()?.first() {
+                    Some(first) => checkpoints.diff(first.number)?,
+                    None => String::new(),
+                };
+                if diff.is_empty() {
+                    println!("{}: no changes", project.display());
+                } else {
+                    println!("{}:\n{}", project.display(), diff);
+                }
+            }
+        }
+        SessionCommand::Checkpoints { id } => {
+            let checkpoints = session_checkpoints(store, &id)?.list()?;
+            if checkpoints.is_empty() {
+                println!("No checkpoints for session {}", id);
+            }
+            for checkpoint in checkpoints {
+                println!(
+                    "{}  {}  {}",
+                    checkpoint.number,
+                    &checkpoint.commit[..8],
+                    checkpoint.description
+                );
+            }
+        }
+        SessionCommand::Rollback { id, checkpoint } => {
+            let checkpoint = session_checkpoints(store, &id)?.restore(checkpoint)?;
+            println!(
+                "Restored checkpoint {} ({})",
+                checkpoint.number, checkpoint.description
+            );
+        }
+        SessionCommand::Export { id, format, output } => {
+            let session = store.load(&id)?;
+            let output =
+                output.unwrap_or_else(|| PathBuf::from(format!("{}.{}", id, format.extension())));
+            std::fs::write(&output, export_session(&session, format))?;
+            println!("Exported session {} to {}", id, output.display());
+        }
+    }
+    Ok(())
+}
+
+fn run_memory_command(command: MemoryCommand) -> Result<()> {
+    let path = match &command {
+        MemoryCommand::Show { path }
+        | MemoryCommand::Edit { path }
+        | MemoryCommand::Clear { path } => path,
+    };
+    let root_path = path
+        .canonicalize()
+        .context("Failed to resolve project path")?;
+    let memory = ProjectMemory::default_location(&root_path)?;
+
+    match command {
+        MemoryCommand::Show { .. } => {
+            let content = memory.load()?;
+            if content.is_empty() {
+                println!("No project memory for {}", root_path.display());
+            } else {
+                print!("{}", content);
+            }
+        }
+        MemoryCommand::Edit { .. } => {
+            if let Some(parent) = memory.path().parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            run_editor(memory.path())?;
+        }
+        MemoryCommand::Clear { .. } => {
+            memory.save("")?;
+            println!("Cleared the project memory of {}", root_path.display());
+        }
+    }
+    Ok(())
+}
+
+fn run_prompts_command(command: PromptsCommand) -> Result<()> {
+    let path = match &command {
+        PromptsCommand::List { path } | PromptsCommand::Show { path, .. } => path,
+    };
+    let root_path = path
+        .canonicalize()
+        .context("Failed to resolve project path")?;
+    let library = PromptLibrary::for_project(&root_path)?;
+
+    match command {
+        PromptsCommand::List { .. } => {
+            let prompts = library.list()?;
+            if prompts.is_empty() {
+                println!(
+                    "No prompts, add Markdown files to {} or {}",
+                    settings::config_dir()?.join("prompts").display(),
+                    root_path.join(prompts::PROJECT_PROMPTS_DIR).display()
+                );
+            }
+            for prompt in prompts {
+                println!("{:<20} {}", prompt.name, prompt.summary());
+                let variables = prompt.variables();
+                if !variables.is_empty() {
+                    println!("{:<20} variables: {}", "", variables.join(", "));
+                }
+            }
+        }
+        PromptsCommand::Show { name, .. } => {
+            let prompt = library.get(&name)?;
+            println!("{}", prompt.path.display());
+            print!("{}", prompt.template);
+        }
+    }
+    Ok(())
+}
+
+fn run_permissions_command(command: PermissionsCommand) -> Result<()> {
+    let path = match &command {
+        PermissionsCommand::List { path }
+        | PermissionsCommand::Revoke { path, .. }
+        | PermissionsCommand::Clear { path }
+        | PermissionsCommand::Log { path, .. } => path,
+    };
+    let root_path = path
+        .canonicalize()
+        .context("Failed to resolve project path")?;
+    let grants = PermissionGrants::default_location(&root_path)?;
+
+    match command {
+        PermissionsCommand::List { .. } => {
+            let grants = grants.load()?;
+            if grants.is_empty() {
+                println!("No permissions granted for {}", root_path.display());
+            }
+            for (index, grant) in grants.iter().enumerate() {
+                println!(
+                    "{:>3}  {}  {} {}",
+                    index + 1,
+                    grant.granted_at.format("%Y-%m-%d %H:%M"),
+                    grant.tool,
+                    grant.pattern
+                );
+            }
+        }
+        PermissionsCommand::Revoke { number, .. } => {
+            let grant = grants.revoke(number.checked_sub(1).context("Numbers start at 1")?)?;
+            println!(
+                "Revoked the permission for {} {}",
+                grant.tool, grant.pattern
+            );
+        }
+        PermissionsCommand::Clear { .. } => {
+            grants.clear()?;
+            println!("Revoked all permissions for {}", root_path.display());
+        }
+        PermissionsCommand::Log {
+            session,
+            all_projects,
+            denied,
+            limit,
+            ..
+        } => {
+            let entries: Vec<_> = PermissionAuditLog::load(&PermissionAuditLog::default_path()?)?
+                .into_iter()
+                .filter(|entry| all_projects || entry.project == root_path)
+                .filter(|entry| session.as_ref().is_none_or(|id| &entry.session_id == id))
+                .filter(|entry| !denied || entry.decision.is_denied())
+                .collect();
+            if entries.is_empty() {
+                println!("No permission requests recorded");
+            }
+            for entry in &entries[entries.len().saturating_sub(limit)..] {
+                println!(
+                    "{}  {}  {:<20}  {} {}",
+                    entry
+                        .timestamp
+                        .with_timezone(&chrono::Local)
+                        .format("%Y-%m-%d %H:%M:%S"),
+                    entry.session_id,
+                    entry.decision.to_string(),
+                    entry.tool,
+                    entry.summary()
+                );
+                if all_projects {
+                    println!("    in {}", entry.project.display());
+                }
+            }
+        }
     }
+    Ok(())
+}
+
+/// Permission mode of a run: the one given on the command line, else the one of the
+/// settings. Without a mode, `--ask-permission` asks before commands like `auto-edit`.
+fn permission_mode(
+    mode: Option<PermissionMode>,
+    ask_permission: bool,
+    settings: &Settings,
+) -> PermissionMode {
+    mode.or(settings.mode)
+        .unwrap_or(if ask_permission || settings.ask_permission {
+            PermissionMode::AutoEdit
+        } else {
+            PermissionMode::FullAuto
+        })
+}
+
+/// Options of a run which don't stick with its session
+struct RunOptions {
+    mode: PermissionMode,
+    dry_run: bool,
+    self_review: bool,
+    /// Don't save the session and take no checkpoints, for one-shot runs
+    ephemeral: bool,
+}
+
+/// Sets up the agent for working on a session in the project in `root_path`, with the
+/// provider, model, profile, system message and worktree of the session and everything
+/// else from the settings
+fn create_agent(
+    settings: Settings,
+    root_path: &Path,
+    session_store: SessionStore,
+    session: Session,
+    ui: Box<dyn UserInterface>,
+    permissions: Box<dyn PermissionMediator>,
+    options: RunOptions,
+) -> Result<Agent> {
+    // Setup LLM client with the provider of the session
+    let llm_client = create_llm_client(&session.metadata.llm_config, &settings.providers)
+        .context("Failed to initialize LLM client")?;
+    let llm_client = Box::new(MeteredProvider::new(
+        llm_client,
+        session.metadata.llm_config.provider.name(),
+    ));
+    let attachments = session.state.attachments.clone();
+    let plan_mode = session.metadata.plan_mode;
+    let model = session.metadata.llm_config.model_name();
+    let profile = match &session.metadata.profile {
+        Some(name) => settings.profile(name)?.clone(),
+        None => Profile::default(),
+    };
+    let system_message = match session
+        .metadata
+        .system_message
+        .as_ref()
+        .or(settings.system_message.as_ref())
+    {
+        Some(path) => Some(
+            std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read system message {}", path.display()))?,
+        ),
+        None => None,
+    };
+    // Sessions in a worktree work there, but share memory and grants with the project
+    let work_dir = session.metadata.work_dir().to_path_buf();
+    let checkpoints = if options.ephemeral {
+        None
+    } else {
+        GitCheckpoints::new(&work_dir, &session.metadata.id)
+    };
+    let audit = PermissionAuditLog::new(
+        PermissionAuditLog::default_path()?,
+        &session.metadata.id,
+        root_path,
+    );
+    let projects = session
+        .metadata
+        .projects
+        .iter()
+        .filter(|project| {
+            let exists = project.is_dir();
+            if !exists {
+                warn!(
+                    "Project {} no longer exists, leaving it out",
+                    project.display()
+                );
+            }
+            exists
+        })
+        .map(|project| {
+            let checkpoints = if options.ephemeral {
+                None
+            } else {
+                GitCheckpoints::new(project, &session.metadata.id)
+            };
+            AdditionalProject::new(
+                project.clone(),
+                checkpoints.map(|c| Box::new(c) as Box<dyn CheckpointStore>),
+            )
+        })
+        .collect();
+    let state_persistence: Box<dyn StatePersistence> = if options.ephemeral {
+        Box::new(EphemeralStatePersistence)
+    } else {
+        Box::new(FileStatePersistence::new(session_store, session))
+    };
+
+    let mut explorer = Explorer::new(work_dir.clone());
+    if !options.ephemeral {
+        explorer = explorer.with_search_index(SearchIndex::default_location(&work_dir)?);
+    }
+    let mut agent = Agent::new(
+        llm_client,
+        Box::new(explorer),
+        Box::new(DefaultCommandExecutor::new(work_dir.clone())),
+        ui,
+        state_persistence,
+    );
+    agent.set_attachments(attachments);
+    agent.set_additional_projects(projects);
+    agent.set_hooks(settings.hooks);
+    agent.set_project_rules(settings.project_rules);
+    agent.set_plan_mode(plan_mode);
+    agent.set_budget(settings.budget, model);
+    agent.set_dry_run(options.dry_run || profile.dry_run);
+    agent.set_tool_scope(profile.tools);
+    let mut disabled_tools: Vec<String> = settings
+        .disabled_tools
+        .iter()
+        .chain(&profile.disabled_tools)
+        .cloned()
+        .collect();
+    let forge = forge::detect(&work_dir, &settings.forges);
+    if forge.is_none() {
+        // Without a forge, its tools would only fail
+        disabled_tools.extend(FORGE_TOOLS.iter().map(|tool| tool.to_string()));
+    }
+    let tracker = settings.tracker.as_ref().map(tracker::create).transpose()?;
+    if tracker.is_none() {
+        disabled_tools.extend(TRACKER_TOOLS.iter().map(|tool| tool.to_string()));
+    }
+    agent.set_disabled_tools(disabled_tools);
+    if let Some(forge) = forge {
+        info!("Issues and pull requests from {}", forge.repository());
+        agent.set_forge(forge);
+    }
+    if let Some(tracker) = tracker {
+        info!("Tickets from {}", tracker.description());
+        agent.set_tracker(tracker);
+    }
+    if let Some(instructions) = profile.instructions {
+        agent.set_instructions(instructions);
+    }
+    agent.set_project_memory(ProjectMemory::default_location(root_path)?);
+    agent.set_prompt_library(PromptLibrary::for_project(root_path)?);
+    agent.set_self_review(options.self_review || profile.self_review || settings.self_review);
+    if let Some(system_message) = system_message {
+        agent.set_system_message(system_message);
+    }
+    agent.set_permission_mediator(permissions);
+    // Permanent grants of the project apply after the rules of the settings
+    let grants = PermissionGrants::default_location(root_path)?;
+    let mut rules = settings.command_rules;
+    rules.extend(grants.load()?.iter().map(Grant::rule));
+    agent.set_permission_policy(PermissionPolicy {
+        mode: options.mode,
+        rules,
+        writable_roots: settings.writable_roots,
+        auto_approve: settings.auto_approve,
+    });
+    agent.set_permission_grants(grants);
+    agent.set_permission_audit(audit);
+    match checkpoints {
+        Some(checkpoints) => agent.set_checkpoints(Box::new(checkpoints)),
+        None if options.ephemeral => {}
+        None => info!("Project is not a git repository, checkpoints are disabled"),
+    }
+    Ok(agent)
+}
+
+/// Opens a file in the editor of the user and waits until it is closed
+fn run_editor(path: &Path) -> Result<()> {
+    let editor = std::env::var("EDITOR").unwrap_or_else(|_| "vi".to_string());
+    let status = std::process::Command::new(&editor)
+        .arg(path)
+        .status()
+        .with_context(|| format!("Failed to run editor '{}'", editor))?;
+    if !status.success() {
+        anyhow::bail!("Editor '{}' exited with {}", editor, status);
+    }
+    Ok(())
+}
+
+/// Reads the task of a pipe run from stdin, the input follows the task given on the
+/// command line, like `git diff | code-assistant run --pipe -t "Review this diff"`
+fn read_piped_task(task: Option<String>) -> Result<String> {
+    if io::IsTerminal::is_terminal(&io::stdin()) {
+        anyhow::bail!(
+            "--pipe reads the task from stdin, e.g. `echo \"task\" | code-assistant run --pipe`"
+        );
+    }
+    let mut input = String::new();
+    io::Read::read_to_string(&mut io::stdin(), &mut input)
+        .context("Failed to read the task from stdin")?;
+    let input = input.trim();
+    let task = match task {
+        Some(task) if input.is_empty() => task,
+        Some(task) => format!("{}\n\n{}", task, input),
+        None => input.to_string(),
+    };
+    if task.trim().is_empty() {
+        anyhow::bail!("The task is empty, aborting");
+    }
+    Ok(task)
+}
+
+/// Lets the user write the task in the editor, starting from a draft
+fn compose_task(draft: &str) -> Result<String> {
+    let path = std::env::temp_dir().join(format!("code-assistant-task-{}.md", std::process::id()));
+    std::fs::write(&path, draft)?;
+    let task = run_editor(&path).and_then(|_| Ok(std::fs::read_to_string(&path)?));
+    let _ = std::fs::remove_file(&path);
+    let task = task?.trim().to_string();
+    if task.is_empty() {
+        anyhow::bail!("The task is empty, aborting");
+    }
+    Ok(task)
+}
+
+fn run_auth_command(command: AuthCommand) -> Result<()> {
+    match command {
+        AuthCommand::Set { provider } => {
+            let var = auth::env_var(provider)
+                .with_context(|| format!("Provider {} needs no API key", provider.name()))?;
+            let key = read_secret(&format!("API key for {}: ", provider.name()))?;
+            auth::keyring::set(provider.name(), &key)?;
+            println!("Stored the API key for {}", provider.name());
+            if std::env::var(var).is_ok() {
+                println!(
+                    "Note: {} is set and takes precedence over the stored key",
+                    var
+                );
+            }
+        }
+        AuthCommand::Delete { provider } => {
+            if auth::keyring::delete(provider.name())? {
+                println!("Removed the API key for {}", provider.name());
+            } else {
+                println!("No API key stored for {}", provider.name());
+            }
+        }
+        AuthCommand::SetToken { service } => {
+            let token = read_secret(&format!("Access token for {}: ", service.name()))?;
+            auth::keyring::set(service.name(), &token)?;
+            println!("Stored the access token for {}", service.name());
+            if std::env::var(service.env_var()).is_ok() {
+                println!(
+                    "Note: {} is set and takes precedence over the stored token",
+                    service.env_var()
+                );
+            }
+        }
+        AuthCommand::DeleteToken { service } => {
+            if auth::keyring::delete(service.name())? {
+                println!("Removed the access token for {}", service.name());
+            } else {
+                println!("No access token stored for {}", service.name());
+            }
+        }
+        AuthCommand::Status => print_api_key_status(""),
+    }
+    Ok(())
+}
+
+/// Reads a key or token from stdin, after printing the prompt
+fn read_secret(prompt: &str) -> Result<String> {
+    print!("{}", prompt);
+    io::Write::flush(&mut io::stdout())?;
+    let mut secret = String::new();
+    io::stdin().read_line(&mut secret)?;
+    let secret = secret.trim();
+    if secret.is_empty() {
+        anyhow::bail!("Nothing given");
+    }
+    Ok(secret.to_string())
+}
+
+/// Prints where the API key of each provider comes from
+fn print_api_key_status(indent: &str) {
+    for provider in LLMProviderType::value_variants() {
+        let Some(var) = auth::env_var(*provider) else {
+            continue;
+        };
+        let source = match auth::api_key(*provider) {
+            Some((_, auth::KeySource::Environment)) => var.to_string(),
+            Some((_, auth::KeySource::Keyring)) => "OS credential store".to_string(),
+            None => "not set".to_string(),
+        };
+        println!("{}{:<10} {}", indent, provider.name(), source);
+    }
+    for service in TokenService::value_variants() {
+        let source = if std::env::var(service.env_var()).is_ok() {
+            service.env_var().to_string()
+        } else if service.token().is_some() {
+            "OS credential store".to_string()
+        } else {
+            "not set".to_string()
+        };
+        println!("{}{:<10} {}", indent, service.name(), source);
+    }
+}
+
+fn run_models_command(command: ModelsCommand) -> Result<()> {
+    match command {
+        ModelsCommand::List { provider, path } => {
+            let root_path = path
+                .canonicalize()
+                .context("Failed to resolve project path")?;
+            let settings = Settings::load_for_project(&root_path)?;
+            let shown = |p: LLMProviderType| provider.is_none_or(|provider| provider == p);
+
+            println!("Default models:");
+            for p in LLMProviderType::value_variants() {
+                if shown(*p) {
+                    println!(
+                        "  {:<10} {}",
+                        p.name(),
+                        p.default_model().unwrap_or("(none, select one with -m)")
+                    );
+                }
+            }
+
+            let aliases: Vec<_> = settings
+                .models
+                .aliases
+                .iter()
+                .filter(|(_, alias)| alias.provider.is_none_or(shown))
+                .collect();
+            if !aliases.is_empty() {
+                println!("Aliases:");
+                for (name, alias) in aliases {
+                    let provider = alias.provider.map_or("(any)", |p| p.name());
+                    println!("  {:<10} {:<10} {}", name, provider, alias.model);
+                }
+            }
+            if let Some(model) = settings.models.default_for(&root_path) {
+                println!("New sessions in {} use {}", root_path.display(), model);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_projects_command(command: ProjectsCommand) -> Result<()> {
+    match command {
+        ProjectsCommand::Add { model, path } => {
+            let root_path = path
+                .canonicalize()
+                .context("Failed to resolve project path")?;
+            if !root_path.is_dir() {
+                anyhow::bail!("Path '{}' is not a directory", root_path.display());
+            }
+            Settings::set_project_model(&root_path, Some(&model))?;
+            println!("New sessions in {} use {}", root_path.display(), model);
+        }
+        ProjectsCommand::Remove { path } => {
+            // The directory may be gone already
+            let root_path = path.canonicalize().unwrap_or(path);
+            if Settings::set_project_model(&root_path, None)? {
+                println!("Removed the default model of {}", root_path.display());
+            } else {
+                println!("{} has no default model", root_path.display());
+            }
+        }
+        ProjectsCommand::List => {
+            let models = Settings::load()?.models;
+            if models.projects.is_empty() {
+                println!("No project directories with a default model");
+            }
+            for (root_path, model) in &models.projects {
+                let missing = if root_path.is_dir() { "" } else { " (missing)" };
+                println!("{:<10} {}{}", model, root_path.display(), missing);
+            }
+            if let Some(model) = &models.default {
+                println!("Other projects use {}", model);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_config_command(command: ConfigCommand) -> Result<()> {
+    match command {
+        ConfigCommand::Doctor { path } => {
+            let root_path = path
+                .canonicalize()
+                .context("Failed to resolve project path")?;
+            let config_dir = settings::config_dir()?;
+            match settings::config_profile() {
+                Some(name) => {
+                    println!("Configuration profile {} in {}", name, config_dir.display())
+                }
+                None => println!("Configuration in {}", config_dir.display()),
+            }
+
+            println!("Settings:");
+            for file in [
+                config_dir.join(settings::SETTINGS_FILE),
+                root_path.join(settings::PROJECT_CONFIG_FILE),
+            ] {
+                let found = if file.exists() { "found" } else { "not found" };
+                println!("  {}: {}", file.display(), found);
+            }
+            println!("API keys:");
+            print_api_key_status("  ");
+
+            let mut problems = Settings::diagnose(&root_path)?;
+            if problems.is_empty() {
+                // Providers the configuration refers to need an API key
+                let settings = Settings::load_for_project(&root_path)?;
+                let default_model = settings.models.default_for(&root_path).cloned();
+                let mut used = vec![(
+                    settings
+                        .models
+                        .resolve(None, default_model)
+                        .0
+                        .unwrap_or_default(),
+                    "new sessions".to_string(),
+                )];
+                used.extend(settings.models.aliases.iter().filter_map(|(name, alias)| {
+                    Some((alias.provider?, format!("model alias '{}'", name)))
+                }));
+                used.extend(settings.profiles.iter().filter_map(|(name, profile)| {
+                    Some((profile.provider?, format!("profile '{}'", name)))
+                }));
+                for provider in LLMProviderType::value_variants() {
+                    let users: Vec<_> = used
+                        .iter()
+                        .filter(|(p, _)| p == provider)
+                        .map(|(_, user)| user.as_str())
+                        .collect();
+                    if users.is_empty() {
+                        continue;
+                    }
+                    if let Err(e) = auth::require_api_key(*provider) {
+                        problems.push(format!("{} (used by {})", e, users.join(", ")));
+                    }
+                }
+            }
+
+            if problems.is_empty() {
+                println!("No problems found");
+            } else {
+                println!("Problems:");
+                for problem in &problems {
+                    println!("  {}", problem);
+                }
+                anyhow::bail!("Found {} problem(s) in the configuration", problems.len());
+            }
+        }
+    }
+    Ok(())
 }
 
 fn setup_logging(verbose: bool, use_stdout: bool) {
@@ -146,35 +1646,106 @@ fn setup_logging(verbose: bool, use_stdout: bool) {
 async fn main() -> Result<()> {
     // Parse command line arguments
     let args = Args::parse();
+    settings::select_config_profile(args.config_profile)?;
 
     match args.mode {
         Mode::Agent {
             path,
             task,
+            prompt,
+            vars,
+            editor,
             continue_task,
+            session,
+            pick_session: pick,
+            fork_at,
+            edit_message,
+            message,
+            list_sessions: list,
+            archived,
+            all_projects,
+            attachments,
+            projects,
+            system_message,
+            profile,
+            plan,
+            dry_run,
+            worktree,
+            self_review,
+            ask_permission,
+            mode,
+            max_iterations,
+            max_cost,
+            max_minutes,
+            max_tokens,
+            ci,
+            report,
+            pipe,
+            tags,
+            output,
             verbose,
             provider,
             model,
             num_ctx,
         } => {
-            // Setup logging based on verbose flag
-            setup_logging(verbose, true);
+            // Setup logging based on verbose flag, JSON output keeps stdout clean for events
+            // and pipe runs keep it clean for the answer
+            let json_output = output == OutputFormat::Json;
+            setup_logging(verbose, !json_output && !pipe);
+
+            let settings = Settings::load()?;
+            let session_store = SessionStore::default_location()?;
+            if let Err(e) = vacuum_sessions(&session_store, &settings) {
+                warn!("Failed to apply the session retention policy: {:#}", e);
+            }
+            if list {
+                let root_path = if all_projects {
+                    None
+                } else {
+                    Some(
+                        path.canonicalize()
+                            .context("Failed to resolve project path")?,
+                    )
+                };
+                return list_sessions(&session_store, root_path.as_deref(), &tags, archived);
+            }
 
             // Ensure the path exists and is a directory
             if !path.is_dir() {
                 anyhow::bail!("Path '{}' is not a directory", path.display());
             }
 
-            // Setup LLM client with the specified provider
-            let llm_client = create_llm_client(provider, model, num_ctx)
-                .context("Failed to initialize LLM client")?;
-
             // Setup dynamic types
             let root_path = path.canonicalize()?;
-            let explorer = Box::new(Explorer::new(root_path.clone()));
-            let terminal_ui = Box::new(TerminalUI::new());
-            let command_executor = Box::new(DefaultCommandExecutor);
-            let state_persistence = Box::new(FileStatePersistence::new(root_path.clone()));
+            let mut settings = Settings::load_for_project(&root_path)?;
+            settings
+                .budget
+                .apply_overrides(max_iterations, max_cost, max_minutes, max_tokens);
+            let ui: Box<dyn UserInterface> = if pipe {
+                Box::new(PipeUI)
+            } else if json_output {
+                Box::new(JsonUI)
+            } else {
+                Box::new(TerminalUI::with_notifications(
+                    settings.notifications.clone(),
+                ))
+            };
+            // A prompt of the library is the task, or the draft of it in the editor
+            let task = match prompt {
+                Some(name) => Some(
+                    PromptLibrary::for_project(&root_path)?
+                        .get(&name)?
+                        .render(&prompts::parse_values(&vars)?)?,
+                ),
+                None => task,
+            };
+            let task = if pipe {
+                Some(read_piped_task(task)?)
+            } else if editor {
+                Some(compose_task(task.as_deref().unwrap_or(""))?)
+            } else {
+                task
+            };
 
             // Validate parameters
             if continue_task && task.is_some() {
@@ -187,21 +1758,265 @@ async fn main() -> Result<()> {
                 anyhow::bail!("Either --task or --continue must be specified");
             }
 
-            // Initialize agent
-            let mut agent = Agent::new(
-                llm_client,
-                explorer,
-                command_executor,
-                terminal_ui,
-                state_persistence,
-            );
+            let attachments = resolve_attachments(&root_path, &attachments)?;
+            let projects = resolve_projects(&projects)?;
+            let system_message = system_message
+                .map(|path| {
+                    path.canonicalize()
+                        .with_context(|| format!("System message '{}' not found", path.display()))
+                })
+                .transpose()?;
+
+            // Model and provider of a profile apply unless given on the command line
+            let (provider, model) = match &profile {
+                Some(name) => {
+                    let profile = settings.profile(name)?;
+                    (
+                        provider.or(profile.provider),
+                        model.or_else(|| profile.model.clone()),
+                    )
+                }
+                None => (provider, model),
+            };
+            // New sessions without a provider or model of their own use the default model
+            let model = match (&provider, model) {
+                (None, None) if !continue_task => settings.models.default_for(&root_path).cloned(),
+                (_, model) => model,
+            };
+            let (provider, model) = settings.models.resolve(provider, model);
+
+            // Select the session to work in
+            let session = if continue_task {
+                let mut session = match session {
+                    Some(id) => session_store.load(&id)?,
+                    None if pick => pick_session(&session_store, &root_path)?,
+                    None => session_store
+                        .latest_unfinished(&root_path)?
+                        .context("No saved session found for this project")?,
+                };
+                // Checkpoint taken before the first modification which is rewound
+                let rewind = edit_message.or(fork_at).and_then(|action_count| {
+                    let checkpoint = session.state.checkpoint_at(action_count)?;
+                    Some((session.metadata.id.clone(), checkpoint))
+                });
+                if let Some(action_count) = fork_at {
+                    let fork = session_store.fork(&session, action_count)?;
+                    println!(
+                        "Forked session {} at action {} into new session {}",
+                        session.metadata.id, action_count, fork.metadata.id
+                    );
+                    if let Some(worktree) = &fork.metadata.worktree {
+                        info!(
+                            "Working in worktree {} on branch {}",
+                            worktree.path.display(),
+                            worktree.branch
+                        );
+                    }
+                    session = fork;
+                }
+                let edited = match (edit_message, message) {
+                    (Some(index), Some(message)) => {
+                        session.state.edit_user_message(index, message)?;
+                        true
+                    }
+                    _ => false,
+                };
+                if let Some((id, checkpoint)) = rewind {
+                    offer_restore(&session_store, &id, checkpoint)?;
+                }
+                session.state.add_attachments(&attachments);
+                session.metadata.add_projects(&projects);
+                // Overrides given on the command line stick with the session
+                if system_message.is_some() {
+                    session.metadata.system_message = system_message;
+                }
+                if profile.is_some() {
+                    session.metadata.profile = profile;
+                }
+                session
+                    .metadata
+                    .llm_config
+                    .apply_overrides(provider, model, num_ctx);
+                if edited {
+                    session_store.rewrite(&session)?;
+                } else {
+                    session_store.save(&session)?;
+                }
+                session
+            } else {
+                let mut session = Session::new(root_path.clone());
+                session.metadata.add_tags(&tags);
+                session.state.add_attachments(&attachments);
+                session.metadata.add_projects(&projects);
+                session.metadata.system_message = system_message;
+                session.metadata.plan_mode = plan || mode == Some(PermissionMode::Plan);
+                session.metadata.profile = profile;
+                session
+                    .metadata
+                    .llm_config
+                    .apply_overrides(provider, model, num_ctx);
+                if worktree {
+                    let worktree =
+                        Worktree::create_in_default_location(&root_path, &session.metadata.id)?;
+                    info!(
+                        "Working in worktree {} on branch {}",
+                        worktree.path.display(),
+                        worktree.branch
+                    );
+                    session.metadata.worktree = Some(worktree);
+                }
+                session
+            };
+
+            let mode = permission_mode(mode, ask_permission, &settings);
+            let notifier = TerminalUI::with_notifications(settings.notifications.clone());
+            let permissions: Box<dyn PermissionMediator> = if json_output {
+                Box::new(JsonUI)
+            } else {
+                Box::new(TerminalUI::with_notifications(
+                    settings.notifications.clone(),
+                ))
+            };
+            // Nobody answers questions or permission requests in CI and pipe mode
+            let (ui, permissions): (Box<dyn UserInterface>, Box<dyn PermissionMediator>) =
+                if ci || pipe {
+                    let ci_ui = CiUI::new(ui);
+                    (Box::new(ci_ui.clone()), Box::new(ci_ui))
+                } else {
+                    (ui, permissions)
+                };
+            let session_id = session.metadata.id.clone();
+            let webhooks = Arc::new(Notifier::new(&settings.notifications));
+            let permissions: Box<dyn PermissionMediator> = if webhooks.is_empty() {
+                permissions
+            } else {
+                Box::new(NotifyingMediator::new(
+                    permissions,
+                    webhooks.clone(),
+                    &session_id,
+                    &root_path,
+                ))
+            };
+            let started = Instant::now();
+            let mut agent = create_agent(
+                settings,
+                &root_path,
+                session_store,
+                session,
+                ui,
+                permissions,
+                RunOptions {
+                    mode,
+                    dry_run,
+                    self_review,
+                    ephemeral: pipe,
+                },
+            )?;
 
             // Get task either from state file or argument
-            if continue_task {
-                agent.start_from_state().await?;
+            let result = if continue_task {
+                agent.start_from_state().await
+            } else {
+                agent.start_with_task(task.unwrap()).await
+            };
+            webhooks
+                .send(&Notification::run_ended(
+                    &session_id,
+                    &root_path,
+                    agent.task(),
+                    &result,
+                    agent.completed(),
+                    agent.completion_message(),
+                    started.elapsed(),
+                ))
+                .await;
+            if pipe {
+                let message = agent.completion_message().map(str::to_string);
+                if json_output {
+                    let mut summary = summary_json(&result, agent.usage(), agent.estimated_cost());
+                    summary["message"] = serde_json::json!(message);
+                    JsonUI.emit(&summary)?;
+                } else if let Some(message) = &message {
+                    println!("{}", message);
+                }
+                result?;
+                if message.is_none() {
+                    anyhow::bail!("The agent stopped without completing the task");
+                }
+                return Ok(());
+            }
+            if ci {
+                let task = agent.task().to_string();
+                let outcome = CiOutcome::new(
+                    &result,
+                    agent.completed(),
+                    agent.budget_exceeded().is_some(),
+                );
+                let report_json = ci::report(
+                    outcome,
+                    &CiRun {
+                        session_id: &session_id,
+                        task: &task,
+                        result: &result,
+                        actions: agent.actions(),
+                        budget_exceeded: agent.budget_exceeded(),
+                        usage: agent.usage(),
+                        cost: agent.estimated_cost(),
+                        duration: started.elapsed(),
+                    },
+                );
+                std::fs::write(&report, serde_json::to_string_pretty(&report_json)?)
+                    .with_context(|| format!("Failed to write report {}", report.display()))?;
+                if json_output {
+                    JsonUI.emit(&report_json)?;
+                } else {
+                    if let Err(e) = &result {
+                        eprintln!("Error: {:#}", e);
+                    }
+                    eprintln!(
+                        "Run ended: {}, report written to {}",
+                        outcome.name(),
+                        report.display()
+                    );
+                }
+                std::process::exit(outcome.exit_code());
+            }
+            if json_output {
+                JsonUI.emit(&summary_json(
+                    &result,
+                    agent.usage(),
+                    agent.estimated_cost(),
+                ))?;
             } else {
-                agent.start_with_task(task.unwrap()).await?;
+                notifier.notify(match &result {
+                    Ok(()) => "The agent finished working",
+                    Err(_) => "The agent stopped with an error",
+                });
+            }
+            result?;
+        }
+
+        Mode::Daemon { port, verbose } => {
+            setup_logging(verbose, false);
+            let settings = Settings::load()?;
+            let auth = LocalAuth::load(settings.allowed_origins)?;
+            daemon::run(port, settings.schedules, auth).await?;
+        }
+
+        Mode::Api {
+            path,
+            port,
+            verbose,
+        } => {
+            setup_logging(verbose, false);
+            let root_path = path
+                .canonicalize()
+                .context("Failed to resolve project path")?;
+            if !root_path.is_dir() {
+                anyhow::bail!("Path '{}' is not a directory", root_path.display());
             }
+            let auth = LocalAuth::load(Settings::load()?.allowed_origins)?;
+            api::run(root_path, port, auth).await?;
         }
 
         Mode::Server { path, verbose } => {
@@ -219,9 +2034,50 @@ async fn main() -> Result<()> {
             }
 
             // Initialize server
-            let mut server = MCPServer::new(root_path)?;
+            let settings = Settings::load_for_project(&root_path)?;
+            let mut server = MCPServer::new(root_path, settings.server.disabled_tools)?;
             server.run().await?;
         }
+
+        Mode::Session { command } => {
+            setup_logging(false, true);
+            run_session_command(&SessionStore::default_location()?, command)?;
+        }
+
+        Mode::Prompts { command } => {
+            setup_logging(false, true);
+            run_prompts_command(command)?;
+        }
+
+        Mode::Memory { command } => {
+            setup_logging(false, true);
+            run_memory_command(command)?;
+        }
+
+        Mode::Auth { command } => {
+            setup_logging(false, true);
+            run_auth_command(command)?;
+        }
+
+        Mode::Permissions { command } => {
+            setup_logging(false, true);
+            run_permissions_command(command)?;
+        }
+
+        Mode::Models { command } => {
+            setup_logging(false, true);
+            run_models_command(command)?;
+        }
+
+        Mode::Config { command } => {
+            setup_logging(false, true);
+            run_config_command(command)?;
+        }
+
+        Mode::Projects { command } => {
+            setup_logging(false, true);
+            run_projects_command(command)?;
+        }
     }
 
     Ok(())
@@ -1,6 +1,7 @@
 use super::resources::ResourceManager;
 use super::types::*;
 use crate::explorer::Explorer;
+use crate::search_index::SearchIndex;
 use crate::types::{CodeExplorer, FileUpdate, SearchMode, SearchOptions};
 use crate::utils::format_with_line_numbers;
 use crate::utils::{CommandExecutor, DefaultCommandExecutor};
@@ -9,19 +10,36 @@ use std::path::PathBuf;
 use tokio::io::{AsyncWriteExt, Stdout};
 use tracing::{debug, error, trace};
 
+/// Names of the tools the server offers
+pub const TOOL_NAMES: &[&str] = &[
+    "search",
+    "execute-command",
+    "list-files",
+    "load-file",
+    "summarize",
+    "update-file",
+    "delete-file",
+];
+
 pub struct MessageHandler {
     explorer: Box<dyn CodeExplorer>,
     command_executor: Box<dyn CommandExecutor>,
     resources: ResourceManager,
+    /// Names of the tools which are neither listed nor can be called
+    disabled_tools: Vec<String>,
     stdout: Stdout,
 }
 
 impl MessageHandler {
-    pub fn new(root_path: PathBuf, stdout: Stdout) -> Result<Self> {
+    pub fn new(root_path: PathBuf, disabled_tools: Vec<String>, stdout: Stdout) -> Result<Self> {
         Ok(Self {
-            explorer: Box::new(Explorer::new(root_path.clone())),
-            command_executor: Box::new(DefaultCommandExecutor),
+            explorer: Box::new(
+                Explorer::new(root_path.clone())
+                    .with_search_index(SearchIndex::default_location(&root_path)?),
+            ),
+            command_executor: Box::new(DefaultCommandExecutor::new(root_path.clone())),
             resources: ResourceManager::new(),
+            disabled_tools,
             stdout,
         })
     }
@@ -210,182 +228,184 @@ impl MessageHandler {
     /// Handle tools/list request
     async fn handle_tools_list(&mut self, id: RequestId) -> Result<()> {
         debug!("Handling tools/list request");
-        self.send_response(
-            id,
-            ListToolsResult {
-                tools: vec![
-                    Tool {
-                        name: "search".to_string(),
-                        description: Some("Search for text in files with advanced options".to_string()),
-                        input_schema: serde_json::json!({
-                            "type": "object",
-                            "properties": {
-                                "query": {
-                                    "type": "string",
-                                    "description": "The text to search for. Supports regular expressions."
-                                },
-                                "path": {
-                                    "type": "string",
-                                    "description": "Optional: directory path to search in (relative to project root)"
-                                },
-                                "max_results": {
-                                    "type": "integer",
-                                    "description": "Optional: maximum number of results to return"
-                                },
-                                "case_sensitive": {
-                                    "type": "boolean",
-                                    "description": "Optional: whether the search should be case-sensitive (default: false)"
-                                },
-                                "whole_words": {
-                                    "type": "boolean",
-                                    "description": "Optional: match whole words only (default: false)"
-                                },
-                                "mode": {
-                                    "type": "string",
-                                    "description": "Optional: search mode - 'exact' (default) for standard text search, or 'regex' for regular expressions",
-                                    "enum": ["exact", "regex"]
-                                }
-                            },
-                            "required": ["query"]
-                        }),
+        let mut tools = vec![
+            Tool {
+                name: "search".to_string(),
+                description: Some("Search for text in files with advanced options".to_string()),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "query": {
+                            "type": "string",
+                            "description": "The text to search for. Supports regular expressions."
+                        },
+                        "path": {
+                            "type": "string",
+                            "description": "Optional: directory path to search in (relative to project root)"
+                        },
+                        "max_results": {
+                            "type": "integer",
+                            "description": "Optional: maximum number of results to return"
+                        },
+                        "case_sensitive": {
+                            "type": "boolean",
+                            "description": "Optional: whether the search should be case-sensitive (default: false)"
+                        },
+                        "whole_words": {
+                            "type": "boolean",
+                            "description": "Optional: match whole words only (default: false)"
+                        },
+                        "mode": {
+                            "type": "string",
+                            "description": "Optional: search mode - 'exact' (default) for standard text search, or 'regex' for regular expressions",
+                            "enum": ["exact", "regex"]
+                        }
                     },
-                    Tool {
-                        name: "execute-command".to_string(),
-                        description: Some("Execute a command line program".to_string()),
-                        input_schema: serde_json::json!({
-                            "type": "object",
-                            "properties": {
-                                "command_line": {
-                                    "type": "string",
-                                    "description": "The complete command to execute"
-                                },
-                                "working_dir": {
-                                    "type": "string",
-                                    "description": "Optional: working directory for the command"
-                                }
-                            },
-                            "required": ["command_line"]
-                        }),
+                    "required": ["query"]
+                }),
+            },
+            Tool {
+                name: "execute-command".to_string(),
+                description: Some("Execute a command line program".to_string()),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "command_line": {
+                            "type": "string",
+                            "description": "The complete command to execute"
+                        },
+                        "working_dir": {
+                            "type": "string",
+                            "description": "Optional: working directory for the command"
+                        }
                     },
-                    Tool {
-                        name: "list-files".to_string(),
-                        description: Some("List files in a directory".to_string()),
-                        input_schema: serde_json::json!({
-                            "type": "object",
-                            "properties": {
-                                "path": {
-                                    "type": "string",
-                                    "description": "Directory path relative to project root"
-                                },
-                                "max_depth": {
-                                    "type": "integer",
-                                    "description": "Maximum directory depth"
-                                }
-                            },
-                            "required": ["path"]
-                        }),
+                    "required": ["command_line"]
+                }),
+            },
+            Tool {
+                name: "list-files".to_string(),
+                description: Some("List files in a directory".to_string()),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "path": {
+                            "type": "string",
+                            "description": "Directory path relative to project root"
+                        },
+                        "max_depth": {
+                            "type": "integer",
+                            "description": "Maximum directory depth"
+                        }
                     },
-                    Tool {
-                        name: "load-file".to_string(),
-                        description: Some(
-                            "Load a file into working memory for access as a resource".to_string(),
-                        ),
-                        input_schema: serde_json::json!({
-                            "type": "object",
-                            "properties": {
-                                "path": {
-                                    "type": "string",
-                                    "description": "Relative path to the file from project root"
-                                }
-                            },
-                            "required": ["path"]
-                        }),
+                    "required": ["path"]
+                }),
+            },
+            Tool {
+                name: "load-file".to_string(),
+                description: Some(
+                    "Load a file into working memory for access as a resource".to_string(),
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "path": {
+                            "type": "string",
+                            "description": "Relative path to the file from project root"
+                        }
                     },
-                    Tool {
-                        name: "summarize".to_string(),
-                        description: Some("Replace file content with a summary in working memory, unloading the full content.".to_string()),
-                        input_schema: serde_json::json!({
-                            "type": "object",
-                            "properties": {
-                                "files": {
-                                    "type": "array",
-                                    "items": {
-                                        "type": "object",
-                                        "properties": {
-                                            "path": {
-                                                "type": "string",
-                                                "description": "Path to the file to summarize"
-                                            },
-                                            "summary": {
-                                                "type": "string",
-                                                "description": "Your summary of the file contents"
-                                            }
-                                        },
-                                        "required": ["path", "summary"]
+                    "required": ["path"]
+                }),
+            },
+            Tool {
+                name: "summarize".to_string(),
+                description: Some("Replace file content with a summary in working memory, unloading the full content.".to_string()),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "files": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "path": {
+                                        "type": "string",
+                                        "description": "Path to the file to summarize"
+                                    },
+                                    "summary": {
+                                        "type": "string",
+                                        "description": "Your summary of the file contents"
                                     }
-                                }
-                            },
-                            "required": ["files"]
-                        }),
-                    },
-                    Tool {
-                        name: "update-file".to_string(),
-                        description: Some(
-                            "Update sections in an existing file based on line numbers. IMPORTANT: Line numbers are 1-based, \
-                             matching the line numbers shown when viewing file resources. The end_line is exclusive, \
-                             meaning the section to replace ends before that line. For example, to replace lines 1-3, \
-                             use start_line: 1, end_line: 4. To insert new content without replacing anything, \
-                             use the same start_line and end_line. Provide the new content parameter first, \
-                             then start_line and end_line parameter according to what needs to be replaced.".to_string()
-                        ),
-                        input_schema: serde_json::json!({
-                            "type": "object",
-                            "properties": {
-                                "path": {
-                                    "type": "string",
-                                    "description": "Relative path to the file to update"
                                 },
-                                "updates": {
-                                    "type": "array",
-                                    "description": "List of updates to apply to the file",
-                                    "items": {
-                                        "type": "object",
-                                        "properties": {
-                                            "new_content": {
-                                                "type": "string",
-                                                "description": "The new content to insert (without line numbers)"
-                                            },
-                                            "start_line": {
-                                                "type": "integer",
-                                                "description": "First line number to replace (1-based, matching the displayed line numbers)"
-                                            },
-                                            "end_line": {
-                                                "type": "integer",
-                                                "description": "Line number right after the section to replace (1-based, matching the displayed line numbers)"
-                                            }
-                                        },
-                                        "required": ["new_content", "start_line", "end_line"]
+                                "required": ["path", "summary"]
+                            }
+                        }
+                    },
+                    "required": ["files"]
+                }),
+            },
+            Tool {
+                name: "update-file".to_string(),
+                description: Some(
+                    "Update sections in an existing file based on line numbers. IMPORTANT: Line numbers are 1-based, \
+                     matching the line numbers shown when viewing file resources. The end_line is exclusive, \
+                     meaning the section to replace ends before that line. For example, to replace lines 1-3, \
+                     use start_line: 1, end_line: 4. To insert new content without replacing anything, \
+                     use the same start_line and end_line. Provide the new content parameter first, \
+                     then start_line and end_line parameter according to what needs to be replaced.".to_string()
+                ),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "path": {
+                            "type": "string",
+                            "description": "Relative path to the file to update"
+                        },
+                        "updates": {
+                            "type": "array",
+                            "description": "List of updates to apply to the file",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "new_content": {
+                                        "type": "string",
+                                        "description": "The new content to insert (without line numbers)"
+                                    },
+                                    "start_line": {
+                                        "type": "integer",
+                                        "description": "First line number to replace (1-based, matching the displayed line numbers)"
+                                    },
+                                    "end_line": {
+                                        "type": "integer",
+                                        "description": "Line number right after the section to replace (1-based, matching the displayed line numbers)"
                                     }
-                                }
-                            },
-                            "required": ["path", "updates"]
-                        }),
+                                },
+                                "required": ["new_content", "start_line", "end_line"]
+                            }
+                        }
                     },
-                    Tool {
-                        name: "delete-file".to_string(),
-                        description: Some("Delete a file from the workspace. This operation cannot be undone!".to_string()),
-                        input_schema: serde_json::json!({
-                            "type": "object",
-                            "properties": {
-                                "path": {
-                                    "type": "string",
-                                    "description": "Relative path to the file to delete"
-                                }
-                            },
-                            "required": ["path"]
-                        }),
+                    "required": ["path", "updates"]
+                }),
+            },
+            Tool {
+                name: "delete-file".to_string(),
+                description: Some("Delete a file from the workspace. This operation cannot be undone!".to_string()),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "path": {
+                            "type": "string",
+                            "description": "Relative path to the file to delete"
+                        }
                     },
-                ],
+                    "required": ["path"]
+                }),
+            },
+        ];
+        tools.retain(|tool| !self.disabled_tools.contains(&tool.name));
+        self.send_response(
+            id,
+            ListToolsResult {
+                tools,
                 next_cursor: None,
             },
         )
@@ -395,6 +415,16 @@ impl MessageHandler {
     /// Handle tools/call request
     async fn handle_tool_call(&mut self, id: RequestId, params: ToolCallParams) -> Result<()> {
         debug!("Handling tool call for {}", params.name);
+        if self.disabled_tools.contains(&params.name) {
+            return self
+                .send_error(
+                    id,
+                    -32601,
+                    format!("Tool {} is disabled in the settings", params.name),
+                    None,
+                )
+                .await;
+        }
         let result = match params.name.as_str() {
             "load-file" => {
                 let path = match params.arguments {
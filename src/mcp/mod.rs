@@ -3,4 +3,5 @@ mod resources;
 mod server;
 mod types;
 
+pub use handler::TOOL_NAMES;
 pub use server::MCPServer;
@@ -9,9 +9,9 @@ pub struct MCPServer {
 }
 
 impl MCPServer {
-    pub fn new(root_path: PathBuf) -> Result<Self> {
+    pub fn new(root_path: PathBuf, disabled_tools: Vec<String>) -> Result<Self> {
         Ok(Self {
-            handler: MessageHandler::new(root_path, tokio::io::stdout())?,
+            handler: MessageHandler::new(root_path, disabled_tools, tokio::io::stdout())?,
         })
     }
 
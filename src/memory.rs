@@ -0,0 +1,129 @@
+use crate::settings::config_dir;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// The project path turned into a file name with a hash of the canonical path, e.g.
+/// `-home-user-project-f8a71a04e8340307.md`. The readable part alone is the same for
+/// paths like `/a/b-c` and `/a/b/c`.
+pub fn project_file_name(root_dir: &Path, extension: &str) -> String {
+    let root_dir = root_dir
+        .canonicalize()
+        .unwrap_or_else(|_| root_dir.to_path_buf());
+    format!(
+        "{}-{:016x}.{}",
+        readable_name(&root_dir),
+        fnv1a(root_dir.to_string_lossy().as_bytes()),
+        extension
+    )
+}
+
+fn readable_name(root_dir: &Path) -> String {
+    root_dir
+        .to_string_lossy()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect()
+}
+
+/// 64-bit FNV-1a, which unlike the hasher of the standard library stays the same
+/// across Rust versions
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Notes the agent keeps about a project across sessions, like build commands,
+/// architecture and gotchas. Stored as Markdown file per project in the config directory.
+#[derive(Debug, Clone)]
+pub struct ProjectMemory {
+    path: PathBuf,
+}
+
+impl ProjectMemory {
+    /// Memory of the project in `root_dir`, stored inside `dir`. Notes stored by earlier
+    /// versions under the path alone are moved over.
+    pub fn new(dir: &Path, root_dir: &Path) -> Self {
+        let path = dir.join(project_file_name(root_dir, "md"));
+        let legacy = dir.join(format!("{}.md", readable_name(root_dir)));
+        if !path.exists() && legacy.exists() {
+            if let Err(e) = std::fs::rename(&legacy, &path) {
+                warn!("Failed to move project memory {}: {}", legacy.display(), e);
+            }
+        }
+        Self { path }
+    }
+
+    /// Uses the memory directory inside the user's config directory
+    pub fn default_location(root_dir: &Path) -> Result<Self> {
+        Ok(Self::new(&config_dir()?.join("memory"), root_dir))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the notes, empty if there are none yet
+    pub fn load(&self) -> Result<String> {
+        if !self.path.exists() {
+            return Ok(String::new());
+        }
+        std::fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read project memory {}", self.path.display()))
+    }
+
+    /// Replaces the notes, empty content removes the memory file
+    pub fn save(&self, content: &str) -> Result<()> {
+        if content.trim().is_empty() {
+            if self.path.exists() {
+                std::fs::remove_file(&self.path)?;
+            }
+            return Ok(());
+        }
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&self.path, content)
+            .with_context(|| format!("Failed to write project memory {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_project_memory() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        // Notes of an earlier version are moved to the new name
+        std::fs::write(
+            temp_dir.path().join("-home-user-my-project.md"),
+            "- Old notes\n",
+        )?;
+        let memory = ProjectMemory::new(temp_dir.path(), Path::new("/home/user/my project"));
+        assert_eq!(
+            memory.path(),
+            temp_dir
+                .path()
+                .join("-home-user-my-project-aa24f4ba6c08d84d.md")
+        );
+        assert_eq!(memory.load()?, "- Old notes\n");
+
+        memory.save("- Build with `cargo build --release`\n")?;
+        assert_eq!(memory.load()?, "- Build with `cargo build --release`\n");
+
+        // Other projects have their own memory, even if their paths look the same
+        let other = ProjectMemory::new(temp_dir.path(), Path::new("/home/user/other"));
+        assert_eq!(other.load()?, "");
+        assert_ne!(
+            project_file_name(Path::new("/a/b-c"), "md"),
+            project_file_name(Path::new("/a/b/c"), "md")
+        );
+
+        memory.save("")?;
+        assert!(!memory.path().exists());
+        Ok(())
+    }
+}
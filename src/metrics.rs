@@ -0,0 +1,365 @@
+use crate::llm::{LLMProvider, LLMRequest, LLMResponse};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Upper bounds in seconds of the buckets of the duration histograms
+const BUCKETS: &[f64] = &[0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0];
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    /// Observations per bucket, not cumulative
+    counts: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if let Some(bucket) = BUCKETS.iter().position(|bound| value <= *bound) {
+            self.counts[bucket] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Values {
+    /// By provider and status
+    llm_requests: BTreeMap<(String, &'static str), u64>,
+    llm_request_duration: BTreeMap<String, Histogram>,
+    /// By provider and `input` or `output`
+    tokens: BTreeMap<(String, &'static str), u64>,
+    /// By tool and status
+    tool_executions: BTreeMap<(String, &'static str), u64>,
+    tool_duration: BTreeMap<String, Histogram>,
+    /// By outcome
+    sessions: BTreeMap<&'static str, u64>,
+    active_sessions: i64,
+}
+
+/// Counters and histograms of the agent runs in this process, served in the Prometheus
+/// text format by the daemon
+#[derive(Debug, Default)]
+pub struct Metrics {
+    values: Mutex<Values>,
+}
+
+/// Metrics of the process
+pub fn global() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::default)
+}
+
+fn status(success: bool) -> &'static str {
+    if success {
+        "success"
+    } else {
+        "error"
+    }
+}
+
+impl Metrics {
+    pub fn record_llm_request(
+        &self,
+        provider: &str,
+        duration: Duration,
+        result: &Result<LLMResponse>,
+    ) {
+        let mut values = self.values.lock().unwrap();
+        *values
+            .llm_requests
+            .entry((provider.to_string(), status(result.is_ok())))
+            .or_default() += 1;
+        values
+            .llm_request_duration
+            .entry(provider.to_string())
+            .or_default()
+            .observe(duration.as_secs_f64());
+        if let Ok(response) = result {
+            *values
+                .tokens
+                .entry((provider.to_string(), "input"))
+                .or_default() += response.usage.input_tokens;
+            *values
+                .tokens
+                .entry((provider.to_string(), "output"))
+                .or_default() += response.usage.output_tokens;
+        }
+    }
+
+    pub fn record_tool(&self, tool: &str, success: bool, duration: Duration) {
+        let mut values = self.values.lock().unwrap();
+        *values
+            .tool_executions
+            .entry((tool.to_string(), status(success)))
+            .or_default() += 1;
+        values
+            .tool_duration
+            .entry(tool.to_string())
+            .or_default()
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Counts a run as active until the returned guard is dropped
+    pub fn session_started(&'static self) -> ActiveSession {
+        self.values.lock().unwrap().active_sessions += 1;
+        ActiveSession {
+            metrics: self,
+            outcome: "aborted",
+        }
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let values = self.values.lock().unwrap();
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "code_assistant_llm_requests_total",
+            "counter",
+            "Requests to LLM providers",
+        );
+        for ((provider, status), value) in &values.llm_requests {
+            sample(
+                &mut out,
+                "code_assistant_llm_requests_total",
+                &[("provider", provider), ("status", status)],
+                *value as f64,
+            );
+        }
+        histogram(
+            &mut out,
+            "code_assistant_llm_request_duration_seconds",
+            "Duration of requests to LLM providers",
+            "provider",
+            &values.llm_request_duration,
+        );
+        header(
+            &mut out,
+            "code_assistant_tokens_total",
+            "counter",
+            "Tokens used by LLM requests",
+        );
+        for ((provider, kind), value) in &values.tokens {
+            sample(
+                &mut out,
+                "code_assistant_tokens_total",
+                &[("provider", provider), ("type", kind)],
+                *value as f64,
+            );
+        }
+        header(
+            &mut out,
+            "code_assistant_tool_executions_total",
+            "counter",
+            "Tool calls executed by the agent",
+        );
+        for ((tool, status), value) in &values.tool_executions {
+            sample(
+                &mut out,
+                "code_assistant_tool_executions_total",
+                &[("tool", tool), ("status", status)],
+                *value as f64,
+            );
+        }
+        histogram(
+            &mut out,
+            "code_assistant_tool_duration_seconds",
+            "Duration of tool executions",
+            "tool",
+            &values.tool_duration,
+        );
+        header(
+            &mut out,
+            "code_assistant_sessions_total",
+            "counter",
+            "Agent runs which ended, by outcome",
+        );
+        for (outcome, value) in &values.sessions {
+            sample(
+                &mut out,
+                "code_assistant_sessions_total",
+                &[("outcome", outcome)],
+                *value as f64,
+            );
+        }
+        header(
+            &mut out,
+            "code_assistant_active_sessions",
+            "gauge",
+            "Agent runs in progress",
+        );
+        sample(
+            &mut out,
+            "code_assistant_active_sessions",
+            &[],
+            values.active_sessions as f64,
+        );
+        out
+    }
+}
+
+/// Run in progress, counted with its outcome once dropped. Runs dropped without an
+/// outcome were aborted, like when the client of the daemon disconnected.
+pub struct ActiveSession {
+    metrics: &'static Metrics,
+    outcome: &'static str,
+}
+
+impl ActiveSession {
+    /// `outcome` is `completed`, `not_completed` or `error`
+    pub fn ended(mut self, outcome: &'static str) {
+        self.outcome = outcome;
+    }
+}
+
+impl Drop for ActiveSession {
+    fn drop(&mut self) {
+        let mut values = self.metrics.values.lock().unwrap();
+        values.active_sessions -= 1;
+        *values.sessions.entry(self.outcome).or_default() += 1;
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn sample(out: &mut String, name: &str, labels: &[(&str, &str)], value: f64) {
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(key, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", key, value)
+        })
+        .collect();
+    if labels.is_empty() {
+        let _ = writeln!(out, "{} {}", name, value);
+    } else {
+        let _ = writeln!(out, "{}{{{}}} {}", name, labels.join(","), value);
+    }
+}
+
+fn histogram(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    label: &str,
+    histograms: &BTreeMap<String, Histogram>,
+) {
+    header(out, name, "histogram", help);
+    let bucket_name = format!("{}_bucket", name);
+    for (key, histogram) in histograms {
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(histogram.counts) {
+            cumulative += count;
+            sample(
+                out,
+                &bucket_name,
+                &[(label, key), ("le", &bound.to_string())],
+                cumulative as f64,
+            );
+        }
+        sample(
+            out,
+            &bucket_name,
+            &[(label, key), ("le", "+Inf")],
+            histogram.count as f64,
+        );
+        sample(
+            out,
+            &format!("{}_sum", name),
+            &[(label, key)],
+            histogram.sum,
+        );
+        sample(
+            out,
+            &format!("{}_count", name),
+            &[(label, key)],
+            histogram.count as f64,
+        );
+    }
+}
+
+/// Records the requests of a provider in the metrics of the process
+pub struct MeteredProvider {
+    inner: Box<dyn LLMProvider>,
+    provider: &'static str,
+}
+
+impl MeteredProvider {
+    pub fn new(inner: Box<dyn LLMProvider>, provider: &'static str) -> Self {
+        Self { inner, provider }
+    }
+}
+
+#[async_trait]
+impl LLMProvider for MeteredProvider {
+    async fn send_message(&self, request: LLMRequest) -> Result<LLMResponse> {
+        let started = Instant::now();
+        let result = self.inner.send_message(request).await;
+        global().record_llm_request(self.provider, started.elapsed(), &result);
+        result
+    }
+
+    fn context_window(&self) -> Option<usize> {
+        self.inner.context_window()
+    }
+
+    async fn count_tokens(&self, request: &LLMRequest) -> Result<usize> {
+        self.inner.count_tokens(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics: &'static Metrics = Box::leak(Box::default());
+        metrics.record_tool("ExecuteCommand", true, Duration::from_millis(700));
+        metrics.record_tool("ExecuteCommand", false, Duration::from_secs(400));
+        metrics.record_llm_request(
+            "anthropic",
+            Duration::from_secs(2),
+            &Err(anyhow::anyhow!("Rate limited")),
+        );
+        let active = metrics.session_started();
+        metrics.session_started().ended("completed");
+
+        let text = metrics.render();
+        assert!(text.contains(
+            "code_assistant_tool_executions_total{tool=\"ExecuteCommand\",status=\"error\"} 1\n"
+        ));
+        assert!(text.contains(
+            "code_assistant_tool_duration_seconds_bucket{tool=\"ExecuteCommand\",le=\"0.5\"} 0\n\
+             code_assistant_tool_duration_seconds_bucket{tool=\"ExecuteCommand\",le=\"1\"} 1\n"
+        ));
+        // Observations above the largest bucket only count for +Inf
+        assert!(text.contains(
+            "code_assistant_tool_duration_seconds_bucket{tool=\"ExecuteCommand\",le=\"300\"} 1\n\
+             code_assistant_tool_duration_seconds_bucket{tool=\"ExecuteCommand\",le=\"+Inf\"} 2\n\
+             code_assistant_tool_duration_seconds_sum{tool=\"ExecuteCommand\"} 400.7\n"
+        ));
+        assert!(text.contains(
+            "code_assistant_llm_requests_total{provider=\"anthropic\",status=\"error\"} 1\n"
+        ));
+        assert!(text.contains("code_assistant_sessions_total{outcome=\"completed\"} 1\n"));
+        assert!(text.contains("code_assistant_active_sessions 1\n"));
+        drop(active);
+        assert!(metrics
+            .render()
+            .contains("code_assistant_sessions_total{outcome=\"aborted\"} 1\n"));
+    }
+}
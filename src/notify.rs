@@ -0,0 +1,338 @@
+use crate::permissions::{PermissionDecision, PermissionMediator, PermissionRequest};
+use crate::settings::{NotificationSettings, WebhookSettings};
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+/// Discord rejects messages with more characters
+const MAX_DISCORD_LENGTH: usize = 2000;
+
+/// What a webhook is posted about
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationEvent {
+    /// The agent completed the task
+    Finished,
+    /// The agent stopped with an error or without completing the task
+    Failed,
+    /// The agent waits for the user to grant or deny a permission
+    Permission,
+}
+
+/// Payload format of a webhook
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    /// Slack incoming webhook, also understood by Mattermost and Rocket.Chat
+    Slack,
+    Discord,
+    /// JSON object with all fields of the notification, for custom receivers
+    Json,
+}
+
+impl WebhookFormat {
+    fn detect(url: &str) -> Self {
+        if url.contains("hooks.slack.com") {
+            WebhookFormat::Slack
+        } else if url.contains("discord.com/api/webhooks")
+            || url.contains("discordapp.com/api/webhooks")
+        {
+            WebhookFormat::Discord
+        } else {
+            WebhookFormat::Json
+        }
+    }
+}
+
+/// Something that happened in a session which is worth a message
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub event: NotificationEvent,
+    pub session_id: String,
+    pub project: PathBuf,
+    /// First line of the task, empty if it is not known
+    pub task: String,
+    /// Completion message, error or requested permission
+    pub details: String,
+    /// How long the task ran, `None` for permission requests
+    pub duration: Option<Duration>,
+}
+
+impl Notification {
+    /// Notification about the end of a run, `message` is the completion message of the agent
+    pub fn run_ended(
+        session_id: &str,
+        project: &Path,
+        task: &str,
+        result: &Result<()>,
+        completed: bool,
+        message: Option<&str>,
+        duration: Duration,
+    ) -> Self {
+        let (event, details) = match result {
+            Ok(()) if completed => (
+                NotificationEvent::Finished,
+                message.unwrap_or_default().to_string(),
+            ),
+            Ok(()) => (
+                NotificationEvent::Failed,
+                "The agent stopped without completing the task".to_string(),
+            ),
+            Err(e) => (NotificationEvent::Failed, format!("{:#}", e)),
+        };
+        Self {
+            event,
+            session_id: session_id.to_string(),
+            project: project.to_path_buf(),
+            task: task.lines().next().unwrap_or_default().to_string(),
+            details,
+            duration: Some(duration),
+        }
+    }
+}
+
+/// Posts notifications to the webhooks of the settings
+pub struct Notifier {
+    client: Client,
+    webhooks: Vec<WebhookSettings>,
+    session_url: Option<String>,
+}
+
+impl Notifier {
+    pub fn new(settings: &NotificationSettings) -> Self {
+        Self {
+            client: Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+            webhooks: settings.webhooks.clone(),
+            session_url: settings.session_url.clone(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.webhooks.is_empty()
+    }
+
+    /// Posts the notification to all webhooks interested in it. Failures are only
+    /// logged, a broken webhook must not stop the agent.
+    pub async fn send(&self, notification: &Notification) {
+        for webhook in self
+            .webhooks
+            .iter()
+            .filter(|webhook| wants(webhook, notification.event, notification.duration))
+        {
+            let format = webhook
+                .format
+                .unwrap_or_else(|| WebhookFormat::detect(&webhook.url));
+            let result = self
+                .client
+                .post(&webhook.url)
+                .json(&self.payload(format, notification))
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = result {
+                warn!("Failed to post notification to webhook: {}", e);
+            }
+        }
+    }
+
+    fn link(&self, session_id: &str) -> Option<String> {
+        self.session_url
+            .as_ref()
+            .map(|url| url.replace("{session}", session_id))
+    }
+
+    fn text(&self, notification: &Notification) -> String {
+        let headline = match notification.event {
+            NotificationEvent::Finished => "✅ Task finished",
+            NotificationEvent::Failed => "❌ Task failed",
+            NotificationEvent::Permission => "✋ Permission required",
+        };
+        let mut text = match notification.duration {
+            Some(duration) => format!("{} after {}", headline, format_duration(duration)),
+            None => headline.to_string(),
+        };
+        if !notification.task.is_empty() {
+            text.push_str(&format!(": {}", notification.task));
+        }
+        if !notification.details.is_empty() {
+            text.push_str(&format!("\n{}", notification.details));
+        }
+        text.push_str(&format!(
+            "\nSession {} in {}",
+            notification.session_id,
+            notification.project.display()
+        ));
+        match self.link(&notification.session_id) {
+            Some(link) => text.push_str(&format!("\n{}", link)),
+            None => text.push_str(&format!(
+                "\nContinue with `code-assistant agent --continue --session {}`",
+                notification.session_id
+            )),
+        }
+        text
+    }
+
+    fn payload(&self, format: WebhookFormat, notification: &Notification) -> serde_json::Value {
+        let text = self.text(notification);
+        match format {
+            WebhookFormat::Slack => json!({ "text": text }),
+            WebhookFormat::Discord => {
+                json!({ "content": text.chars().take(MAX_DISCORD_LENGTH).collect::<String>() })
+            }
+            WebhookFormat::Json => json!({
+                "event": notification.event,
+                "session_id": notification.session_id,
+                "project": notification.project,
+                "task": notification.task,
+                "details": notification.details,
+                "duration_secs": notification.duration.map(|duration| duration.as_secs()),
+                "session_url": self.link(&notification.session_id),
+                "text": text,
+            }),
+        }
+    }
+}
+
+fn wants(webhook: &WebhookSettings, event: NotificationEvent, duration: Option<Duration>) -> bool {
+    (webhook.events.is_empty() || webhook.events.contains(&event))
+        && duration.is_none_or(|duration| duration.as_secs() >= webhook.min_duration_secs)
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Posts a notification before passing permission requests on, so the user learns
+/// about an agent waiting for them while they are away
+pub struct NotifyingMediator {
+    inner: Box<dyn PermissionMediator>,
+    notifier: Arc<Notifier>,
+    session_id: String,
+    project: PathBuf,
+}
+
+impl NotifyingMediator {
+    pub fn new(
+        inner: Box<dyn PermissionMediator>,
+        notifier: Arc<Notifier>,
+        session_id: &str,
+        project: &Path,
+    ) -> Self {
+        Self {
+            inner,
+            notifier,
+            session_id: session_id.to_string(),
+            project: project.to_path_buf(),
+        }
+    }
+}
+
+#[async_trait]
+impl PermissionMediator for NotifyingMediator {
+    async fn request_permission(&self, request: &PermissionRequest) -> Result<PermissionDecision> {
+        self.notifier
+            .send(&Notification {
+                event: NotificationEvent::Permission,
+                session_id: self.session_id.clone(),
+                project: self.project.clone(),
+                task: String::new(),
+                details: format!("{}: {}", request.tool, request.reason.summary()),
+                duration: None,
+            })
+            .await;
+        self.inner.request_permission(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn webhook(events: Vec<NotificationEvent>, min_duration_secs: u64) -> WebhookSettings {
+        WebhookSettings {
+            url: "https://hooks.slack.com/services/T0/B0/X".to_string(),
+            format: None,
+            events,
+            min_duration_secs,
+        }
+    }
+
+    #[test]
+    fn test_wants() {
+        let all = webhook(Vec::new(), 300);
+        assert!(wants(
+            &all,
+            NotificationEvent::Finished,
+            Some(Duration::from_secs(600))
+        ));
+        // Quick tasks are not worth a message, waiting for a permission always is
+        assert!(!wants(
+            &all,
+            NotificationEvent::Failed,
+            Some(Duration::from_secs(10))
+        ));
+        assert!(wants(&all, NotificationEvent::Permission, None));
+
+        let failures = webhook(vec![NotificationEvent::Failed], 0);
+        assert!(!wants(
+            &failures,
+            NotificationEvent::Finished,
+            Some(Duration::from_secs(10))
+        ));
+    }
+
+    #[test]
+    fn test_payload() {
+        assert_eq!(
+            WebhookFormat::detect("https://discord.com/api/webhooks/1/abc"),
+            WebhookFormat::Discord
+        );
+        assert_eq!(
+            WebhookFormat::detect("https://example.com/hook"),
+            WebhookFormat::Json
+        );
+
+        let notification = Notification::run_ended(
+            "1234",
+            Path::new("/home/user/project"),
+            "Fix the tests\nThey fail on CI",
+            &Ok(()),
+            true,
+            Some("All tests pass"),
+            Duration::from_secs(754),
+        );
+        let notifier = Notifier::new(&NotificationSettings::default());
+        assert_eq!(
+            notifier.payload(WebhookFormat::Slack, &notification)["text"],
+            "✅ Task finished after 12m 34s: Fix the tests\nAll tests pass\n\
+             Session 1234 in /home/user/project\n\
+             Continue with `code-assistant agent --continue --session 1234`"
+        );
+
+        let notifier = Notifier::new(&NotificationSettings {
+            session_url: Some("https://agent.example.com/sessions/{session}".to_string()),
+            ..Default::default()
+        });
+        let payload = notifier.payload(WebhookFormat::Json, &notification);
+        assert_eq!(payload["event"], "finished");
+        assert_eq!(payload["duration_secs"], 754);
+        assert_eq!(
+            payload["session_url"],
+            "https://agent.example.com/sessions/1234"
+        );
+    }
+}
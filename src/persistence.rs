@@ -1,8 +1,15 @@
-use crate::types::ActionResult;
-use anyhow::Result;
+use crate::checkpoint::GitCheckpoints;
+use crate::llm::{LLMProviderType, Usage};
+use crate::settings::{config_dir, SessionSettings};
+use crate::types::{ActionResult, AgentAction, Tool};
+use crate::worktree::Worktree;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-use tracing::debug;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
 
 /// Persistent state of the agent
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -11,54 +18,915 @@ pub struct AgentState {
     pub task: String,
     /// Memory of all previous actions and their results
     pub actions: Vec<ActionResult>,
+    /// Action requested by the LLM whose execution has not finished yet.
+    /// It is only set if the agent was interrupted in the middle of a turn.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pending_action: Option<AgentAction>,
+    /// Files the user attached to the task, loaded into working memory whenever
+    /// the session is started
+    #[serde(default)]
+    pub attachments: Vec<PathBuf>,
+    /// Checkpoints of the project files taken before modifications, by the number of
+    /// actions preceding them
+    #[serde(default)]
+    pub checkpoints: BTreeMap<usize, usize>,
+}
+
+impl AgentState {
+    pub fn add_attachments(&mut self, paths: &[PathBuf]) {
+        for path in paths {
+            if !self.attachments.contains(path) {
+                self.attachments.push(path.clone());
+            }
+        }
+    }
+
+    /// Checkpoint holding the project files as they were after the given number of actions,
+    /// `None` if no files were modified since then
+    pub fn checkpoint_at(&self, action_count: usize) -> Option<usize> {
+        self.checkpoints
+            .range(action_count..)
+            .next()
+            .map(|(_, checkpoint)| *checkpoint)
+    }
+
+    /// Replaces a message of the user and drops everything that happened after it.
+    /// Index 0 refers to the task, any other index to the action with that (1-based)
+    /// number, which has to be a question to the user.
+    pub fn edit_user_message(&mut self, index: usize, message: String) -> Result<()> {
+        self.pending_action = None;
+        if index == 0 {
+            self.task = message;
+            self.actions.clear();
+            self.checkpoints.clear();
+            return Ok(());
+        }
+
+        match self.actions.get(index - 1).map(|action| &action.tool) {
+            Some(Tool::AskUser { .. }) => {
+                self.actions.truncate(index);
+                self.checkpoints
+                    .retain(|action_count, _| *action_count < index);
+                let action = &mut self.actions[index - 1];
+                action.result = message;
+                action.success = true;
+                action.error = None;
+                Ok(())
+            }
+            _ => {
+                let user_messages = self
+                    .actions
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, action)| matches!(action.tool, Tool::AskUser { .. }))
+                    .map(|(i, _)| (i + 1).to_string())
+                    .collect::<Vec<_>>();
+                anyhow::bail!(
+                    "Action {} is not a question to the user. Editable messages: 0 (task){}{}",
+                    index,
+                    if user_messages.is_empty() { "" } else { ", " },
+                    user_messages.join(", ")
+                )
+            }
+        }
+    }
+}
+
+/// Origin of a session that was forked from another session
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ForkOrigin {
+    /// Id of the original session
+    pub session_id: String,
+    /// Number of actions taken over from the original session
+    pub action_count: usize,
+}
+
+/// LLM provider and model a session is running with
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LlmSessionConfig {
+    pub provider: LLMProviderType,
+    /// Model name, the provider's default model is used if not set
+    pub model: Option<String>,
+    /// Context window size (only relevant for Ollama)
+    pub num_ctx: usize,
+}
+
+impl Default for LlmSessionConfig {
+    fn default() -> Self {
+        Self {
+            provider: LLMProviderType::Anthropic,
+            model: None,
+            num_ctx: 8192,
+        }
+    }
+}
+
+impl LlmSessionConfig {
+    /// The configured model, or the default model of the provider
+    pub fn model_name(&self) -> Option<String> {
+        self.model
+            .clone()
+            .or_else(|| self.provider.default_model().map(|m| m.to_string()))
+    }
+
+    /// Applies the given overrides. Switching the provider without naming a model
+    /// resets the model to the default of the new provider.
+    pub fn apply_overrides(
+        &mut self,
+        provider: Option<LLMProviderType>,
+        model: Option<String>,
+        num_ctx: Option<usize>,
+    ) {
+        if let Some(provider) = provider {
+            if provider != self.provider {
+                self.provider = provider;
+                self.model = None;
+            }
+        }
+        if model.is_some() {
+            self.model = model;
+        }
+        if let Some(num_ctx) = num_ctx {
+            self.num_ctx = num_ctx;
+        }
+    }
+}
+
+impl std::fmt::Display for LlmSessionConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let provider = format!("{:?}", self.provider).to_lowercase();
+        match &self.model {
+            Some(model) => write!(f, "{} ({})", provider, model),
+            None => write!(f, "{} (default model)", provider),
+        }
+    }
+}
+
+/// Descriptive information about a saved session
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SessionMetadata {
+    pub id: String,
+    /// Name given by the user, shown instead of the task
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Project directory the session is working on
+    pub root_dir: PathBuf,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// Whether the agent completed the task of this session
+    pub completed: bool,
+    pub forked_from: Option<ForkOrigin>,
+    /// User-defined tags for organizing sessions
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// LLM provider and model used when the session is continued
+    #[serde(default)]
+    pub llm_config: LlmSessionConfig,
+    /// Tokens used by all LLM requests of this session
+    #[serde(default)]
+    pub usage: Usage,
+    /// File with the system message template of this session
+    #[serde(default)]
+    pub system_message: Option<PathBuf>,
+    /// Whether the session started in plan mode
+    #[serde(default)]
+    pub plan_mode: bool,
+    /// Profile from the settings used for the session
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Worktree the session works in instead of the project directory
+    #[serde(default)]
+    pub worktree: Option<Worktree>,
+    /// Further projects the session works on besides `root_dir`
+    #[serde(default)]
+    pub projects: Vec<PathBuf>,
+}
+
+impl SessionMetadata {
+    /// Directory the agent works in, the worktree of the session if it has one
+    pub fn work_dir(&self) -> &Path {
+        self.worktree
+            .as_ref()
+            .map_or(&self.root_dir, |worktree| &worktree.path)
+    }
+
+    pub fn add_tags(&mut self, tags: &[String]) {
+        for tag in tags {
+            if !self.tags.contains(tag) {
+                self.tags.push(tag.clone());
+            }
+        }
+        self.tags.sort();
+    }
+
+    pub fn remove_tags(&mut self, tags: &[String]) {
+        self.tags.retain(|tag| !tags.contains(tag));
+    }
+
+    /// Adds further projects to work on, the project of the session is left out
+    pub fn add_projects(&mut self, projects: &[PathBuf]) {
+        for project in projects {
+            if *project != self.root_dir && !self.projects.contains(project) {
+                self.projects.push(project.clone());
+            }
+        }
+    }
+
+    /// Whether the session carries all of the given tags
+    pub fn has_tags(&self, tags: &[String]) -> bool {
+        tags.iter().all(|tag| self.tags.contains(tag))
+    }
+}
+
+/// A saved session, consisting of its metadata and the agent state
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Session {
+    pub metadata: SessionMetadata,
+    pub state: AgentState,
+}
+
+impl Session {
+    /// Creates a new, empty session for the project in `root_dir`
+    pub fn new(root_dir: PathBuf) -> Self {
+        let now = Utc::now();
+        Self {
+            metadata: SessionMetadata {
+                id: now.format("%Y%m%d-%H%M%S-%3f").to_string(),
+                name: None,
+                root_dir,
+                created_at: now,
+                updated_at: now,
+                completed: false,
+                forked_from: None,
+                tags: Vec::new(),
+                llm_config: LlmSessionConfig::default(),
+                usage: Usage::default(),
+                system_message: None,
+                plan_mode: false,
+                profile: None,
+                worktree: None,
+                projects: Vec::new(),
+            },
+            state: AgentState {
+                task: String::new(),
+                actions: Vec::new(),
+                pending_action: None,
+                attachments: Vec::new(),
+                checkpoints: BTreeMap::new(),
+            },
+        }
+    }
+}
+
+impl Session {
+    /// Name of the session, or the first line of its task
+    pub fn title(&self) -> String {
+        title(&self.metadata, &self.state.task)
+    }
+}
+
+/// A saved session without its actions, as listed by the `SessionStore`
+#[derive(Debug, Clone)]
+pub struct SessionSummary {
+    pub metadata: SessionMetadata,
+    pub task: String,
+    pub action_count: usize,
+}
+
+impl SessionSummary {
+    /// Name of the session, or the first line of its task
+    pub fn title(&self) -> String {
+        title(&self.metadata, &self.task)
+    }
+
+    /// Fuzzy match of the title and id of the session: the characters of the query
+    /// have to appear in the same order, ignoring case
+    pub fn matches(&self, query: &str) -> bool {
+        let text = format!("{} {}", self.title(), self.metadata.id).to_lowercase();
+        let mut chars = text.chars();
+        query
+            .to_lowercase()
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .all(|q| chars.any(|c| c == q))
+    }
+}
+
+fn title(metadata: &SessionMetadata, task: &str) -> String {
+    if let Some(name) = &metadata.name {
+        return name.clone();
+    }
+    task.lines()
+        .next()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.to_string())
+        .unwrap_or_else(|| format!("Session {}", metadata.id))
+}
+
+/// Session file holding everything but the actions, which are stored one per line in
+/// a file of their own. Listing sessions only reads these small files.
+#[derive(Serialize, Deserialize)]
+struct StoredSession {
+    metadata: SessionMetadata,
+    state: StoredState,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredState {
+    task: String,
+    /// Number of valid lines in the actions file
+    #[serde(default)]
+    action_count: usize,
+    /// Length of these lines in bytes, new actions are appended behind them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    actions_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pending_action: Option<AgentAction>,
+    #[serde(default)]
+    attachments: Vec<PathBuf>,
+    #[serde(default)]
+    checkpoints: BTreeMap<usize, usize>,
+    /// Actions of sessions saved before they had a file of their own
+    #[serde(default, skip_serializing)]
+    actions: Option<Vec<ActionResult>>,
+}
+
+impl StoredState {
+    fn action_count(&self) -> usize {
+        self.actions.as_ref().map_or(self.action_count, Vec::len)
+    }
 }
 
 pub trait StatePersistence: Send + Sync {
     fn save_state(&mut self, task: String, actions: Vec<ActionResult>) -> Result<()>;
     fn load_state(&mut self) -> Result<Option<AgentState>>;
-    fn cleanup(&mut self) -> Result<()>;
+    /// Saves the action requested by the LLM before it is executed, so that an
+    /// interrupted turn can be resumed without asking the LLM again
+    fn save_pending_action(&mut self, action: &AgentAction) -> Result<()>;
+    /// Called when the agent completed the task
+    fn mark_completed(&mut self) -> Result<()>;
+    /// Adds the token usage of an LLM request to the session,
+    /// it is saved together with the next state
+    fn record_usage(&mut self, usage: &Usage);
+    /// Remembers the checkpoint taken before the action following `action_count` actions
+    fn record_checkpoint(&mut self, action_count: usize, checkpoint: usize) -> Result<()>;
+}
+
+/// Stores sessions as individual JSON files in a directory, with the actions of each
+/// session in a JSON Lines file next to it
+pub struct SessionStore {
+    dir: PathBuf,
+}
+
+impl SessionStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Uses the sessions directory inside the user's config directory
+    pub fn default_location() -> Result<Self> {
+        Ok(Self::new(config_dir()?.join("sessions")))
+    }
+
+    fn session_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    fn archive_dir(&self) -> PathBuf {
+        self.dir.join("archive")
+    }
+
+    fn archived_session_path(&self, id: &str) -> PathBuf {
+        self.archive_dir().join(format!("{}.json", id))
+    }
+
+    pub fn exists(&self, id: &str) -> bool {
+        self.session_path(id).exists()
+    }
+
+    /// Appends the actions added since the last save and replaces the session file.
+    /// Sessions whose earlier actions changed have to be saved with `rewrite`.
+    pub fn save(&self, session: &Session) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.session_path(&session.metadata.id);
+        debug!("Saving session to {}", path.display());
+        write_session(&path, session, false)
+    }
+
+    /// Saves a session with all of its actions, after earlier actions were changed like
+    /// when editing a message
+    pub fn rewrite(&self, session: &Session) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.session_path(&session.metadata.id);
+        debug!("Rewriting session {}", path.display());
+        write_session(&path, session, true)
+    }
+
+    /// Loads a session, sessions saved with their actions inside are converted so that
+    /// they are listed quickly from then on
+    pub fn load(&self, id: &str) -> Result<Session> {
+        let path = self.session_path(id);
+        debug!("Loading session from {}", path.display());
+        let stored =
+            read_stored_session(&path).with_context(|| format!("Session '{}' not found", id))?;
+        let legacy = stored.state.actions.is_some();
+        let actions = match stored.state.actions {
+            Some(actions) => actions,
+            None => recover_actions(&path, stored.state.action_count)?,
+        };
+        if actions.len() < stored.state.action_count {
+            anyhow::bail!(
+                "Session '{}' has {} actions, but only {} are stored",
+                id,
+                stored.state.action_count,
+                actions.len()
+            );
+        }
+        // The pending action completed if its result was appended
+        let pending_action = if actions.len() > stored.state.action_count {
+            None
+        } else {
+            stored.state.pending_action
+        };
+        let session = Session {
+            metadata: stored.metadata,
+            state: AgentState {
+                task: stored.state.task,
+                actions,
+                pending_action,
+                attachments: stored.state.attachments,
+                checkpoints: stored.state.checkpoints,
+            },
+        };
+        if legacy {
+            debug!("Storing the actions of {} separately", path.display());
+            write_session(&path, &session, true)?;
+        }
+        Ok(session)
+    }
+
+    /// Returns up to `limit` actions of a session starting at `offset`, for showing its
+    /// history page by page without loading all of it
+    pub fn load_actions(&self, id: &str, offset: usize, limit: usize) -> Result<Vec<ActionResult>> {
+        let path = self.session_path(id);
+        let stored =
+            read_stored_session(&path).with_context(|| format!("Session '{}' not found", id))?;
+        if let Some(actions) = stored.state.actions {
+            return Ok(actions.into_iter().skip(offset).take(limit).collect());
+        }
+        let limit = limit.min(stored.state.action_count.saturating_sub(offset));
+        read_actions(&path, offset, limit)
+    }
+
+    /// Returns all saved sessions without their actions, most recently updated first
+    pub fn list(&self) -> Result<Vec<SessionSummary>> {
+        Ok(read_sessions(&self.dir)?
+            .into_iter()
+            .map(|(_, session)| session)
+            .collect())
+    }
+
+    /// Returns all archived sessions without their actions, most recently updated first
+    pub fn list_archived(&self) -> Result<Vec<SessionSummary>> {
+        Ok(read_sessions(&self.archive_dir())?
+            .into_iter()
+            .map(|(_, session)| session)
+            .collect())
+    }
+
+    /// Moves a session into the archive
+    pub fn archive(&self, id: &str) -> Result<()> {
+        if !self.exists(id) {
+            anyhow::bail!("Session '{}' not found", id);
+        }
+        std::fs::create_dir_all(self.archive_dir())?;
+        move_session(&self.session_path(id), &self.archived_session_path(id))
+    }
+
+    /// Moves an archived session back to the active sessions
+    pub fn unarchive(&self, id: &str) -> Result<()> {
+        let path = self.archived_session_path(id);
+        if !path.exists() {
+            anyhow::bail!("Archived session '{}' not found", id);
+        }
+        move_session(&path, &self.session_path(id))
+    }
+
+    /// Deletes an active or archived session
+    pub fn delete(&self, id: &str) -> Result<()> {
+        let path = [self.session_path(id), self.archived_session_path(id)]
+            .into_iter()
+            .find(|path| path.exists())
+            .with_context(|| format!("Session '{}' not found", id))?;
+        remove_session(&path)
+    }
+
+    /// Applies the retention policy: archives sessions which were not updated for too long
+    /// and deletes the oldest archived sessions while the storage limit is exceeded, along
+    /// with their checkpoints and worktrees. Active sessions are never deleted.
+    pub fn vacuum(&self, settings: &SessionSettings) -> Result<VacuumReport> {
+        let mut report = VacuumReport::default();
+
+        if let Some(days) = settings.archive_after_days {
+            let cutoff = Utc::now() - chrono::Duration::days(days as i64);
+            for session in self.list()? {
+                if session.metadata.updated_at < cutoff {
+                    self.archive(&session.metadata.id)?;
+                    report.archived.push(session.metadata.id);
+                }
+            }
+        }
+
+        if let Some(max_storage_mb) = settings.max_storage_mb {
+            let limit = max_storage_mb * 1024 * 1024;
+            let archived = read_sessions(&self.archive_dir())?;
+            let active = read_sessions(&self.dir)?;
+            let mut total_size = archived
+                .iter()
+                .chain(active.iter())
+                .map(|(path, _)| session_size(path))
+                .sum::<u64>();
+
+            // Oldest archived sessions first
+            for (path, session) in archived.into_iter().rev() {
+                if total_size <= limit {
+                    break;
+                }
+                total_size -= session_size(&path);
+                if let Err(e) = remove_session_data(&session.metadata) {
+                    warn!(
+                        "Failed to clean up after session {}: {:#}",
+                        session.metadata.id, e
+                    );
+                }
+                remove_session(&path)?;
+                report.deleted.push(session.metadata.id);
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Returns the most recently updated session for `root_dir` which is not completed
+    pub fn latest_unfinished(&self, root_dir: &Path) -> Result<Option<Session>> {
+        self.list()?
+            .into_iter()
+            .find(|s| !s.metadata.completed && s.metadata.root_dir == root_dir)
+            .map(|s| self.load(&s.metadata.id))
+            .transpose()
+    }
+
+    /// Creates and saves a new session sharing the first `action_count` actions with `session`
+    pub fn fork(&self, session: &Session, action_count: usize) -> Result<Session> {
+        if action_count > session.state.actions.len() {
+            anyhow::bail!(
+                "Cannot fork at action {}, session '{}' has only {} actions",
+                action_count,
+                session.metadata.id,
+                session.state.actions.len()
+            );
+        }
+
+        let mut fork = Session::new(session.metadata.root_dir.clone());
+        while self.exists(&fork.metadata.id) {
+            fork = Session::new(session.metadata.root_dir.clone());
+        }
+        fork.metadata.forked_from = Some(ForkOrigin {
+            session_id: session.metadata.id.clone(),
+            action_count,
+        });
+        fork.metadata.name = session.metadata.name.clone();
+        fork.metadata.tags = session.metadata.tags.clone();
+        fork.metadata.llm_config = session.metadata.llm_config.clone();
+        fork.metadata.system_message = session.metadata.system_message.clone();
+        fork.metadata.plan_mode = session.metadata.plan_mode;
+        fork.metadata.profile = session.metadata.profile.clone();
+        fork.metadata.projects = session.metadata.projects.clone();
+        // The fork continues in a worktree of its own, otherwise its actions would be
+        // replayed in the project directory
+        if let Some(worktree) = &session.metadata.worktree {
+            fork.metadata.worktree =
+                Some(worktree.fork(&session.metadata.root_dir, &fork.metadata.id)?);
+        }
+        fork.state = AgentState {
+            task: session.state.task.clone(),
+            actions: session.state.actions[..action_count].to_vec(),
+            pending_action: None,
+            attachments: session.state.attachments.clone(),
+            // Checkpoints belong to the original session
+            checkpoints: BTreeMap::new(),
+        };
+
+        self.save(&fork)?;
+        Ok(fork)
+    }
+}
+
+/// Sessions affected by `SessionStore::vacuum`
+#[derive(Debug, Default)]
+pub struct VacuumReport {
+    pub archived: Vec<String>,
+    pub deleted: Vec<String>,
+}
+
+/// Reads all session files in `dir` without the actions, most recently updated first
+fn read_sessions(dir: &Path) -> Result<Vec<(PathBuf, SessionSummary)>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut sessions = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        match read_stored_session(&path) {
+            Ok(stored) => sessions.push((
+                path,
+                SessionSummary {
+                    action_count: stored.state.action_count(),
+                    metadata: stored.metadata,
+                    task: stored.state.task,
+                },
+            )),
+            Err(e) => debug!("Skipping invalid session file {}: {:#}", path.display(), e),
+        }
+    }
+
+    sessions.sort_by_key(|(_, s)| std::cmp::Reverse(s.metadata.updated_at));
+    Ok(sessions)
+}
+
+/// File with the actions of the session stored in `path`
+fn actions_path(path: &Path) -> PathBuf {
+    path.with_extension("actions.jsonl")
+}
+
+fn read_stored_session(path: &Path) -> Result<StoredSession> {
+    let json = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&json)?)
+}
+
+/// Appends the actions which are not stored yet and replaces the session file. All
+/// actions are written again if asked to, or if the stored ones can't be appended to.
+fn write_session(path: &Path, session: &Session, rewrite: bool) -> Result<()> {
+    let actions_file = actions_path(path);
+    let actions = &session.state.actions;
+    let stored = if rewrite {
+        None
+    } else {
+        read_stored_session(path).ok()
+    };
+    let appendable = stored.and_then(|stored| {
+        let size = stored.state.actions_size?;
+        let count = stored.state.action_count;
+        (stored.state.actions.is_none()
+            && count <= actions.len()
+            && file_size(&actions_file) >= size)
+            .then_some((count, size))
+    });
+    let actions_size = match appendable {
+        Some((count, size)) => append_actions(&actions_file, size, &actions[count..])?,
+        None => {
+            let lines = action_lines(actions)?;
+            write_atomic(&actions_file, &lines)?;
+            lines.len() as u64
+        }
+    };
+
+    let stored = StoredSession {
+        metadata: session.metadata.clone(),
+        state: StoredState {
+            task: session.state.task.clone(),
+            action_count: actions.len(),
+            actions_size: Some(actions_size),
+            pending_action: session.state.pending_action.clone(),
+            attachments: session.state.attachments.clone(),
+            checkpoints: session.state.checkpoints.clone(),
+            actions: None,
+        },
+    };
+    write_atomic(path, &serde_json::to_string_pretty(&stored)?)
 }
 
+fn action_lines(actions: &[ActionResult]) -> Result<String> {
+    let mut lines = String::new();
+    for action in actions {
+        lines.push_str(&serde_json::to_string(action)?);
+        lines.push('\n');
+    }
+    Ok(lines)
+}
+
+/// Appends actions behind the first `size` bytes of the actions file, anything written
+/// after them without being counted in the session file is dropped. Returns the new size.
+fn append_actions(path: &Path, size: u64, actions: &[ActionResult]) -> Result<u64> {
+    let lines = action_lines(actions)?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Failed to open actions {}", path.display()))?;
+    file.set_len(size)?;
+    file.seek(SeekFrom::End(0))?;
+    file.write_all(lines.as_bytes())?;
+    file.sync_data()?;
+    Ok(size + lines.len() as u64)
+}
+
+/// Reads the actions of a session whose file counts `count` of them. Actions appended
+/// after the session file was written last are recovered up to the last complete line,
+/// a line cut off by a crash while appending is dropped.
+fn recover_actions(path: &Path, count: usize) -> Result<Vec<ActionResult>> {
+    let path = actions_path(path);
+    let file = match std::fs::File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && count == 0 => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read actions {}", path.display()))
+        }
+    };
+    let mut reader = BufReader::new(file);
+    let mut actions = Vec::new();
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 {
+        match serde_json::from_str(line.trim_end()) {
+            Ok(action) if line.ends_with('\n') => actions.push(action),
+            Err(e) if actions.len() < count => {
+                return Err(e).with_context(|| format!("Invalid actions in {}", path.display()))
+            }
+            _ => {
+                warn!(
+                    "Dropping the incomplete action {} of {}",
+                    actions.len() + 1,
+                    path.display()
+                );
+                break;
+            }
+        }
+        line.clear();
+    }
+    if actions.len() > count {
+        warn!(
+            "Recovered {} actions of {} which were not saved completely",
+            actions.len() - count,
+            path.display()
+        );
+    }
+    Ok(actions)
+}
+
+/// Parses `limit` actions starting at `offset`, the lines before are skipped unparsed
+fn read_actions(path: &Path, offset: usize, limit: usize) -> Result<Vec<ActionResult>> {
+    if limit == 0 {
+        return Ok(Vec::new());
+    }
+    let path = actions_path(path);
+    let file = std::fs::File::open(&path)
+        .with_context(|| format!("Failed to read actions {}", path.display()))?;
+    BufReader::new(file)
+        .lines()
+        .skip(offset)
+        .take(limit)
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect::<Result<_>>()
+        .with_context(|| format!("Invalid actions in {}", path.display()))
+}
+
+fn move_session(from: &Path, to: &Path) -> Result<()> {
+    if actions_path(from).exists() {
+        std::fs::rename(actions_path(from), actions_path(to))?;
+    }
+    std::fs::rename(from, to)?;
+    Ok(())
+}
+
+fn remove_session(path: &Path) -> Result<()> {
+    std::fs::remove_file(path)?;
+    if actions_path(path).exists() {
+        std::fs::remove_file(actions_path(path))?;
+    }
+    Ok(())
+}
+
+/// Removes what a session keeps outside of the sessions directory: the checkpoints of its
+/// projects and its worktree, the branch of the worktree is kept
+fn remove_session_data(metadata: &SessionMetadata) -> Result<()> {
+    let work_dirs =
+        std::iter::once(metadata.work_dir()).chain(metadata.projects.iter().map(PathBuf::as_path));
+    for work_dir in work_dirs {
+        if let Some(checkpoints) = GitCheckpoints::new(work_dir, &metadata.id) {
+            checkpoints.remove()?;
+        }
+    }
+    if let Some(worktree) = &metadata.worktree {
+        worktree.remove(&metadata.root_dir)?;
+    }
+    Ok(())
+}
+
+/// Writes to a temporary file first and renames it over the target, so that a crash
+/// while writing never leaves a truncated session file behind
+fn write_atomic(path: &Path, contents: &str) -> Result<()> {
+    let temp_path = path.with_extension("json.tmp");
+    let mut file = std::fs::File::create(&temp_path)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&temp_path, path)
+        .with_context(|| format!("Failed to write session file {}", path.display()))
+}
+
+fn file_size(path: &Path) -> u64 {
+    std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+/// Size of a session file and its actions
+fn session_size(path: &Path) -> u64 {
+    file_size(path) + file_size(&actions_path(path))
+}
+
+/// Persists the agent state into a session of a `SessionStore`
 pub struct FileStatePersistence {
-    root_dir: PathBuf,
+    store: SessionStore,
+    session: Session,
 }
 
 impl FileStatePersistence {
-    pub fn new(root_dir: PathBuf) -> Self {
-        Self { root_dir }
+    pub fn new(store: SessionStore, session: Session) -> Self {
+        Self { store, session }
     }
 }
 
-const STATE_FILE: &str = ".code-assistant.state.json";
-
 impl StatePersistence for FileStatePersistence {
+    /// Called after every action, whose result is appended to the actions file before
+    /// the session file is replaced, so a crash in between loses nothing
     fn save_state(&mut self, task: String, actions: Vec<ActionResult>) -> Result<()> {
-        let state = AgentState { task, actions };
-        let state_path = self.root_dir.join(STATE_FILE);
-        debug!("Saving state to {}", state_path.display());
-        let json = serde_json::to_string_pretty(&state)?;
-        std::fs::write(state_path, json)?;
-        Ok(())
+        self.session.state.task = task;
+        self.session.state.actions = actions;
+        self.session.state.pending_action = None;
+        self.session.metadata.updated_at = Utc::now();
+        self.store.save(&self.session)
     }
 
     fn load_state(&mut self) -> Result<Option<AgentState>> {
-        let state_path = self.root_dir.join(STATE_FILE);
-        if !state_path.exists() {
+        if !self.store.exists(&self.session.metadata.id) {
             return Ok(None);
         }
 
-        debug!("Loading state from {}", state_path.display());
-        let json = std::fs::read_to_string(state_path)?;
-        let state = serde_json::from_str(&json)?;
-        Ok(Some(state))
+        self.session = self.store.load(&self.session.metadata.id)?;
+        Ok(Some(self.session.state.clone()))
     }
 
-    fn cleanup(&mut self) -> Result<()> {
-        let state_path = self.root_dir.join(STATE_FILE);
-        if state_path.exists() {
-            debug!("Removing state file {}", state_path.display());
-            std::fs::remove_file(state_path)?;
-        }
+    fn mark_completed(&mut self) -> Result<()> {
+        self.session.metadata.completed = true;
+        self.session.metadata.updated_at = Utc::now();
+        self.store.save(&self.session)
+    }
+
+    fn save_pending_action(&mut self, action: &AgentAction) -> Result<()> {
+        self.session.state.pending_action = Some(action.clone());
+        self.session.metadata.updated_at = Utc::now();
+        self.store.save(&self.session)
+    }
+
+    fn record_usage(&mut self, usage: &Usage) {
+        self.session.metadata.usage.add(usage);
+    }
+
+    fn record_checkpoint(&mut self, action_count: usize, checkpoint: usize) -> Result<()> {
+        self.session
+            .state
+            .checkpoints
+            .insert(action_count, checkpoint);
+        self.store.save(&self.session)
+    }
+}
+
+/// Keeps nothing, for one-shot runs which leave no session behind
+pub struct EphemeralStatePersistence;
+
+impl StatePersistence for EphemeralStatePersistence {
+    fn save_state(&mut self, _task: String, _actions: Vec<ActionResult>) -> Result<()> {
+        Ok(())
+    }
+
+    fn load_state(&mut self) -> Result<Option<AgentState>> {
+        Ok(None)
+    }
+
+    fn save_pending_action(&mut self, _action: &AgentAction) -> Result<()> {
+        Ok(())
+    }
+
+    fn mark_completed(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn record_usage(&mut self, _usage: &Usage) {}
+
+    fn record_checkpoint(&mut self, _action_count: usize, _checkpoint: usize) -> Result<()> {
         Ok(())
     }
 }
@@ -73,23 +941,521 @@ impl MockStatePersistence {
     pub fn new() -> Self {
         Self { state: None }
     }
+
+    /// Persistence of a session which is continued
+    pub fn with_state(state: AgentState) -> Self {
+        Self { state: Some(state) }
+    }
 }
 
 #[cfg(test)]
 impl StatePersistence for MockStatePersistence {
     fn save_state(&mut self, task: String, actions: Vec<ActionResult>) -> Result<()> {
         // In-Memory state
-        let state = AgentState { task, actions };
+        let checkpoints = self
+            .state
+            .take()
+            .map(|state| state.checkpoints)
+            .unwrap_or_default();
+        let state = AgentState {
+            task,
+            actions,
+            pending_action: None,
+            attachments: Vec::new(),
+            checkpoints,
+        };
         self.state = Some(state);
         Ok(())
     }
 
+    fn save_pending_action(&mut self, action: &AgentAction) -> Result<()> {
+        if let Some(state) = &mut self.state {
+            state.pending_action = Some(action.clone());
+        }
+        Ok(())
+    }
+
     fn load_state(&mut self) -> Result<Option<AgentState>> {
         Ok(self.state.clone())
     }
 
-    fn cleanup(&mut self) -> Result<()> {
-        self.state = None;
+    fn mark_completed(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn record_usage(&mut self, _usage: &Usage) {}
+
+    fn record_checkpoint(&mut self, action_count: usize, checkpoint: usize) -> Result<()> {
+        if let Some(state) = &mut self.state {
+            state.checkpoints.insert(action_count, checkpoint);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::CheckpointStore;
+    use tempfile::TempDir;
+
+    fn create_action(message: &str) -> ActionResult {
+        ActionResult {
+            tool: Tool::MessageUser {
+                message: message.to_string(),
+            },
+            success: true,
+            result: "Message delivered".to_string(),
+            error: None,
+            reasoning: "Informing the user".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_fork_keeps_original_session() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let store = SessionStore::new(temp_dir.path().to_path_buf());
+
+        let mut original = Session::new(PathBuf::from("/project"));
+        original.state.task = "Test task".to_string();
+        original
+            .metadata
+            .add_projects(&[PathBuf::from("/backend"), PathBuf::from("/project")]);
+        original.state.actions = vec![
+            create_action("first"),
+            create_action("second"),
+            create_action("third"),
+        ];
+        store.save(&original)?;
+
+        let fork = store.fork(&original, 2)?;
+        assert_ne!(fork.metadata.id, original.metadata.id);
+        assert_eq!(fork.state.task, "Test task");
+        assert_eq!(fork.state.actions.len(), 2);
+        let origin = fork.metadata.forked_from.as_ref().unwrap();
+        assert_eq!(origin.session_id, original.metadata.id);
+        assert_eq!(origin.action_count, 2);
+        assert_eq!(fork.metadata.projects, vec![PathBuf::from("/backend")]);
+
+        // Both sessions are stored, the original one is unchanged
+        assert_eq!(store.list()?.len(), 2);
+        assert_eq!(store.load(&original.metadata.id)?.state.actions.len(), 3);
+        assert_eq!(store.load(&fork.metadata.id)?.state.actions.len(), 2);
+
+        // Forking beyond the end of the history fails
+        assert!(store.fork(&original, 4).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fork_worktree_session() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let project = temp_dir.path().join("project");
+        std::fs::create_dir(&project)?;
+        let git = |args: &[&str]| -> Result<String> {
+            let output = std::process::Command::new("git")
+                .args(["-c", "user.name=test", "-c", "user.email=test@localhost"])
+                .args(args)
+                .current_dir(&project)
+                .output()?;
+            assert!(output.status.success(), "git {:?} failed", args);
+            Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        };
+        git(&["init", "-q"])?;
+        std::fs::write(project.join("README.md"), "# Project\n")?;
+        git(&["add", "."])?;
+        git(&["commit", "-q", "-m", "Initial commit"])?;
+
+        let store = SessionStore::new(temp_dir.path().join("sessions"));
+        let mut original = Session::new(project.clone());
+        let worktrees = temp_dir.path().join("worktrees");
+        original.metadata.worktree = Some(Worktree::create(
+            &project,
+            &worktrees,
+            &original.metadata.id,
+        )?);
+        original.state.actions = vec![create_action("first")];
+        store.save(&original)?;
+        // Work the original session committed on its branch
+        let original_worktree = original.metadata.worktree.clone().unwrap();
+        std::fs::write(original_worktree.path.join("README.md"), "# Changed\n")?;
+        git(&[
+            "-C",
+            &original_worktree.path.to_string_lossy(),
+            "commit",
+            "-q",
+            "-am",
+            "Change",
+        ])?;
+
+        // The fork works in a worktree of its own, starting from the original branch
+        let fork = store.fork(&original, 1)?;
+        let worktree = fork.metadata.worktree.clone().unwrap();
+        assert_ne!(worktree, original_worktree);
+        assert_eq!(worktree.path, worktrees.join(&fork.metadata.id));
+        assert_eq!(fork.metadata.work_dir(), worktree.path.as_path());
+        assert_eq!(
+            std::fs::read_to_string(worktree.path.join("README.md"))?,
+            "# Changed\n"
+        );
+        assert_eq!(
+            store.load(&fork.metadata.id)?.metadata.worktree,
+            Some(worktree)
+        );
+        assert_eq!(
+            std::fs::read_to_string(project.join("README.md"))?,
+            "# Project\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_matches_query() {
+        let mut session = SessionSummary {
+            metadata: Session::new(PathBuf::from("/project")).metadata,
+            task: "Fix the login bug\nIt happens on Safari".to_string(),
+            action_count: 0,
+        };
+        assert_eq!(session.title(), "Fix the login bug");
+        assert!(session.matches(""));
+        assert!(session.matches("login"));
+        assert!(session.matches("fx lgn"));
+        assert!(!session.matches("safari"));
+
+        session.metadata.name = Some("Auth".to_string());
+        assert_eq!(session.title(), "Auth");
+        assert!(session.matches("auth"));
+        assert!(session.matches(&session.metadata.id.clone()));
+    }
+
+    #[test]
+    fn test_delete_session() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let store = SessionStore::new(temp_dir.path().to_path_buf());
+
+        let mut session = Session::new(PathBuf::from("/project"));
+        session.metadata.name = Some("Refactoring".to_string());
+        store.save(&session)?;
+        let copy = store.fork(&session, 0)?;
+        assert_eq!(copy.metadata.name.as_deref(), Some("Refactoring"));
+
+        // Archived sessions can be deleted as well
+        store.archive(&copy.metadata.id)?;
+        store.delete(&copy.metadata.id)?;
+        store.delete(&session.metadata.id)?;
+        assert!(store.list()?.is_empty());
+        assert!(store.list_archived()?.is_empty());
+        assert!(store.delete(&session.metadata.id).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_edit_user_message() -> Result<()> {
+        let mut state = AgentState {
+            task: "Test task".to_string(),
+            actions: vec![
+                create_action("first"),
+                ActionResult {
+                    tool: Tool::AskUser {
+                        question: "Which one?".to_string(),
+                    },
+                    success: true,
+                    result: "The first one".to_string(),
+                    error: None,
+                    reasoning: "Need a decision".to_string(),
+                },
+                create_action("second"),
+            ],
+            pending_action: None,
+            attachments: Vec::new(),
+            checkpoints: BTreeMap::from([(0, 1), (2, 2)]),
+        };
+        // Files were modified by the first and third action
+        assert_eq!(state.checkpoint_at(0), Some(1));
+        assert_eq!(state.checkpoint_at(1), Some(2));
+        assert_eq!(state.checkpoint_at(2), Some(2));
+        assert_eq!(state.checkpoint_at(3), None);
+
+        // Only questions to the user can be edited
+        assert!(state.edit_user_message(1, "Edited".to_string()).is_err());
+
+        state.edit_user_message(2, "The second one".to_string())?;
+        assert_eq!(state.actions.len(), 2);
+        assert_eq!(state.actions[1].result, "The second one");
+        assert_eq!(state.checkpoint_at(1), None);
+
+        state.edit_user_message(0, "Edited task".to_string())?;
+        assert_eq!(state.task, "Edited task");
+        assert!(state.actions.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_tags() {
+        let mut session = Session::new(PathBuf::from("/project"));
+        session
+            .metadata
+            .add_tags(&["refactoring".to_string(), "backend".to_string()]);
+        session.metadata.add_tags(&["backend".to_string()]);
+        assert_eq!(session.metadata.tags, vec!["backend", "refactoring"]);
+        assert!(session.metadata.has_tags(&["refactoring".to_string()]));
+        assert!(session.metadata.has_tags(&[]));
+
+        session.metadata.remove_tags(&["refactoring".to_string()]);
+        assert_eq!(session.metadata.tags, vec!["backend"]);
+        assert!(!session.metadata.has_tags(&["refactoring".to_string()]));
+    }
+
+    #[test]
+    fn test_llm_config_overrides() {
+        let mut config = LlmSessionConfig::default();
+        config.apply_overrides(None, Some("claude-3-5-haiku-latest".to_string()), None);
+        assert_eq!(config.provider, LLMProviderType::Anthropic);
+        assert_eq!(config.model.as_deref(), Some("claude-3-5-haiku-latest"));
+
+        // Switching the provider drops the model of the previous provider
+        config.apply_overrides(Some(LLMProviderType::OpenAI), None, None);
+        assert_eq!(config.provider, LLMProviderType::OpenAI);
+        assert_eq!(config.model, None);
+
+        config.apply_overrides(
+            Some(LLMProviderType::Ollama),
+            Some("qwen2.5".to_string()),
+            Some(16384),
+        );
+        assert_eq!(config.model.as_deref(), Some("qwen2.5"));
+        assert_eq!(config.num_ctx, 16384);
+
+        // Sessions saved before the config existed use the defaults
+        let mut json = serde_json::to_value(Session::new(PathBuf::from("/project"))).unwrap();
+        json["metadata"]
+            .as_object_mut()
+            .unwrap()
+            .remove("llm_config");
+        let session: Session = serde_json::from_value(json).unwrap();
+        assert_eq!(session.metadata.llm_config, LlmSessionConfig::default());
+    }
+
+    #[test]
+    fn test_pending_action_survives_interruption() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let store = SessionStore::new(temp_dir.path().to_path_buf());
+        let session = Session::new(PathBuf::from("/project"));
+        let id = session.metadata.id.clone();
+
+        let mut persistence = FileStatePersistence::new(store, session);
+        persistence.save_state("Test task".to_string(), vec![create_action("first")])?;
+        persistence.save_pending_action(&AgentAction {
+            tool: Tool::MessageUser {
+                message: "second".to_string(),
+            },
+            reasoning: "Informing the user".to_string(),
+        })?;
+
+        // Simulate a restart after the process died while executing the action
+        let store = SessionStore::new(temp_dir.path().to_path_buf());
+        let mut persistence = FileStatePersistence::new(store, store_load(&temp_dir, &id)?);
+        let state = persistence.load_state()?.unwrap();
+        assert_eq!(state.actions.len(), 1);
+        assert!(matches!(
+            state.pending_action.map(|action| action.tool),
+            Some(Tool::MessageUser { .. })
+        ));
+
+        // Saving the state after the action finished clears the pending action
+        persistence.save_state("Test task".to_string(), vec![create_action("first")])?;
+        assert!(store_load(&temp_dir, &id)?.state.pending_action.is_none());
+
+        // No temporary files are left behind, only the session and its actions
+        let files: Vec<_> = std::fs::read_dir(temp_dir.path())?.collect();
+        assert_eq!(files.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkpoints_are_kept_with_the_state() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let store = SessionStore::new(temp_dir.path().to_path_buf());
+        let session = Session::new(PathBuf::from("/project"));
+        let id = session.metadata.id.clone();
+
+        let mut persistence = FileStatePersistence::new(store, session);
+        persistence.save_state("Test task".to_string(), vec![create_action("first")])?;
+        persistence.record_checkpoint(1, 1)?;
+        persistence.save_state(
+            "Test task".to_string(),
+            vec![create_action("first"), create_action("second")],
+        )?;
+
+        let state = store_load(&temp_dir, &id)?.state;
+        assert_eq!(state.checkpoint_at(0), Some(1));
+        assert_eq!(state.checkpoint_at(2), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_actions_are_loaded_in_pages() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let store = SessionStore::new(temp_dir.path().to_path_buf());
+        let mut session = Session::new(PathBuf::from("/project"));
+        session.state.task = "Test task".to_string();
+        session.state.actions = (0..5).map(|i| create_action(&i.to_string())).collect();
+        store.save(&session)?;
+        let id = session.metadata.id.clone();
+
+        let summaries = store.list()?;
+        assert_eq!(summaries[0].action_count, 5);
+        assert_eq!(summaries[0].title(), "Test task");
+
+        let page = store.load_actions(&id, 3, 10)?;
+        let messages: Vec<_> = page
+            .iter()
+            .map(|action| match &action.tool {
+                Tool::MessageUser { message } => message.as_str(),
+                _ => "",
+            })
+            .collect();
+        assert_eq!(messages, ["3", "4"]);
+        assert!(store.load_actions(&id, 5, 10)?.is_empty());
+
+        // Actions appended after the session file was written last are recovered up to
+        // the last complete line, and the pending action is not resumed
+        session.state.actions.truncate(2);
+        session.state.pending_action = Some(AgentAction {
+            tool: Tool::MessageUser {
+                message: "recovered".to_string(),
+            },
+            reasoning: String::new(),
+        });
+        let path = store.session_path(&id);
+        write_session(&path, &session, true)?;
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(actions_path(&path))?;
+        writeln!(
+            file,
+            "{}",
+            serde_json::to_string(&create_action("recovered"))?
+        )?;
+        write!(file, "{{\"tool\":")?;
+        let recovered = store.load(&id)?;
+        assert_eq!(recovered.state.actions.len(), 3);
+        assert!(recovered.state.pending_action.is_none());
+        assert_eq!(store.load_actions(&id, 0, 10)?.len(), 2);
+
+        // New actions are appended behind the counted ones
+        session.state.actions.push(create_action("new"));
+        store.save(&session)?;
+        let actions = std::fs::read_to_string(actions_path(&path))?;
+        assert_eq!(actions.lines().count(), 3);
+        let loaded = store.load(&id)?.state.actions;
+        assert_eq!(loaded.len(), 3);
+        assert!(matches!(&loaded[2].tool, Tool::MessageUser { message } if message == "new"));
+
+        // Archived sessions keep their actions
+        store.archive(&id)?;
+        store.unarchive(&id)?;
+        assert_eq!(store.load(&id)?.state.actions.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sessions_with_actions_inside_are_converted() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let store = SessionStore::new(temp_dir.path().to_path_buf());
+        let mut session = Session::new(PathBuf::from("/project"));
+        session.state.actions = vec![create_action("first"), create_action("second")];
+        let path = store.session_path(&session.metadata.id);
+        std::fs::write(&path, serde_json::to_string_pretty(&session)?)?;
+
+        // Listing doesn't write anything, loading converts the session
+        assert_eq!(store.list()?[0].action_count, 2);
+        assert_eq!(store.load_actions(&session.metadata.id, 1, 10)?.len(), 1);
+        assert!(!actions_path(&path).exists());
+        assert_eq!(store.load(&session.metadata.id)?.state.actions.len(), 2);
+        assert!(actions_path(&path).exists());
+        let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path)?)?;
+        assert!(json["state"].get("actions").is_none());
+        assert_eq!(store.load(&session.metadata.id)?.state.actions.len(), 2);
+        Ok(())
+    }
+
+    fn store_load(temp_dir: &TempDir, id: &str) -> Result<Session> {
+        SessionStore::new(temp_dir.path().to_path_buf()).load(id)
+    }
+
+    #[test]
+    fn test_vacuum() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let store = SessionStore::new(temp_dir.path().to_path_buf());
+
+        // The old session has a checkpoint in its repository
+        let project = TempDir::new()?;
+        std::process::Command::new("git")
+            .args(["init", "-q"])
+            .current_dir(project.path())
+            .status()?;
+        std::fs::write(project.path().join("main.rs"), "fn main() {}\n")?;
+        let checkpoints = GitCheckpoints::new(project.path(), "old").unwrap();
+        checkpoints.create("Before action 1")?;
+
+        let mut old = Session::new(project.path().to_path_buf());
+        old.metadata.id = "old".to_string();
+        old.metadata.updated_at = Utc::now() - chrono::Duration::days(40);
+        store.save(&old)?;
+
+        let mut completed = Session::new(PathBuf::from("/project"));
+        completed.metadata.id = "completed".to_string();
+        completed.metadata.completed = true;
+        store.save(&completed)?;
+
+        let mut unfinished = Session::new(PathBuf::from("/project"));
+        unfinished.metadata.id = "unfinished".to_string();
+        store.save(&unfinished)?;
+
+        let report = store.vacuum(&SessionSettings {
+            archive_after_days: Some(30),
+            max_storage_mb: None,
+        })?;
+        assert_eq!(report.archived, vec!["old"]);
+        assert!(report.deleted.is_empty());
+        assert_eq!(store.list()?.len(), 2);
+        assert_eq!(store.list_archived()?.len(), 1);
+
+        // With a storage limit of zero, all archived sessions are deleted
+        let report = store.vacuum(&SessionSettings {
+            archive_after_days: None,
+            max_storage_mb: Some(0),
+        })?;
+        assert_eq!(report.deleted, vec!["old"]);
+        assert!(store.list_archived()?.is_empty());
+        assert_eq!(store.list()?.len(), 2);
+        assert!(store.exists("unfinished"));
+        assert!(checkpoints.list()?.is_empty());
+
+        store.archive("unfinished")?;
+        assert!(!store.exists("unfinished"));
+        store.unarchive("unfinished")?;
+        assert!(store.exists("unfinished"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_latest_unfinished() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let store = SessionStore::new(temp_dir.path().to_path_buf());
+        let root_dir = PathBuf::from("/project");
+
+        let mut persistence = FileStatePersistence::new(
+            SessionStore::new(temp_dir.path().to_path_buf()),
+            Session::new(root_dir.clone()),
+        );
+        persistence.save_state("Test task".to_string(), vec![create_action("first")])?;
+        assert!(store.latest_unfinished(&root_dir)?.is_some());
+        assert!(store.latest_unfinished(Path::new("/other"))?.is_none());
+
+        persistence.mark_completed()?;
+        assert!(store.latest_unfinished(&root_dir)?.is_none());
         Ok(())
     }
 }
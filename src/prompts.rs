@@ -0,0 +1,245 @@
+use crate::settings::config_dir;
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Prompts of a project, relative to the project directory
+pub const PROJECT_PROMPTS_DIR: &str = ".code-assistant/prompts";
+
+/// Reusable task template, a Markdown file whose `{{name}}` placeholders are filled
+/// in with the values given when it is used
+#[derive(Debug, Clone, PartialEq)]
+pub struct Prompt {
+    /// File name without the `.md` extension
+    pub name: String,
+    pub path: PathBuf,
+    pub template: String,
+}
+
+fn placeholder() -> &'static Regex {
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    PLACEHOLDER.get_or_init(|| Regex::new(r"\{\{\s*([A-Za-z0-9_-]+)\s*\}\}").unwrap())
+}
+
+impl Prompt {
+    /// First line of the template, without the Markdown heading marker
+    pub fn summary(&self) -> &str {
+        self.template
+            .lines()
+            .find(|line| !line.trim().is_empty())
+            .unwrap_or("")
+            .trim_start_matches('#')
+            .trim()
+    }
+
+    /// Names of the placeholders, in the order they first appear
+    pub fn variables(&self) -> Vec<String> {
+        let mut variables: Vec<String> = Vec::new();
+        for captures in placeholder().captures_iter(&self.template) {
+            let name = &captures[1];
+            if !variables.iter().any(|v| v == name) {
+                variables.push(name.to_string());
+            }
+        }
+        variables
+    }
+
+    /// Fills in the placeholders, all of them need a value
+    pub fn render(&self, values: &BTreeMap<String, String>) -> Result<String> {
+        let missing: Vec<_> = self
+            .variables()
+            .into_iter()
+            .filter(|name| !values.contains_key(name))
+            .collect();
+        if !missing.is_empty() {
+            anyhow::bail!(
+                "Prompt '{}' needs a value for {}, e.g. {}=...",
+                self.name,
+                missing.join(", "),
+                missing[0]
+            );
+        }
+        Ok(placeholder()
+            .replace_all(&self.template, |captures: &regex::Captures| {
+                values[&captures[1]].clone()
+            })
+            .trim()
+            .to_string())
+    }
+}
+
+/// Prompts shared by a team in the repository and the prompts of the user
+pub struct PromptLibrary {
+    /// Directories with prompt files, prompts of later ones replace those of the same name
+    dirs: Vec<PathBuf>,
+}
+
+impl PromptLibrary {
+    pub fn new(dirs: Vec<PathBuf>) -> Self {
+        Self { dirs }
+    }
+
+    /// Prompts of the user in the config directory and of the project in `root_dir`
+    pub fn for_project(root_dir: &Path) -> Result<Self> {
+        Ok(Self::new(vec![
+            config_dir()?.join("prompts"),
+            root_dir.join(PROJECT_PROMPTS_DIR),
+        ]))
+    }
+
+    /// All prompts, sorted by name
+    pub fn list(&self) -> Result<Vec<Prompt>> {
+        let mut prompts = BTreeMap::new();
+        for dir in &self.dirs {
+            if !dir.is_dir() {
+                continue;
+            }
+            let entries = std::fs::read_dir(dir)
+                .with_context(|| format!("Failed to read prompts in {}", dir.display()))?;
+            for entry in entries {
+                let path = entry?.path();
+                if path.extension().is_none_or(|ext| ext != "md") {
+                    continue;
+                }
+                let Some(name) = path.file_stem().and_then(|name| name.to_str()) else {
+                    continue;
+                };
+                let template = std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read prompt {}", path.display()))?;
+                prompts.insert(
+                    name.to_string(),
+                    Prompt {
+                        name: name.to_string(),
+                        path,
+                        template,
+                    },
+                );
+            }
+        }
+        Ok(prompts.into_values().collect())
+    }
+
+    pub fn get(&self, name: &str) -> Result<Prompt> {
+        let prompts = self.list()?;
+        let names: Vec<_> = prompts.iter().map(|p| p.name.clone()).collect();
+        prompts
+            .into_iter()
+            .find(|prompt| prompt.name == name)
+            .with_context(|| {
+                if names.is_empty() {
+                    format!("Unknown prompt '{}', there are no prompts", name)
+                } else {
+                    format!(
+                        "Unknown prompt '{}', available prompts: {}",
+                        name,
+                        names.join(", ")
+                    )
+                }
+            })
+    }
+}
+
+/// Parses values given as `name=value`
+pub fn parse_values(assignments: &[String]) -> Result<BTreeMap<String, String>> {
+    assignments
+        .iter()
+        .map(|assignment| {
+            let (name, value) = assignment
+                .split_once('=')
+                .with_context(|| format!("Expected name=value instead of '{}'", assignment))?;
+            Ok((name.trim().to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Splits a line like `release version="1.2 beta"` at whitespace outside of double quotes
+pub fn split_arguments(line: &str) -> Vec<String> {
+    let mut arguments = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    arguments.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        arguments.push(current);
+    }
+    arguments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_prompt_library() -> Result<()> {
+        let user_dir = TempDir::new()?;
+        let project_dir = TempDir::new()?;
+        std::fs::write(
+            user_dir.path().join("review.md"),
+            "# Review\nReview the changes of {{ branch }}",
+        )?;
+        std::fs::write(user_dir.path().join("notes.txt"), "Not a prompt")?;
+        std::fs::write(
+            project_dir.path().join("release.md"),
+            "Release {{version}}\n\nBump {{version}} in {{file}}.\n",
+        )?;
+        // Prompts of the project replace those of the user
+        std::fs::write(
+            project_dir.path().join("review.md"),
+            "Review {{branch}} strictly",
+        )?;
+
+        let library = PromptLibrary::new(vec![
+            user_dir.path().to_path_buf(),
+            project_dir.path().to_path_buf(),
+            PathBuf::from("/nonexistent"),
+        ]);
+        let prompts = library.list()?;
+        let names: Vec<_> = prompts.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["release", "review"]);
+        assert_eq!(prompts[1].template, "Review {{branch}} strictly");
+
+        let release = library.get("release")?;
+        assert_eq!(release.summary(), "Release {{version}}");
+        assert_eq!(release.variables(), ["version", "file"]);
+        let values = parse_values(&["version=1.2".to_string(), "file=Cargo.toml".to_string()])?;
+        assert_eq!(
+            release.render(&values)?,
+            "Release 1.2\n\nBump 1.2 in Cargo.toml."
+        );
+        let error = release
+            .render(&parse_values(&["version=1.2".to_string()])?)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Prompt 'release' needs a value for file, e.g. file=..."
+        );
+
+        let error = library.get("deploy").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Unknown prompt 'deploy', available prompts: release, review"
+        );
+        assert!(parse_values(&["version".to_string()]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_arguments() {
+        assert_eq!(
+            split_arguments(r#" release  version="1.2 beta" file=Cargo.toml"#),
+            ["release", "version=1.2 beta", "file=Cargo.toml"]
+        );
+    }
+}
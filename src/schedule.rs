@@ -0,0 +1,350 @@
+use crate::ci::CiUI;
+use crate::metrics;
+use crate::notify::{Notification, Notifier};
+use crate::persistence::{Session, SessionStore};
+use crate::settings::Settings;
+use crate::ui::pipe::PipeUI;
+use crate::worktree::Worktree;
+use crate::{create_agent, permission_mode, RunOptions};
+use anyhow::{Context, Result};
+use chrono::{Datelike, Duration, Local, NaiveDateTime, TimeZone, Timelike};
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::time::Instant;
+use tracing::{info, warn};
+
+/// Tag of the sessions of scheduled runs
+pub const SCHEDULED_TAG: &str = "scheduled";
+
+/// Task the daemon starts at the times of a cron expression, like updating the
+/// dependencies every Monday morning
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScheduledTask {
+    /// Name of the schedule, given to the sessions of its runs
+    pub name: String,
+    /// Minute, hour, day of month, month and day of week like `0 6 * * 1`,
+    /// or one of `@hourly`, `@daily`, `@weekly` and `@monthly`
+    pub cron: String,
+    /// Project the task works on
+    pub path: PathBuf,
+    pub task: String,
+    /// Profile from the settings used for the runs
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Model name or alias, the default model of the project if not given
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Work in a git worktree of its own, see `agent --worktree`
+    #[serde(default)]
+    pub worktree: bool,
+}
+
+/// Parsed cron expression, each field as a bit set of the values it matches
+#[derive(Debug, Clone, PartialEq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether day of month and day of week were both restricted, then either one
+    /// has to match like in cron
+    either_day: bool,
+}
+
+impl CronSchedule {
+    pub fn parse(expression: &str) -> Result<Self> {
+        let expression = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            expression => expression,
+        };
+        let fields: Vec<_> = expression.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            anyhow::bail!(
+                "Invalid cron expression '{}', expected 5 fields: minute hour day month weekday",
+                expression
+            );
+        };
+        let either_day = days != "*" && weekdays != "*";
+        let mut weekdays = parse_field(weekdays, 0, 7)
+            .with_context(|| format!("Invalid day of week '{}'", weekdays))?;
+        // Both 0 and 7 are Sunday
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        Ok(Self {
+            minutes: parse_field(minutes, 0, 59)
+                .with_context(|| format!("Invalid minute '{}'", minutes))?,
+            hours: parse_field(hours, 0, 23)
+                .with_context(|| format!("Invalid hour '{}'", hours))?,
+            days: parse_field(days, 1, 31)
+                .with_context(|| format!("Invalid day of month '{}'", days))?,
+            months: parse_field(months, 1, 12)
+                .with_context(|| format!("Invalid month '{}'", months))?,
+            weekdays,
+            either_day,
+        })
+    }
+
+    /// First matching minute after `time`, `None` if there is none within the next years,
+    /// like for February 30th
+    pub fn next_after(&self, time: NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut time = time.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let end = time + Duration::days(5 * 366);
+        while time < end {
+            if !self.matches_day(time) {
+                time = (time.date() + Duration::days(1)).and_hms_opt(0, 0, 0)?;
+            } else if self.hours & (1 << time.hour()) == 0 {
+                time = time.with_minute(0)? + Duration::hours(1);
+            } else if self.minutes & (1 << time.minute()) == 0 {
+                time += Duration::minutes(1);
+            } else {
+                return Some(time);
+            }
+        }
+        None
+    }
+
+    fn matches_day(&self, time: NaiveDateTime) -> bool {
+        if self.months & (1 << time.month()) == 0 {
+            return false;
+        }
+        let day = self.days & (1 << time.day()) != 0;
+        let weekday = self.weekdays & (1 << time.weekday().num_days_from_sunday()) != 0;
+        if self.either_day {
+            day || weekday
+        } else {
+            day && weekday
+        }
+    }
+}
+
+/// Parses a field like `*`, `*/15`, `1-5` or `1,15`, into a bit set of its values
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64> {
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>()?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            anyhow::bail!("Step of zero");
+        }
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (start.parse()?, end.parse()?),
+            // A single value with a step runs until the end, like `5/15`
+            None if part.contains('/') => (range.parse()?, max),
+            None => {
+                let value = range.parse()?;
+                (value, value)
+            }
+        };
+        if start < min || end > max || start > end {
+            anyhow::bail!("Values must be within {}-{}", min, max);
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+/// Starts the scheduled tasks at their times until the daemon stops. Runs of the same
+/// schedule don't overlap, a run taking longer than the interval skips the times missed.
+pub fn start(tasks: Vec<ScheduledTask>) {
+    for task in tasks {
+        let schedule = match CronSchedule::parse(&task.cron) {
+            Ok(schedule) => schedule,
+            Err(e) => {
+                warn!("Schedule '{}' is not started: {:#}", task.name, e);
+                continue;
+            }
+        };
+        tokio::spawn(async move {
+            while let Some(next) = next_run(&schedule) {
+                info!("Schedule '{}' runs next at {}", task.name, next);
+                // Sleeping in steps keeps the schedule after the computer slept
+                while Local::now() < next {
+                    let remaining = (next - Local::now()).to_std().unwrap_or_default();
+                    tokio::time::sleep(remaining.min(std::time::Duration::from_secs(60))).await;
+                }
+                if let Err(e) = run_task(&task).await {
+                    warn!("Scheduled run of '{}' failed: {:#}", task.name, e);
+                }
+            }
+            warn!("Schedule '{}' has no further runs", task.name);
+        });
+    }
+}
+
+/// Next time of the schedule in local time, times skipped by daylight saving time are left out
+fn next_run(schedule: &CronSchedule) -> Option<chrono::DateTime<Local>> {
+    let mut time = Local::now().naive_local();
+    loop {
+        time = schedule.next_after(time)?;
+        if let Some(next) = Local.from_local_datetime(&time).earliest() {
+            return Some(next);
+        }
+    }
+}
+
+/// Runs the task of a schedule in a new session, like `agent --ci`
+async fn run_task(task: &ScheduledTask) -> Result<()> {
+    let root_path = task
+        .path
+        .canonicalize()
+        .with_context(|| format!("Project {} of the schedule not found", task.path.display()))?;
+    let settings = Settings::load_for_project(&root_path)?;
+    let model = match &task.profile {
+        Some(name) => task.model.clone().or(settings.profile(name)?.model.clone()),
+        None => task.model.clone(),
+    };
+    let provider = match &task.profile {
+        Some(name) => settings.profile(name)?.provider,
+        None => None,
+    };
+    let model = model.or_else(|| settings.models.default_for(&root_path).cloned());
+    let (provider, model) = settings.models.resolve(provider, model);
+
+    let mut session = Session::new(root_path.clone());
+    session.metadata.name = Some(task.name.clone());
+    session.metadata.add_tags(&[SCHEDULED_TAG.to_string()]);
+    session.metadata.profile = task.profile.clone();
+    session
+        .metadata
+        .llm_config
+        .apply_overrides(provider, model, None);
+    if task.worktree {
+        session.metadata.worktree = Some(Worktree::create_in_default_location(
+            &root_path,
+            &session.metadata.id,
+        )?);
+    }
+    let id = session.metadata.id.clone();
+    info!(
+        "Starting scheduled run of '{}' in session {}",
+        task.name, id
+    );
+
+    // Nobody answers questions or permission requests of the agent
+    let ui = CiUI::new(Box::new(PipeUI));
+    let mode = permission_mode(None, false, &settings);
+    let notifier = Notifier::new(&settings.notifications);
+    let mut agent = create_agent(
+        settings,
+        &root_path,
+        SessionStore::default_location()?,
+        session,
+        Box::new(ui.clone()),
+        Box::new(ui),
+        RunOptions {
+            mode,
+            dry_run: false,
+            self_review: false,
+            ephemeral: false,
+        },
+    )?;
+    let started = Instant::now();
+    let active = metrics::global().session_started();
+    let result = agent.start_with_task(task.task.clone()).await;
+    active.ended(match &result {
+        Ok(()) if agent.completed() => "completed",
+        Ok(()) => "not_completed",
+        Err(_) => "error",
+    });
+    notifier
+        .send(&Notification::run_ended(
+            &id,
+            &root_path,
+            agent.task(),
+            &result,
+            agent.completed(),
+            agent.completion_message(),
+            started.elapsed(),
+        ))
+        .await;
+    result?;
+    if !agent.completed() {
+        anyhow::bail!("The agent stopped without completing the task");
+    }
+    info!("Scheduled run of '{}' completed", task.name);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn time(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(year, month, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_next_run() -> Result<()> {
+        // 2026-10-16 is a Friday
+        let now = time(2026, 10, 16, 13, 5);
+
+        let weekly = CronSchedule::parse("0 6 * * 1")?;
+        assert_eq!(weekly.next_after(now), Some(time(2026, 10, 19, 6, 0)));
+
+        let quarter_hours = CronSchedule::parse("*/15 9-17 * * 1-5")?;
+        assert_eq!(
+            quarter_hours.next_after(now),
+            Some(time(2026, 10, 16, 13, 15))
+        );
+        assert_eq!(
+            quarter_hours.next_after(time(2026, 10, 16, 17, 45)),
+            Some(time(2026, 10, 19, 9, 0))
+        );
+
+        // The current minute has started already
+        let daily = CronSchedule::parse("@daily")?;
+        assert_eq!(
+            daily.next_after(time(2026, 10, 16, 0, 0)),
+            Some(time(2026, 10, 17, 0, 0))
+        );
+
+        // Sunday can be 0 or 7
+        assert_eq!(
+            CronSchedule::parse("0 0 * * 7")?,
+            CronSchedule::parse("@weekly")?
+        );
+
+        // Day of month or day of week
+        let either = CronSchedule::parse("0 0 1 * 5")?;
+        assert_eq!(either.next_after(now), Some(time(2026, 10, 23, 0, 0)));
+        assert_eq!(
+            either.next_after(time(2026, 10, 30, 12, 0)),
+            Some(time(2026, 11, 1, 0, 0))
+        );
+
+        assert_eq!(CronSchedule::parse("0 0 30 2 *")?.next_after(now), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        for expression in [
+            "",
+            "0 6 * *",
+            "60 * * * *",
+            "0 0 0 * *",
+            "*/0 * * * *",
+            "a * * * *",
+        ] {
+            assert!(
+                CronSchedule::parse(expression).is_err(),
+                "'{}' should be invalid",
+                expression
+            );
+        }
+    }
+}
@@ -0,0 +1,305 @@
+use crate::memory::project_file_name;
+use crate::settings::config_dir;
+use anyhow::{Context, Result};
+use regex_syntax::hir::literal::{ExtractKind, Extractor};
+use regex_syntax::ParserBuilder;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+/// Increased when the format changes, older indexes are rebuilt
+const INDEX_VERSION: u32 = 1;
+
+/// Files modified this recently are not indexed, a change within the same tick of the
+/// file system clock would go unnoticed
+const RACY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Trigrams of the files of a project, which lets searches skip the files that can't
+/// contain a match. There is no file watcher, an entry is used as long as the size and
+/// modification time of its file are unchanged, other files are read and indexed again.
+/// Stored as JSON per project in the config directory.
+pub struct SearchIndex {
+    path: PathBuf,
+    root_dir: PathBuf,
+    /// Loaded on the first search
+    files: Option<HashMap<PathBuf, IndexedFile>>,
+    changed: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredIndex {
+    version: u32,
+    files: HashMap<PathBuf, IndexedFile>,
+}
+
+/// Bloom filter of the lowercase ASCII trigrams of a file. It never misses a trigram,
+/// only few trigrams it doesn't contain pass.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexedFile {
+    size: u64,
+    /// Nanoseconds since the epoch
+    modified: u64,
+    bits: Vec<u64>,
+}
+
+impl IndexedFile {
+    fn new(content: &str, size: u64, modified: u64) -> Self {
+        let trigrams: HashSet<u32> = trigrams(content.as_bytes()).collect();
+        // About a fifth of the bits are set, a power of two keeps the hashing cheap
+        let len = (trigrams.len() * 5).next_power_of_two().max(256);
+        let mut bits = vec![0; len / 64];
+        for trigram in trigrams {
+            let bit = bit_of(trigram, len);
+            bits[bit / 64] |= 1 << (bit % 64);
+        }
+        Self {
+            size,
+            modified,
+            bits,
+        }
+    }
+
+    fn contains(&self, trigram: u32) -> bool {
+        let bit = bit_of(trigram, self.bits.len() * 64);
+        self.bits[bit / 64] & (1 << (bit % 64)) != 0
+    }
+}
+
+fn bit_of(trigram: u32, len: usize) -> usize {
+    (trigram.wrapping_mul(0x9E37_79B1) as usize) & (len - 1)
+}
+
+/// Lowercase trigrams made of ASCII characters only, case-insensitive matches of
+/// other characters can differ in their bytes
+fn trigrams(bytes: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    bytes
+        .windows(3)
+        .filter(|window| window.is_ascii())
+        .map(|window| {
+            let [a, b, c] = [0, 1, 2].map(|i| window[i].to_ascii_lowercase() as u32);
+            (a << 16) | (b << 8) | c
+        })
+}
+
+/// Trigrams a file needs for a match of the regular expression, every match starts with
+/// one of the alternatives and a file needs all trigrams of one of them. `None` if the
+/// file contents can't be narrowed down, like for `.*` or `[a-z]+`.
+pub fn required_trigrams(pattern: &str, case_insensitive: bool) -> Option<Vec<Vec<u32>>> {
+    let hir = ParserBuilder::new()
+        .case_insensitive(case_insensitive)
+        .build()
+        .parse(pattern)
+        .ok()?;
+    let prefixes = Extractor::new().kind(ExtractKind::Prefix).extract(&hir);
+    let mut alternatives: Vec<Vec<u32>> = Vec::new();
+    for literal in prefixes.literals()? {
+        let mut required: Vec<u32> = trigrams(literal.as_bytes()).collect();
+        if required.is_empty() {
+            return None;
+        }
+        required.sort_unstable();
+        required.dedup();
+        // Case variants of the same literal have the same trigrams
+        if !alternatives.contains(&required) {
+            alternatives.push(required);
+        }
+    }
+    (!alternatives.is_empty()).then_some(alternatives)
+}
+
+fn modified_nanos(metadata: &Metadata) -> Option<u64> {
+    let modified = metadata.modified().ok()?;
+    if SystemTime::now().duration_since(modified).ok()? < RACY_INTERVAL {
+        return None;
+    }
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_nanos() as u64)
+}
+
+impl SearchIndex {
+    /// Index of the project in `root_dir`, stored inside `dir`
+    pub fn new(dir: &Path, root_dir: &Path) -> Self {
+        Self {
+            path: dir.join(project_file_name(root_dir, "json")),
+            root_dir: root_dir.to_path_buf(),
+            files: None,
+            changed: false,
+        }
+    }
+
+    /// Uses the search-index directory inside the user's config directory
+    pub fn default_location(root_dir: &Path) -> Result<Self> {
+        Ok(Self::new(&config_dir()?.join("search-index"), root_dir))
+    }
+
+    fn files(&mut self) -> &mut HashMap<PathBuf, IndexedFile> {
+        let path = &self.path;
+        self.files.get_or_insert_with(|| {
+            let stored = std::fs::read_to_string(path)
+                .ok()
+                .and_then(|json| serde_json::from_str::<StoredIndex>(&json).ok())
+                .filter(|stored| stored.version == INDEX_VERSION);
+            match stored {
+                Some(stored) => stored.files,
+                None => {
+                    debug!("No search index at {}, building it", path.display());
+                    HashMap::new()
+                }
+            }
+        })
+    }
+
+    /// Whether the index knows that the file can't match, `false` if the file needs to be
+    /// searched because it may match or its entry is missing or outdated
+    pub fn excludes(&mut self, path: &Path, metadata: &Metadata, required: &[Vec<u32>]) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.root_dir) else {
+            return false;
+        };
+        let modified = modified_nanos(metadata);
+        let Some(file) = self.files().get(relative) else {
+            return false;
+        };
+        if file.size != metadata.len() || Some(file.modified) != modified {
+            return false;
+        }
+        !required
+            .iter()
+            .any(|trigrams| trigrams.iter().all(|&trigram| file.contains(trigram)))
+    }
+
+    /// Indexes the content of a file which was read for a search
+    pub fn update(&mut self, path: &Path, metadata: &Metadata, content: &str) {
+        let Ok(relative) = path.strip_prefix(&self.root_dir) else {
+            return;
+        };
+        let relative = relative.to_path_buf();
+        let Some(modified) = modified_nanos(metadata) else {
+            if self.files().remove(&relative).is_some() {
+                self.changed = true;
+            }
+            return;
+        };
+        let files = self.files();
+        if files
+            .get(&relative)
+            .is_some_and(|file| file.size == metadata.len() && file.modified == modified)
+        {
+            return;
+        }
+        files.insert(
+            relative,
+            IndexedFile::new(content, metadata.len(), modified),
+        );
+        self.changed = true;
+    }
+
+    /// Drops the entries of files which no longer exist, after a search of the whole project
+    /// visited the files `seen`
+    pub fn retain(&mut self, seen: &HashSet<PathBuf>) {
+        let root_dir = self.root_dir.clone();
+        let files = self.files();
+        let count = files.len();
+        files.retain(|relative, _| seen.contains(&root_dir.join(relative)));
+        if files.len() != count {
+            self.changed = true;
+        }
+    }
+
+    pub fn root_dir(&self) -> &Path {
+        &self.root_dir
+    }
+
+    /// Writes the index if files were indexed since it was loaded
+    pub fn save(&mut self) -> Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        let Some(files) = self.files.take() else {
+            return Ok(());
+        };
+        let stored = StoredIndex {
+            version: INDEX_VERSION,
+            files,
+        };
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let result = std::fs::write(&self.path, serde_json::to_string(&stored)?)
+            .with_context(|| format!("Failed to write search index {}", self.path.display()));
+        self.files = Some(stored.files);
+        self.changed = false;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_required_trigrams() {
+        let trigrams_of = |text: &str| {
+            let mut trigrams: Vec<u32> = trigrams(text.as_bytes()).collect();
+            trigrams.sort_unstable();
+            trigrams.dedup();
+            trigrams
+        };
+        assert_eq!(
+            required_trigrams("fn main", false),
+            Some(vec![trigrams_of("fn main")])
+        );
+        assert_eq!(
+            required_trigrams(r"\bFooBar\b", true),
+            Some(vec![trigrams_of("foobar")])
+        );
+        assert_eq!(
+            required_trigrams("(create|delete)_file", false),
+            Some(vec![trigrams_of("create_file"), trigrams_of("delete_file")])
+        );
+        assert_eq!(required_trigrams("ab", false), None);
+        assert_eq!(required_trigrams("[a-z]+_file", false), None);
+        assert_eq!(required_trigrams("(foo", false), None);
+    }
+
+    #[test]
+    fn test_search_index() -> Result<()> {
+        let index_dir = TempDir::new()?;
+        let project = TempDir::new()?;
+        let file = project.path().join("main.rs");
+        std::fs::write(&file, "fn main() {\n    println!(\"Hello\");\n}\n")?;
+        // Older files are indexed, a recent change could go unnoticed
+        let old = SystemTime::now() - Duration::from_secs(60);
+        std::fs::File::options()
+            .write(true)
+            .open(&file)?
+            .set_modified(old)?;
+        let metadata = std::fs::metadata(&file)?;
+
+        let hello = required_trigrams("hello", true).unwrap();
+        let goodbye = required_trigrams("goodbye", false).unwrap();
+        let mut index = SearchIndex::new(index_dir.path(), project.path());
+        assert!(!index.excludes(&file, &metadata, &goodbye));
+        index.update(&file, &metadata, &std::fs::read_to_string(&file)?);
+        assert!(!index.excludes(&file, &metadata, &hello));
+        assert!(index.excludes(&file, &metadata, &goodbye));
+        index.save()?;
+
+        // The stored index is used until the file changes
+        let mut index = SearchIndex::new(index_dir.path(), project.path());
+        assert!(index.excludes(&file, &metadata, &goodbye));
+        std::fs::write(&file, "// goodbye\n")?;
+        let metadata = std::fs::metadata(&file)?;
+        assert!(!index.excludes(&file, &metadata, &goodbye));
+        index.update(&file, &metadata, "// goodbye\n");
+        assert!(!index.excludes(&file, &metadata, &goodbye));
+
+        index.retain(&HashSet::new());
+        index.save()?;
+        let mut index = SearchIndex::new(index_dir.path(), project.path());
+        assert!(index.files().is_empty());
+        Ok(())
+    }
+}
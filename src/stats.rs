@@ -0,0 +1,179 @@
+use crate::export::tool_name_and_params;
+use crate::llm::Usage;
+use crate::persistence::Session;
+use crate::types::Tool;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::PathBuf;
+
+/// Prices in USD per million input and output tokens, matched by model name prefix
+const MODEL_PRICES: &[(&str, f64, f64)] = &[
+    ("claude-3-5-sonnet", 3.0, 15.0),
+    ("claude-3-5-haiku", 0.8, 4.0),
+    ("claude-3-opus", 15.0, 75.0),
+    ("claude-3-haiku", 0.25, 1.25),
+    ("gpt-4o-mini", 0.15, 0.6),
+    ("gpt-4o", 2.5, 10.0),
+];
+
+/// Aggregated statistics of a session
+#[derive(Debug)]
+pub struct SessionStats {
+    pub turns: usize,
+    pub usage: Usage,
+    pub model: Option<String>,
+    /// Estimated cost in USD, `None` if the price of the model is unknown
+    pub estimated_cost: Option<f64>,
+    pub tool_invocations: BTreeMap<String, usize>,
+    /// Files written, updated or deleted by the agent
+    pub files_touched: BTreeSet<PathBuf>,
+}
+
+impl SessionStats {
+    pub fn from_session(session: &Session) -> Self {
+        let actions = &session.state.actions;
+        let mut tool_invocations = BTreeMap::new();
+        let mut files_touched = BTreeSet::new();
+        for action in actions {
+            let (name, _) = tool_name_and_params(&action.tool);
+            *tool_invocations.entry(name).or_insert(0) += 1;
+            if !action.success {
+                continue;
+            }
+            match &action.tool {
+                Tool::WriteFile { path, .. } | Tool::UpdateFile { path, .. } => {
+                    files_touched.insert(path.clone());
+                }
+                Tool::DeleteFiles { paths } => files_touched.extend(paths.iter().cloned()),
+                _ => {}
+            }
+        }
+
+        let usage = session.metadata.usage;
+        let model = session.metadata.llm_config.model_name();
+        let estimated_cost = model
+            .as_deref()
+            .and_then(|model| estimate_cost(model, &usage));
+
+        Self {
+            turns: actions.len(),
+            usage,
+            model,
+            estimated_cost,
+            tool_invocations,
+            files_touched,
+        }
+    }
+}
+
+/// Estimates the cost of the given usage in USD based on the list prices of the model
+pub fn estimate_cost(model: &str, usage: &Usage) -> Option<f64> {
+    MODEL_PRICES
+        .iter()
+        .find(|(prefix, _, _)| model.starts_with(prefix))
+        .map(|(_, input_price, output_price)| {
+            (usage.input_tokens as f64 * input_price + usage.output_tokens as f64 * output_price)
+                / 1_000_000.0
+        })
+}
+
+impl fmt::Display for SessionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Turns:          {}", self.turns)?;
+        writeln!(f, "Input tokens:   {}", self.usage.input_tokens)?;
+        writeln!(f, "Output tokens:  {}", self.usage.output_tokens)?;
+        let model = self.model.as_deref().unwrap_or("unknown model");
+        match self.estimated_cost {
+            Some(cost) => writeln!(f, "Estimated cost: ${:.2} ({})", cost, model)?,
+            None => writeln!(f, "Estimated cost: unknown ({})", model)?,
+        }
+
+        if !self.tool_invocations.is_empty() {
+            writeln!(f, "Tool invocations:")?;
+            for (tool, count) in &self.tool_invocations {
+                writeln!(f, "  {:<15} {}", tool, count)?;
+            }
+        }
+        if !self.files_touched.is_empty() {
+            writeln!(f, "Files touched:")?;
+            for path in &self.files_touched {
+                writeln!(f, "  {}", path.display())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ActionResult;
+
+    fn create_action(tool: Tool, success: bool) -> ActionResult {
+        ActionResult {
+            tool,
+            success,
+            result: String::new(),
+            error: None,
+            reasoning: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_session_stats() {
+        let mut session = Session::new(PathBuf::from("/project"));
+        session.metadata.usage = Usage {
+            input_tokens: 200_000,
+            output_tokens: 10_000,
+        };
+        session.state.actions = vec![
+            create_action(
+                Tool::ReadFiles {
+                    paths: vec![PathBuf::from("src/lib.rs")],
+                },
+                true,
+            ),
+            create_action(
+                Tool::WriteFile {
+                    path: PathBuf::from("src/new.rs"),
+                    content: String::new(),
+                },
+                true,
+            ),
+            create_action(
+                Tool::DeleteFiles {
+                    paths: vec![PathBuf::from("src/old.rs")],
+                },
+                false,
+            ),
+            create_action(
+                Tool::ReadFiles {
+                    paths: vec![PathBuf::from("src/new.rs")],
+                },
+                true,
+            ),
+        ];
+
+        let stats = SessionStats::from_session(&session);
+        assert_eq!(stats.turns, 4);
+        assert_eq!(stats.tool_invocations["ReadFiles"], 2);
+        assert_eq!(stats.tool_invocations["DeleteFiles"], 1);
+        // Failed deletion did not touch the file
+        assert_eq!(
+            stats.files_touched.into_iter().collect::<Vec<_>>(),
+            vec![PathBuf::from("src/new.rs")]
+        );
+        // Default model of the Anthropic provider: 0.6$ input + 0.15$ output
+        assert_eq!(stats.estimated_cost, Some(0.75));
+    }
+
+    #[test]
+    fn test_unknown_model_has_no_cost() {
+        let usage = Usage {
+            input_tokens: 1000,
+            output_tokens: 1000,
+        };
+        assert_eq!(estimate_cost("llama3.2", &usage), None);
+        assert!(estimate_cost("gpt-4o-mini-2024-07-18", &usage).unwrap() < 0.001);
+    }
+}
@@ -0,0 +1,182 @@
+use super::{check_key, check_response, missing_token, Ticket, Tracker, TrackerType};
+use crate::forge::Comment;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::Value;
+
+#[derive(Debug, Deserialize)]
+struct JiraIssue {
+    key: String,
+    fields: JiraFields,
+}
+
+#[derive(Debug, Deserialize)]
+struct JiraFields {
+    summary: String,
+    status: JiraStatus,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    comment: Option<JiraComments>,
+    /// Custom fields, the acceptance criteria among them
+    #[serde(flatten)]
+    other: serde_json::Map<String, Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JiraStatus {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct JiraComments {
+    comments: Vec<JiraComment>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JiraComment {
+    author: JiraUser,
+    body: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JiraUser {
+    display_name: String,
+}
+
+/// Client of the Jira REST API, version 2 returns descriptions and comments as text
+/// instead of document trees
+pub struct Jira {
+    client: Client,
+    base_url: String,
+    email: Option<String>,
+    token: Option<String>,
+    acceptance_criteria_field: Option<String>,
+}
+
+impl Jira {
+    pub fn new(
+        url: &str,
+        email: Option<String>,
+        token: Option<String>,
+        acceptance_criteria_field: Option<String>,
+    ) -> Self {
+        Self {
+            client: Client::new(),
+            base_url: url.trim_end_matches('/').to_string(),
+            email,
+            token,
+            acceptance_criteria_field,
+        }
+    }
+
+    fn ticket_from(&self, issue: JiraIssue) -> Ticket {
+        let fields = issue.fields;
+        // Text fields hold strings, multi-line lists arrays of them
+        let acceptance_criteria = self.acceptance_criteria_field.as_ref().and_then(|field| {
+            match fields.other.get(field)? {
+                Value::String(text) => Some(text.clone()),
+                Value::Array(items) => Some(
+                    items
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                ),
+                _ => None,
+            }
+        });
+        Ticket {
+            key: issue.key,
+            title: fields.summary,
+            status: fields.status.name,
+            description: fields.description.unwrap_or_default(),
+            acceptance_criteria,
+            comments: fields
+                .comment
+                .map(|comment| comment.comments)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|comment| Comment {
+                    author: comment.author.display_name,
+                    body: comment.body,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[async_trait]
+impl Tracker for Jira {
+    fn description(&self) -> String {
+        format!("Jira at {}", self.base_url)
+    }
+
+    async fn ticket(&self, key: &str) -> Result<Ticket> {
+        check_key(key)?;
+        let token = self
+            .token
+            .as_ref()
+            .ok_or_else(|| missing_token(TrackerType::Jira))?;
+        let mut fields = "summary,status,description,comment".to_string();
+        if let Some(field) = &self.acceptance_criteria_field {
+            fields.push(',');
+            fields.push_str(field);
+        }
+        let request = self
+            .client
+            .get(format!("{}/rest/api/2/issue/{}", self.base_url, key))
+            .query(&[("fields", fields)])
+            .header("Accept", "application/json");
+        // Jira Cloud takes the API token with the account, Server and Data Center
+        // take personal access tokens
+        let request = match &self.email {
+            Some(email) => request.basic_auth(email, Some(token)),
+            None => request.bearer_auth(token),
+        };
+        let issue: JiraIssue = check_response(request.send().await?, "Jira")
+            .await?
+            .json()
+            .await
+            .context("Failed to parse Jira API response")?;
+        Ok(self.ticket_from(issue))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_issue_response() {
+        let issue: JiraIssue = serde_json::from_str(
+            r#"{
+                "key": "PROJ-1234",
+                "fields": {
+                    "summary": "Export as CSV",
+                    "status": {"name": "In Progress"},
+                    "description": "Users want to export reports.",
+                    "comment": {"comments": [
+                        {"author": {"displayName": "Jane Doe"}, "body": "Semicolons please"}
+                    ]},
+                    "customfield_10042": "Header row"
+                }
+            }"#,
+        )
+        .unwrap();
+        let jira = Jira::new(
+            "https://example.atlassian.net/",
+            None,
+            None,
+            Some("customfield_10042".to_string()),
+        );
+        let ticket = jira.ticket_from(issue);
+        assert_eq!(ticket.title, "Export as CSV");
+        assert_eq!(ticket.acceptance_criteria.as_deref(), Some("Header row"));
+        assert_eq!(ticket.comments[0].author, "Jane Doe");
+        assert_eq!(jira.description(), "Jira at https://example.atlassian.net");
+    }
+}
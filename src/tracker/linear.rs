@@ -0,0 +1,161 @@
+use super::{check_key, check_response, missing_token, Ticket, Tracker, TrackerType};
+use crate::forge::Comment;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::json;
+
+const API_URL: &str = "https://api.linear.app/graphql";
+
+/// The issue is looked up by its identifier like `ENG-123`
+const ISSUE_QUERY: &str = "query($id: String!) { issue(id: $id) { \
+    identifier title description state { name } \
+    comments { nodes { body user { name } } } } }";
+
+#[derive(Debug, Deserialize)]
+struct GraphQLResponse {
+    data: Option<IssueData>,
+    #[serde(default)]
+    errors: Vec<GraphQLError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphQLError {
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IssueData {
+    issue: LinearIssue,
+}
+
+#[derive(Debug, Deserialize)]
+struct LinearIssue {
+    identifier: String,
+    title: String,
+    #[serde(default)]
+    description: Option<String>,
+    state: LinearState,
+    comments: LinearComments,
+}
+
+#[derive(Debug, Deserialize)]
+struct LinearState {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct LinearComments {
+    nodes: Vec<LinearComment>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LinearComment {
+    body: String,
+    /// Missing for comments of integrations
+    user: Option<LinearUser>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LinearUser {
+    name: String,
+}
+
+/// Client of the Linear GraphQL API. Linear has no field for acceptance criteria,
+/// teams keep them in the description.
+pub struct Linear {
+    client: Client,
+    token: Option<String>,
+}
+
+impl Linear {
+    pub fn new(token: Option<String>) -> Self {
+        Self {
+            client: Client::new(),
+            token,
+        }
+    }
+}
+
+fn ticket_from(issue: LinearIssue) -> Ticket {
+    Ticket {
+        key: issue.identifier,
+        title: issue.title,
+        status: issue.state.name,
+        description: issue.description.unwrap_or_default(),
+        acceptance_criteria: None,
+        comments: issue
+            .comments
+            .nodes
+            .into_iter()
+            .map(|comment| Comment {
+                author: comment
+                    .user
+                    .map_or_else(|| "integration".to_string(), |user| user.name),
+                body: comment.body,
+            })
+            .collect(),
+    }
+}
+
+#[async_trait]
+impl Tracker for Linear {
+    fn description(&self) -> String {
+        "Linear".to_string()
+    }
+
+    async fn ticket(&self, key: &str) -> Result<Ticket> {
+        check_key(key)?;
+        let token = self
+            .token
+            .as_ref()
+            .ok_or_else(|| missing_token(TrackerType::Linear))?;
+        // Personal API keys are sent without a scheme
+        let response = self
+            .client
+            .post(API_URL)
+            .header("Authorization", token)
+            .json(&json!({ "query": ISSUE_QUERY, "variables": { "id": key } }))
+            .send()
+            .await?;
+        let response: GraphQLResponse = check_response(response, "Linear")
+            .await?
+            .json()
+            .await
+            .context("Failed to parse Linear API response")?;
+        if let Some(error) = response.errors.first() {
+            anyhow::bail!("Linear API request failed: {}", error.message);
+        }
+        let data = response
+            .data
+            .ok_or_else(|| anyhow::anyhow!("Ticket {} not found", key))?;
+        Ok(ticket_from(data.issue))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_issue_response() {
+        let response: GraphQLResponse = serde_json::from_str(
+            r#"{"data": {"issue": {
+                "identifier": "ENG-123",
+                "title": "Flaky login test",
+                "description": null,
+                "state": {"name": "Todo"},
+                "comments": {"nodes": [
+                    {"body": "Fails on CI only", "user": {"name": "Jane"}},
+                    {"body": "Linked PR #4", "user": null}
+                ]}
+            }}}"#,
+        )
+        .unwrap();
+        let ticket = ticket_from(response.data.unwrap().issue);
+        assert_eq!(ticket.key, "ENG-123");
+        assert_eq!(ticket.description, "");
+        assert_eq!(ticket.comments[1].author, "integration");
+    }
+}
@@ -0,0 +1,216 @@
+pub mod jira;
+pub mod linear;
+
+use crate::auth::keyring;
+use crate::forge::Comment;
+use anyhow::Result;
+use async_trait::async_trait;
+use clap::ValueEnum;
+use reqwest::Response;
+use serde::Deserialize;
+use tracing::warn;
+
+pub use jira::Jira;
+pub use linear::Linear;
+
+/// Names of the tools working with the issue tracker, left out if none is configured
+pub const TRACKER_TOOLS: &[&str] = &["FetchTicket"];
+
+/// Issue tracker used next to the forge, where tickets have keys like `PROJ-1234`
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrackerType {
+    Jira,
+    Linear,
+}
+
+impl TrackerType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TrackerType::Jira => "jira",
+            TrackerType::Linear => "linear",
+        }
+    }
+
+    /// Environment variable holding the API token
+    pub fn env_var(&self) -> &'static str {
+        match self {
+            TrackerType::Jira => "JIRA_API_TOKEN",
+            TrackerType::Linear => "LINEAR_API_KEY",
+        }
+    }
+
+    /// Looks up the API token, the environment variable takes precedence over
+    /// the OS credential store
+    pub fn token(&self) -> Option<String> {
+        if let Ok(token) = std::env::var(self.env_var()) {
+            return Some(token);
+        }
+        match keyring::get(self.name()) {
+            Ok(token) => token,
+            Err(e) => {
+                warn!("{:#}", e);
+                None
+            }
+        }
+    }
+}
+
+/// Connection to the issue tracker of a project
+#[derive(Debug, Clone, Deserialize)]
+pub struct TrackerSettings {
+    #[serde(rename = "type")]
+    pub tracker_type: TrackerType,
+    /// Base URL of the Jira instance, like `https://example.atlassian.net`
+    pub url: Option<String>,
+    /// Account the API token of Jira Cloud belongs to. Without it, the token is sent
+    /// as personal access token of Jira Server or Data Center.
+    pub email: Option<String>,
+    /// Jira field holding the acceptance criteria, like `customfield_10042`
+    pub acceptance_criteria_field: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ticket {
+    pub key: String,
+    pub title: String,
+    pub status: String,
+    pub description: String,
+    /// Acceptance criteria kept apart from the description
+    pub acceptance_criteria: Option<String>,
+    pub comments: Vec<Comment>,
+}
+
+impl Ticket {
+    /// Renders the ticket and its comments for the agent
+    pub fn format(&self) -> String {
+        let mut text = format!(
+            "{} {} ({})\n\n{}\n",
+            self.key,
+            self.title,
+            self.status,
+            self.description.trim()
+        );
+        if let Some(criteria) = &self.acceptance_criteria {
+            text.push_str(&format!("\nAcceptance criteria:\n{}\n", criteria.trim()));
+        }
+        for comment in &self.comments {
+            text.push_str(&format!(
+                "\nComment by {}:\n{}\n",
+                comment.author,
+                comment.body.trim()
+            ));
+        }
+        text
+    }
+}
+
+/// Tickets of the issue tracker a project is planned in
+#[async_trait]
+pub trait Tracker: Send + Sync {
+    /// Description of the tracker, like `Jira at https://example.atlassian.net`
+    fn description(&self) -> String;
+
+    async fn ticket(&self, key: &str) -> Result<Ticket>;
+}
+
+/// Creates the client of the configured tracker
+pub fn create(settings: &TrackerSettings) -> Result<Box<dyn Tracker>> {
+    let token = settings.tracker_type.token();
+    Ok(match settings.tracker_type {
+        TrackerType::Jira => {
+            let url = settings
+                .url
+                .as_deref()
+                .ok_or_else(|| anyhow::anyhow!("The Jira tracker needs a url"))?;
+            Box::new(Jira::new(
+                url,
+                settings.email.clone(),
+                token,
+                settings.acceptance_criteria_field.clone(),
+            ))
+        }
+        TrackerType::Linear => Box::new(Linear::new(token)),
+    })
+}
+
+/// Checks that a key looks like `PROJ-1234`, so it can go into a URL unescaped
+pub fn check_key(key: &str) -> Result<()> {
+    let valid = key.split_once('-').is_some_and(|(project, number)| {
+        !project.is_empty()
+            && project
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && !number.is_empty()
+            && number.chars().all(|c| c.is_ascii_digit())
+    });
+    if !valid {
+        anyhow::bail!("'{}' is not a ticket key like PROJ-1234", key);
+    }
+    Ok(())
+}
+
+/// Fails with the error message of the API if the request was not successful
+async fn check_response(response: Response, service: &str) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let text = response.text().await.unwrap_or_default();
+    // Jira lists its errors in `errorMessages`, GraphQL in `errors`
+    let message = serde_json::from_str::<serde_json::Value>(&text)
+        .ok()
+        .and_then(|value| {
+            value["errorMessages"][0]
+                .as_str()
+                .or_else(|| value["errors"][0]["message"].as_str())
+                .map(str::to_string)
+        })
+        .unwrap_or(text);
+    anyhow::bail!("{} API request failed ({}): {}", service, status, message)
+}
+
+/// Error for requests without an API token
+fn missing_token(tracker_type: TrackerType) -> anyhow::Error {
+    anyhow::anyhow!(
+        "No API token for {}, set {} or run `code-assistant auth set-token {}`",
+        tracker_type.name(),
+        tracker_type.env_var(),
+        tracker_type.name()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_key() {
+        assert!(check_key("PROJ-1234").is_ok());
+        assert!(check_key("ENG-7").is_ok());
+        assert!(check_key("PROJ-").is_err());
+        assert!(check_key("1234").is_err());
+        assert!(check_key("PROJ-12/../x").is_err());
+    }
+
+    #[test]
+    fn test_format_ticket() {
+        let ticket = Ticket {
+            key: "PROJ-1234".to_string(),
+            title: "Export as CSV".to_string(),
+            status: "In Progress".to_string(),
+            description: "Users want to export reports.\n".to_string(),
+            acceptance_criteria: Some("- Header row\n- UTF-8".to_string()),
+            comments: vec![Comment {
+                author: "Jane Doe".to_string(),
+                body: "Semicolons please".to_string(),
+            }],
+        };
+        assert_eq!(
+            ticket.format(),
+            "PROJ-1234 Export as CSV (In Progress)\n\nUsers want to export reports.\n\n\
+             Acceptance criteria:\n- Header row\n- UTF-8\n\n\
+             Comment by Jane Doe:\nSemicolons please\n"
+        );
+    }
+}
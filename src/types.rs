@@ -20,12 +20,16 @@ pub struct WorkingMemory {
     pub file_summaries: HashMap<PathBuf, String>,
     /// Complete file tree of the repository
     pub file_tree: Option<FileTreeEntry>,
+    /// File trees of the further projects of the task, by their root directory
+    pub project_trees: HashMap<PathBuf, FileTreeEntry>,
     /// Current task description
     pub current_task: String,
     /// Memory of previous actions and their results
     pub action_history: Vec<ActionResult>,
     /// Additional context or notes the agent has generated
     pub notes: Vec<String>,
+    /// Steps of the plan approved by the user
+    pub plan: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -71,6 +75,28 @@ pub enum Tool {
         /// Optional working directory for the command
         working_dir: Option<PathBuf>,
     },
+    /// Replace the notes about the project which are kept across sessions
+    UpdateProjectMemory { content: String },
+    /// Submit a plan for approval by the user (in plan mode)
+    SubmitPlan { steps: Vec<String> },
+    /// Restore the project files of a checkpoint taken before files were modified
+    RollbackToCheckpoint {
+        /// Number of the checkpoint, the latest one if not given
+        checkpoint: Option<usize>,
+    },
+    /// Fetch an issue with its comments from the repository's forge
+    FetchIssue { number: u64 },
+    /// Fetch a pull request with its comments and diff from the repository's forge
+    FetchPullRequest { number: u64 },
+    /// Open a pull request for the current branch
+    CreatePullRequest {
+        title: String,
+        body: String,
+        /// Branch to merge into, the default branch if not given
+        base: Option<String>,
+    },
+    /// Fetch a ticket with its comments from the issue tracker, by a key like `PROJ-1234`
+    FetchTicket { key: String },
     /// Search for text in files
     Search {
         /// The text to search for
@@ -88,6 +114,27 @@ pub enum Tool {
     },
 }
 
+impl Tool {
+    /// Whether the tool leaves the project untouched, only these are available in plan mode
+    pub fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            Tool::ListFiles { .. }
+                | Tool::ReadFiles { .. }
+                | Tool::Summarize { .. }
+                | Tool::AskUser { .. }
+                | Tool::MessageUser { .. }
+                | Tool::CompleteTask { .. }
+                | Tool::Search { .. }
+                | Tool::SubmitPlan { .. }
+                | Tool::UpdateProjectMemory { .. }
+                | Tool::FetchIssue { .. }
+                | Tool::FetchPullRequest { .. }
+                | Tool::FetchTicket { .. }
+        )
+    }
+}
+
 /// Result of a tool execution
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ActionResult {
@@ -127,7 +174,7 @@ pub struct ToolDescription {
 }
 
 /// Represents the parsed response from the LLM
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AgentAction {
     pub tool: Tool,
     pub reasoning: String,
@@ -177,12 +224,27 @@ pub struct SearchResult {
     pub match_ranges: Vec<(usize, usize)>, // Start and end positions of matches in the line
 }
 
-pub trait CodeExplorer {
+pub trait CodeExplorer: Send + Sync {
     fn root_dir(&self) -> PathBuf;
     /// Reads the content of a file
     fn read_file(&self, path: &PathBuf) -> Result<String>;
     fn create_initial_tree(&self, max_depth: usize) -> Result<FileTreeEntry>;
     fn list_files(&self, path: &PathBuf, max_depth: Option<usize>) -> Result<FileTreeEntry>;
+    /// Reads several files, the results are in the order of the paths
+    fn read_files(&self, paths: &[PathBuf]) -> Vec<Result<String>> {
+        paths.iter().map(|path| self.read_file(path)).collect()
+    }
+    /// Lists several paths, the results are in the order of the paths
+    fn list_paths(
+        &self,
+        paths: &[PathBuf],
+        max_depth: Option<usize>,
+    ) -> Vec<Result<FileTreeEntry>> {
+        paths
+            .iter()
+            .map(|path| self.list_files(path, max_depth))
+            .collect()
+    }
     /// Applies FileUpdates to a file
     fn apply_updates(&self, path: &Path, updates: &[FileUpdate]) -> Result<String>;
     /// Search for text in files with advanced options
@@ -0,0 +1,138 @@
+use super::{UIError, UIEvent, UIMessage, UserInterface};
+use crate::llm::Usage;
+use crate::permissions::{PermissionDecision, PermissionMediator, PermissionRequest};
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use serde_json::json;
+use std::io::{self, Write};
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+/// Encodes a message for the user as JSON object
+pub fn message_json(message: &UIMessage) -> serde_json::Value {
+    let (kind, text) = match message {
+        UIMessage::Action(text) => ("action", text),
+        UIMessage::Question(text) => ("question", text),
+        UIMessage::Reasoning(text) => ("reasoning", text),
+        UIMessage::Review(text) => ("review", text),
+        UIMessage::Diff(text) => ("diff", text),
+    };
+    json!({ "type": kind, "text": text })
+}
+
+pub fn permission_json(request: &PermissionRequest) -> serde_json::Value {
+    json!({
+        "type": "permission_requested",
+        "tool": request.tool,
+        "reason": request.reason,
+        "grant": request.grant_pattern(),
+    })
+}
+
+/// Expects `granted`, `granted_session`, `granted_project` or `denied` as answer to
+/// a permission request, anything else denies the request
+pub fn parse_decision(answer: &str) -> PermissionDecision {
+    serde_json::from_value(json!(answer.trim())).unwrap_or(PermissionDecision::Denied)
+}
+
+/// Final event of a run
+pub fn summary_json(
+    result: &anyhow::Result<()>,
+    usage: &Usage,
+    cost: Option<f64>,
+) -> serde_json::Value {
+    json!({
+        "type": "summary",
+        "success": result.is_ok(),
+        "error": result.as_ref().err().map(|e| format!("{:#}", e)),
+        "input_tokens": usage.input_tokens,
+        "output_tokens": usage.output_tokens,
+        "cost": cost,
+    })
+}
+
+/// Prints messages and events as newline-delimited JSON objects to stdout, for
+/// running the agent from scripts and CI. Answers to questions and permission
+/// requests are read as lines from stdin.
+pub struct JsonUI;
+
+impl JsonUI {
+    pub fn emit(&self, value: &serde_json::Value) -> Result<(), UIError> {
+        let mut stdout = io::stdout().lock();
+        writeln!(stdout, "{}", value)?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    /// Reads a line from stdin, empty at the end of the input
+    async fn read_line(&self) -> Result<String, UIError> {
+        let mut line = String::new();
+        BufReader::new(tokio::io::stdin())
+            .read_line(&mut line)
+            .await?;
+        Ok(line.trim().to_string())
+    }
+}
+
+#[async_trait]
+impl UserInterface for JsonUI {
+    async fn display(&self, message: UIMessage) -> Result<(), UIError> {
+        self.emit(&message_json(&message))
+    }
+
+    async fn get_input(&self, prompt: &str) -> Result<String, UIError> {
+        self.emit(&json!({ "type": "input_requested", "prompt": prompt }))?;
+        self.read_line().await
+    }
+
+    async fn event(&self, event: UIEvent) -> Result<(), UIError> {
+        let value = serde_json::to_value(event).map_err(io::Error::from)?;
+        self.emit(&value)
+    }
+
+    fn interrupted(&self) -> BoxFuture<'static, ()> {
+        Box::pin(async {
+            if tokio::signal::ctrl_c().await.is_err() {
+                std::future::pending::<()>().await
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl PermissionMediator for JsonUI {
+    async fn request_permission(
+        &self,
+        request: &PermissionRequest,
+    ) -> anyhow::Result<PermissionDecision> {
+        self.emit(&permission_json(request))?;
+        Ok(parse_decision(&self.read_line().await?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_lines() {
+        assert_eq!(
+            message_json(&UIMessage::Reasoning("Reading the code".to_string())).to_string(),
+            r#"{"text":"Reading the code","type":"reasoning"}"#
+        );
+        let event = UIEvent::ToolResult {
+            tool: "ExecuteCommand".to_string(),
+            success: false,
+            output: String::new(),
+            error: Some("Command failed".to_string()),
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"type":"tool_result","tool":"ExecuteCommand","success":false,"output":"","error":"Command failed"}"#
+        );
+        assert_eq!(
+            parse_decision("granted_session\n"),
+            PermissionDecision::GrantedSession
+        );
+        assert_eq!(parse_decision("yes"), PermissionDecision::Denied);
+    }
+}
@@ -1,5 +1,9 @@
+pub mod json;
+pub mod pipe;
 pub mod terminal;
 use async_trait::async_trait;
+use futures::future::BoxFuture;
+use serde::Serialize;
 use thiserror::Error;
 
 #[derive(Debug, Clone)]
@@ -10,6 +14,35 @@ pub enum UIMessage {
     Question(String),
     // LLM's reasoning about its next action
     Reasoning(String),
+    // Review of the agent's changes before it completes the task
+    Review(String),
+    // Unified diff of a file modified by the agent
+    Diff(String),
+}
+
+/// Structured events of the agent, for programs driving it rather than users
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UIEvent {
+    /// The agent is about to execute a tool
+    ToolCall {
+        tool: String,
+        params: serde_json::Value,
+    },
+    /// Result of a tool, including the output of hooks
+    ToolResult {
+        tool: String,
+        success: bool,
+        output: String,
+        error: Option<String>,
+    },
+    /// Tokens used by the run so far, after each response of the LLM
+    Usage {
+        input_tokens: u64,
+        output_tokens: u64,
+        /// Estimated cost in USD, if the prices of the model are known
+        cost: Option<f64>,
+    },
 }
 
 #[derive(Error, Debug)]
@@ -29,4 +62,16 @@ pub trait UserInterface: Send + Sync {
 
     /// Get input from the user
     async fn get_input(&self, prompt: &str) -> Result<String, UIError>;
+
+    /// Reports a structured event, ignored by interfaces for humans which show
+    /// the corresponding messages instead
+    async fn event(&self, _event: UIEvent) -> Result<(), UIError> {
+        Ok(())
+    }
+
+    /// Resolves when the user interrupts the agent to steer it with a new instruction.
+    /// The future does not borrow the UI, so that the agent can keep working meanwhile.
+    fn interrupted(&self) -> BoxFuture<'static, ()> {
+        Box::pin(std::future::pending())
+    }
 }
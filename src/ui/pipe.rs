@@ -0,0 +1,29 @@
+use super::{UIError, UIMessage, UserInterface};
+use async_trait::async_trait;
+use std::io::{self, IsTerminal, Write};
+
+/// Interface of one-shot runs in a shell pipeline: stdout is reserved for the answer,
+/// so the actions of the agent are shown on stderr, and only if somebody watches it.
+/// Stdin holds the task, so there is nobody to ask.
+pub struct PipeUI;
+
+#[async_trait]
+impl UserInterface for PipeUI {
+    async fn display(&self, message: UIMessage) -> Result<(), UIError> {
+        let mut stderr = io::stderr().lock();
+        if let UIMessage::Action(msg) = message {
+            if stderr.is_terminal() {
+                writeln!(stderr, "{}", msg)?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_input(&self, _prompt: &str) -> Result<String, UIError> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Nobody can answer in pipe mode, decide on your own",
+        )
+        .into())
+    }
+}
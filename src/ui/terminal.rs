@@ -1,13 +1,44 @@
 use super::{UIError, UIMessage, UserInterface};
+use crate::permissions::{
+    CommandPattern, PermissionDecision, PermissionMediator, PermissionRequest,
+    PermissionRequestReason,
+};
+use crate::settings::NotificationSettings;
 use async_trait::async_trait;
-use std::io::{self, Write};
+use futures::future::BoxFuture;
+use std::io::{self, IsTerminal, Write};
 use tokio::io::{AsyncBufReadExt, BufReader};
+use tracing::warn;
 
-pub struct TerminalUI; // Simplified struct, no fields needed
+pub struct TerminalUI {
+    notifications: NotificationSettings,
+}
 
 impl TerminalUI {
-    pub fn new() -> Self {
-        Self
+    pub fn with_notifications(notifications: NotificationSettings) -> Self {
+        Self { notifications }
+    }
+
+    /// Draws the attention of the user, who might be working in another window
+    pub fn notify(&self, message: &str) {
+        let mut stdout = io::stdout().lock();
+        if self.notifications.bell {
+            let _ = write!(stdout, "\x07");
+        }
+        if self.notifications.osc777 {
+            let _ = write!(stdout, "\x1b]777;notify;code-assistant;{}\x07", message);
+        }
+        let _ = stdout.flush();
+
+        if let Some(command) = &self.notifications.command {
+            if let Err(e) = std::process::Command::new("sh")
+                .args(["-c", command])
+                .env("CODE_ASSISTANT_MESSAGE", message)
+                .spawn()
+            {
+                warn!("Failed to run notification command '{}': {}", command, e);
+            }
+        }
     }
 
     async fn write_line(&self, s: &str) -> Result<(), UIError> {
@@ -15,6 +46,26 @@ impl TerminalUI {
         writeln!(stdout, "{}", s)?;
         Ok(())
     }
+
+    /// Colors added lines green, removed lines red, hunk headers cyan and file headers bold,
+    /// unless the output is redirected
+    fn color_diff_line(line: &str) -> String {
+        if !io::stdout().is_terminal() {
+            return line.to_string();
+        }
+        let color = if line.starts_with("+++") || line.starts_with("---") {
+            "1"
+        } else if line.starts_with('+') {
+            "32"
+        } else if line.starts_with('-') {
+            "31"
+        } else if line.starts_with("@@") {
+            "36"
+        } else {
+            return line.to_string();
+        };
+        format!("\x1b[{}m{}\x1b[0m", color, line)
+    }
 }
 
 #[async_trait]
@@ -22,13 +73,29 @@ impl UserInterface for TerminalUI {
     async fn display(&self, message: UIMessage) -> Result<(), UIError> {
         match message {
             UIMessage::Action(msg) => self.write_line(&msg).await?,
-            UIMessage::Question(msg) => self.write_line(&format!("{}\n> ", msg)).await?,
+            UIMessage::Question(msg) => {
+                self.notify(msg.lines().next().unwrap_or("The agent has a question"));
+                self.write_line(&format!("{}\n> ", msg)).await?
+            }
             UIMessage::Reasoning(msg) => {
                 self.write_line("").await?;
                 self.write_line("Reasoning:").await?;
                 self.write_line(&format!("  {}", msg)).await?;
                 self.write_line("").await?;
             }
+            UIMessage::Review(msg) => {
+                self.write_line("").await?;
+                self.write_line("Review:").await?;
+                for line in msg.lines() {
+                    self.write_line(&format!("  {}", line)).await?;
+                }
+                self.write_line("").await?;
+            }
+            UIMessage::Diff(diff) => {
+                for line in diff.lines() {
+                    self.write_line(&Self::color_diff_line(line)).await?;
+                }
+            }
         }
         Ok(())
     }
@@ -44,4 +111,105 @@ impl UserInterface for TerminalUI {
 
         Ok(line.trim().to_string())
     }
+
+    fn interrupted(&self) -> BoxFuture<'static, ()> {
+        Box::pin(async {
+            if tokio::signal::ctrl_c().await.is_err() {
+                std::future::pending::<()>().await
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl PermissionMediator for TerminalUI {
+    async fn request_permission(
+        &self,
+        request: &PermissionRequest,
+    ) -> anyhow::Result<PermissionDecision> {
+        self.notify(&format!("Permission required: {}", request.tool));
+        self.write_line("").await?;
+        self.write_line(&format!("Permission required: {}", request.tool))
+            .await?;
+        match &request.reason {
+            PermissionRequestReason::ExecuteCommand {
+                command_line,
+                working_dir,
+            } => {
+                self.write_line(&format!("  Command:     {}", command_line))
+                    .await?;
+                self.write_line(&format!("  Working dir: {}", working_dir.display()))
+                    .await?;
+            }
+            PermissionRequestReason::ModifyFiles { paths } => {
+                for path in paths {
+                    self.write_line(&format!("  File: {}", path.display()))
+                        .await?;
+                }
+            }
+            PermissionRequestReason::WritePath { paths } => {
+                for path in paths {
+                    self.write_line(&format!("  Outside of the project: {}", path.display()))
+                        .await?;
+                }
+            }
+            PermissionRequestReason::Publish { description } => {
+                self.write_line(&format!("  Publish: {}", description))
+                    .await?;
+            }
+        }
+
+        let pattern = request.grant_pattern();
+        let question = match &pattern {
+            Some(pattern) => format!(
+                "Allow? [y]es once, [a]lways in this session, [p]ermanently for {} in this project, [N]o: ",
+                pattern
+            ),
+            None if matches!(request.reason, PermissionRequestReason::WritePath { .. }) => {
+                "Allow? [y]es once, [a]lways for these directories in this session, [N]o: "
+                    .to_string()
+            }
+            None => "Allow? [y]es once, [a]lways in this session, [N]o: ".to_string(),
+        };
+        loop {
+            let answer = self.get_input(&question).await?;
+            match answer.to_lowercase().as_str() {
+                "y" | "yes" => return Ok(PermissionDecision::Granted),
+                "a" | "always" => return Ok(PermissionDecision::GrantedSession),
+                "p" | "permanently" if pattern.is_some() => {
+                    return Ok(PermissionDecision::GrantedProject)
+                }
+                "" | "n" | "no" => return Ok(PermissionDecision::Denied),
+                _ => continue,
+            }
+        }
+    }
+
+    async fn confirm_grant(
+        &self,
+        request: &PermissionRequest,
+        pattern: &CommandPattern,
+    ) -> anyhow::Result<Option<CommandPattern>> {
+        let question = match pattern {
+            CommandPattern::Prefix(_) => format!(
+                "Permanently allow {}? [Enter] to confirm, a longer prefix of the command to narrow it down, [n]o to allow it once: ",
+                pattern
+            ),
+            _ => format!("Permanently allow {}? [Y/n]: ", pattern),
+        };
+        loop {
+            let answer = self.get_input(&question).await?;
+            match answer.trim() {
+                "" | "y" | "Y" | "yes" => return Ok(Some(pattern.clone())),
+                "n" | "N" | "no" => return Ok(None),
+                prefix => match request.narrowed_grant(prefix) {
+                    Some(narrowed) => return Ok(Some(narrowed)),
+                    None => {
+                        self.write_line(&format!("`{}` doesn't narrow down {}", prefix, pattern))
+                            .await?
+                    }
+                },
+            }
+        }
+    }
 }
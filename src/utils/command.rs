@@ -16,7 +16,17 @@ pub trait CommandExecutor: Send + Sync {
     ) -> Result<CommandOutput>;
 }
 
-pub struct DefaultCommandExecutor;
+/// Runs commands in a shell, in the project unless a working directory is given.
+/// Relative working directories are resolved against the project.
+pub struct DefaultCommandExecutor {
+    root_dir: PathBuf,
+}
+
+impl DefaultCommandExecutor {
+    pub fn new(root_dir: PathBuf) -> Self {
+        Self { root_dir }
+    }
+}
 
 #[async_trait::async_trait]
 impl CommandExecutor for DefaultCommandExecutor {
@@ -25,20 +35,22 @@ impl CommandExecutor for DefaultCommandExecutor {
         command_line: &str,
         working_dir: Option<&PathBuf>,
     ) -> Result<CommandOutput> {
+        let working_dir = match working_dir {
+            Some(dir) => self.root_dir.join(dir),
+            None => self.root_dir.clone(),
+        };
         // Validate working_dir first
-        if let Some(dir) = working_dir {
-            if !dir.exists() {
-                return Err(anyhow::anyhow!(
-                    "Working directory does not exist: {}",
-                    dir.display()
-                ));
-            }
-            if !dir.is_dir() {
-                return Err(anyhow::anyhow!(
-                    "Path is not a directory: {}",
-                    dir.display()
-                ));
-            }
+        if !working_dir.exists() {
+            return Err(anyhow::anyhow!(
+                "Working directory does not exist: {}",
+                working_dir.display()
+            ));
+        }
+        if !working_dir.is_dir() {
+            return Err(anyhow::anyhow!(
+                "Path is not a directory: {}",
+                working_dir.display()
+            ));
         }
         // Create shell command using login shell or fallback
         #[cfg(target_family = "unix")]
@@ -53,9 +65,7 @@ impl CommandExecutor for DefaultCommandExecutor {
         #[cfg(target_family = "windows")]
         cmd.args(["/C", command_line]);
 
-        if let Some(dir) = working_dir {
-            cmd.current_dir(dir);
-        }
+        cmd.current_dir(&working_dir);
         let output = cmd.output()?;
 
         Ok(CommandOutput {
@@ -0,0 +1,150 @@
+/// Lines of context around changes in a diff
+const CONTEXT_LINES: usize = 3;
+
+/// Above this number of compared line pairs, files are shown as completely replaced
+const MAX_COMPARISONS: usize = 4_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Change {
+    Equal,
+    Removed,
+    Added,
+}
+
+/// Computes the changes turning `old` into `new` via their longest common subsequence
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(Change, &'a str)> {
+    if old.len().saturating_mul(new.len()) > MAX_COMPARISONS {
+        return old
+            .iter()
+            .map(|line| (Change::Removed, *line))
+            .chain(new.iter().map(|line| (Change::Added, *line)))
+            .collect();
+    }
+
+    // lcs[i][j] is the length of the common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            changes.push((Change::Equal, old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            changes.push((Change::Removed, old[i]));
+            i += 1;
+        } else {
+            changes.push((Change::Added, new[j]));
+            j += 1;
+        }
+    }
+    changes
+}
+
+/// Renders the changes from `old` to `new` content of a file as unified diff,
+/// empty if the contents are equal
+pub fn unified_diff(path: &str, old: &str, new: &str) -> String {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let changes = diff_lines(&old_lines, &new_lines);
+    if changes.iter().all(|(change, _)| *change == Change::Equal) {
+        return String::new();
+    }
+
+    let mut diff = format!("--- a/{}\n+++ b/{}\n", path, path);
+    let mut index = 0;
+    // Line numbers of the old and new file before changes[index]
+    let (mut old_line, mut new_line) = (0, 0);
+    while index < changes.len() {
+        // Find the next change
+        let Some(offset) = changes[index..]
+            .iter()
+            .position(|(change, _)| *change != Change::Equal)
+        else {
+            break;
+        };
+        let first_change = index + offset;
+        let start = first_change.saturating_sub(CONTEXT_LINES).max(index);
+        old_line += start - index;
+        new_line += start - index;
+
+        // Extend the hunk as long as changes are close enough to each other
+        let mut end = first_change;
+        let mut equal_run = 0;
+        while end < changes.len() && equal_run <= 2 * CONTEXT_LINES {
+            if changes[end].0 == Change::Equal {
+                equal_run += 1;
+            } else {
+                equal_run = 0;
+            }
+            end += 1;
+        }
+        if equal_run > CONTEXT_LINES {
+            end -= equal_run - CONTEXT_LINES;
+        }
+
+        let hunk = &changes[start..end];
+        let old_count = hunk.iter().filter(|(c, _)| *c != Change::Added).count();
+        let new_count = hunk.iter().filter(|(c, _)| *c != Change::Removed).count();
+        // Empty ranges refer to the line before them
+        let first_line = |line: usize, count: usize| if count == 0 { line } else { line + 1 };
+        diff.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            first_line(old_line, old_count),
+            old_count,
+            first_line(new_line, new_count),
+            new_count
+        ));
+        for (change, line) in hunk {
+            let prefix = match change {
+                Change::Equal => ' ',
+                Change::Removed => '-',
+                Change::Added => '+',
+            };
+            diff.push(prefix);
+            diff.push_str(line);
+            diff.push('\n');
+        }
+
+        old_line += old_count;
+        new_line += new_count;
+        index = end;
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified_diff() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\nm\n";
+        let new = "a\nB\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\nm\nn\n";
+        assert_eq!(
+            unified_diff("src/lib.rs", old, new),
+            "--- a/src/lib.rs\n+++ b/src/lib.rs\n\
+             @@ -1,5 +1,5 @@\n a\n-b\n+B\n c\n d\n e\n\
+             @@ -11,3 +11,4 @@\n k\n l\n m\n+n\n"
+        );
+    }
+
+    #[test]
+    fn test_unified_diff_of_new_and_equal_files() {
+        assert_eq!(
+            unified_diff("new.rs", "", "fn main() {}\n"),
+            "--- a/new.rs\n+++ b/new.rs\n@@ -0,0 +1,1 @@\n+fn main() {}\n"
+        );
+        assert_eq!(unified_diff("same.rs", "x\n", "x\n"), "");
+    }
+}
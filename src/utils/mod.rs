@@ -1,8 +1,10 @@
 mod command;
+mod diff;
 mod file_updater;
 mod utils;
 
 #[allow(unused_imports)]
 pub use command::{CommandExecutor, CommandOutput, DefaultCommandExecutor};
+pub use diff::unified_diff;
 pub use file_updater::apply_content_updates;
 pub use utils::format_with_line_numbers;
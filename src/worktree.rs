@@ -0,0 +1,147 @@
+use crate::settings::config_dir;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Prefix of the branches created for sessions running in their own worktree
+const BRANCH_PREFIX: &str = "code-assistant/";
+
+/// Git worktree a session works in instead of the project directory, checked out on
+/// a branch of its own. Parallel sessions don't get into each other's way, and the
+/// result of each session can be reviewed and merged as branch.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Worktree {
+    pub path: PathBuf,
+    pub branch: String,
+}
+
+impl Worktree {
+    /// Creates a worktree for the session inside `dir`, branching off the commit
+    /// checked out in the project
+    pub fn create(root_dir: &Path, dir: &Path, session_id: &str) -> Result<Self> {
+        Self::create_at(root_dir, dir, session_id, "HEAD")
+    }
+
+    /// Creates a worktree for a fork of the session working in this worktree, next to
+    /// it and branching off its branch
+    pub fn fork(&self, root_dir: &Path, session_id: &str) -> Result<Self> {
+        let dir = self
+            .path
+            .parent()
+            .context("Worktree without a parent directory")?;
+        Self::create_at(root_dir, dir, session_id, &self.branch)
+    }
+
+    fn create_at(root_dir: &Path, dir: &Path, session_id: &str, start: &str) -> Result<Self> {
+        let worktree = Self {
+            path: dir.join(session_id),
+            branch: format!("{}{}", BRANCH_PREFIX, session_id),
+        };
+        std::fs::create_dir_all(dir)?;
+        git(
+            root_dir,
+            &[
+                "worktree",
+                "add",
+                "-b",
+                &worktree.branch,
+                &worktree.path.to_string_lossy(),
+                start,
+            ],
+        )?;
+        Ok(worktree)
+    }
+
+    /// Uses the worktrees directory inside the user's config directory
+    pub fn create_in_default_location(root_dir: &Path, session_id: &str) -> Result<Self> {
+        Self::create(root_dir, &config_dir()?.join("worktrees"), session_id)
+    }
+
+    /// Removes the worktree including changes which were not committed, the branch
+    /// is kept
+    pub fn remove(&self, root_dir: &Path) -> Result<()> {
+        if !self.path.exists() {
+            // Clean up the administrative files of a worktree deleted by hand
+            git(root_dir, &["worktree", "prune"])?;
+            return Ok(());
+        }
+        git(
+            root_dir,
+            &[
+                "worktree",
+                "remove",
+                "--force",
+                &self.path.to_string_lossy(),
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+fn git(root_dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(root_dir)
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_worktree() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let project = temp_dir.path().join("project");
+        std::fs::create_dir(&project)?;
+        git(&project, &["init", "-q"])?;
+        std::fs::write(project.join("README.md"), "# Project\n")?;
+        git(&project, &["add", "."])?;
+        git(
+            &project,
+            &[
+                "-c",
+                "user.name=test",
+                "-c",
+                "user.email=test@localhost",
+                "commit",
+                "-q",
+                "-m",
+                "Initial commit",
+            ],
+        )?;
+
+        let worktree = Worktree::create(&project, &temp_dir.path().join("worktrees"), "session")?;
+        assert_eq!(worktree.branch, "code-assistant/session");
+        assert_eq!(
+            std::fs::read_to_string(worktree.path.join("README.md"))?,
+            "# Project\n"
+        );
+
+        // Changes in the worktree leave the project alone
+        std::fs::write(worktree.path.join("README.md"), "# Changed\n")?;
+        assert_eq!(
+            std::fs::read_to_string(project.join("README.md"))?,
+            "# Project\n"
+        );
+
+        worktree.remove(&project)?;
+        assert!(!worktree.path.exists());
+        assert_eq!(
+            git(&project, &["branch", "--list", "code-assistant/*"])?,
+            "code-assistant/session"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::process::{Command, Stdio};
+
+/// Serves the Ollama chat API, every request is answered by completing the task
+fn serve_ollama(answer: &str) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let action = serde_json::json!({
+        "reasoning": "The answer is known",
+        "tool": { "name": "CompleteTask", "params": { "message": answer } }
+    });
+    let body = serde_json::json!({
+        "message": { "content": action.to_string() },
+        "done_reason": "stop"
+    })
+    .to_string();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let mut reader = BufReader::new(&stream);
+            let mut content_length = 0;
+            let mut line = String::new();
+            while reader.read_line(&mut line).is_ok_and(|n| n > 0) && line != "\r\n" {
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap_or(0);
+                    }
+                }
+                line.clear();
+            }
+            let mut request = vec![0; content_length];
+            let _ = reader.read_exact(&mut request);
+            let _ = write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+        }
+    });
+    port
+}
+
+#[test]
+fn test_pipe_prints_only_the_answer() {
+    let home = tempfile::TempDir::new().unwrap();
+    let project = tempfile::TempDir::new().unwrap();
+    let port = serve_ollama("The settings are parsed in settings.rs");
+    let config_dir = home.path().join(".config").join("code-assistant");
+    std::fs::create_dir_all(&config_dir).unwrap();
+    std::fs::write(
+        config_dir.join("settings.json"),
+        serde_json::json!({
+            "providers": { "ollama": { "base_url": format!("http://127.0.0.1:{}", port) } }
+        })
+        .to_string(),
+    )
+    .unwrap();
+    // A detected forge is logged, like other things happening during the run
+    for args in [
+        &["init", "-q"][..],
+        &[
+            "remote",
+            "add",
+            "origin",
+            "https://github.com/owner/repo.git",
+        ],
+    ] {
+        let status = Command::new("git")
+            .args(args)
+            .current_dir(project.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_code-assistant"))
+        .args(["run", "--pipe", "-p", "ollama", "-m", "llama3", "--path"])
+        .arg(project.path())
+        .env("HOME", home.path())
+        .env("XDG_CONFIG_HOME", home.path().join(".config"))
+        .env_remove("CODE_ASSISTANT_CONFIG_PROFILE")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"Where are the settings parsed?")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "The settings are parsed in settings.rs\n"
+    );
+    assert!(
+        stderr.contains("Issues and pull requests from"),
+        "{}",
+        stderr
+    );
+}